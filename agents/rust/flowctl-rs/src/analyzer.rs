@@ -1,74 +1,227 @@
 //! Code analyzer for finding instrumentable functions
 
+use serde::Serialize;
 use std::fs;
-use std::path::{Path, PathBuf};
-use syn::{visit::Visit, File, Item, ItemFn};
-use walkdir::WalkDir;
+use std::path::Path;
+use syn::{
+    visit::Visit, Attribute, Block, ImplItemFn, Item, ItemFn, ItemImpl, ItemMod, Signature, TraitItemFn,
+    Visibility,
+};
 
-#[derive(Debug, Clone, Default)]
+use crate::detect;
+use crate::recommend::{self, Recommendation};
+use crate::workspace;
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct AnalysisStats {
     pub total_files: usize,
     pub total_functions: usize,
     pub instrumentable_functions: usize,
     pub instrumented_functions: usize,
+    /// Functions instrumented by another framework, e.g. `#[tracing::instrument]`
+    pub other_instrumented_functions: usize,
     pub total_lines: usize,
     pub async_functions: usize,
     pub sync_functions: usize,
     pub public_functions: usize,
     pub private_functions: usize,
+    /// Percentage of candidate functions (instrumented + instrumentable) carrying `#[trace]`
+    pub coverage: f64,
+    /// Per-crate totals for directories and cargo workspaces
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub crates: Vec<CrateStats>,
+    /// Per-file breakdown, in analysis order
+    pub files: Vec<FileStats>,
+    /// Uninstrumented functions ranked by tracing value (only when requested)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recommendations: Vec<Recommendation>,
+}
+
+/// Function counts and coverage for one crate
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CrateStats {
+    pub name: String,
+    pub files: usize,
+    pub total_functions: usize,
+    pub instrumentable_functions: usize,
+    pub instrumented_functions: usize,
+    pub coverage: f64,
+}
+
+/// Function counts and coverage for one source file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FileStats {
+    pub path: String,
+    /// Crate the file belongs to (empty when a single file is analyzed)
+    #[serde(rename = "crate", skip_serializing_if = "String::is_empty")]
+    pub crate_name: String,
+    /// Module path derived from the file location, e.g. `crate::handlers::users`
+    pub module: String,
+    pub lines: usize,
+    pub total_functions: usize,
+    pub instrumentable_functions: usize,
+    pub instrumented_functions: usize,
+    pub other_instrumented_functions: usize,
+    pub coverage: f64,
+}
+
+impl AnalysisStats {
+    /// Whether instrumentation coverage is at least `min` percent
+    pub fn meets_coverage(&self, min: f64) -> bool {
+        self.coverage >= min
+    }
+
+    /// Add one file's counts to the totals and record its breakdown
+    fn add_file(&mut self, path: &Path, root: &Path, crate_name: &str, file: AnalysisStats) {
+        self.total_files += 1;
+        self.total_lines += file.total_lines;
+        self.total_functions += file.total_functions;
+        self.instrumentable_functions += file.instrumentable_functions;
+        self.instrumented_functions += file.instrumented_functions;
+        self.other_instrumented_functions += file.other_instrumented_functions;
+        self.async_functions += file.async_functions;
+        self.sync_functions += file.sync_functions;
+        self.public_functions += file.public_functions;
+        self.private_functions += file.private_functions;
+        self.recommendations.extend(file.recommendations);
+
+        if !crate_name.is_empty() {
+            if self.crates.last().is_none_or(|last| last.name != crate_name) {
+                self.crates.push(CrateStats {
+                    name: crate_name.to_string(),
+                    ..Default::default()
+                });
+            }
+            if let Some(stats) = self.crates.last_mut() {
+                stats.files += 1;
+                stats.total_functions += file.total_functions;
+                stats.instrumentable_functions += file.instrumentable_functions;
+                stats.instrumented_functions += file.instrumented_functions;
+                stats.coverage = coverage(stats.instrumented_functions, stats.instrumentable_functions);
+            }
+        }
+
+        self.files.push(FileStats {
+            path: path.display().to_string(),
+            crate_name: crate_name.to_string(),
+            module: module_path(path, root),
+            lines: file.total_lines,
+            total_functions: file.total_functions,
+            instrumentable_functions: file.instrumentable_functions,
+            instrumented_functions: file.instrumented_functions,
+            other_instrumented_functions: file.other_instrumented_functions,
+            coverage: coverage(file.instrumented_functions, file.instrumentable_functions),
+        });
+
+        self.coverage = coverage(self.instrumented_functions, self.instrumentable_functions);
+    }
+}
+
+/// Percentage of candidates that are instrumented; 100 when there are none
+fn coverage(instrumented: usize, instrumentable: usize) -> f64 {
+    let candidates = instrumented + instrumentable;
+    if candidates == 0 {
+        100.0
+    } else {
+        instrumented as f64 * 100.0 / candidates as f64
+    }
+}
+
+/// Derive a module path from a file location relative to the analyzed root
+///
+/// `src/lib.rs` and `src/main.rs` map to `crate`, `src/a/mod.rs` to
+/// `crate::a` and `src/a/b.rs` to `crate::a::b`. Files outside `src` keep
+/// their path segments as-is.
+pub fn module_path(path: &Path, root: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut segments: Vec<String> = relative
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+
+    if let Some(src) = segments.iter().position(|segment| segment == "src") {
+        segments.drain(..=src);
+    }
+    if matches!(segments.last().map(String::as_str), Some("lib" | "main" | "mod")) {
+        segments.pop();
+    }
+
+    std::iter::once("crate".to_string())
+        .chain(segments)
+        .collect::<Vec<_>>()
+        .join("::")
 }
 
-pub struct Analyzer;
+pub struct Analyzer {
+    recommend: bool,
+}
 
 impl Analyzer {
     pub fn new() -> Self {
-        Self
+        Self { recommend: false }
+    }
+
+    /// Also collect ranked instrumentation recommendations
+    pub fn with_recommendations(mut self, recommend: bool) -> Self {
+        self.recommend = recommend;
+        self
     }
 
     pub fn analyze_path(&self, path: &Path) -> Result<AnalysisStats, String> {
         let mut stats = AnalysisStats::default();
 
         if path.is_file() {
-            self.analyze_file(path, &mut stats)?;
+            let root = path.parent().unwrap_or(path);
+            self.analyze_file(path, root, "", &mut stats)?;
         } else if path.is_dir() {
             self.analyze_directory(path, &mut stats)?;
         } else {
             return Err(format!("Path not found: {}", path.display()));
         }
 
+        recommend::rank(&mut stats.recommendations);
         Ok(stats)
     }
 
     fn analyze_directory(&self, dir: &Path, stats: &mut AnalysisStats) -> Result<(), String> {
-        for entry in WalkDir::new(dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "rs"))
-        {
-            self.analyze_file(entry.path(), stats)?;
+        for sources in workspace::crate_sources(dir) {
+            for file in &sources.files {
+                self.analyze_file(file, dir, &sources.name, stats)?;
+            }
         }
 
         Ok(())
     }
 
-    fn analyze_file(&self, file: &Path, stats: &mut AnalysisStats) -> Result<(), String> {
+    fn analyze_file(
+        &self,
+        file: &Path,
+        root: &Path,
+        crate_name: &str,
+        stats: &mut AnalysisStats,
+    ) -> Result<(), String> {
         let content = fs::read_to_string(file)
             .map_err(|e| format!("Failed to read file {}: {}", file.display(), e))?;
 
-        // Count lines
-        stats.total_lines += content.lines().count();
-        stats.total_files += 1;
-
         // Parse file
         let syntax = syn::parse_file(&content)
             .map_err(|e| format!("Failed to parse file {}: {}", file.display(), e))?;
 
         // Visit and analyze functions
         let mut visitor = FunctionVisitor {
-            stats: stats.clone(),
+            stats: AnalysisStats {
+                total_lines: content.lines().count(),
+                ..Default::default()
+            },
+            in_trait_impl: false,
+            in_test_module: false,
+            impl_type: None,
+            in_traced_impl: false,
+            file: self.recommend.then(|| file.display().to_string()),
         };
         visitor.visit_file(&syntax);
-        *stats = visitor.stats;
+        stats.add_file(file, root, crate_name, visitor.stats);
 
         Ok(())
     }
@@ -76,48 +229,132 @@ impl Analyzer {
 
 struct FunctionVisitor {
     stats: AnalysisStats,
+    /// Whether the visitor is inside an `impl Trait for Type` block
+    in_trait_impl: bool,
+    /// Whether the visitor is inside a `#[cfg(test)]` module
+    in_test_module: bool,
+    /// Self type of the enclosing impl block, for naming methods
+    impl_type: Option<String>,
+    /// Whether the enclosing impl block is traced as a whole with `#[trace]`
+    in_traced_impl: bool,
+    /// File being analyzed, set when recommendations are collected
+    file: Option<String>,
 }
 
-impl<'ast> Visit<'ast> for FunctionVisitor {
-    fn visit_item_fn(&mut self, node: &'ast ItemFn) {
+impl FunctionVisitor {
+    /// Classify one function or method with a body
+    fn record(&mut self, attrs: &[Attribute], sig: &Signature, block: &Block, is_public: bool) {
         self.stats.total_functions += 1;
 
         // Check if async
-        if node.sig.asyncness.is_some() {
+        if sig.asyncness.is_some() {
             self.stats.async_functions += 1;
         } else {
             self.stats.sync_functions += 1;
         }
 
         // Check visibility
-        match &node.vis {
-            syn::Visibility::Public(_) => self.stats.public_functions += 1,
-            _ => self.stats.private_functions += 1,
+        if is_public {
+            self.stats.public_functions += 1;
+        } else {
+            self.stats.private_functions += 1;
         }
 
-        // Check if already instrumented
-        let has_trace_attr = node
-            .attrs
-            .iter()
-            .any(|attr| attr.path().is_ident("trace"));
-
-        if has_trace_attr {
+        // Check if already instrumented, by FlowTrace or another framework
+        if self.in_traced_impl || attrs.iter().any(detect::is_trace_attribute) {
             self.stats.instrumented_functions += 1;
+        } else if attrs.iter().any(detect::is_other_instrumentation) {
+            self.stats.other_instrumented_functions += 1;
         } else {
-            // Check if instrumentable (has body, not in test module)
-            let is_test = node
-                .attrs
-                .iter()
-                .any(|attr| attr.path().is_ident("test") || attr.path().is_ident("cfg"));
+            // Check if instrumentable (has body, not test code)
+            let is_test = self.in_test_module || attrs.iter().any(detect::is_test_attribute);
 
-            if node.block.stmts.len() > 0 && !is_test {
+            if !block.stmts.is_empty() && !is_test {
                 self.stats.instrumentable_functions += 1;
+                self.recommend(sig, block, is_public);
             }
         }
+    }
+}
+
+impl FunctionVisitor {
+    fn recommend(&mut self, sig: &Signature, block: &Block, is_public: bool) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let line = sig.fn_token.span.start().line;
+        let lines = block.brace_token.span.close().end().line.saturating_sub(line) + 1;
+        let (score, reasons) = recommend::score(sig, block, is_public, lines);
+        if score == 0 {
+            return;
+        }
+
+        let function = match &self.impl_type {
+            Some(type_name) => format!("{}::{}", type_name, sig.ident),
+            None => sig.ident.to_string(),
+        };
+
+        self.stats.recommendations.push(Recommendation {
+            function,
+            file: file.clone(),
+            line,
+            score,
+            reasons,
+        });
+    }
+}
+
+impl<'ast> Visit<'ast> for FunctionVisitor {
+    fn visit_item_fn(&mut self, node: &'ast ItemFn) {
+        let is_public = matches!(node.vis, Visibility::Public(_));
+        self.record(&node.attrs, &node.sig, &node.block, is_public);
 
         syn::visit::visit_item_fn(self, node);
     }
 
+    fn visit_item_mod(&mut self, node: &'ast ItemMod) {
+        let outer = self.in_test_module;
+        self.in_test_module |= node.attrs.iter().any(detect::is_cfg_test);
+        syn::visit::visit_item_mod(self, node);
+        self.in_test_module = outer;
+    }
+
+    fn visit_item_impl(&mut self, node: &'ast ItemImpl) {
+        let outer = (self.in_trait_impl, self.impl_type.take(), self.in_traced_impl);
+        self.in_trait_impl = node.trait_.is_some();
+        self.in_traced_impl = node.attrs.iter().any(detect::is_trace_attribute);
+        self.impl_type = Some(match &*node.self_ty {
+            syn::Type::Path(type_path) => type_path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident.to_string())
+                .unwrap_or_default(),
+            other => quote::quote!(#other).to_string(),
+        });
+
+        syn::visit::visit_item_impl(self, node);
+        (self.in_trait_impl, self.impl_type, self.in_traced_impl) = outer;
+    }
+
+    fn visit_impl_item_fn(&mut self, node: &'ast ImplItemFn) {
+        // Trait impl methods are as visible as the trait itself
+        let is_public = self.in_trait_impl || matches!(node.vis, Visibility::Public(_));
+        self.record(&node.attrs, &node.sig, &node.block, is_public);
+
+        syn::visit::visit_impl_item_fn(self, node);
+    }
+
+    fn visit_trait_item_fn(&mut self, node: &'ast TraitItemFn) {
+        // Only default methods have a body to instrument
+        if let Some(block) = &node.default {
+            self.record(&node.attrs, &node.sig, block, true);
+        }
+
+        syn::visit::visit_trait_item_fn(self, node);
+    }
+
     fn visit_item(&mut self, node: &'ast Item) {
         // Also visit nested items (impl blocks, etc.)
         syn::visit::visit_item(self, node);
@@ -145,9 +382,152 @@ mod tests {
         let temp_file = std::env::temp_dir().join("test.rs");
         std::fs::write(&temp_file, code).unwrap();
 
-        let stats = analyzer.analyze_file(&temp_file, &mut AnalysisStats::default());
-        assert!(stats.is_ok());
+        let mut stats = AnalysisStats::default();
+        let result = analyzer.analyze_file(&temp_file, &std::env::temp_dir(), "", &mut stats);
+        assert!(result.is_ok());
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_counts_impl_and_trait_methods() {
+        let code = r#"
+            pub struct Service;
+
+            impl Service {
+                pub async fn load(&self) { fetch().await; }
+                fn helper(&self) { work(); }
+                #[trace]
+                pub fn traced(&self) { work(); }
+            }
+
+            impl Display for Service {
+                fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "svc") }
+            }
+
+            #[trace]
+            impl Debug for Service {
+                fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "Service") }
+            }
+
+            trait Store {
+                fn get(&self) -> u32;
+                fn get_or_zero(&self) -> u32 { self.get() }
+            }
+        "#;
+
+        let temp_file = std::env::temp_dir().join("flowctl_analyze_methods.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let stats = Analyzer::new().analyze_path(&temp_file).unwrap();
+        assert_eq!(stats.total_functions, 6);
+        assert_eq!(stats.async_functions, 1);
+        assert_eq!(stats.public_functions, 5);
+        assert_eq!(stats.private_functions, 1);
+        assert_eq!(stats.instrumented_functions, 2);
+        assert_eq!(stats.instrumentable_functions, 4);
 
         std::fs::remove_file(temp_file).unwrap();
     }
+
+    #[test]
+    fn test_detects_other_instrumentation_and_test_modules() {
+        let code = r#"
+            #[flowtrace_derive::trace]
+            fn traced() { work(); }
+
+            #[tracing::instrument]
+            fn other() { work(); }
+
+            #[cfg(feature = "db")]
+            fn feature_gated() { work(); }
+
+            #[cfg(test)]
+            mod tests {
+                fn fixture() { work(); }
+
+                #[tokio::test]
+                async fn it_works() { work(); }
+            }
+        "#;
+
+        let temp_file = std::env::temp_dir().join("flowctl_analyze_detect.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let stats = Analyzer::new().analyze_path(&temp_file).unwrap();
+        assert_eq!(stats.total_functions, 5);
+        assert_eq!(stats.instrumented_functions, 1);
+        assert_eq!(stats.other_instrumented_functions, 1);
+        assert_eq!(stats.instrumentable_functions, 1);
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_recommendations_ranked() {
+        let code = r#"
+            fn add(a: u32, b: u32) -> u32 { a + b }
+
+            struct Repo;
+
+            impl Repo {
+                pub async fn load(&self, id: u32) -> Result<User, Error> {
+                    self.pool.query(id).await
+                }
+            }
+
+            fn save() -> Result<(), Error> { Ok(()) }
+        "#;
+
+        let temp_file = std::env::temp_dir().join("flowctl_analyze_recommend.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let stats = Analyzer::new()
+            .with_recommendations(true)
+            .analyze_path(&temp_file)
+            .unwrap();
+        let ranked: Vec<&str> = stats
+            .recommendations
+            .iter()
+            .map(|r| r.function.as_str())
+            .collect();
+        assert_eq!(ranked, vec!["Repo::load", "save"]);
+        assert_eq!(stats.recommendations[0].line, 7);
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_per_file_breakdown() {
+        let dir = std::env::temp_dir().join("flowctl_analyze_breakdown");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/handlers")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "#[trace]\nfn a() { work(); }\nfn b() { work(); }").unwrap();
+        std::fs::write(dir.join("src/handlers/users.rs"), "fn c() { work(); }").unwrap();
+
+        let stats = Analyzer::new().analyze_path(&dir).unwrap();
+        assert_eq!(stats.total_files, 2);
+        assert_eq!(stats.files[0].module, "crate::handlers::users");
+        assert_eq!(stats.files[0].coverage, 0.0);
+        assert_eq!(stats.files[1].module, "crate");
+        assert_eq!(stats.files[1].coverage, 50.0);
+        assert!((stats.coverage - 100.0 / 3.0).abs() < 1e-9);
+        assert!(stats.meets_coverage(30.0));
+        assert!(!stats.meets_coverage(60.0));
+
+        assert_eq!(stats.crates.len(), 1);
+        assert_eq!(stats.crates[0].name, "flowctl_analyze_breakdown");
+        assert_eq!(stats.crates[0].files, 2);
+        assert_eq!(stats.files[0].crate_name, "flowctl_analyze_breakdown");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_module_path() {
+        let root = Path::new("/project");
+        assert_eq!(module_path(Path::new("/project/src/main.rs"), root), "crate");
+        assert_eq!(module_path(Path::new("/project/src/db/mod.rs"), root), "crate::db");
+        assert_eq!(module_path(Path::new("/project/src/db/pool.rs"), root), "crate::db::pool");
+    }
 }
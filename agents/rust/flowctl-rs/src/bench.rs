@@ -0,0 +1,122 @@
+//! Generated micro-benchmark for `flowctl-rs bench-overhead`
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::probe::Probe;
+
+/// Directory under the project's `target/` holding the generated crate
+const BENCH_DIR: &str = "flowtrace-bench";
+
+/// Benchmark program: the same function with and without `#[trace]`
+const BENCH_MAIN: &str = r#"use flowtrace_derive::trace;
+use std::hint::black_box;
+use std::time::Instant;
+
+fn untraced(n: u64) -> u64 {
+    n.wrapping_mul(31).wrapping_add(7)
+}
+
+#[trace]
+fn traced(n: u64) -> u64 {
+    n.wrapping_mul(31).wrapping_add(7)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let iterations: u64 = args.next().and_then(|v| v.parse().ok()).unwrap_or(100_000);
+    let log_file = args.next().unwrap_or_else(|| "flowtrace-bench.jsonl".to_string());
+
+    let config = flowtrace_agent::Config {
+        log_file,
+        ..Default::default()
+    };
+    flowtrace_agent::start_tracing(config).expect("failed to start FlowTrace");
+
+    // Warm up caches and the logger before measuring
+    for i in 0..iterations / 10 {
+        black_box(untraced(black_box(i)));
+        black_box(traced(black_box(i)));
+    }
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        black_box(untraced(black_box(i)));
+    }
+    let untraced_ns = start.elapsed().as_nanos();
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        black_box(traced(black_box(i)));
+    }
+    let traced_ns = start.elapsed().as_nanos();
+
+    flowtrace_agent::stop_tracing();
+    println!(
+        "{{\"iterations\":{},\"untraced_ns\":{},\"traced_ns\":{}}}",
+        iterations, untraced_ns, traced_ns
+    );
+}
+"#;
+
+/// Timings reported by the generated benchmark
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct BenchReport {
+    pub iterations: u64,
+    pub untraced_ns: u128,
+    pub traced_ns: u128,
+}
+
+impl BenchReport {
+    /// Average nanoseconds per untraced call
+    pub fn untraced_per_call(&self) -> f64 {
+        self.untraced_ns as f64 / self.iterations.max(1) as f64
+    }
+
+    /// Average nanoseconds per traced call
+    pub fn traced_per_call(&self) -> f64 {
+        self.traced_ns as f64 / self.iterations.max(1) as f64
+    }
+
+    /// Extra nanoseconds each traced call costs
+    pub fn overhead_per_call(&self) -> f64 {
+        self.traced_per_call() - self.untraced_per_call()
+    }
+}
+
+/// Generate the benchmark crate under `project/target`, run it in release
+/// mode and parse its timings
+pub fn run(project: &Path, iterations: u64) -> Result<BenchReport, String> {
+    let probe = Probe::create(project, BENCH_DIR, BENCH_MAIN)?;
+
+    let log_file = probe.dir.join("flowtrace-bench.jsonl");
+    let _ = fs::remove_file(&log_file);
+
+    let iterations = iterations.to_string();
+    let stdout = probe.run([OsStr::new(&iterations), log_file.as_os_str()])?;
+    parse_report(&stdout)
+}
+
+fn parse_report(stdout: &str) -> Result<BenchReport, String> {
+    stdout
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str(line.trim()).ok())
+        .ok_or_else(|| format!("Unexpected benchmark output: {}", stdout.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_report() {
+        let report = parse_report("noise\n{\"iterations\":1000,\"untraced_ns\":2000,\"traced_ns\":502000}\n").unwrap();
+
+        assert_eq!(report.untraced_per_call(), 2.0);
+        assert_eq!(report.traced_per_call(), 502.0);
+        assert_eq!(report.overhead_per_call(), 500.0);
+        assert!(parse_report("error").is_err());
+    }
+}
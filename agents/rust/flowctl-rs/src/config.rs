@@ -0,0 +1,198 @@
+//! Project configuration loaded from `flowtrace.toml`
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the project configuration file
+pub const CONFIG_FILE: &str = "flowtrace.toml";
+
+/// Contents written by `flowctl-rs init`
+pub const DEFAULT_CONFIG: &str = r#"# FlowTrace project configuration
+# Read by flowctl-rs; CLI flags take precedence where both are given.
+
+[instrument]
+# Only process files matching these globs (relative to the instrumented path)
+include = []
+# Never process files matching these globs
+exclude = ["**/generated/**", "**/migrations/**"]
+# Only instrument functions whose name (or Type::name) matches this regex
+# fn_filter = "^handle_"
+public_only = false
+async_only = false
+# min_lines = 5
+
+[budgets]
+# Latency budgets in milliseconds checked by `flowctl-rs summary`,
+# keyed by function name or module::function
+# "app::handlers::checkout" = 250
+
+# Named profiles, selected with `flowctl-rs instrument --profile NAME` and by
+# the agent's Config::from_file (FLOWTRACE_PROFILE or [agent] profile).
+# A profile's include/exclude/fn_filter/... replace the [instrument] values.
+# [profiles.debug-billing]
+# modules = ["billing"]
+# exclude_modules = ["billing::metrics"]
+# stdout = true
+"#;
+
+/// Settings shared by flowctl-rs commands for one project
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProjectConfig {
+    pub instrument: InstrumentConfig,
+    /// `[budgets]` section: maximum call duration in milliseconds per function
+    pub budgets: BTreeMap<String, f64>,
+    /// `[profiles.<name>]` sections: named instrumentation profiles
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// `[instrument]` section: which files and functions get `#[trace]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct InstrumentConfig {
+    /// Glob patterns a file must match to be processed (all files if empty)
+    pub include: Vec<String>,
+    /// Glob patterns for files that are never processed
+    pub exclude: Vec<String>,
+    /// Regex a function name must match to be instrumented
+    pub fn_filter: Option<String>,
+    /// Only instrument `pub` functions and trait impl methods
+    pub public_only: bool,
+    /// Only instrument `async fn`s
+    pub async_only: bool,
+    /// Only instrument functions spanning at least this many lines
+    pub min_lines: Option<usize>,
+}
+
+/// `[profiles.<name>]` section, also read by the agent's `Config::from_file`
+///
+/// Agent-only keys such as `stdout` or `log_file` are ignored here.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    #[serde(flatten)]
+    pub instrument: InstrumentConfig,
+    /// Only process these modules (and their submodules); all if empty
+    pub modules: Vec<String>,
+    /// Never process these modules (and their submodules)
+    pub exclude_modules: Vec<String>,
+}
+
+impl ProjectConfig {
+    /// Load the closest `flowtrace.toml` at or above `path`, or defaults if none exists
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match find_config(path) {
+            Some(file) => Self::from_file(&file),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parse a specific configuration file
+    pub fn from_file(file: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+
+        toml::from_str(&content).map_err(|e| format!("Invalid {}: {}", file.display(), e))
+    }
+
+    /// Look up a named profile
+    pub fn profile(&self, name: &str) -> Result<&ProfileConfig, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("Profile '{}' not found: no [profiles] defined in {}", name, CONFIG_FILE)
+            } else {
+                format!("Profile '{}' not found (available: {})", name, known.join(", "))
+            }
+        })
+    }
+}
+
+/// Find the closest `flowtrace.toml` at or above `path`
+pub fn find_config(path: &Path) -> Option<PathBuf> {
+    let start = path.canonicalize().ok()?;
+    let mut dir = if start.is_file() { start.parent()? } else { start.as_path() };
+
+    loop {
+        let config = dir.join(CONFIG_FILE);
+        if config.is_file() {
+            return Some(config);
+        }
+        dir = dir.parent()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_instrument_section() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+            [instrument]
+            exclude = ["src/generated/**", "migrations/**"]
+            fn_filter = "^handle_"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.instrument.include.is_empty());
+        assert_eq!(config.instrument.exclude.len(), 2);
+        assert_eq!(config.instrument.fn_filter.as_deref(), Some("^handle_"));
+    }
+
+    #[test]
+    fn test_default_config_parses() {
+        let config: ProjectConfig = toml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(config.instrument.exclude.len(), 2);
+        assert!(config.instrument.fn_filter.is_none());
+        assert!(config.budgets.is_empty());
+    }
+
+    #[test]
+    fn test_parse_budgets() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+            [budgets]
+            "app::checkout" = 250
+            load_user = 12.5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.budgets["app::checkout"], 250.0);
+        assert_eq!(config.budgets["load_user"], 12.5);
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+            [profiles.debug-billing]
+            include = ["src/billing/**"]
+            modules = ["billing"]
+            exclude_modules = ["billing::metrics"]
+            stdout = true
+
+            [profiles.minimal]
+            public_only = true
+            "#,
+        )
+        .unwrap();
+
+        let profile = config.profile("debug-billing").unwrap();
+        assert_eq!(profile.instrument.include, vec!["src/billing/**"]);
+        assert_eq!(profile.modules, vec!["billing"]);
+        assert!(config.profile("minimal").unwrap().instrument.public_only);
+        assert!(config.profile("missing").unwrap_err().contains("debug-billing, minimal"));
+    }
+
+    #[test]
+    fn test_empty_config() {
+        let config: ProjectConfig = toml::from_str("").unwrap();
+        assert!(config.instrument.exclude.is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+//! Joining application logs to call trees for `flowctl-rs correlate`
+//!
+//! A log line belongs to a trace when it mentions the trace ID as a word, e.g.
+//! `trace_id=4bf92f35...` written from `TraceContext::current()`. Lines that
+//! start with a timestamp are placed among the calls of their trace by time;
+//! the rest follow the calls in file order.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use colored::*;
+
+use crate::prune::{self, TimeSpec};
+use crate::trace::{self, Call};
+
+/// One line of the application log
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// 1-based line in the log file
+    pub line: usize,
+    /// Leading timestamp in microseconds, when the line has one
+    pub timestamp: Option<i64>,
+    pub text: String,
+}
+
+/// The calls and log lines of one trace
+#[derive(Debug)]
+pub struct Correlated {
+    pub trace_id: String,
+    pub roots: Vec<Call>,
+    pub logs: Vec<LogLine>,
+}
+
+/// A row of the merged view
+#[derive(Debug)]
+pub enum Entry<'a> {
+    Enter(&'a Call),
+    Exit(&'a Call),
+    Log(&'a LogLine),
+}
+
+/// Group root calls by trace and attach the lines of `log` that mention their trace ID
+///
+/// Traces are returned in the order they started. Time-of-day timestamps in
+/// the log are taken to be on the day of the first call.
+pub fn correlate(roots: Vec<Call>, log: impl BufRead) -> Result<Vec<Correlated>, String> {
+    let first_timestamp = roots.first().map_or(0, |call| call.start);
+    let mut traces: Vec<Correlated> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for root in roots {
+        let Some(trace_id) = root.trace_id.clone() else {
+            continue;
+        };
+        let position = *index.entry(trace_id.clone()).or_insert_with(|| {
+            traces.push(Correlated {
+                trace_id,
+                roots: Vec::new(),
+                logs: Vec::new(),
+            });
+            traces.len() - 1
+        });
+        traces[position].roots.push(root);
+    }
+
+    for (number, text) in log.lines().enumerate() {
+        let text = text.map_err(|e| format!("Failed to read log: {}", e))?;
+        let Some(&position) = words(&text).find_map(|word| index.get(word)) else {
+            continue;
+        };
+        traces[position].logs.push(LogLine {
+            line: number + 1,
+            timestamp: log_timestamp(&text, first_timestamp),
+            text,
+        });
+    }
+    Ok(traces)
+}
+
+/// Candidate trace IDs in a log line
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .filter(|word| !word.is_empty())
+}
+
+/// Timestamp at the start of a log line: `2024-05-01T12:00:00.123Z`, `[2024-05-01 12:00:00,123]` or `12:00:00`
+fn log_timestamp(text: &str, first_timestamp: i64) -> Option<i64> {
+    let mut tokens = text.split_whitespace();
+    let first = tokens.next()?.trim_start_matches('[');
+    let parse = |value: &str| -> Option<i64> {
+        let value = value.trim_end_matches(']').replace(',', ".");
+        // A leading number is more likely a PID or counter than epoch microseconds
+        if value.parse::<i64>().is_ok() {
+            return None;
+        }
+        prune::parse_time(&value).ok().map(|spec: TimeSpec| spec.resolve(first_timestamp))
+    };
+    parse(first).or_else(|| parse(&format!("{} {}", first, tokens.next()?)))
+}
+
+impl Correlated {
+    /// Calls and log lines in time order, with their nesting depth
+    ///
+    /// A log line is nested inside the innermost call running at its timestamp.
+    pub fn entries(&self) -> Vec<(usize, Entry<'_>)> {
+        let mut timed: Vec<&LogLine> = self.logs.iter().filter(|log| log.timestamp.is_some()).collect();
+        timed.sort_by_key(|log| (log.timestamp, log.line));
+        let mut timed = timed.into_iter().peekable();
+
+        let mut entries = Vec::new();
+        for root in &self.roots {
+            walk(root, 0, &mut timed, &mut entries);
+        }
+        entries.extend(timed.map(|log| (0, Entry::Log(log))));
+        entries.extend(self.logs.iter().filter(|log| log.timestamp.is_none()).map(|log| (0, Entry::Log(log))));
+        entries
+    }
+
+    /// Calls in the trace, nested ones included
+    pub fn call_count(&self) -> usize {
+        fn count(call: &Call) -> usize {
+            1 + call.children.iter().map(count).sum::<usize>()
+        }
+        self.roots.iter().map(count).sum()
+    }
+
+    /// The merged view as text, one row per entry, times relative to the first call
+    pub fn render(&self) -> Vec<String> {
+        let start = self.roots.first().map_or(0, |call| call.start);
+        let offset = |micros: i64| {
+            let sign = if micros < start { '-' } else { '+' };
+            format!("{}{}", sign, trace::format_micros((micros - start).abs()))
+        };
+        self.entries()
+            .into_iter()
+            .map(|(depth, entry)| {
+                let indent = "  ".repeat(depth);
+                let (time, row) = match entry {
+                    Entry::Enter(call) => (offset(call.start), format!("→ {}", call.name())),
+                    Entry::Exit(call) => {
+                        let duration = match call.end {
+                            Some(_) => format!("({})", trace::format_micros(call.duration_micros.unwrap_or_default())),
+                            None => "(unfinished)".to_string(),
+                        };
+                        let row = match &call.exception {
+                            Some(exception) => format!("💥 {} {} {}", call.name().red(), duration.dimmed(), exception),
+                            None => format!("← {} {}", call.name(), duration.dimmed()),
+                        };
+                        (offset(call.finish()), row)
+                    }
+                    Entry::Log(log) => (
+                        log.timestamp.map(offset).unwrap_or_default(),
+                        format!("{} {}", "▸".cyan(), log.text),
+                    ),
+                };
+                format!("{:>10}  {}{}", time, indent, row)
+            })
+            .collect()
+    }
+}
+
+fn walk<'a>(
+    call: &'a Call,
+    depth: usize,
+    logs: &mut std::iter::Peekable<std::vec::IntoIter<&'a LogLine>>,
+    entries: &mut Vec<(usize, Entry<'a>)>,
+) {
+    while let Some(log) = logs.next_if(|log| log.timestamp < Some(call.start)) {
+        entries.push((depth, Entry::Log(log)));
+    }
+    entries.push((depth, Entry::Enter(call)));
+    for child in &call.children {
+        walk(child, depth + 1, logs, entries);
+    }
+    while let Some(log) = logs.next_if(|log| log.timestamp <= Some(call.finish())) {
+        entries.push((depth + 1, Entry::Log(log)));
+    }
+    entries.push((depth, Entry::Exit(call)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::event;
+
+    const TRACE: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn roots() -> Vec<Call> {
+        let mut events = vec![
+            event("ENTER", "checkout", 1_000_000, "main"),
+            event("ENTER", "charge", 1_100_000, "main"),
+            event("EXCEPTION", "charge", 1_300_000, "main"),
+            event("EXIT", "checkout", 1_500_000, "main"),
+            event("ENTER", "other", 2_000_000, "main"),
+            event("EXIT", "other", 2_100_000, "main"),
+        ];
+        for (line, event) in events.iter_mut().enumerate() {
+            event.line = line + 1;
+            event.trace_id = Some(if event.function == "other" { "b" } else { TRACE }.to_string());
+        }
+        events[2].exception = Some("declined".to_string());
+        trace::build_calls(&events)
+    }
+
+    #[test]
+    fn test_log_timestamp() {
+        assert_eq!(log_timestamp("1970-01-01T00:00:01.2Z INFO ok", 0), Some(1_200_000));
+        assert_eq!(log_timestamp("[1970-01-01 00:00:01,200] INFO ok", 0), Some(1_200_000));
+        assert_eq!(log_timestamp("00:00:02 WARN slow", 5), Some(2_000_000));
+        assert_eq!(log_timestamp("1234 INFO pid first", 0), None);
+        assert_eq!(log_timestamp("INFO no time", 0), None);
+    }
+
+    #[test]
+    fn test_correlate() {
+        colored::control::set_override(false);
+        let log = format!(
+            "00:00:00.5 INFO starting trace_id={TRACE}\n\
+             00:00:01.2 INFO charging card trace_id={TRACE}\n\
+             00:00:01.2 INFO unrelated trace_id=c\n\
+             00:00:01.4 WARN retry queued [{TRACE}]\n\
+             panic note {TRACE}\n\
+             00:00:02.05 INFO health trace_id=b\n"
+        );
+        let traces = correlate(roots(), log.as_bytes()).unwrap();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].trace_id, TRACE);
+        assert_eq!(traces[0].call_count(), 2);
+        assert_eq!(traces[0].logs.len(), 4);
+        assert_eq!(traces[1].logs[0].line, 6);
+
+        assert_eq!(
+            traces[0].render(),
+            vec![
+                format!(" -500.00ms  ▸ 00:00:00.5 INFO starting trace_id={TRACE}"),
+                "      +0µs  → app::checkout".to_string(),
+                " +100.00ms    → app::charge".to_string(),
+                format!(" +200.00ms      ▸ 00:00:01.2 INFO charging card trace_id={TRACE}"),
+                " +300.00ms    💥 app::charge (200.00ms) declined".to_string(),
+                format!(" +400.00ms    ▸ 00:00:01.4 WARN retry queued [{TRACE}]"),
+                " +500.00ms  ← app::checkout (500.00ms)".to_string(),
+                format!("            ▸ panic note {TRACE}"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Attribute classification shared by the analyzer and instrumenter
+
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Meta, Token};
+
+/// Crates that export the FlowTrace `trace` attribute
+const FLOWTRACE_CRATES: [&str; 2] = ["flowtrace_derive", "flowtrace_agent"];
+
+fn segments(attr: &Attribute) -> Vec<String> {
+    attr.path()
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect()
+}
+
+/// `#[trace]`, `#[flowtrace_derive::trace]` or `#[flowtrace_agent::trace]`
+pub fn is_trace_attribute(attr: &Attribute) -> bool {
+    match segments(attr).as_slice() {
+        [name] => name == "trace",
+        [root, name] => name == "trace" && FLOWTRACE_CRATES.contains(&root.as_str()),
+        _ => false,
+    }
+}
+
+/// Instrumentation from another framework, e.g. `#[tracing::instrument]`
+pub fn is_other_instrumentation(attr: &Attribute) -> bool {
+    match segments(attr).as_slice() {
+        [name] => name == "instrument",
+        [root, name] => {
+            name == "instrument" && (root == "tracing" || root == "tracing_attributes")
+        }
+        _ => false,
+    }
+}
+
+/// Test or benchmark markers: `#[test]`, `#[bench]`, `#[tokio::test]`, `#[cfg(test)]`, ...
+pub fn is_test_attribute(attr: &Attribute) -> bool {
+    if is_cfg_test(attr) {
+        return true;
+    }
+
+    segments(attr)
+        .last()
+        .is_some_and(|name| name == "test" || name == "bench")
+}
+
+/// Whether a `#[cfg(...)]` attribute only enables the item under `cfg(test)`
+pub fn is_cfg_test(attr: &Attribute) -> bool {
+    if !attr.path().is_ident("cfg") {
+        return false;
+    }
+
+    match &attr.meta {
+        Meta::List(list) => list
+            .parse_args::<Meta>()
+            .map(|meta| implies_test(&meta))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Whether a cfg predicate can only hold when compiling tests
+fn implies_test(meta: &Meta) -> bool {
+    match meta {
+        Meta::Path(path) => path.is_ident("test"),
+        Meta::List(list) => {
+            let nested = match list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated) {
+                Ok(nested) => nested,
+                Err(_) => return false,
+            };
+
+            if list.path.is_ident("all") {
+                nested.iter().any(implies_test)
+            } else if list.path.is_ident("any") {
+                !nested.is_empty() && nested.iter().all(implies_test)
+            } else {
+                false
+            }
+        }
+        Meta::NameValue(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(source: &str) -> Attribute {
+        let mut attrs = syn::parse_str::<syn::ItemFn>(&format!("{} fn f() {{}}", source))
+            .unwrap()
+            .attrs;
+        attrs.remove(0)
+    }
+
+    #[test]
+    fn test_trace_attribute_paths() {
+        assert!(is_trace_attribute(&attr("#[trace]")));
+        assert!(is_trace_attribute(&attr("#[flowtrace_derive::trace]")));
+        assert!(is_trace_attribute(&attr("#[flowtrace_agent::trace(skip(password))]")));
+        assert!(!is_trace_attribute(&attr("#[log::trace]")));
+    }
+
+    #[test]
+    fn test_other_instrumentation() {
+        assert!(is_other_instrumentation(&attr("#[tracing::instrument]")));
+        assert!(is_other_instrumentation(&attr("#[instrument(skip(self))]")));
+        assert!(!is_other_instrumentation(&attr("#[trace]")));
+    }
+
+    #[test]
+    fn test_cfg_test_detection() {
+        assert!(is_test_attribute(&attr("#[cfg(test)]")));
+        assert!(is_test_attribute(&attr("#[cfg(all(test, feature = \"db\"))]")));
+        assert!(is_test_attribute(&attr("#[tokio::test]")));
+        assert!(!is_test_attribute(&attr("#[cfg(feature = \"db\")]")));
+        assert!(!is_test_attribute(&attr("#[cfg(not(test))]")));
+        assert!(!is_test_attribute(&attr("#[cfg(any(test, unix))]")));
+    }
+}
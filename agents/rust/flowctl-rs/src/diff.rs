@@ -0,0 +1,119 @@
+//! Per-function latency comparison of two runs for `flowctl-rs diff`
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::trace::Call;
+
+/// Finished calls of one function in one run
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub calls: usize,
+    pub total_micros: i64,
+    /// Fingerprints its calls were recorded with; empty for agents not recording them
+    pub fingerprints: BTreeSet<String>,
+}
+
+impl Stats {
+    pub fn mean_micros(&self) -> f64 {
+        self.total_micros as f64 / self.calls.max(1) as f64
+    }
+}
+
+/// A function called in both runs
+#[derive(Debug, Clone)]
+pub struct FunctionDiff {
+    pub name: String,
+    pub baseline: Stats,
+    pub current: Stats,
+}
+
+impl FunctionDiff {
+    /// Change of the mean latency, in percent of the baseline
+    pub fn change_percent(&self) -> f64 {
+        let baseline = self.baseline.mean_micros();
+        if baseline == 0.0 {
+            return 0.0;
+        }
+        (self.current.mean_micros() - baseline) * 100.0 / baseline
+    }
+
+    /// Whether the function's code changed between the runs; `None` when a
+    /// run has no fingerprints to tell
+    pub fn code_changed(&self) -> Option<bool> {
+        if self.baseline.fingerprints.is_empty() || self.current.fingerprints.is_empty() {
+            return None;
+        }
+        Some(self.baseline.fingerprints != self.current.fingerprints)
+    }
+}
+
+/// Finished calls per function (`module::function`) in `roots`
+pub fn function_stats(roots: &[Call]) -> BTreeMap<String, Stats> {
+    let mut functions: BTreeMap<String, Stats> = BTreeMap::new();
+    let mut stack: Vec<&Call> = roots.iter().collect();
+    while let Some(call) = stack.pop() {
+        stack.extend(&call.children);
+        let Some(duration) = call.duration_micros.filter(|_| call.end.is_some()) else {
+            continue;
+        };
+        let stats = functions.entry(call.name()).or_default();
+        stats.calls += 1;
+        stats.total_micros += duration;
+        stats.fingerprints.extend(call.fingerprint.clone());
+    }
+    functions
+}
+
+/// Functions finished in both runs, largest latency change first
+pub fn diff(baseline: &[Call], current: &[Call]) -> Vec<FunctionDiff> {
+    let mut current = function_stats(current);
+    let mut diffs: Vec<FunctionDiff> = function_stats(baseline)
+        .into_iter()
+        .filter_map(|(name, baseline)| {
+            let current = current.remove(&name)?;
+            Some(FunctionDiff { name, baseline, current })
+        })
+        .collect();
+    diffs.sort_by(|a, b| b.change_percent().abs().total_cmp(&a.change_percent().abs()));
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::build_calls;
+    use crate::trace::tests::event;
+
+    fn run(load_micros: i64, load_fingerprint: &str) -> Vec<Call> {
+        let mut events = vec![
+            event("ENTER", "handle", 0, "t1"),
+            event("ENTER", "load", 100, "t1"),
+            event("EXIT", "load", 100 + load_micros, "t1"),
+            event("EXIT", "handle", 1_000 + load_micros, "t1"),
+        ];
+        for event in &mut events {
+            event.fingerprint = Some(if event.function == "load" { load_fingerprint } else { "h1" }.to_string());
+        }
+        build_calls(&events)
+    }
+
+    #[test]
+    fn test_diff() {
+        let diffs = diff(&run(100, "a1"), &run(300, "a2"));
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].name, "app::load");
+        assert_eq!(diffs[0].change_percent(), 200.0);
+        assert_eq!(diffs[0].code_changed(), Some(true));
+        assert_eq!(diffs[1].name, "app::handle");
+        assert_eq!(diffs[1].code_changed(), Some(false));
+    }
+
+    #[test]
+    fn test_diff_without_fingerprints() {
+        let mut baseline = run(100, "a1");
+        baseline[0].children[0].fingerprint = None;
+        let diffs = diff(&baseline, &run(100, "a1"));
+        assert_eq!(diffs[0].change_percent(), 0.0);
+        assert_eq!(diffs.iter().find(|d| d.name == "app::load").unwrap().code_changed(), None);
+    }
+}
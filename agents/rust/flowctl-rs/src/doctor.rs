@@ -0,0 +1,424 @@
+//! Setup diagnostics for `flowctl-rs doctor`
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use syn::visit::Visit;
+use syn::{Attribute, ImplItemFn, ItemFn, Signature, TraitItemFn};
+
+use crate::detect;
+use crate::manifest::FLOWTRACE_DEPENDENCIES;
+use crate::probe::Probe;
+use crate::workspace;
+
+/// Framework crates paired with the `flowtrace-agent` feature providing their middleware
+const FRAMEWORK_FEATURES: [(&str, &str); 3] =
+    [("actix-web", "actix"), ("axum", "axum"), ("rocket", "rocket")];
+
+/// Smoke test program: one traced call written to the log file given as argument
+const PROBE_MAIN: &str = r#"use flowtrace_derive::trace;
+
+#[trace]
+fn doctor_probe(value: u32) -> Result<u32, String> {
+    Ok(value + 1)
+}
+
+fn main() {
+    let log_file = std::env::args().nth(1).expect("log file argument");
+    let config = flowtrace_agent::Config {
+        log_file,
+        ..Default::default()
+    };
+    flowtrace_agent::start_tracing(config).expect("failed to start FlowTrace");
+    let _ = doctor_probe(41);
+    flowtrace_agent::stop_tracing();
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Outcome of one diagnostic, with a suggested fix when it did not pass
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub status: Status,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Dependencies and feature flags declared in `dir/Cargo.toml`
+pub fn check_manifest(dir: &Path) -> Vec<Check> {
+    let manifest_path = dir.join("Cargo.toml");
+    let content = match fs::read_to_string(&manifest_path) {
+        Ok(content) => content,
+        Err(_) => {
+            return vec![Check::fail(
+                format!("No Cargo.toml in {}", dir.display()),
+                "Run doctor from the root of the crate you want to trace",
+            )]
+        }
+    };
+
+    match content.parse::<toml::Table>() {
+        Ok(manifest) => manifest_checks(&manifest),
+        Err(e) => vec![Check::fail(
+            format!("Cargo.toml does not parse: {}", e.message()),
+            "Fix the manifest syntax, then rerun doctor",
+        )],
+    }
+}
+
+fn manifest_checks(manifest: &toml::Table) -> Vec<Check> {
+    let dependency = |name: &str| manifest.get("dependencies").and_then(|deps| deps.get(name));
+    let mut checks = Vec::new();
+
+    for (name, version) in FLOWTRACE_DEPENDENCIES {
+        checks.push(match dependency(name) {
+            Some(_) => Check::ok(format!("{} dependency declared", name)),
+            None => Check::fail(
+                format!("{} dependency missing", name),
+                format!("Add `{} = \"{}\"` under [dependencies] or run `flowctl-rs init`", name, version),
+            ),
+        });
+    }
+
+    let enabled: Vec<&str> = dependency("flowtrace-agent")
+        .and_then(|spec| spec.get("features"))
+        .and_then(|features| features.as_array())
+        .map(|features| features.iter().filter_map(|f| f.as_str()).collect())
+        .unwrap_or_default();
+
+    for (framework, feature) in FRAMEWORK_FEATURES {
+        let uses_framework = dependency(framework).is_some();
+        let feature_on = enabled.contains(&feature) || enabled.contains(&"all-frameworks");
+
+        if uses_framework && !feature_on {
+            checks.push(Check::warn(
+                format!("{} is used but flowtrace-agent's `{}` feature is off", framework, feature),
+                format!(
+                    "Enable it for request tracing: flowtrace-agent = {{ version = \"1.0\", features = [\"{}\"] }}",
+                    feature
+                ),
+            ));
+        } else if feature_on && !uses_framework {
+            checks.push(Check::warn(
+                format!("flowtrace-agent's `{}` feature is on but {} is not a dependency", feature, framework),
+                format!("Drop the `{}` feature to avoid compiling unused middleware", feature),
+            ));
+        } else if feature_on {
+            checks.push(Check::ok(format!("`{}` middleware feature enabled", feature)));
+        }
+    }
+
+    checks
+}
+
+/// Attribute combinations in the sources that trace incorrectly or not at all
+pub fn check_sources(dir: &Path) -> Vec<Check> {
+    let files = if dir.join("src").is_dir() {
+        workspace::rust_files(&dir.join("src"))
+    } else {
+        workspace::rust_files(dir)
+    };
+
+    let mut checks = Vec::new();
+    for file in &files {
+        if let Ok(content) = fs::read_to_string(file) {
+            checks.extend(source_checks(&file.display().to_string(), &content));
+        }
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::ok(format!(
+            "No conflicting attributes in {} source files",
+            files.len()
+        )));
+    }
+
+    checks
+}
+
+fn source_checks(file: &str, content: &str) -> Vec<Check> {
+    let syntax = match syn::parse_file(content) {
+        Ok(syntax) => syntax,
+        Err(e) => {
+            return vec![Check::warn(
+                format!("{} does not parse: {}", file, e),
+                "Fix the syntax error; the analyzer and instrumenter skip this file",
+            )]
+        }
+    };
+
+    let mut visitor = ConflictVisitor {
+        file,
+        checks: Vec::new(),
+    };
+    visitor.visit_file(&syntax);
+    visitor.checks
+}
+
+struct ConflictVisitor<'a> {
+    file: &'a str,
+    checks: Vec<Check>,
+}
+
+impl ConflictVisitor<'_> {
+    fn inspect(&mut self, attrs: &[Attribute], sig: &Signature, top_level: bool) {
+        if !attrs.iter().any(detect::is_trace_attribute) {
+            return;
+        }
+
+        let location = format!("{}:{}", self.file, sig.ident.span().start().line);
+        let name = sig.ident.to_string();
+
+        if top_level && name == "main" && !attrs.iter().any(starts_tracing) {
+            let runtime = attrs
+                .iter()
+                .find(|attr| is_runtime_attribute(attr))
+                .map(|attr| format!(" (with #[{}])", path_string(attr)))
+                .unwrap_or_default();
+            self.checks.push(Check::warn(
+                format!("#[trace] on main{} at {}", runtime, location),
+                "ENTER is logged before start_tracing runs; use #[trace(main)] to start tracing from the environment first",
+            ));
+        }
+
+        if sig.constness.is_some() {
+            self.checks.push(Check::fail(
+                format!("#[trace] on const fn {} at {}", name, location),
+                "The traced wrapper is not const; remove #[trace] or the const qualifier",
+            ));
+        }
+
+        if attrs.iter().any(detect::is_other_instrumentation) {
+            self.checks.push(Check::warn(
+                format!("{} at {} has both #[trace] and #[instrument]", name, location),
+                "Keep one instrumentation attribute to avoid duplicate spans",
+            ));
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for ConflictVisitor<'_> {
+    fn visit_item_fn(&mut self, node: &'ast ItemFn) {
+        self.inspect(&node.attrs, &node.sig, true);
+        syn::visit::visit_item_fn(self, node);
+    }
+
+    fn visit_impl_item_fn(&mut self, node: &'ast ImplItemFn) {
+        self.inspect(&node.attrs, &node.sig, false);
+        syn::visit::visit_impl_item_fn(self, node);
+    }
+
+    fn visit_trait_item_fn(&mut self, node: &'ast TraitItemFn) {
+        self.inspect(&node.attrs, &node.sig, false);
+        syn::visit::visit_trait_item_fn(self, node);
+    }
+}
+
+fn path_string(attr: &Attribute) -> String {
+    attr.path()
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// `#[trace(main)]`, which starts the tracer itself
+fn starts_tracing(attr: &Attribute) -> bool {
+    detect::is_trace_attribute(attr)
+        && match &attr.meta {
+            syn::Meta::List(list) => flowtrace_codegen::TraceArgs::parse(list.tokens.clone()).is_ok_and(|args| args.main),
+            _ => false,
+        }
+}
+
+/// `#[tokio::main]`, `#[actix_web::main]`, `#[async_std::main]`, ...
+fn is_runtime_attribute(attr: &Attribute) -> bool {
+    let segments = attr.path().segments.len();
+    segments > 1
+        && attr
+            .path()
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "main")
+}
+
+/// Toolchain version, derive expansion and a one-event runtime smoke test
+pub fn check_runtime(dir: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    match Command::new("rustc").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            checks.push(Check::ok(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+        }
+        _ => {
+            checks.push(Check::fail(
+                "rustc not found",
+                "Install a Rust toolchain with rustup and make sure it is on PATH",
+            ));
+            return checks;
+        }
+    }
+
+    let probe = match Probe::create(dir, "flowtrace-doctor", PROBE_MAIN) {
+        Ok(probe) => probe,
+        Err(e) => {
+            checks.push(Check::fail(e, "Fix Cargo.toml so the probe crate can be generated"));
+            return checks;
+        }
+    };
+
+    let log_file = probe.dir.join("flowtrace-doctor.jsonl");
+    let _ = fs::remove_file(&log_file);
+
+    if let Err(e) = probe.run([log_file.as_os_str()]) {
+        let first_error = e
+            .lines()
+            .find(|line| line.trim_start().starts_with("error"))
+            .unwrap_or("probe crate failed to build")
+            .trim()
+            .to_string();
+        checks.push(Check::fail(
+            format!("#[trace] probe did not build or run: {}", first_error),
+            format!(
+                "Make flowtrace-agent and flowtrace-derive versions match, then run `cargo build --manifest-path {}` for the full error",
+                probe.dir.join("Cargo.toml").display()
+            ),
+        ));
+        return checks;
+    }
+    checks.push(Check::ok("#[trace] expands and compiles on this toolchain"));
+
+    let log = fs::read_to_string(&log_file).unwrap_or_default();
+    checks.push(match probe_events(&log) {
+        (enter, exit) if enter > 0 && exit > 0 => {
+            Check::ok(format!("Runtime smoke test wrote ENTER/EXIT to {}", log_file.display()))
+        }
+        _ => Check::fail(
+            "Runtime smoke test wrote no ENTER/EXIT events",
+            "Check that the log directory is writable and FLOWTRACE_* variables are valid",
+        ),
+    });
+
+    checks
+}
+
+/// Count ENTER and EXIT events of the probe function in a JSONL log
+fn probe_events(log: &str) -> (usize, usize) {
+    let events: Vec<serde_json::Value> = log
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|event: &serde_json::Value| event["method"] == "doctor_probe")
+        .collect();
+
+    let count = |kind: &str| events.iter().filter(|event| event["event"] == kind).count();
+    (count("ENTER"), count("EXIT"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(checks: &[Check]) -> Vec<Status> {
+        checks.iter().map(|check| check.status).collect()
+    }
+
+    #[test]
+    fn test_manifest_checks_features() {
+        let manifest: toml::Table = r#"
+            [dependencies]
+            actix-web = "4"
+            flowtrace-agent = { version = "1.0", features = ["rocket"] }
+        "#
+        .parse()
+        .unwrap();
+
+        let checks = manifest_checks(&manifest);
+        assert_eq!(
+            statuses(&checks),
+            vec![Status::Ok, Status::Fail, Status::Warn, Status::Warn]
+        );
+        assert!(checks[2].message.contains("actix-web is used"));
+        assert!(checks[3].message.contains("`rocket` feature is on"));
+    }
+
+    #[test]
+    fn test_source_checks_conflicts() {
+        let code = r#"
+            #[trace]
+            #[tokio::main]
+            async fn main() {}
+
+            #[async_trait]
+            impl Store for Db {
+                #[trace]
+                async fn load(&self) {}
+            }
+
+            #[trace]
+            #[tracing::instrument]
+            fn both() {}
+
+            #[trace]
+            fn fine() {}
+
+            mod cli {
+                #[trace(main)]
+                fn main() {}
+            }
+        "#;
+
+        let checks = source_checks("lib.rs", code);
+        let messages: Vec<&str> = checks.iter().map(|check| check.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "#[trace] on main (with #[tokio::main]) at lib.rs:4",
+                "both at lib.rs:14 has both #[trace] and #[instrument]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_probe_events() {
+        let log = concat!(
+            "{\"event\":\"ENTER\",\"method\":\"doctor_probe\"}\n",
+            "{\"event\":\"EXIT\",\"method\":\"doctor_probe\"}\n",
+            "{\"event\":\"ENTER\",\"method\":\"other\"}\n",
+            "not json\n",
+        );
+        assert_eq!(probe_events(log), (1, 1));
+    }
+}
@@ -0,0 +1,438 @@
+//! Previews of the code `#[trace]` generates, for `flowctl-rs expand`
+
+use std::fs;
+use std::path::Path;
+use syn::visit::Visit;
+use syn::{ImplItemFn, ItemFn, ItemImpl, ItemTrait, TraitItemFn};
+
+use crate::detect;
+use crate::instrumenter::impl_type_name;
+
+/// One function, or impl block, and the code `#[trace]` turns it into
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    /// `name`, `Type::name` or `impl Type`
+    pub name: String,
+    pub line: usize,
+    pub expanded: String,
+}
+
+/// Expand every function in `file` named `function` (`name`, `Type::name`, or
+/// `impl Type` for the impl blocks of a type)
+pub fn expand_file(file: &Path, function: &str) -> Result<Vec<Expansion>, String> {
+    let content = fs::read_to_string(file)
+        .map_err(|e| format!("Failed to read file {}: {}", file.display(), e))?;
+    let expansions = expand_source(&content, function)
+        .map_err(|e| format!("Failed to parse file {}: {}", file.display(), e))?;
+
+    if expansions.is_empty() {
+        return Err(format!("No function named '{}' in {}", function, file.display()));
+    }
+    Ok(expansions)
+}
+
+/// Expand every function in `source` named `function`, formatted with prettyplease
+pub fn expand_source(source: &str, function: &str) -> Result<Vec<Expansion>, syn::Error> {
+    let syntax = syn::parse_file(source)?;
+    let mut finder = FunctionFinder {
+        function,
+        impl_type: None,
+        traced_impl: None,
+        found: Vec::new(),
+    };
+    finder.visit_file(&syntax);
+
+    finder
+        .found
+        .into_iter()
+        .map(|found| {
+            let (name, line, expanded) = match found {
+                Found::Function(name, mut item, traced_impl) => {
+                    let line = item.sig.fn_token.span.start().line;
+                    // Honor the arguments of an existing #[trace(...)], on the function or else
+                    // its impl block, then expand as if it were the only one on the function
+                    let owner_attrs = traced_impl.iter().flat_map(|owner| &owner.attrs);
+                    let args = trace_args(item.attrs.iter().chain(owner_attrs))?;
+                    item.attrs.retain(|attr| !detect::is_trace_attribute(attr));
+
+                    let expanded = match &traced_impl {
+                        Some(owner) => flowtrace_codegen::trace_method(&args, &item, owner),
+                        None => flowtrace_codegen::trace(&args, &item),
+                    };
+                    (name, line, expanded)
+                }
+                Found::Impl(name, mut item) => {
+                    let line = item.impl_token.span.start().line;
+                    let args = trace_args(item.attrs.iter())?;
+                    item.attrs.retain(|attr| !detect::is_trace_attribute(attr));
+                    (name, line, flowtrace_codegen::trace_impl(&args, &item))
+                }
+            };
+
+            let expanded: syn::File = syn::parse2(expanded)?;
+            Ok(Expansion {
+                name,
+                line,
+                expanded: prettyplease::unparse(&expanded),
+            })
+        })
+        .collect()
+}
+
+/// Arguments of the first #[trace(...)] among `attrs`, or the defaults without one
+fn trace_args<'a>(
+    mut attrs: impl Iterator<Item = &'a syn::Attribute>,
+) -> Result<flowtrace_codegen::TraceArgs, syn::Error> {
+    match attrs.find(|attr| detect::is_trace_attribute(attr)).map(|attr| &attr.meta) {
+        Some(syn::Meta::List(list)) => flowtrace_codegen::TraceArgs::parse(list.tokens.clone()),
+        _ => Ok(flowtrace_codegen::TraceArgs::default()),
+    }
+}
+
+/// A function or impl block named like the one to expand
+enum Found {
+    /// A function, with the impl block it belongs to when that carries `#[trace]` itself
+    Function(String, Box<ItemFn>, Option<ItemImpl>),
+    /// An impl block, traced as a whole
+    Impl(String, ItemImpl),
+}
+
+struct FunctionFinder<'a> {
+    function: &'a str,
+    /// Self type of the enclosing impl block, or the enclosing trait, for naming methods
+    impl_type: Option<String>,
+    /// Enclosing impl block when it carries `#[trace]` itself
+    traced_impl: Option<ItemImpl>,
+    found: Vec<Found>,
+}
+
+impl FunctionFinder<'_> {
+    fn matches(&self, name: &str) -> bool {
+        self.function == name
+            || self
+                .impl_type
+                .as_ref()
+                .is_some_and(|type_name| self.function == format!("{}::{}", type_name, name))
+    }
+
+    fn qualified(&self, name: &str) -> String {
+        match &self.impl_type {
+            Some(type_name) => format!("{}::{}", type_name, name),
+            None => name.to_string(),
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for FunctionFinder<'_> {
+    fn visit_item_fn(&mut self, node: &'ast ItemFn) {
+        if self.impl_type.is_none() && self.matches(&node.sig.ident.to_string()) {
+            self.found.push(Found::Function(node.sig.ident.to_string(), Box::new(node.clone()), None));
+        }
+        syn::visit::visit_item_fn(self, node);
+    }
+
+    fn visit_item_impl(&mut self, node: &'ast ItemImpl) {
+        let type_name = impl_type_name(node);
+        if self.function == format!("impl {}", type_name) {
+            self.found.push(Found::Impl(format!("impl {}", type_name), node.clone()));
+        }
+
+        let outer = self.impl_type.replace(type_name);
+        let traced = node.attrs.iter().any(detect::is_trace_attribute).then(|| node.clone());
+        let outer_traced = std::mem::replace(&mut self.traced_impl, traced);
+        syn::visit::visit_item_impl(self, node);
+        self.impl_type = outer;
+        self.traced_impl = outer_traced;
+    }
+
+    fn visit_impl_item_fn(&mut self, node: &'ast ImplItemFn) {
+        let name = node.sig.ident.to_string();
+        if self.matches(&name) {
+            // `#[trace]` sees a method as a plain function item
+            let item = ItemFn {
+                attrs: node.attrs.clone(),
+                vis: node.vis.clone(),
+                sig: node.sig.clone(),
+                block: Box::new(node.block.clone()),
+            };
+            self.found.push(Found::Function(self.qualified(&name), Box::new(item), self.traced_impl.clone()));
+        }
+        syn::visit::visit_impl_item_fn(self, node);
+    }
+
+    fn visit_item_trait(&mut self, node: &'ast ItemTrait) {
+        let outer = self.impl_type.replace(node.ident.to_string());
+        syn::visit::visit_item_trait(self, node);
+        self.impl_type = outer;
+    }
+
+    fn visit_trait_item_fn(&mut self, node: &'ast TraitItemFn) {
+        let name = node.sig.ident.to_string();
+        // Only default methods have a body to instrument
+        if let (true, Some(block)) = (self.matches(&name), &node.default) {
+            let item = ItemFn {
+                attrs: node.attrs.clone(),
+                vis: syn::Visibility::Inherited,
+                sig: node.sig.clone(),
+                block: Box::new(block.clone()),
+            };
+            self.found.push(Found::Function(self.qualified(&name), Box::new(item), None));
+        }
+        syn::visit::visit_trait_item_fn(self, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+        #[trace(warn_over_ms = 2.5, target = "db", max_depth = 2)]
+        pub fn load(id: u32) -> Result<User, String> {
+            db::find(id)
+        }
+
+        struct Service;
+
+        impl Service {
+            #[trace(tracer = SERVICE_TRACER)]
+            fn load(&self) -> u32 {
+                7
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_expand_function() {
+        let expansions = expand_source(SOURCE, "load").unwrap();
+        assert_eq!(expansions.len(), 2);
+        assert_eq!(expansions[0].name, "load");
+        assert_eq!(expansions[0].line, 3);
+
+        let expanded = &expansions[0].expanded;
+        assert!(expanded.starts_with("pub fn load(id: u32) -> Result<User, String> {"));
+        assert!(expanded.contains("flowtrace_agent::TraceEvent::enter"));
+        assert!(expanded.contains("flowtrace_agent::TraceEvent::exception"));
+        assert!(expanded.contains(".with_target(\"db\")"));
+        assert!(expanded.contains(".with_budget(2500i64, false)"));
+        assert!(expanded.contains(".with_max_depth(2usize)"));
+        assert!(!expanded.contains("#[trace]"));
+    }
+
+    #[test]
+    fn test_expand_method() {
+        let expansions = expand_source(SOURCE, "Service::load").unwrap();
+        assert_eq!(expansions.len(), 1);
+        assert_eq!(expansions[0].name, "Service::load");
+        assert!(expansions[0].expanded.contains("fn load(&self) -> u32 {"));
+        assert!(expansions[0].expanded.contains("SERVICE_TRACER\n        .log("));
+        assert!(!expansions[0].expanded.contains("flowtrace_agent::log_event"));
+    }
+
+    #[test]
+    fn test_expand_method_of_traced_impl() {
+        let source = r#"
+            #[trace(target = "orders")]
+            impl Order {
+                fn total(&self) -> u32 { 0 }
+
+                #[trace(warn_over_ms = 5)]
+                fn ship(&mut self) {}
+            }
+        "#;
+        let total = &expand_source(source, "Order::total").unwrap()[0].expanded;
+        assert!(total.contains(r#"concat!(module_path!(), "::Order")"#), "{}", total);
+        assert!(total.contains(r#".with_target("orders")"#));
+
+        // A method's own arguments replace the block's
+        let ship = &expand_source(source, "Order::ship").unwrap()[0].expanded;
+        assert!(ship.contains(r#"concat!(module_path!(), "::Order")"#));
+        assert!(ship.contains(".with_budget(5000i64, false)"));
+        assert!(!ship.contains(".with_target("));
+
+        let block = expand_source(source, "impl Order").unwrap();
+        assert_eq!(block.len(), 1);
+        assert_eq!(block[0].line, 3);
+        assert!(block[0].expanded.starts_with("impl Order {"), "{}", block[0].expanded);
+        assert_eq!(block[0].expanded.matches(r#"concat!(module_path!(), "::Order")"#).count(), 2);
+        assert!(!block[0].expanded.contains("#[trace"));
+    }
+
+    #[test]
+    fn test_expand_impl_future() {
+        let source = r#"
+            #[trace]
+            fn fetch(id: u32) -> impl Future<Output = Result<u32, String>> + Send {
+                client::get(id)
+            }
+        "#;
+        let expanded = &expand_source(source, "fetch").unwrap()[0].expanded;
+        // The returned future is instrumented, not just the call building it
+        assert!(expanded.contains("let __flowtrace_future = {"));
+        assert!(expanded.contains("async move {"));
+        assert!(expanded.contains("__flowtrace_future.await"));
+        assert!(expanded.contains("flowtrace_agent::TraceEvent::exception"));
+        assert!(!expanded.contains("catch_unwind"));
+    }
+
+    #[test]
+    fn test_expand_boxed_future() {
+        // What `#[async_trait]` turns an `async fn` method into
+        let source = r#"
+            #[trace]
+            fn find<'a>(&'a self, id: u32) -> Pin<Box<dyn Future<Output = Result<User, String>> + Send + 'a>> {
+                Box::pin(async move { self.load(id).await })
+            }
+        "#;
+        let expanded = &expand_source(source, "find").unwrap()[0].expanded;
+        assert!(expanded.contains("Box::pin(async move {"));
+        assert!(expanded.contains("__flowtrace_future.await"));
+        assert!(!expanded.contains("catch_unwind"));
+    }
+
+    #[test]
+    fn test_expand_capture_self() {
+        let source = r#"
+            impl Order {
+                #[trace(capture_self(fields(id, status)))]
+                fn total(&self, discount: u32) -> u32 { 0 }
+            }
+
+            #[trace(capture_self(fields(id)))]
+            fn free(order: Order) {}
+        "#;
+        let expanded = &expand_source(source, "Order::total").unwrap()[0].expanded;
+        assert!(expanded.contains(r#""self.id", self.id"#), "{}", expanded);
+        assert!(expanded.contains(r#""self.status", self.status"#));
+        assert!(expanded.contains(r#""discount", discount"#));
+        assert!(!expanded.contains("self.lines"));
+
+        let rejected = &expand_source(source, "free").unwrap()[0].expanded;
+        assert!(rejected.contains("compile_error!"));
+        assert!(rejected.contains("`capture_self` requires a method taking `self`"));
+    }
+
+    #[test]
+    fn test_expand_echo_args() {
+        let source = r#"
+            #[trace(echo_args(user_id))]
+            fn charge(user_id: u64, cents: u32) -> Result<(), String> { Ok(()) }
+
+            #[trace(echo_args(account))]
+            fn refund(user_id: u64) {}
+        "#;
+        let expanded = &expand_source(source, "charge").unwrap()[0].expanded;
+        let capture = expanded.find("let __flowtrace_echo = format!").unwrap();
+        assert!(capture < expanded.find("TraceEvent::enter").unwrap());
+        assert!(expanded.contains(r#""user_id", user_id"#), "{}", expanded);
+        assert_eq!(expanded.matches(".with_args(__flowtrace_echo)").count(), 3);
+
+        let rejected = &expand_source(source, "refund").unwrap()[0].expanded;
+        assert!(rejected.contains("`echo_args`: `account` is not an argument of this function"));
+    }
+
+    #[test]
+    fn test_expand_generics() {
+        let source = r#"
+            #[trace]
+            fn repeat<T: Clone>(item: T, count: usize) -> Vec<T> { vec![item; count] }
+
+            #[trace]
+            fn largest<'a, T>(items: &'a [T], fallback: &'a T) -> &'a T where T: PartialOrd + Debug { fallback }
+
+            #[trace]
+            fn parse<T, E>(text: &str, parser: impl Fn(&str) -> Result<T, E>) -> Result<T, E> { parser(text) }
+        "#;
+        // Arguments and results of a type without a `Debug` bound are left out
+        let repeat = &expand_source(source, "repeat").unwrap()[0].expanded;
+        assert!(repeat.contains(r#""count", count"#), "{}", repeat);
+        assert!(!repeat.contains(r#""item", item"#));
+        assert!(!repeat.contains(r#"format!("{:?}", __flowtrace_result)"#));
+
+        // A `where` clause bound counts, through references and lifetimes
+        let largest = &expand_source(source, "largest").unwrap()[0].expanded;
+        assert!(largest.contains(r#""items", items"#), "{}", largest);
+        assert!(largest.contains(r#""fallback", fallback"#));
+        assert!(largest.contains(r#"Some(format!("{:?}", __flowtrace_result))"#));
+
+        let parse = &expand_source(source, "parse").unwrap()[0].expanded;
+        assert!(parse.contains(r#""text", text"#), "{}", parse);
+        assert!(!parse.contains(r#""parser", parser"#));
+        assert!(parse.contains("Ok(_) =>"));
+        assert!(parse.contains("std::any::type_name_of_val(error)"));
+    }
+
+    #[test]
+    fn test_expand_trait_default_method() {
+        let source = r#"
+            trait Shape {
+                fn area(&self) -> f64;
+
+                #[trace]
+                fn larger(&self, other: &Self, margin: f64) -> bool { self.area() > other.area() + margin }
+
+                #[trace]
+                fn twice(&self, other: Self) -> Self where Self: Debug + Sized { other }
+            }
+        "#;
+        assert!(expand_source(source, "Shape::area").unwrap().is_empty());
+        let larger = &expand_source(source, "Shape::larger").unwrap()[0].expanded;
+        assert!(larger.contains(r#""margin", margin"#), "{}", larger);
+        assert!(!larger.contains(r#""other", other"#));
+
+        let twice = &expand_source(source, "Shape::twice").unwrap()[0].expanded;
+        assert!(twice.contains(r#""other", other"#), "{}", twice);
+    }
+
+    #[test]
+    fn test_expand_main() {
+        let source = r#"
+            #[trace(main)]
+            fn main() { run(); }
+        "#;
+        let expanded = &expand_source(source, "main").unwrap()[0].expanded;
+        let init = expanded.find("flowtrace_agent::init_from_env()").unwrap();
+        assert!(init < expanded.find("TraceEvent::enter").unwrap());
+    }
+
+    #[test]
+    fn test_expand_async_no_move() {
+        let source = r#"
+            #[trace]
+            async fn load(id: u32) -> u32 { id }
+
+            #[trace(no_move)]
+            async fn save(id: u32) -> u32 { id }
+        "#;
+        let moving = &expand_source(source, "load").unwrap()[0].expanded;
+        assert!(moving.contains("let __flowtrace_result = async move {"));
+        let borrowing = &expand_source(source, "save").unwrap()[0].expanded;
+        assert!(borrowing.contains("let __flowtrace_result = async {"));
+        assert!(!borrowing.contains("async move"));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let parse = |source: &str| syn::parse_str::<ItemFn>(source).unwrap();
+        let original = parse("fn load(id: u32) -> u32 { id + 1 }");
+        let reformatted = parse("fn load( id : u32 )\n    -> u32 {\n    id + 1\n}");
+        let new_body = parse("fn load(id: u32) -> u32 { id + 2 }");
+
+        let fingerprint = flowtrace_codegen::fingerprint(&original, true);
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprint, flowtrace_codegen::fingerprint(&reformatted, true));
+        assert_ne!(fingerprint, flowtrace_codegen::fingerprint(&new_body, true));
+        assert_eq!(
+            flowtrace_codegen::fingerprint(&original, false),
+            flowtrace_codegen::fingerprint(&new_body, false)
+        );
+
+        let expanded = &expand_source(SOURCE, "load").unwrap()[0].expanded;
+        assert!(expanded.contains(".with_fingerprint(\""), "{}", expanded);
+    }
+
+    #[test]
+    fn test_expand_missing_function() {
+        assert!(expand_source(SOURCE, "save").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+//! File and function selection for instrumentation
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use std::path::Path;
+
+/// Include/exclude globs for files, plus name and shape criteria for functions
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    fn_filter: Option<Regex>,
+    public_only: bool,
+    async_only: bool,
+    min_lines: usize,
+    modules: Vec<String>,
+    exclude_modules: Vec<String>,
+}
+
+impl Filter {
+    /// Compile a filter, returning an error naming the first invalid pattern
+    pub fn new(include: &[String], exclude: &[String], fn_filter: Option<&str>) -> Result<Self, String> {
+        let fn_filter = fn_filter
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| format!("Invalid function filter '{}': {}", pattern, e))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            include: build_globs(include)?,
+            exclude: build_globs(exclude)?,
+            fn_filter,
+            ..Default::default()
+        })
+    }
+
+    /// Only select `pub` functions and trait impl methods
+    pub fn public_only(mut self, public_only: bool) -> Self {
+        self.public_only = public_only;
+        self
+    }
+
+    /// Only select `async fn`s
+    pub fn async_only(mut self, async_only: bool) -> Self {
+        self.async_only = async_only;
+        self
+    }
+
+    /// Only select functions spanning at least this many lines
+    pub fn min_lines(mut self, min_lines: usize) -> Self {
+        self.min_lines = min_lines;
+        self
+    }
+
+    /// Only process these modules (and their submodules), skipping `exclude`
+    pub fn modules(mut self, include: Vec<String>, exclude: Vec<String>) -> Self {
+        self.modules = include;
+        self.exclude_modules = exclude;
+        self
+    }
+
+    /// Whether a file, relative to the processed root, should be processed
+    pub fn matches_path(&self, relative: &Path) -> bool {
+        if let Some(include) = &self.include {
+            if !include.is_match(relative) {
+                return false;
+            }
+        }
+
+        !self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(relative))
+    }
+
+    /// Whether a module path such as `crate::billing::invoice` should be processed
+    ///
+    /// Patterns match a module and its submodules, with or without the leading
+    /// crate segment, the same way the agent's `Config::allows_module` does.
+    pub fn matches_module(&self, module: &str) -> bool {
+        let matches = |pattern: &String| module_matches(module, pattern);
+
+        (self.modules.is_empty() || self.modules.iter().any(matches))
+            && !self.exclude_modules.iter().any(matches)
+    }
+
+    /// Whether a function (`name` or `Type::name`) should be instrumented
+    pub fn matches_fn(&self, name: &str) -> bool {
+        self.fn_filter.as_ref().is_none_or(|regex| regex.is_match(name))
+    }
+
+    /// Whether a function's visibility, asyncness and length pass the filter
+    pub fn matches_shape(&self, is_public: bool, is_async: bool, lines: usize) -> bool {
+        (!self.public_only || is_public) && (!self.async_only || is_async) && lines >= self.min_lines
+    }
+}
+
+fn module_matches(module: &str, pattern: &str) -> bool {
+    let within = |module: &str| {
+        module == pattern || module.strip_prefix(pattern).is_some_and(|rest| rest.starts_with("::"))
+    };
+
+    within(module) || module.split_once("::").is_some_and(|(_, rest)| within(rest))
+}
+
+fn build_globs(patterns: &[String]) -> Result<Option<GlobSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| format!("Invalid glob '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+
+    builder.build().map(Some).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_filters() {
+        let filter = Filter::new(
+            &["src/**".to_string()],
+            &["src/generated/**".to_string()],
+            None,
+        )
+        .unwrap();
+
+        assert!(filter.matches_path(Path::new("src/service.rs")));
+        assert!(!filter.matches_path(Path::new("src/generated/proto.rs")));
+        assert!(!filter.matches_path(Path::new("build.rs")));
+    }
+
+    #[test]
+    fn test_fn_filter() {
+        let filter = Filter::new(&[], &[], Some("^(handle_|OrderService::)")).unwrap();
+
+        assert!(filter.matches_fn("handle_request"));
+        assert!(filter.matches_fn("OrderService::cancel"));
+        assert!(!filter.matches_fn("helper"));
+    }
+
+    #[test]
+    fn test_shape_filters() {
+        let filter = Filter::default().public_only(true).async_only(true).min_lines(5);
+
+        assert!(filter.matches_shape(true, true, 5));
+        assert!(!filter.matches_shape(false, true, 10));
+        assert!(!filter.matches_shape(true, false, 10));
+        assert!(!filter.matches_shape(true, true, 4));
+        assert!(Filter::default().matches_shape(false, false, 1));
+    }
+
+    #[test]
+    fn test_module_filters() {
+        let filter = Filter::default().modules(vec!["billing".to_string()], vec!["billing::metrics".to_string()]);
+
+        assert!(filter.matches_module("crate::billing"));
+        assert!(filter.matches_module("crate::billing::invoice"));
+        assert!(!filter.matches_module("crate::billing::metrics"));
+        assert!(!filter.matches_module("crate::billing_old"));
+        assert!(!filter.matches_module("crate"));
+        assert!(Filter::default().matches_module("crate::anything"));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(Filter::new(&["src/[".to_string()], &[], None).is_err());
+        assert!(Filter::new(&[], &[], Some("(")).is_err());
+    }
+}
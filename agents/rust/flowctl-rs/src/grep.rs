@@ -0,0 +1,154 @@
+//! Payload search for `flowctl-rs grep`
+
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder};
+
+use crate::trace::Call;
+
+/// Which payload of a call to search
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Field {
+    Any,
+    Args,
+    Result,
+    Exception,
+}
+
+/// Compiled search pattern
+pub struct Matcher {
+    regex: Regex,
+    field: Field,
+}
+
+impl Matcher {
+    /// Match `pattern` literally, or as a regex when `is_regex` is set
+    pub fn new(pattern: &str, is_regex: bool, ignore_case: bool, field: Field) -> Result<Self, String> {
+        let source = if is_regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+
+        Ok(Self { regex, field })
+    }
+
+    /// The first searched payload of `call` containing the pattern
+    pub fn find<'a>(&self, call: &'a Call) -> Option<(&'static str, &'a str)> {
+        let payloads = [
+            (Field::Args, "args", call.args.as_deref()),
+            (Field::Result, "result", call.result.as_deref()),
+            (Field::Exception, "exception", call.exception.as_deref()),
+        ];
+
+        payloads
+            .into_iter()
+            .filter(|(field, _, _)| self.field == Field::Any || self.field == *field)
+            .find_map(|(_, label, value)| {
+                let value = value?;
+                self.regex.is_match(value).then_some((label, value))
+            })
+    }
+
+    fn subtree_matches(&self, call: &Call) -> bool {
+        self.find(call).is_some() || call.children.iter().any(|child| self.subtree_matches(child))
+    }
+}
+
+/// One row of a rendered call tree
+pub struct TreeLine<'a> {
+    pub depth: usize,
+    pub call: &'a Call,
+    /// Matching payload label and value, when this call matched
+    pub matched: Option<(&'static str, &'a str)>,
+}
+
+/// Call trees containing a match, pruned to the paths leading to matches
+///
+/// Ancestors of a matching call are kept for context and the full subtree of
+/// the matching call is shown, so the calls it made are visible too.
+pub fn search<'a>(roots: &'a [Call], matcher: &Matcher) -> Vec<Vec<TreeLine<'a>>> {
+    roots
+        .iter()
+        .filter(|root| matcher.subtree_matches(root))
+        .map(|root| {
+            let mut lines = Vec::new();
+            collect(root, 0, matcher, false, &mut lines);
+            lines
+        })
+        .collect()
+}
+
+fn collect<'a>(call: &'a Call, depth: usize, matcher: &Matcher, inside_match: bool, lines: &mut Vec<TreeLine<'a>>) {
+    let matched = matcher.find(call);
+    if !inside_match && matched.is_none() && !matcher.subtree_matches(call) {
+        return;
+    }
+
+    lines.push(TreeLine { depth, call, matched });
+    for child in &call.children {
+        collect(child, depth + 1, matcher, inside_match || matched.is_some(), lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::build_calls;
+    use crate::trace::tests::event;
+
+    #[test]
+    fn test_search_prunes_to_matches() {
+        let mut load = event("ENTER", "load_order", 1, "t1");
+        load.args = Some("{\"id\": 12345}".to_string());
+        let events = vec![
+            event("ENTER", "handle", 0, "t1"),
+            load,
+            event("ENTER", "query", 2, "t1"),
+            event("EXIT", "query", 3, "t1"),
+            event("EXIT", "load_order", 4, "t1"),
+            event("ENTER", "audit", 5, "t1"),
+            event("EXIT", "audit", 6, "t1"),
+            event("EXIT", "handle", 7, "t1"),
+            event("ENTER", "unrelated", 8, "t1"),
+            event("EXIT", "unrelated", 9, "t1"),
+        ];
+        let roots = build_calls(&events);
+
+        let matcher = Matcher::new("12345", false, false, Field::Any).unwrap();
+        let trees = search(&roots, &matcher);
+        assert_eq!(trees.len(), 1);
+
+        let rows: Vec<(usize, &str, bool)> = trees[0]
+            .iter()
+            .map(|line| (line.depth, line.call.function.as_str(), line.matched.is_some()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(0, "handle", false), (1, "load_order", true), (2, "query", false)]
+        );
+    }
+
+    #[test]
+    fn test_matcher_fields() {
+        let call = Call {
+            args: Some("{\"user\": \"Alice\"}".to_string()),
+            exception: Some("alice not found".to_string()),
+            ..Default::default()
+        };
+
+        let any = Matcher::new("alice", false, true, Field::Any).unwrap();
+        assert_eq!(any.find(&call).map(|(label, _)| label), Some("args"));
+
+        let exception = Matcher::new("alice", false, false, Field::Exception).unwrap();
+        assert_eq!(exception.find(&call).map(|(label, _)| label), Some("exception"));
+
+        let regex = Matcher::new("Al.ce", true, false, Field::Result).unwrap();
+        assert!(regex.find(&call).is_none());
+        assert!(Matcher::new("(", true, false, Field::Any).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Critical path and self-time analysis for `flowctl-rs hotpath`
+
+use crate::trace::Call;
+
+/// A call on the critical path and the time it contributes to it
+#[derive(Debug, Clone)]
+pub struct Step<'a> {
+    pub call: &'a Call,
+    pub depth: usize,
+    /// Microseconds of end-to-end latency spent in this call's own code
+    pub contribution: i64,
+}
+
+/// Time spent in a call itself, excluding time covered by its children
+pub fn self_time(call: &Call) -> i64 {
+    let start = call.start;
+    let end = call.finish();
+
+    let mut children: Vec<(i64, i64)> = call
+        .children
+        .iter()
+        .map(|child| (child.start.max(start), child.finish().min(end)))
+        .filter(|(from, to)| to > from)
+        .collect();
+    children.sort();
+
+    let mut covered = 0;
+    let mut cursor = start;
+    for (from, to) in children {
+        let from = from.max(cursor);
+        if to > from {
+            covered += to - from;
+            cursor = to;
+        }
+    }
+
+    (end - start - covered).max(0)
+}
+
+/// Chain of calls that determined the end-to-end latency of `root`
+///
+/// Walks backwards from the root's end: the child finishing last before the
+/// cursor is on the path, then the cursor jumps to that child's start. Time
+/// not covered by such a child is the parent's own contribution. Steps are
+/// returned in start order.
+pub fn critical_path(root: &Call) -> Vec<Step<'_>> {
+    let mut steps = Vec::new();
+    walk(root, 0, root.finish(), &mut steps);
+    steps.sort_by_key(|step| (step.call.start, step.depth));
+    steps
+}
+
+fn walk<'a>(call: &'a Call, depth: usize, bound: i64, steps: &mut Vec<Step<'a>>) {
+    let mut cursor = call.finish().min(bound);
+    let mut contribution = 0;
+
+    loop {
+        let last = call
+            .children
+            .iter()
+            .filter(|child| child.start < cursor)
+            .max_by_key(|child| (child.finish().min(cursor), child.start));
+
+        let Some(child) = last else { break };
+        let child_end = child.finish().min(cursor);
+
+        contribution += cursor - child_end;
+        walk(child, depth + 1, child_end, steps);
+        cursor = child.start.max(call.start);
+    }
+
+    contribution += (cursor - call.start).max(0);
+    steps.push(Step {
+        call,
+        depth,
+        contribution,
+    });
+}
+
+/// Every call in the tree with its self time, largest first
+pub fn by_self_time(root: &Call) -> Vec<(&Call, i64)> {
+    let mut calls = Vec::new();
+    collect(root, &mut calls);
+    calls.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+    calls
+}
+
+fn collect<'a>(call: &'a Call, calls: &mut Vec<(&'a Call, i64)>) {
+    calls.push((call, self_time(call)));
+    for child in &call.children {
+        collect(child, calls);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(function: &str, start: i64, end: i64, children: Vec<Call>) -> Call {
+        Call {
+            function: function.to_string(),
+            start,
+            end: Some(end),
+            children,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_self_time_merges_overlapping_children() {
+        let root = call(
+            "root",
+            0,
+            100,
+            vec![call("a", 10, 40, vec![]), call("b", 30, 60, vec![]), call("c", 90, 120, vec![])],
+        );
+        // Children cover 10..60 and 90..100
+        assert_eq!(self_time(&root), 40);
+    }
+
+    #[test]
+    fn test_critical_path_follows_latest_finisher() {
+        // `fast` overlaps `slow` but finishes first, so only `slow` is on the path
+        let root = call(
+            "root",
+            0,
+            100,
+            vec![
+                call("prepare", 0, 20, vec![]),
+                call("fast", 20, 50, vec![]),
+                call("slow", 20, 90, vec![call("query", 30, 80, vec![])]),
+            ],
+        );
+
+        let path = critical_path(&root);
+        let steps: Vec<(&str, usize, i64)> = path
+            .iter()
+            .map(|step| (step.call.function.as_str(), step.depth, step.contribution))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![("root", 0, 10), ("prepare", 1, 20), ("slow", 1, 20), ("query", 2, 50)]
+        );
+        assert_eq!(path.iter().map(|step| step.contribution).sum::<i64>(), 100);
+
+        let hottest = by_self_time(&root);
+        assert_eq!(hottest[0].0.function, "query");
+        assert_eq!(hottest[0].1, 50);
+    }
+}
@@ -2,23 +2,99 @@
 
 use std::fs;
 use std::path::{Path, PathBuf};
-use syn::{parse_file, Attribute, Item, ItemFn};
-use quote::quote;
+use proc_macro2::LineColumn;
+use syn::visit::Visit;
+use syn::{
+    parse_file, Attribute, Block, File, ImplItem, ImplItemFn, Item, ItemFn, ItemImpl, Signature,
+    TraitItemFn, Type, UseTree, Visibility,
+};
+use quote::{quote, ToTokens};
+
+use crate::analyzer::module_path;
+use crate::detect;
+use crate::filter::Filter;
+use crate::workspace;
 
 #[derive(Debug)]
 pub struct InstrumentResult {
     pub count: usize,
     pub functions: Vec<String>,
     pub backup_path: Option<String>,
+    /// A `use` line for the macro was added (instrument) or removed (uninstrument)
+    pub import_changed: bool,
+}
+
+/// How the `trace` attribute resolves in a file
+#[derive(Debug, PartialEq)]
+enum TraceImport {
+    /// `trace` already refers to the FlowTrace macro
+    Present,
+    /// `trace` is imported from elsewhere (e.g. `log::trace`)
+    Conflict,
+    Missing,
+}
+
+const TRACE_IMPORT: &str = "use flowtrace_derive::trace;";
+
+/// Instrumentation results for all files of one crate
+#[derive(Debug, Default)]
+pub struct CrateResult {
+    pub name: String,
+    pub files: Vec<(PathBuf, InstrumentResult)>,
+    pub errors: Vec<String>,
+}
+
+impl CrateResult {
+    /// Total number of functions instrumented in this crate
+    pub fn count(&self) -> usize {
+        self.files.iter().map(|(_, result)| result.count).sum()
+    }
+
+    /// Number of files that contained instrumentable functions
+    pub fn files_changed(&self) -> usize {
+        self.files.iter().filter(|(_, result)| result.count > 0).count()
+    }
 }
 
 pub struct Instrumenter {
     create_backup: bool,
+    filter: Filter,
 }
 
 impl Instrumenter {
     pub fn new(create_backup: bool) -> Self {
-        Self { create_backup }
+        Self {
+            create_backup,
+            filter: Filter::default(),
+        }
+    }
+
+    /// Restrict which files and functions are processed
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Instrument a single file, a directory tree, or a whole cargo workspace
+    ///
+    /// Directories are grouped per crate using `cargo metadata` when they are
+    /// part of a cargo project. Files that fail to parse are reported in the
+    /// crate's `errors` instead of aborting the whole run. Path filters apply
+    /// to files found under a directory; an explicitly given file is always used.
+    pub fn instrument_path(&self, path: &Path, dry_run: bool) -> Result<Vec<CrateResult>, String> {
+        process_path(path, &self.filter, |file| self.instrument_file(file, dry_run))
+    }
+
+    /// Remove FlowTrace attributes from a file, directory tree, or cargo workspace
+    pub fn uninstrument_path(
+        &self,
+        path: &Path,
+        dry_run: bool,
+        remove_imports: bool,
+    ) -> Result<Vec<CrateResult>, String> {
+        process_path(path, &self.filter, |file| {
+            self.uninstrument_file(file, dry_run, remove_imports)
+        })
     }
 
     pub fn instrument_file(
@@ -30,36 +106,70 @@ impl Instrumenter {
             .map_err(|e| format!("Failed to read file {}: {}", file.display(), e))?;
 
         // Parse file
-        let mut syntax = parse_file(&content)
+        let syntax = parse_file(&content)
             .map_err(|e| format!("Failed to parse file {}: {}", file.display(), e))?;
 
         let mut instrumented_functions = Vec::new();
+        let mut attribute_lines = Vec::new();
 
-        // Instrument functions
-        for item in &mut syntax.items {
-            if let Item::Fn(func) = item {
-                if should_instrument(func) {
+        // Find functions and methods in impl blocks to instrument
+        for item in &syntax.items {
+            match item {
+                Item::Fn(func)
+                    if should_instrument(func)
+                        && self.filter.matches_fn(&func.sig.ident.to_string())
+                        && self.filter.matches_shape(
+                            matches!(func.vis, Visibility::Public(_)),
+                            func.sig.asyncness.is_some(),
+                            line_count(&func.vis, &func.sig, &func.block),
+                        ) =>
+                {
                     instrumented_functions.push(func.sig.ident.to_string());
+                    attribute_lines.push(item_start_line(&func.vis, &func.sig));
+                }
+                // A traced impl block instruments its methods itself
+                Item::Impl(item_impl)
+                    if !is_test_function(&item_impl.attrs) && !has_trace_attribute(&item_impl.attrs) =>
+                {
+                    let type_name = impl_type_name(item_impl);
+                    let is_trait_impl = item_impl.trait_.is_some();
 
-                    if !dry_run {
-                        add_trace_attribute(func);
+                    for impl_item in &item_impl.items {
+                        if let ImplItem::Fn(method) = impl_item {
+                            let name = format!("{}::{}", type_name, method.sig.ident);
+                            let is_public = is_trait_impl || matches!(method.vis, Visibility::Public(_));
+                            let lines = line_count(&method.vis, &method.sig, &method.block);
+
+                            if should_instrument_method(method)
+                                && self.filter.matches_fn(&name)
+                                && self.filter.matches_shape(is_public, method.sig.asyncness.is_some(), lines)
+                            {
+                                instrumented_functions.push(name);
+                                attribute_lines.push(item_start_line(&method.vis, &method.sig));
+                            }
+                        }
                     }
                 }
+                _ => {}
             }
         }
 
         let mut backup_path = None;
+        let mut import_changed = false;
 
         if !dry_run && !instrumented_functions.is_empty() {
-            // Create backup if requested
-            if self.create_backup {
-                let backup = file.with_extension("rs.bak");
-                fs::copy(file, &backup).map_err(|e| format!("Failed to create backup: {}", e))?;
-                backup_path = Some(backup.to_string_lossy().to_string());
-            }
+            backup_path = self.backup(file)?;
 
-            // Write instrumented code
-            let instrumented_code = quote! { #syntax }.to_string();
+            // Import the macro, or fall back to the full path if `trace` is taken
+            let (attribute, use_after) = match trace_import(&syntax) {
+                TraceImport::Present => ("#[trace]", None),
+                TraceImport::Conflict => ("#[flowtrace_derive::trace]", None),
+                TraceImport::Missing => ("#[trace]", Some(import_line(&syntax))),
+            };
+            import_changed = use_after.is_some();
+
+            // Write instrumented code, leaving everything else byte-for-byte intact
+            let instrumented_code = insert_lines(&content, &attribute_lines, attribute, use_after);
             fs::write(file, instrumented_code)
                 .map_err(|e| format!("Failed to write file: {}", e))?;
         }
@@ -68,28 +178,149 @@ impl Instrumenter {
             count: instrumented_functions.len(),
             functions: instrumented_functions,
             backup_path,
+            import_changed,
+        })
+    }
+
+    /// Strip FlowTrace `#[trace]` attributes, and optionally the imports of
+    /// the macro, leaving the rest of the file untouched
+    pub fn uninstrument_file(
+        &self,
+        file: &Path,
+        dry_run: bool,
+        remove_imports: bool,
+    ) -> Result<InstrumentResult, String> {
+        let content = fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read file {}: {}", file.display(), e))?;
+
+        let syntax = parse_file(&content)
+            .map_err(|e| format!("Failed to parse file {}: {}", file.display(), e))?;
+
+        let mut collector = TraceAttrCollector {
+            bare_trace: trace_import(&syntax) != TraceImport::Conflict,
+            impl_types: Vec::new(),
+            functions: Vec::new(),
+            ranges: Vec::new(),
+        };
+        collector.visit_file(&syntax);
+
+        let mut ranges = collector.ranges;
+        let mut import_changed = false;
+        if remove_imports {
+            for item in &syntax.items {
+                if let Item::Use(item_use) = item {
+                    if is_trace_only_import(&item_use.tree) {
+                        ranges.push(token_range(item.to_token_stream()));
+                        import_changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut backup_path = None;
+
+        if !dry_run && !ranges.is_empty() {
+            backup_path = self.backup(file)?;
+
+            fs::write(file, remove_ranges(&content, &ranges))
+                .map_err(|e| format!("Failed to write file: {}", e))?;
+        }
+
+        Ok(InstrumentResult {
+            count: collector.functions.len(),
+            functions: collector.functions,
+            backup_path,
+            import_changed,
         })
     }
+
+    /// Copy `file` to `file.rs.bak` if backups are enabled
+    fn backup(&self, file: &Path) -> Result<Option<String>, String> {
+        if !self.create_backup {
+            return Ok(None);
+        }
+
+        let backup = file.with_extension("rs.bak");
+        fs::copy(file, &backup).map_err(|e| format!("Failed to create backup: {}", e))?;
+        Ok(Some(backup.to_string_lossy().to_string()))
+    }
+}
+
+/// Apply a per-file operation to a file or to every crate under a directory
+fn process_path(
+    path: &Path,
+    filter: &Filter,
+    op: impl Fn(&Path) -> Result<InstrumentResult, String>,
+) -> Result<Vec<CrateResult>, String> {
+    if path.is_file() {
+        let result = op(path)?;
+        return Ok(vec![CrateResult {
+            name: path.display().to_string(),
+            files: vec![(path.to_path_buf(), result)],
+            errors: Vec::new(),
+        }]);
+    }
+
+    if !path.is_dir() {
+        return Err(format!("Path not found: {}", path.display()));
+    }
+
+    let mut crates = Vec::new();
+    for sources in workspace::crate_sources(path) {
+        let mut crate_result = CrateResult {
+            name: sources.name,
+            ..Default::default()
+        };
+
+        for file in sources.files {
+            let relative = file.strip_prefix(path).unwrap_or(&file);
+            if !filter.matches_path(relative) || !filter.matches_module(&module_path(&file, path)) {
+                continue;
+            }
+
+            match op(&file) {
+                Ok(result) => crate_result.files.push((file, result)),
+                Err(e) => crate_result.errors.push(e),
+            }
+        }
+
+        crates.push(crate_result);
+    }
+
+    Ok(crates)
 }
 
 fn should_instrument(func: &ItemFn) -> bool {
-    // Don't instrument if already has #[trace]
-    if has_trace_attribute(func) {
+    is_instrumentable(&func.attrs, &func.sig, &func.block)
+}
+
+fn should_instrument_method(method: &ImplItemFn) -> bool {
+    is_instrumentable(&method.attrs, &method.sig, &method.block)
+}
+
+fn is_instrumentable(attrs: &[Attribute], sig: &Signature, block: &Block) -> bool {
+    // Don't instrument if already has #[trace] or another framework's instrumentation
+    if has_trace_attribute(attrs) {
         return false;
     }
 
     // Don't instrument test functions
-    if is_test_function(func) {
+    if is_test_function(attrs) {
         return false;
     }
 
     // Don't instrument functions without body
-    if func.block.stmts.is_empty() {
+    if block.stmts.is_empty() {
+        return false;
+    }
+
+    // Don't instrument const fns, the expansion is not const-evaluable
+    if sig.constness.is_some() {
         return false;
     }
 
     // Don't instrument certain special functions
-    let name = func.sig.ident.to_string();
+    let name = sig.ident.to_string();
     if name == "main" || name == "init" || name.starts_with("test_") {
         return false;
     }
@@ -97,23 +328,286 @@ fn should_instrument(func: &ItemFn) -> bool {
     true
 }
 
-fn has_trace_attribute(func: &ItemFn) -> bool {
-    func.attrs
+fn has_trace_attribute(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| detect::is_trace_attribute(attr) || detect::is_other_instrumentation(attr))
+}
+
+fn is_test_function(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(detect::is_test_attribute)
+}
+
+/// Name of the type an impl block belongs to, used when reporting methods
+pub fn impl_type_name(item_impl: &ItemImpl) -> String {
+    match &*item_impl.self_ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string())
+            .unwrap_or_default(),
+        other => quote! { #other }.to_string(),
+    }
+}
+
+/// 1-based line of the first token after the attributes (visibility or signature)
+fn item_start_line(vis: &Visibility, sig: &Signature) -> usize {
+    let mut tokens = proc_macro2::TokenStream::new();
+    vis.to_tokens(&mut tokens);
+    sig.to_tokens(&mut tokens);
+
+    tokens
+        .into_iter()
+        .next()
+        .map(|token| token.span().start().line)
+        .unwrap_or(1)
+}
+
+/// Whether the file already imports the FlowTrace `trace` macro
+fn trace_import(syntax: &File) -> TraceImport {
+    let mut imports = Vec::new();
+    for item in &syntax.items {
+        if let Item::Use(item_use) = item {
+            collect_imports(&item_use.tree, &mut Vec::new(), &mut imports);
+        }
+    }
+
+    let mut status = TraceImport::Missing;
+    for (prefix, name) in imports {
+        let from_flowtrace = prefix
+            .first()
+            .is_some_and(|root| root == "flowtrace_agent" || root == "flowtrace_derive");
+
+        if from_flowtrace && (name == "trace" || name == "*") {
+            return TraceImport::Present;
+        }
+        if name == "trace" {
+            status = TraceImport::Conflict;
+        }
+    }
+
+    status
+}
+
+/// Flatten a use tree into (path prefix, imported name) pairs
+fn collect_imports(tree: &UseTree, prefix: &mut Vec<String>, out: &mut Vec<(Vec<String>, String)>) {
+    match tree {
+        UseTree::Path(path) => {
+            prefix.push(path.ident.to_string());
+            collect_imports(&path.tree, prefix, out);
+            prefix.pop();
+        }
+        UseTree::Name(name) => out.push((prefix.clone(), name.ident.to_string())),
+        UseTree::Rename(rename) => out.push((prefix.clone(), rename.rename.to_string())),
+        UseTree::Glob(_) => out.push((prefix.clone(), "*".to_string())),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                collect_imports(tree, prefix, out);
+            }
+        }
+    }
+}
+
+/// Whether a use tree imports nothing but the FlowTrace `trace` macro
+fn is_trace_only_import(tree: &UseTree) -> bool {
+    let mut imports = Vec::new();
+    collect_imports(tree, &mut Vec::new(), &mut imports);
+
+    match imports.as_slice() {
+        [(prefix, name)] => {
+            name == "trace"
+                && prefix.len() == 1
+                && (prefix[0] == "flowtrace_derive" || prefix[0] == "flowtrace_agent")
+        }
+        _ => false,
+    }
+}
+
+/// Whether an attribute is the FlowTrace `#[trace]` macro (bare or by path)
+fn is_flowtrace_attribute(attr: &Attribute, bare_trace: bool) -> bool {
+    (bare_trace || !attr.path().is_ident("trace")) && detect::is_trace_attribute(attr)
+}
+
+type TokenRange = (LineColumn, LineColumn);
+
+/// Collects the source ranges of FlowTrace attributes on functions, methods and impl blocks
+struct TraceAttrCollector {
+    bare_trace: bool,
+    impl_types: Vec<String>,
+    functions: Vec<String>,
+    ranges: Vec<TokenRange>,
+}
+
+impl TraceAttrCollector {
+    fn collect(&mut self, attrs: &[Attribute], name: String) {
+        let mut found = false;
+        for attr in attrs {
+            if is_flowtrace_attribute(attr, self.bare_trace) {
+                self.ranges.push(token_range(attr.to_token_stream()));
+                found = true;
+            }
+        }
+
+        if found {
+            self.functions.push(name);
+        }
+    }
+
+    fn qualified(&self, ident: &syn::Ident) -> String {
+        match self.impl_types.last() {
+            Some(type_name) => format!("{}::{}", type_name, ident),
+            None => ident.to_string(),
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for TraceAttrCollector {
+    fn visit_item_fn(&mut self, node: &'ast ItemFn) {
+        self.collect(&node.attrs, node.sig.ident.to_string());
+        syn::visit::visit_item_fn(self, node);
+    }
+
+    fn visit_item_impl(&mut self, node: &'ast ItemImpl) {
+        let type_name = impl_type_name(node);
+        self.collect(&node.attrs, format!("impl {}", type_name));
+
+        self.impl_types.push(type_name);
+        syn::visit::visit_item_impl(self, node);
+        self.impl_types.pop();
+    }
+
+    fn visit_impl_item_fn(&mut self, node: &'ast ImplItemFn) {
+        let name = self.qualified(&node.sig.ident);
+        self.collect(&node.attrs, name);
+        syn::visit::visit_impl_item_fn(self, node);
+    }
+
+    fn visit_trait_item_fn(&mut self, node: &'ast TraitItemFn) {
+        self.collect(&node.attrs, node.sig.ident.to_string());
+        syn::visit::visit_trait_item_fn(self, node);
+    }
+}
+
+/// Source range covered by a token stream
+fn token_range(tokens: proc_macro2::TokenStream) -> TokenRange {
+    let mut tokens = tokens.into_iter();
+    let first = tokens.next().map(|token| token.span());
+    let last = tokens.last().map(|token| token.span()).or(first);
+
+    match (first, last) {
+        (Some(first), Some(last)) => (first.start(), last.end()),
+        _ => (LineColumn { line: 1, column: 0 }, LineColumn { line: 1, column: 0 }),
+    }
+}
+
+/// Remove the given ranges from `content`, dropping whole lines when a range
+/// is the only thing on them
+fn remove_ranges(content: &str, ranges: &[TokenRange]) -> String {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(index, _)| index + 1))
+        .collect();
+
+    let offset = |position: LineColumn| -> usize {
+        let start = line_starts.get(position.line - 1).copied().unwrap_or(content.len());
+        content[start..]
+            .char_indices()
+            .nth(position.column)
+            .map(|(index, _)| start + index)
+            .unwrap_or(content.len())
+    };
+
+    let mut spans: Vec<(usize, usize)> = ranges
+        .iter()
+        .map(|(start, end)| {
+            let mut from = offset(*start);
+            let mut to = offset(*end);
+
+            let line_start = content[..from].rfind('\n').map_or(0, |index| index + 1);
+            let line_end = content[to..].find('\n').map_or(content.len(), |index| to + index);
+            if content[line_start..from].trim().is_empty() && content[to..line_end].trim().is_empty() {
+                from = line_start;
+                to = (line_end + 1).min(content.len());
+            }
+
+            (from, to)
+        })
+        .collect();
+
+    spans.sort();
+    spans.dedup();
+
+    let mut output = content.to_string();
+    for (from, to) in spans.into_iter().rev() {
+        output.replace_range(from..to, "");
+    }
+
+    output
+}
+
+/// Line after which the import is inserted: the last top-level `use`, else
+/// the last inner attribute or module doc comment, else the top of the file
+fn import_line(syntax: &File) -> usize {
+    let last_use = syntax
+        .items
         .iter()
-        .any(|attr| attr.path().is_ident("trace"))
+        .filter(|item| matches!(item, Item::Use(_)))
+        .filter_map(|item| last_token_line(item.to_token_stream()))
+        .max();
+
+    last_use
+        .or_else(|| {
+            syntax
+                .attrs
+                .iter()
+                .filter_map(|attr| last_token_line(attr.to_token_stream()))
+                .max()
+        })
+        .unwrap_or(0)
 }
 
-fn is_test_function(func: &ItemFn) -> bool {
-    func.attrs.iter().any(|attr| {
-        attr.path().is_ident("test")
-            || attr.path().is_ident("cfg")
-            || attr.path().is_ident("bench")
-    })
+fn last_token_line(tokens: proc_macro2::TokenStream) -> Option<usize> {
+    tokens.into_iter().last().map(|token| token.span().end().line)
 }
 
-fn add_trace_attribute(func: &mut ItemFn) {
-    let trace_attr: Attribute = syn::parse_quote! { #[trace] };
-    func.attrs.push(trace_attr);
+/// Number of source lines from the signature to the closing brace
+fn line_count(vis: &Visibility, sig: &Signature, block: &Block) -> usize {
+    let end = block.brace_token.span.close().end().line;
+    end.saturating_sub(item_start_line(vis, sig)) + 1
+}
+
+/// Insert `attribute` before each of the given 1-based lines, matching the
+/// indentation of the line it annotates, and the trace import after
+/// `use_after` (0 meaning the top of the file)
+fn insert_lines(content: &str, lines: &[usize], attribute: &str, use_after: Option<usize>) -> String {
+    let mut output = String::with_capacity(content.len() + lines.len() * 16);
+    let newline = if content.contains("\r\n") { "\r\n" } else { "\n" };
+
+    if use_after == Some(0) {
+        output.push_str(TRACE_IMPORT);
+        output.push_str(newline);
+        output.push_str(newline);
+    }
+
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        if lines.contains(&(index + 1)) {
+            let indent: String = line.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+            output.push_str(&indent);
+            output.push_str(attribute);
+            output.push_str(newline);
+        }
+        output.push_str(line);
+
+        if use_after == Some(index + 1) {
+            if !line.ends_with('\n') {
+                output.push_str(newline);
+            }
+            output.push_str(TRACE_IMPORT);
+            output.push_str(newline);
+        }
+    }
+
+    output
 }
 
 #[cfg(test)]
@@ -151,4 +645,249 @@ mod tests {
         let syntax = syn::parse_str::<ItemFn>(code).unwrap();
         assert!(!should_instrument(&syntax));
     }
+
+    #[test]
+    fn test_should_instrument_impl_method() {
+        let code = r#"
+            fn get_user(&self, id: u32) -> Option<User> { self.users.get(&id).cloned() }
+        "#;
+
+        let method = syn::parse_str::<ImplItemFn>(code).unwrap();
+        assert!(should_instrument_method(&method));
+    }
+
+    #[test]
+    fn test_instrument_file_impl_blocks() {
+        let code = r#"
+            struct Service;
+
+            impl Service {
+                pub fn handle(&self) { println!("handle"); }
+                const fn limit() -> usize { 10 }
+            }
+
+            impl Drop for Service {
+                fn drop(&mut self) { println!("drop"); }
+            }
+        "#;
+
+        let temp_file = std::env::temp_dir().join("flowctl_instrument_impl.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let result = Instrumenter::new(false)
+            .instrument_file(&temp_file, true)
+            .unwrap();
+        assert_eq!(result.functions, vec!["Service::handle", "Service::drop"]);
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_instrument_skips_traced_impl_blocks() {
+        let code = r#"
+            struct Service;
+
+            #[trace]
+            impl Service {
+                pub fn handle(&self) { println!("handle"); }
+            }
+
+            impl Service {
+                fn other(&self) { println!("other"); }
+            }
+        "#;
+
+        let temp_file = std::env::temp_dir().join("flowctl_instrument_traced_impl.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let result = Instrumenter::new(false)
+            .instrument_file(&temp_file, true)
+            .unwrap();
+        assert_eq!(result.functions, vec!["Service::other"]);
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_instrument_path_directory() {
+        let dir = std::env::temp_dir().join("flowctl_instrument_dir");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("a.rs"), "fn one() { work(); }").unwrap();
+        std::fs::write(dir.join("nested/b.rs"), "fn two() { work(); }\nfn three() { work(); }").unwrap();
+        std::fs::write(dir.join("broken.rs"), "fn {").unwrap();
+
+        let crates = Instrumenter::new(false).instrument_path(&dir, true).unwrap();
+        assert_eq!(crates.len(), 1);
+        assert_eq!(crates[0].count(), 3);
+        assert_eq!(crates[0].files_changed(), 2);
+        assert_eq!(crates[0].errors.len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_instrument_file_shape_filters() {
+        let code = r#"
+            pub async fn fetch() {
+                let a = 1;
+                let b = 2;
+                work(a, b).await;
+            }
+
+            async fn private_fetch() {
+                let a = 1;
+                let b = 2;
+                work(a, b).await;
+            }
+
+            pub fn sync_entry() { work(); }
+
+            pub struct Api;
+
+            impl Handler for Api {
+                async fn call(&self) {
+                    let a = 1;
+                    let b = 2;
+                    work(a, b).await;
+                }
+            }
+        "#;
+
+        let temp_file = std::env::temp_dir().join("flowctl_instrument_shape.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let filter = Filter::default().public_only(true).async_only(true).min_lines(5);
+        let result = Instrumenter::new(false)
+            .with_filter(filter)
+            .instrument_file(&temp_file, true)
+            .unwrap();
+        assert_eq!(result.functions, vec!["fetch", "Api::call"]);
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_instrument_path_with_filter() {
+        let dir = std::env::temp_dir().join("flowctl_instrument_filter");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("generated")).unwrap();
+        std::fs::write(dir.join("handlers.rs"), "fn handle_a() { work(); }\nfn helper() { work(); }").unwrap();
+        std::fs::write(dir.join("generated/proto.rs"), "fn handle_b() { work(); }").unwrap();
+
+        let filter = Filter::new(&[], &["generated/**".to_string()], Some("^handle_")).unwrap();
+        let crates = Instrumenter::new(false)
+            .with_filter(filter)
+            .instrument_path(&dir, true)
+            .unwrap();
+
+        let functions: Vec<&String> = crates[0]
+            .files
+            .iter()
+            .flat_map(|(_, result)| &result.functions)
+            .collect();
+        assert_eq!(functions, vec!["handle_a"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_instrument_preserves_formatting() {
+        let code = "// Service layer\n\nimpl Service {\n    /// Loads a user\n    pub fn load(&self, id: u32) -> User {\n        // cache first\n        self.cache.get(id)\n    }\n}\n\n#[inline]\nfn helper() { work(); }\n";
+
+        let temp_file = std::env::temp_dir().join("flowctl_instrument_format.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let result = Instrumenter::new(false)
+            .instrument_file(&temp_file, false)
+            .unwrap();
+        assert_eq!(result.count, 2);
+
+        let instrumented = std::fs::read_to_string(&temp_file).unwrap();
+        assert_eq!(
+            instrumented,
+            "use flowtrace_derive::trace;\n\n// Service layer\n\nimpl Service {\n    /// Loads a user\n    #[trace]\n    pub fn load(&self, id: u32) -> User {\n        // cache first\n        self.cache.get(id)\n    }\n}\n\n#[inline]\n#[trace]\nfn helper() { work(); }\n"
+        );
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_trace_import_detection() {
+        let present = syn::parse_file("use flowtrace_agent::{trace, Config};").unwrap();
+        assert_eq!(trace_import(&present), TraceImport::Present);
+
+        let conflict = syn::parse_file("use log::{debug, trace};").unwrap();
+        assert_eq!(trace_import(&conflict), TraceImport::Conflict);
+
+        let missing = syn::parse_file("use std::fs;").unwrap();
+        assert_eq!(trace_import(&missing), TraceImport::Missing);
+    }
+
+    #[test]
+    fn test_import_inserted_after_last_use() {
+        let code = "//! Handlers\n\nuse std::fs;\nuse std::io::{\n    Read,\n    Write,\n};\n\nfn handle() { work(); }\n";
+        let syntax = syn::parse_file(code).unwrap();
+
+        let output = insert_lines(code, &[9], "#[trace]", Some(import_line(&syntax)));
+        assert_eq!(
+            output,
+            "//! Handlers\n\nuse std::fs;\nuse std::io::{\n    Read,\n    Write,\n};\nuse flowtrace_derive::trace;\n\n#[trace]\nfn handle() { work(); }\n"
+        );
+    }
+
+    #[test]
+    fn test_conflicting_import_uses_full_path() {
+        let code = "use log::trace;\n\nfn handle() { trace!(\"x\"); }\n";
+
+        let temp_file = std::env::temp_dir().join("flowctl_instrument_conflict.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let result = Instrumenter::new(false)
+            .instrument_file(&temp_file, false)
+            .unwrap();
+        assert!(!result.import_changed);
+
+        let instrumented = std::fs::read_to_string(&temp_file).unwrap();
+        assert_eq!(
+            instrumented,
+            "use log::trace;\n\n#[flowtrace_derive::trace]\nfn handle() { trace!(\"x\"); }\n"
+        );
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_uninstrument_round_trip() {
+        let code = "use std::fs;\n\npub struct Store;\n\nimpl Store {\n    /// Reads\n    pub fn read(&self) -> String { fs::read_to_string(\"x\").unwrap() }\n}\n\n#[inline]\nfn helper() { work(); }\n";
+
+        let temp_file = std::env::temp_dir().join("flowctl_uninstrument.rs");
+        std::fs::write(&temp_file, code).unwrap();
+
+        let instrumenter = Instrumenter::new(false);
+        instrumenter.instrument_file(&temp_file, false).unwrap();
+
+        let result = instrumenter.uninstrument_file(&temp_file, false, true).unwrap();
+        assert_eq!(result.functions, vec!["Store::read", "helper"]);
+        assert!(result.import_changed);
+        assert_eq!(std::fs::read_to_string(&temp_file).unwrap(), code);
+
+        std::fs::remove_file(temp_file).unwrap();
+    }
+
+    #[test]
+    fn test_uninstrument_keeps_foreign_trace() {
+        let code = "use log::trace;\n\n#[flowtrace_agent::trace] #[trace] fn a() {}\n";
+
+        let syntax = syn::parse_file(code).unwrap();
+        let mut collector = TraceAttrCollector {
+            bare_trace: trace_import(&syntax) != TraceImport::Conflict,
+            impl_types: Vec::new(),
+            functions: Vec::new(),
+            ranges: Vec::new(),
+        };
+        collector.visit_file(&syntax);
+
+        assert_eq!(remove_ranges(code, &collector.ranges), "use log::trace;\n\n #[trace] fn a() {}\n");
+    }
 }
@@ -2,15 +2,42 @@
 //!
 //! Analyze and instrument Rust code for tracing
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod analyzer;
+mod bench;
+mod config;
+mod correlate;
+mod detect;
+mod diff;
+mod doctor;
+mod expand;
+mod filter;
+mod grep;
+mod hotpath;
 mod instrumenter;
+mod manifest;
+mod otlp;
+mod probe;
+mod prune;
+mod recommend;
+mod scaffold;
+mod summary;
+#[cfg(feature = "live")]
+mod tail;
+mod timeline;
+mod trace;
+#[cfg(feature = "tui")]
+mod tui;
+mod watch;
+mod workspace;
 
 use analyzer::Analyzer;
-use instrumenter::Instrumenter;
+use config::ProjectConfig;
+use filter::Filter;
+use instrumenter::{CrateResult, Instrumenter};
 
 #[derive(Parser)]
 #[command(name = "flowctl-rs")]
@@ -30,11 +57,46 @@ enum Commands {
         /// Show detailed statistics
         #[arg(short, long)]
         verbose: bool,
+
+        /// List function counts and coverage for each file/module
+        #[arg(long)]
+        breakdown: bool,
+
+        /// Rank the N most valuable functions to instrument first
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10")]
+        recommend: Option<usize>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Exit with an error when coverage is below --min-coverage (for CI)
+        #[arg(long)]
+        check: bool,
+
+        /// Minimum instrumentation coverage percentage required by --check
+        #[arg(long, value_name = "PERCENT", default_value_t = 80.0, requires = "check")]
+        min_coverage: f64,
+    },
+
+    /// Re-run analysis whenever Rust sources change
+    Watch {
+        /// Path to Rust file or directory
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Polling interval in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+
+        /// Also show which functions in changed files would be instrumented
+        #[arg(long)]
+        instrument_diff: bool,
     },
 
     /// Instrument Rust code with #[trace] attributes
     Instrument {
-        /// Path to Rust file
+        /// Path to Rust file, directory, or cargo workspace
         path: PathBuf,
 
         /// Dry run - show what would be instrumented without modifying files
@@ -44,44 +106,502 @@ enum Commands {
         /// Create backup before modifying
         #[arg(short, long, default_value_t = true)]
         backup: bool,
+
+        /// Add flowtrace-agent and flowtrace-derive to the nearest Cargo.toml
+        #[arg(long)]
+        add_deps: bool,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+
+    /// Remove #[trace] attributes previously added by instrument
+    Uninstrument {
+        /// Path to Rust file, directory, or cargo workspace
+        path: PathBuf,
+
+        /// Dry run - show what would be removed without modifying files
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Create backup before modifying
+        #[arg(short, long, default_value_t = true)]
+        backup: bool,
+
+        /// Also remove `use flowtrace_derive::trace;` style imports
+        #[arg(long)]
+        remove_imports: bool,
+
+        /// Only process files matching this glob (repeatable, relative to path)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Skip files matching this glob (repeatable, relative to path)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+    },
+
+    /// Show the code #[trace] generates for a function
+    Expand {
+        /// Rust file containing the function
+        file: PathBuf,
+
+        /// Function name, Type::name for methods, or "impl Type" for whole impl blocks
+        function: String,
+    },
+
+    /// Set up FlowTrace in a cargo project (flowtrace.toml and dependencies)
+    Init {
+        /// Project directory containing Cargo.toml
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Also start tracing at the top of fn main in src/main.rs
+        #[arg(long)]
+        with_main: bool,
+    },
+
+    /// Measure the per-call runtime cost of #[trace] in a project
+    BenchOverhead {
+        /// Project directory containing Cargo.toml
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Calls measured for each variant
+        #[arg(long, default_value_t = 100_000)]
+        iterations: u64,
+    },
+
+    /// Search trace files for a value in call arguments, results or errors
+    Grep {
+        /// Text to search for
+        pattern: String,
+
+        /// Trace files to search
+        #[arg(default_value = "flowtrace.jsonl")]
+        files: Vec<PathBuf>,
+
+        /// Treat the pattern as a regular expression
+        #[arg(short = 'E', long)]
+        regex: bool,
+
+        /// Ignore case when matching
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Payload to search
+        #[arg(long, value_enum, default_value_t = grep::Field::Any)]
+        field: grep::Field,
+    },
+
+    /// Draw an ASCII Gantt chart of calls per thread
+    Timeline {
+        /// Trace file to render
+        #[arg(default_value = "flowtrace.jsonl")]
+        file: PathBuf,
+
+        /// Start of the window, in milliseconds after the first event
+        #[arg(long, value_name = "MS")]
+        from_ms: Option<f64>,
+
+        /// End of the window, in milliseconds after the first event
+        #[arg(long, value_name = "MS")]
+        to_ms: Option<f64>,
+
+        /// Show only the window of the first top-level call with this name
+        #[arg(long, value_name = "FUNCTION")]
+        call: Option<String>,
+
+        /// Columns used for the bars
+        #[arg(long, default_value_t = 60)]
+        width: usize,
+
+        /// Maximum call nesting depth to show
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+
+    /// Show the critical path and self time of one request tree
+    Hotpath {
+        /// Trace file to analyze
+        #[arg(default_value = "flowtrace.jsonl")]
+        file: PathBuf,
+
+        /// Analyze the first top-level call with this name instead of the slowest
+        #[arg(long, value_name = "FUNCTION")]
+        call: Option<String>,
+
+        /// Number of calls listed by self time
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+
+    /// Summarize a trace run: errors, slowest calls and latency budget violations
+    Summary {
+        /// Trace file to summarize
+        #[arg(default_value = "flowtrace.jsonl")]
+        file: PathBuf,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = SummaryFormat::Text)]
+        format: SummaryFormat,
+
+        /// Number of slowest calls to report
+        #[arg(long, default_value_t = 5)]
+        slowest: usize,
+
+        /// Exit with an error when the run has exceptions or budget violations
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Compare per-function latency of two runs, telling code changes from drift
+    Diff {
+        /// Trace file of the earlier run
+        baseline: PathBuf,
+
+        /// Trace file of the later run
+        current: PathBuf,
+
+        /// Only show functions whose mean latency changed by at least this percent
+        #[arg(long, default_value_t = 10.0)]
+        threshold: f64,
+    },
+
+    /// Follow the live event stream of a running agent
+    #[cfg(feature = "live")]
+    Tail {
+        /// WebSocket address the agent serves (its `websocket_addr`), e.g. ws://127.0.0.1:7878
+        #[arg(long, value_name = "URL")]
+        connect: String,
+
+        /// Only show events from this module and its submodules (repeatable)
+        #[arg(long)]
+        module: Vec<String>,
+
+        /// Only show events at this level or above (debug, info, warn, error)
+        #[arg(long)]
+        level: Option<String>,
+    },
+
+    /// Browse call trees and per-function stats interactively
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Trace files to load
+        #[arg(default_value = "flowtrace.jsonl")]
+        files: Vec<PathBuf>,
+    },
+
+    /// Extract a slice of a trace file by time, trace ID, target, function or size
+    Prune {
+        /// Trace file to read
+        input: PathBuf,
+
+        /// File to write the kept events to
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Keep events at or after this time (HH:MM[:SS] or YYYY-MM-DDTHH:MM[:SS], UTC)
+        #[arg(long, value_parser = prune::parse_time)]
+        from: Option<prune::TimeSpec>,
+
+        /// Keep events at or before this time (HH:MM[:SS] or YYYY-MM-DDTHH:MM[:SS], UTC)
+        #[arg(long, value_parser = prune::parse_time)]
+        to: Option<prune::TimeSpec>,
+
+        /// Keep events of one trace
+        #[arg(long)]
+        trace_id: Option<String>,
+
+        /// Keep events of one `#[trace(target = "...")]` subsystem
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Keep top-level call trees that call this function
+        #[arg(long, value_name = "FUNCTION")]
+        function: Option<String>,
+
+        /// Keep only the most recent events fitting in this size (e.g. 100MB)
+        #[arg(long, value_parser = prune::parse_size)]
+        max_size: Option<u64>,
+    },
+
+    /// Interleave application log lines with the calls of the traces they mention
+    Correlate {
+        /// Trace file to read
+        trace: PathBuf,
+
+        /// Application log whose lines carry trace IDs
+        log: PathBuf,
+
+        /// Show only this trace, even without log lines
+        #[arg(long)]
+        trace_id: Option<String>,
+    },
+
+    /// Convert a trace file for other tools, e.g. OTLP spans for Jaeger or Tempo
+    Convert {
+        /// Trace file to read
+        input: PathBuf,
+
+        /// Format to convert to
+        #[arg(long, value_enum)]
+        to: ConvertFormat,
+
+        /// File to write the converted trace to
+        #[arg(short, long, required_unless_present = "push")]
+        output: Option<PathBuf>,
+
+        /// Also send the spans to this OTLP/HTTP endpoint (e.g. http://localhost:4318)
+        #[arg(long, value_name = "ENDPOINT")]
+        push: Option<String>,
     },
 
     /// Validate FlowTrace setup
     Validate,
 
+    /// Diagnose FlowTrace setup in depth and suggest fixes
+    Doctor {
+        /// Project directory containing Cargo.toml
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Skip building and running the #[trace] smoke test
+        #[arg(long)]
+        no_build: bool,
+    },
+
     /// Show version information
     Version,
 }
 
+/// Output format for reports
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SummaryFormat {
+    Text,
+    /// JUnit XML for CI test report viewers
+    Junit,
+    /// GitHub check-run annotations as JSON
+    Github,
+}
+
+/// Target format of `convert`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ConvertFormat {
+    /// OTLP/JSON trace export request
+    Otlp,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Options selecting which files and functions get instrumented
+#[derive(Args, Default)]
+struct SelectionArgs {
+    /// Only process files matching this glob (repeatable, relative to path)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files matching this glob (repeatable, relative to path)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Only instrument functions whose name (or Type::name) matches this regex
+    #[arg(long, value_name = "REGEX")]
+    fn_filter: Option<String>,
+
+    /// Only instrument public functions and trait impl methods
+    #[arg(long)]
+    public_only: bool,
+
+    /// Only instrument async functions
+    #[arg(long)]
+    async_only: bool,
+
+    /// Only instrument functions spanning at least this many lines
+    #[arg(long, value_name = "N")]
+    min_lines: Option<usize>,
+
+    /// Use a [profiles.NAME] section of flowtrace.toml instead of [instrument]
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Analyze { path, verbose } => {
-            analyze_command(path, verbose);
+        Commands::Analyze {
+            path,
+            verbose,
+            breakdown,
+            recommend,
+            format,
+            check,
+            min_coverage,
+        } => {
+            let min_coverage = check.then_some(min_coverage);
+            analyze_command(path, verbose, breakdown, recommend, format, min_coverage);
+        }
+        Commands::Watch {
+            path,
+            interval_ms,
+            instrument_diff,
+        } => {
+            watch_command(path, interval_ms, instrument_diff);
         }
         Commands::Instrument {
             path,
             dry_run,
             backup,
+            add_deps,
+            selection,
+        } => {
+            let filter = build_filter(&path, selection);
+            instrument_command(path, dry_run, backup, add_deps, filter);
+        }
+        Commands::Uninstrument {
+            path,
+            dry_run,
+            backup,
+            remove_imports,
+            include,
+            exclude,
+        } => {
+            let selection = SelectionArgs {
+                include,
+                exclude,
+                ..Default::default()
+            };
+            let filter = build_filter(&path, selection);
+            uninstrument_command(path, dry_run, backup, remove_imports, filter);
+        }
+        Commands::Expand { file, function } => {
+            expand_command(&file, &function);
+        }
+        Commands::Init { path, with_main } => {
+            init_command(path, with_main);
+        }
+        Commands::BenchOverhead { path, iterations } => {
+            bench_overhead_command(path, iterations);
+        }
+        Commands::Grep {
+            pattern,
+            files,
+            regex,
+            ignore_case,
+            field,
+        } => {
+            grep_command(&pattern, &files, regex, ignore_case, field);
+        }
+        Commands::Timeline {
+            file,
+            from_ms,
+            to_ms,
+            call,
+            width,
+            depth,
+        } => {
+            let range = (from_ms, to_ms);
+            timeline_command(&file, range, call.as_deref(), width, depth);
+        }
+        Commands::Hotpath { file, call, top } => {
+            hotpath_command(&file, call.as_deref(), top);
+        }
+        Commands::Summary {
+            file,
+            format,
+            slowest,
+            check,
+        } => {
+            summary_command(&file, format, slowest, check);
+        }
+        Commands::Diff {
+            baseline,
+            current,
+            threshold,
+        } => {
+            diff_command(&baseline, &current, threshold);
+        }
+        #[cfg(feature = "live")]
+        Commands::Tail { connect, module, level } => {
+            tail_command(&connect, &module, level.as_deref());
+        }
+        #[cfg(feature = "tui")]
+        Commands::Tui { files } => {
+            tui_command(&files);
+        }
+        Commands::Prune {
+            input,
+            output,
+            from,
+            to,
+            trace_id,
+            target,
+            function,
+            max_size,
         } => {
-            instrument_command(path, dry_run, backup);
+            let options = prune::PruneOptions {
+                from,
+                to,
+                trace_id,
+                target,
+                function,
+                max_bytes: max_size,
+            };
+            prune_command(&input, &output, &options);
+        }
+        Commands::Correlate { trace, log, trace_id } => {
+            correlate_command(&trace, &log, trace_id.as_deref());
+        }
+        Commands::Convert { input, to, output, push } => {
+            convert_command(&input, to, output.as_deref(), push.as_deref());
         }
         Commands::Validate => {
             validate_command();
         }
+        Commands::Doctor { path, no_build } => {
+            doctor_command(path, no_build);
+        }
         Commands::Version => {
             version_command();
         }
     }
 }
 
-fn analyze_command(path: PathBuf, verbose: bool) {
+fn analyze_command(
+    path: PathBuf,
+    verbose: bool,
+    breakdown: bool,
+    recommend: Option<usize>,
+    format: OutputFormat,
+    min_coverage: Option<f64>,
+) {
+    let analyzer = Analyzer::new().with_recommendations(recommend.is_some());
+
+    if format == OutputFormat::Json {
+        match analyzer.analyze_path(&path) {
+            Ok(mut stats) => {
+                stats.recommendations.truncate(recommend.unwrap_or(0));
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap_or_default());
+                if let Some(min) = min_coverage {
+                    check_coverage(&stats, min);
+                }
+            }
+            Err(e) => {
+                eprintln!("{} {}", "❌ Error:".red().bold(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     println!("{}", "🔍 Analyzing Rust project...".cyan().bold());
     println!();
 
-    let analyzer = Analyzer::new();
-
     match analyzer.analyze_path(&path) {
         Ok(stats) => {
             println!("{}", "📊 Analysis Results:".green().bold());
@@ -96,7 +616,14 @@ fn analyze_command(path: PathBuf, verbose: bool) {
                 "  {} already instrumented",
                 stats.instrumented_functions.to_string().blue()
             );
+            if stats.other_instrumented_functions > 0 {
+                println!(
+                    "  {} instrumented by other framework (e.g. tracing)",
+                    stats.other_instrumented_functions.to_string().magenta()
+                );
+            }
             println!("  {} lines of code", stats.total_lines.to_string().yellow());
+            println!("  {:.1}% instrumentation coverage", stats.coverage);
 
             if verbose {
                 println!();
@@ -107,6 +634,60 @@ fn analyze_command(path: PathBuf, verbose: bool) {
                 println!("  Private functions: {}", stats.private_functions);
             }
 
+            if stats.crates.len() > 1 {
+                println!();
+                println!("{}", "📦 Per-crate Breakdown:".cyan().bold());
+                for krate in &stats.crates {
+                    println!(
+                        "  {} {} files, {} functions, {} instrumentable, {} instrumented, {:.1}% coverage",
+                        krate.name.bold(),
+                        krate.files,
+                        krate.total_functions,
+                        krate.instrumentable_functions.to_string().green(),
+                        krate.instrumented_functions.to_string().blue(),
+                        krate.coverage
+                    );
+                }
+            }
+
+            if breakdown {
+                println!();
+                println!("{}", "📁 Per-file Breakdown:".cyan().bold());
+                for file in &stats.files {
+                    let module = if stats.crates.len() > 1 {
+                        format!("{}: {}", file.crate_name, file.module)
+                    } else {
+                        file.module.clone()
+                    };
+                    println!("  {} {}", module.bold(), format!("({})", file.path).dimmed());
+                    println!(
+                        "    {} functions, {} instrumentable, {} instrumented, {:.1}% coverage",
+                        file.total_functions,
+                        file.instrumentable_functions.to_string().green(),
+                        file.instrumented_functions.to_string().blue(),
+                        file.coverage
+                    );
+                }
+            }
+
+            if let Some(limit) = recommend {
+                println!();
+                println!("{}", "🎯 Instrument These First:".cyan().bold());
+                if stats.recommendations.is_empty() {
+                    println!("  No high-value candidates found");
+                }
+                for (rank, rec) in stats.recommendations.iter().take(limit).enumerate() {
+                    println!(
+                        "  {}. {} {} {}",
+                        rank + 1,
+                        rec.function.yellow().bold(),
+                        format!("({}:{})", rec.file, rec.line).dimmed(),
+                        format!("score {}", rec.score).green()
+                    );
+                    println!("     {}", rec.reasons.join(", "));
+                }
+            }
+
             if stats.instrumentable_functions > 0 {
                 println!();
                 println!(
@@ -115,6 +696,11 @@ fn analyze_command(path: PathBuf, verbose: bool) {
                         .green()
                 );
             }
+
+            if let Some(min) = min_coverage {
+                println!();
+                check_coverage(&stats, min);
+            }
         }
         Err(e) => {
             eprintln!("{} {}", "❌ Error:".red().bold(), e);
@@ -123,109 +709,1050 @@ fn analyze_command(path: PathBuf, verbose: bool) {
     }
 }
 
-fn instrument_command(path: PathBuf, dry_run: bool, backup: bool) {
-    if dry_run {
-        println!(
-            "{}",
-            "🔍 Dry run - no files will be modified".yellow().bold()
+/// Fail the process when coverage is under `min` percent
+fn check_coverage(stats: &analyzer::AnalysisStats, min: f64) {
+    if stats.meets_coverage(min) {
+        eprintln!(
+            "{} {:.1}% coverage meets the {:.1}% minimum",
+            "✅ Check passed:".green().bold(),
+            stats.coverage,
+            min
         );
-        println!();
     } else {
-        println!("{}", "🔧 Instrumenting Rust code...".cyan().bold());
-        println!();
+        eprintln!(
+            "{} {:.1}% coverage is below the {:.1}% minimum ({} functions still uninstrumented)",
+            "❌ Check failed:".red().bold(),
+            stats.coverage,
+            min,
+            stats.instrumentable_functions
+        );
+        std::process::exit(1);
     }
+}
 
-    let instrumenter = Instrumenter::new(backup);
-
-    match instrumenter.instrument_file(&path, dry_run) {
-        Ok(result) => {
-            if dry_run {
-                println!("{}", "📝 Functions that would be instrumented:".green().bold());
-                println!();
-                for func in result.functions {
-                    println!("  • {} {}", "fn".blue(), func.yellow());
-                }
-                println!();
-                println!("  Total: {} functions", result.count.to_string().green());
-            } else {
-                println!("{}", "✅ Instrumentation complete!".green().bold());
-                println!();
-                println!(
-                    "  {} functions instrumented",
-                    result.count.to_string().green()
-                );
-
-                if backup {
-                    println!(
-                        "  Backup created: {}",
-                        result.backup_path.unwrap_or_default()
-                    );
-                }
+fn watch_command(path: PathBuf, interval_ms: u64, instrument_diff: bool) {
+    let analyzer = Analyzer::new();
+    let instrumenter = Instrumenter::new(false).with_filter(build_filter(&path, SelectionArgs::default()));
 
-                println!();
-                println!("{}",  "💡 Next steps:".cyan());
-                println!("  1. Add flowtrace-agent and flowtrace-derive to Cargo.toml");
-                println!("  2. Run your application");
-                println!("  3. Check flowtrace.jsonl for traces");
-            }
-        }
+    let mut stats = match analyzer.analyze_path(&path) {
+        Ok(stats) => stats,
         Err(e) => {
             eprintln!("{} {}", "❌ Error:".red().bold(), e);
             std::process::exit(1);
         }
-    }
-}
+    };
+    let mut snapshot = watch::Snapshot::take(&path);
 
-fn validate_command() {
-    println!("{}", "🔍 Validating FlowTrace setup...".cyan().bold());
-    println!();
+    println!(
+        "{} {} ({} files, {} functions, {:.1}% coverage)",
+        "👀 Watching".cyan().bold(),
+        path.display(),
+        stats.total_files,
+        stats.total_functions,
+        stats.coverage
+    );
+    println!("   Press Ctrl+C to stop");
 
-    let mut all_ok = true;
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(interval_ms));
 
-    // Check Cargo.toml
-    if std::path::Path::new("Cargo.toml").exists() {
-        println!("{} Cargo.toml found", "✅".green());
+        let current = watch::Snapshot::take(&path);
+        let changed = current.changed_since(&snapshot);
+        if changed.is_empty() {
+            continue;
+        }
+        snapshot = current;
 
-        // Try to read and parse Cargo.toml
-        if let Ok(content) = std::fs::read_to_string("Cargo.toml") {
-            if content.contains("flowtrace-agent") {
-                println!("{} flowtrace-agent dependency found", "✅".green());
-            } else {
-                println!("{} flowtrace-agent dependency missing", "⚠️".yellow());
-                println!("   Add: flowtrace-agent = \"1.0\"");
-                all_ok = false;
+        println!();
+        println!("{} {} file(s) changed", "🔄".cyan(), changed.len());
+        for file in &changed {
+            println!("   {}", file.display().to_string().dimmed());
+        }
+
+        // Files are often mid-edit; keep the last good stats on parse errors
+        match analyzer.analyze_path(&path) {
+            Ok(updated) => {
+                let deltas = watch::stats_delta(&stats, &updated);
+                if deltas.is_empty() {
+                    println!("   No change in instrumentation stats");
+                } else {
+                    println!("   {}", deltas.join(", ").yellow());
+                }
+                stats = updated;
             }
+            Err(e) => println!("   {} {}", "⚠️".yellow(), e),
+        }
 
-            if content.contains("flowtrace-derive") {
-                println!("{} flowtrace-derive dependency found", "✅".green());
-            } else {
-                println!("{} flowtrace-derive dependency missing", "⚠️".yellow());
-                println!("   Add: flowtrace-derive = \"1.0\"");
-                all_ok = false;
+        if instrument_diff {
+            for file in changed.iter().filter(|file| file.is_file()) {
+                if let Ok(result) = instrumenter.instrument_file(file, true) {
+                    for func in &result.functions {
+                        println!("   {} {} {}", "+ #[trace]".green(), "fn".blue(), func.yellow());
+                    }
+                }
             }
         }
-    } else {
-        println!("{} Cargo.toml not found", "❌".red());
-        all_ok = false;
     }
+}
 
-    // Check src directory
-    if std::path::Path::new("src").exists() {
-        println!("{} src/ directory found", "✅".green());
-    } else {
-        println!("{} src/ directory not found", "❌".red());
-        all_ok = false;
+/// Combine CLI selection flags with the `[instrument]` section of flowtrace.toml
+///
+/// Globs from both sources apply, boolean flags are enabled by either, and
+/// CLI `--fn-filter`/`--min-lines` values replace the configured ones. With
+/// `--profile`, that profile's settings are used in place of `[instrument]`.
+fn build_filter(path: &Path, selection: SelectionArgs) -> Filter {
+    let SelectionArgs {
+        mut include,
+        mut exclude,
+        fn_filter,
+        public_only,
+        async_only,
+        min_lines,
+        profile,
+    } = selection;
+
+    let filter = ProjectConfig::load(path).and_then(|config| {
+        let (instrument, modules, exclude_modules) = match profile {
+            Some(name) => {
+                let profile = config.profile(&name)?.clone();
+                (profile.instrument, profile.modules, profile.exclude_modules)
+            }
+            None => (config.instrument, Vec::new(), Vec::new()),
+        };
+        include.extend(instrument.include);
+        exclude.extend(instrument.exclude);
+        let fn_filter = fn_filter.or(instrument.fn_filter);
+
+        Ok(Filter::new(&include, &exclude, fn_filter.as_deref())?
+            .public_only(public_only || instrument.public_only)
+            .async_only(async_only || instrument.async_only)
+            .min_lines(min_lines.or(instrument.min_lines).unwrap_or(0))
+            .modules(modules, exclude_modules))
+    });
+
+    match filter {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
     }
+}
 
-    println!();
-    if all_ok {
-        println!("{}", "✅ FlowTrace setup looks good!".green().bold());
-    } else {
+fn instrument_command(path: PathBuf, dry_run: bool, backup: bool, add_deps: bool, filter: Filter) {
+    if dry_run {
         println!(
             "{}",
-            "⚠️ FlowTrace setup needs attention".yellow().bold()
+            "🔍 Dry run - no files will be modified".yellow().bold()
         );
-        std::process::exit(1);
+        println!();
+    } else {
+        println!("{}", "🔧 Instrumenting Rust code...".cyan().bold());
+        println!();
+    }
+
+    let instrumenter = Instrumenter::new(backup).with_filter(filter);
+
+    let crates = match instrumenter.instrument_path(&path, dry_run) {
+        Ok(crates) => crates,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let single_file = path.is_file();
+
+    if dry_run {
+        println!("{}", "📝 Functions that would be instrumented:".green().bold());
+    } else {
+        println!("{}", "✅ Instrumentation complete!".green().bold());
+    }
+
+    print_crate_results(&crates, single_file, dry_run);
+
+    let total: usize = crates.iter().map(|c| c.count()).sum();
+    let files: usize = crates.iter().map(|c| c.files_changed()).sum();
+
+    println!();
+    if dry_run {
+        println!("  Total: {} functions", total.to_string().green());
+    } else {
+        println!("  {} functions instrumented", total.to_string().green());
+    }
+    if !single_file {
+        println!(
+            "  {} files across {} crates",
+            files.to_string().yellow(),
+            crates.len().to_string().yellow()
+        );
+    }
+
+    if dry_run {
+        return;
+    }
+
+    let imports = crates
+        .iter()
+        .flat_map(|c| &c.files)
+        .filter(|(_, result)| result.import_changed)
+        .count();
+    if imports > 0 {
+        println!("  {} `use flowtrace_derive::trace;` imports added", imports.to_string().yellow());
+    }
+
+    let mut deps_wired = false;
+    if add_deps && total > 0 {
+        match manifest::find_manifest(&path) {
+            Some(manifest_path) => match manifest::add_dependencies(&manifest_path) {
+                Ok(added) => {
+                    deps_wired = true;
+                    for dependency in added {
+                        println!("  Added {} to {}", dependency.green(), manifest_path.display());
+                    }
+                }
+                Err(e) => eprintln!("  {} {}", "⚠️".yellow(), e),
+            },
+            None => eprintln!("  {} No Cargo.toml found for {}", "⚠️".yellow(), path.display()),
+        }
+    }
+
+    println!();
+    println!("{}",  "💡 Next steps:".cyan());
+    if deps_wired {
+        println!("  1. Run your application");
+        println!("  2. Check flowtrace.jsonl for traces");
+    } else {
+        println!("  1. Add flowtrace-agent and flowtrace-derive to Cargo.toml (or rerun with --add-deps)");
+        println!("  2. Run your application");
+        println!("  3. Check flowtrace.jsonl for traces");
+    }
+}
+
+/// Print per-crate, per-file results of an instrument/uninstrument run
+fn print_crate_results(crates: &[CrateResult], single_file: bool, dry_run: bool) {
+    for crate_result in crates {
+        if !single_file {
+            println!();
+            println!(
+                "  {} {}: {} functions in {} files",
+                "📦".cyan(),
+                crate_result.name.bold(),
+                crate_result.count().to_string().green(),
+                crate_result.files_changed().to_string().yellow()
+            );
+        }
+
+        for (file, result) in &crate_result.files {
+            if result.count == 0 {
+                continue;
+            }
+
+            if dry_run {
+                let indent = if single_file { "  " } else { "      " };
+                if !single_file {
+                    println!("    {}", file.display().to_string().dimmed());
+                }
+                for func in &result.functions {
+                    println!("{}• {} {}", indent, "fn".blue(), func.yellow());
+                }
+            } else if let Some(backup_path) = &result.backup_path {
+                println!("  Backup created: {}", backup_path);
+            }
+        }
+
+        for error in &crate_result.errors {
+            println!("  {} {}", "⚠️".yellow(), error);
+        }
+    }
+}
+
+fn uninstrument_command(
+    path: PathBuf,
+    dry_run: bool,
+    backup: bool,
+    remove_imports: bool,
+    filter: Filter,
+) {
+    if dry_run {
+        println!(
+            "{}",
+            "🔍 Dry run - no files will be modified".yellow().bold()
+        );
+    } else {
+        println!("{}", "🧹 Removing FlowTrace instrumentation...".cyan().bold());
+    }
+    println!();
+
+    let instrumenter = Instrumenter::new(backup).with_filter(filter);
+
+    let crates = match instrumenter.uninstrument_path(&path, dry_run, remove_imports) {
+        Ok(crates) => crates,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let single_file = path.is_file();
+
+    if dry_run {
+        println!("{}", "📝 Functions that would be uninstrumented:".green().bold());
+    } else {
+        println!("{}", "✅ Instrumentation removed!".green().bold());
+    }
+
+    print_crate_results(&crates, single_file, dry_run);
+
+    let total: usize = crates.iter().map(|c| c.count()).sum();
+    let imports = crates
+        .iter()
+        .flat_map(|c| &c.files)
+        .filter(|(_, result)| result.import_changed)
+        .count();
+
+    println!();
+    if dry_run {
+        println!("  Total: {} functions", total.to_string().green());
+    } else {
+        println!("  {} functions uninstrumented", total.to_string().green());
+    }
+    if remove_imports && imports > 0 {
+        println!("  {} trace imports removed", imports.to_string().yellow());
+    }
+}
+
+fn expand_command(file: &Path, function: &str) {
+    let expansions = match expand::expand_file(file, function) {
+        Ok(expansions) => expansions,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    for (index, expansion) in expansions.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!(
+            "{}",
+            format!("// #[trace] expansion of {} ({}:{})", expansion.name, file.display(), expansion.line).dimmed()
+        );
+        print!("{}", expansion.expanded);
+    }
+}
+
+fn init_command(path: PathBuf, with_main: bool) {
+    println!("{}", "🚀 Initializing FlowTrace...".cyan().bold());
+    println!();
+
+    let report = match scaffold::init_project(&path, with_main) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    match &report.config_path {
+        Some(config_path) => println!("{} Created {}", "✅".green(), config_path.display()),
+        None => println!("{} {} already exists", "✅".green(), config::CONFIG_FILE),
+    }
+
+    if report.dependencies_added.is_empty() {
+        println!("{} FlowTrace dependencies already present", "✅".green());
+    }
+    for dependency in &report.dependencies_added {
+        println!("{} Added {} to Cargo.toml", "✅".green(), dependency);
+    }
+
+    if with_main {
+        match &report.main_updated {
+            Some(main_path) => println!("{} Tracing started from fn main in {}", "✅".green(), main_path.display()),
+            None => println!("{} fn main already starts tracing", "✅".green()),
+        }
+    }
+
+    println!();
+    println!("{}", "💡 Next steps:".cyan());
+    println!("  1. Run 'flowctl-rs instrument src' to add #[trace] attributes");
+    if !with_main {
+        println!("  2. Call flowtrace_agent::start_tracing(Config::from_env()) in main (or rerun with --with-main)");
+    }
+}
+
+fn bench_overhead_command(path: PathBuf, iterations: u64) {
+    println!("{}", "⏱️  Measuring tracing overhead...".cyan().bold());
+    println!("   Building benchmark in release mode, this may take a while");
+    println!();
+
+    match bench::run(&path, iterations) {
+        Ok(report) => {
+            println!("{}", "📊 Overhead Results:".green().bold());
+            println!();
+            println!("  {} calls per variant", report.iterations.to_string().yellow());
+            println!("  {:>10.1} ns/call without #[trace]", report.untraced_per_call());
+            println!("  {:>10.1} ns/call with #[trace]", report.traced_per_call());
+            println!(
+                "  {} per traced call",
+                format!("{:>+10.1} ns", report.overhead_per_call()).yellow().bold()
+            );
+            println!();
+            println!(
+                "{}",
+                "💡 Overhead includes writing ENTER/EXIT events to the log file".dimmed()
+            );
+        }
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn grep_command(pattern: &str, files: &[PathBuf], regex: bool, ignore_case: bool, field: grep::Field) {
+    let matcher = match grep::Matcher::new(pattern, regex, ignore_case, field) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut total = 0;
+    for file in files {
+        let roots = match trace::read_events(file) {
+            Ok(events) => trace::build_calls(&events),
+            Err(e) => {
+                eprintln!("{} {}", "❌ Error:".red().bold(), e);
+                std::process::exit(1);
+            }
+        };
+
+        for tree in grep::search(&roots, &matcher) {
+            total += 1;
+            let root_start = tree[0].call.start;
+            println!(
+                "{} {}",
+                format!("{}:{}", file.display(), tree[0].call.line).dimmed(),
+                format!("[{}]", tree[0].call.thread).dimmed()
+            );
+
+            for line in &tree {
+                let call = line.call;
+                let duration = call
+                    .duration_micros
+                    .map(trace::format_micros)
+                    .unwrap_or_else(|| "unfinished".to_string());
+                let name = if call.failed() {
+                    call.name().red().bold()
+                } else if line.matched.is_some() {
+                    call.name().yellow().bold()
+                } else {
+                    call.name().normal()
+                };
+
+                println!(
+                    "  {}{} {} {}",
+                    "  ".repeat(line.depth),
+                    name,
+                    format!("({})", duration).cyan(),
+                    format!("+{}", trace::format_micros(call.start - root_start)).dimmed()
+                );
+                if let Some((label, value)) = line.matched {
+                    println!("  {}  {} {}", "  ".repeat(line.depth), format!("{}:", label).green(), truncate(value, 120));
+                }
+            }
+            println!();
+        }
+    }
+
+    if total == 0 {
+        println!("No calls matching '{}'", pattern);
+        std::process::exit(1);
+    }
+    println!("{} call tree(s) matched", total.to_string().yellow());
+}
+
+fn timeline_command(
+    file: &Path,
+    (from_ms, to_ms): (Option<f64>, Option<f64>),
+    call: Option<&str>,
+    width: usize,
+    depth: Option<usize>,
+) {
+    let roots = match trace::read_events(file) {
+        Ok(events) => trace::build_calls(&events),
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let Some(full) = timeline::Window::of(&roots) else {
+        println!("No calls found in {}", file.display());
+        return;
+    };
+    let base = match call {
+        Some(name) => match timeline::Window::of_call(&roots, name) {
+            Some(window) => window,
+            None => {
+                eprintln!("{} No top-level call named '{}'", "❌ Error:".red().bold(), name);
+                std::process::exit(1);
+            }
+        },
+        None => full,
+    };
+    let window = base.select(from_ms, to_ms);
+    let chart = timeline::render(&roots, window, width, depth);
+
+    println!(
+        "{} {} → {} ({})",
+        "⏱️  Timeline".cyan().bold(),
+        format!("+{}", trace::format_micros(window.start - full.start)).dimmed(),
+        format!("+{}", trace::format_micros(window.end - full.start)).dimmed(),
+        trace::format_micros(window.duration())
+    );
+
+    let label_width = chart
+        .lanes
+        .iter()
+        .flat_map(|lane| {
+            std::iter::once(lane.thread.chars().count())
+                .chain(lane.rows.iter().map(|row| row.depth * 2 + 2 + row.name.chars().count()))
+        })
+        .max()
+        .unwrap_or(0)
+        .min(40);
+
+    for lane in &chart.lanes {
+        println!();
+        println!(
+            "{:<label_width$} |{}|",
+            truncate(&lane.thread, label_width).bold(),
+            lane.activity.dimmed()
+        );
+        for row in &lane.rows {
+            let label = truncate(&format!("{}{}", "  ".repeat(row.depth + 1), row.name), label_width);
+            let bar = if row.failed { row.bar.red() } else { row.bar.green() };
+            let duration = row
+                .duration_micros
+                .map(trace::format_micros)
+                .unwrap_or_else(|| "unfinished".to_string());
+            println!("{:<label_width$} |{}| {}", label, bar, duration.cyan());
+        }
+        if let Some((from, to)) = lane.gaps.first() {
+            println!(
+                "{:<label_width$}  {} longest idle gap {} at +{}",
+                "",
+                "·".dimmed(),
+                trace::format_micros(to - from).yellow(),
+                trace::format_micros(from - full.start)
+            );
+        }
+    }
+
+    if chart.overlap.contains('▲') {
+        println!();
+        println!("{:<label_width$} |{}|", "overlap", chart.overlap.magenta());
+    }
+}
+
+fn hotpath_command(file: &Path, call: Option<&str>, top: usize) {
+    let roots = match trace::read_events(file) {
+        Ok(events) => trace::build_calls(&events),
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let root = match call {
+        Some(name) => roots
+            .iter()
+            .find(|root| root.function == name || root.name() == name),
+        None => roots.iter().max_by_key(|root| root.finish() - root.start),
+    };
+    let Some(root) = root else {
+        eprintln!("{} No matching top-level call in {}", "❌ Error:".red().bold(), file.display());
+        std::process::exit(1);
+    };
+
+    let total = (root.finish() - root.start).max(1);
+    let percent = |micros: i64| micros as f64 * 100.0 / total as f64;
+
+    println!(
+        "{} {} {} {}",
+        "🔥 Hot path for".cyan().bold(),
+        root.name().bold(),
+        format!("({})", trace::format_micros(total)).cyan(),
+        format!("{}:{} [{}]", file.display(), root.line, root.thread).dimmed()
+    );
+    println!();
+    println!("{}", "Critical path:".bold());
+    for step in hotpath::critical_path(root) {
+        println!(
+            "  {}{} {} {}",
+            "  ".repeat(step.depth),
+            step.call.name(),
+            trace::format_micros(step.contribution).yellow(),
+            format!("{:.1}%", percent(step.contribution)).dimmed()
+        );
+    }
+
+    println!();
+    println!("{}", "Self time:".bold());
+    let hottest = hotpath::by_self_time(root);
+    for (call, micros) in hottest.iter().take(top) {
+        println!(
+            "  {:>6.1}%  {:>10}  {}",
+            percent(*micros),
+            trace::format_micros(*micros),
+            call.name()
+        );
+    }
+
+    if let Some((call, micros)) = hottest.first() {
+        println!();
+        println!(
+            "{} {} spends {:.1}% of the request in its own code",
+            "💡".green(),
+            call.name().yellow().bold(),
+            percent(*micros)
+        );
+    }
+}
+
+fn diff_command(baseline: &Path, current: &Path, threshold: f64) {
+    let read = |file: &Path| match trace::read_events(file) {
+        Ok(events) => trace::build_calls(&events),
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+    let diffs = diff::diff(&read(baseline), &read(current));
+
+    println!(
+        "{} {} → {}",
+        "📊 Latency Diff:".green().bold(),
+        baseline.display().to_string().dimmed(),
+        current.display().to_string().dimmed()
+    );
+    println!();
+
+    let changed: Vec<&diff::FunctionDiff> = diffs
+        .iter()
+        .filter(|diff| diff.change_percent().abs() >= threshold)
+        .collect();
+    if changed.is_empty() {
+        println!("  No function's mean latency changed by {}% or more", threshold);
+        return;
+    }
+    for diff in &changed {
+        let change = format!("{:+.1}%", diff.change_percent());
+        let change = if diff.change_percent() > 0.0 { change.red() } else { change.green() };
+        let cause = match diff.code_changed() {
+            Some(true) => "code changed".yellow(),
+            Some(false) => "same code".cyan(),
+            None => "no fingerprint".dimmed(),
+        };
+        println!(
+            "  {:>8}  {:>10} → {:<10}  {} ({})",
+            change,
+            trace::format_micros(diff.baseline.mean_micros() as i64),
+            trace::format_micros(diff.current.mean_micros() as i64),
+            diff.name.bold(),
+            cause
+        );
+    }
+    println!();
+    println!(
+        "  {} {} of {} functions called in both runs",
+        "Changed:".dimmed(),
+        changed.len(),
+        diffs.len()
+    );
+}
+
+fn summary_command(file: &Path, format: SummaryFormat, slowest: usize, check: bool) {
+    let roots = match trace::read_events(file) {
+        Ok(events) => trace::build_calls(&events),
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+    let config = match ProjectConfig::load(file) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let summary = summary::summarize(&roots, &config.budgets, slowest);
+    let path = file.display().to_string();
+
+    match format {
+        SummaryFormat::Junit => print!("{}", summary::to_junit(&summary, &path)),
+        SummaryFormat::Github => {
+            let annotations = summary::to_annotations(&summary, &path);
+            println!("{}", serde_json::to_string_pretty(&annotations).unwrap_or_default());
+        }
+        SummaryFormat::Text => {
+            println!("{} {}", "📋 Trace Summary:".green().bold(), path.dimmed());
+            println!();
+            for agent in trace::read_agent_info(file).unwrap_or_default() {
+                let pid = agent.pid.map(|pid| format!(", pid {}", pid)).unwrap_or_default();
+                println!(
+                    "  {} agent {} (schema v{}{})",
+                    agent.language.cyan(),
+                    agent.agent_version,
+                    agent.schema_version,
+                    pid
+                );
+                if agent.schema_version > trace::SCHEMA_VERSION {
+                    println!(
+                        "  {} schema v{} is newer than this flowctl-rs understands (v{})",
+                        "⚠️".yellow(),
+                        agent.schema_version,
+                        trace::SCHEMA_VERSION
+                    );
+                }
+            }
+            println!("  {} calls", summary.total_calls.to_string().yellow());
+            if summary.sampled {
+                println!(
+                    "  {} calls estimated before sampling",
+                    format!("~{:.0}", summary.estimated_calls).yellow()
+                );
+            }
+            println!("  {} raised exceptions", summary.failures.len().to_string().red());
+            if summary.unfinished_calls > 0 {
+                println!("  {} never returned", summary.unfinished_calls.to_string().magenta());
+            }
+            println!(
+                "  {} functions over budget",
+                summary.violations.len().to_string().yellow()
+            );
+
+            if !summary.slowest.is_empty() {
+                println!();
+                println!("{}", "🐢 Slowest Calls:".cyan().bold());
+                for slow in &summary.slowest {
+                    println!(
+                        "  {:>10}  {} {}",
+                        trace::format_micros(slow.duration_micros),
+                        slow.name,
+                        format!("(line {})", slow.line).dimmed()
+                    );
+                }
+            }
+
+            if !summary.violations.is_empty() {
+                println!();
+                println!("{}", "⏰ Budget Violations:".yellow().bold());
+                for violation in &summary.violations {
+                    println!(
+                        "  {} {} call(s) over {}, worst {} {}",
+                        violation.name.bold(),
+                        violation.count,
+                        trace::format_micros(violation.budget_micros),
+                        trace::format_micros(violation.worst_micros).red(),
+                        format!("(line {})", violation.line).dimmed()
+                    );
+                }
+            }
+
+            if !summary.failures.is_empty() {
+                println!();
+                println!("{}", "💥 Exceptions:".red().bold());
+                for failure in &summary.failures {
+                    println!(
+                        "  {} {} {}",
+                        failure.name.bold(),
+                        truncate(&failure.exception, 120),
+                        format!("(line {})", failure.line).dimmed()
+                    );
+                }
+            }
+        }
+    }
+
+    if check && summary.has_problems() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "live")]
+fn tail_command(url: &str, modules: &[String], level: Option<&str>) {
+    let url = tail::stream_url(url, modules, level);
+    let mut socket = match tungstenite::connect(url.as_str()) {
+        Ok((socket, _)) => socket,
+        Err(e) => {
+            eprintln!("{} Failed to connect to {}: {}", "❌ Error:".red().bold(), url, e);
+            std::process::exit(1);
+        }
+    };
+
+    loop {
+        match socket.read() {
+            Ok(tungstenite::Message::Text(text)) => {
+                if let Some(line) = tail::format_line(&text) {
+                    println!("{}", line);
+                }
+            }
+            Ok(tungstenite::Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(e) => {
+                eprintln!("{} {}", "❌ Error:".red().bold(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    println!("{}", "Stream closed".dimmed());
+}
+
+#[cfg(feature = "tui")]
+fn tui_command(files: &[PathBuf]) {
+    let mut roots = Vec::new();
+    for file in files {
+        match trace::read_events(file) {
+            Ok(events) => roots.extend(trace::build_calls(&events)),
+            Err(e) => {
+                eprintln!("{} {}", "❌ Error:".red().bold(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    roots.sort_by_key(|call| call.start);
+
+    if let Err(e) = tui::run(roots) {
+        eprintln!("{} {}", "❌ Error:".red().bold(), e);
+        std::process::exit(1);
+    }
+}
+
+fn prune_command(input: &Path, output: &Path, options: &prune::PruneOptions) {
+    if input == output {
+        eprintln!("{} Output must differ from the input file", "❌ Error:".red().bold());
+        std::process::exit(1);
+    }
+
+    match prune::prune(input, output, options) {
+        Ok(report) => {
+            println!(
+                "{} Kept {} of {} lines ({} bytes) in {}",
+                "✂️ ".green(),
+                report.lines_kept.to_string().yellow(),
+                report.lines_read,
+                report.bytes_written,
+                output.display()
+            );
+        }
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn correlate_command(trace_file: &Path, log_file: &Path, trace_id: Option<&str>) {
+    let result = trace::read_events(trace_file).and_then(|events| {
+        let log = std::fs::File::open(log_file).map_err(|e| format!("Failed to open {}: {}", log_file.display(), e))?;
+        correlate::correlate(trace::build_calls(&events), std::io::BufReader::new(log))
+    });
+    let traces = match result {
+        Ok(traces) => traces,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let selected: Vec<_> = traces
+        .iter()
+        .filter(|trace| match trace_id {
+            Some(id) => trace.trace_id == id,
+            None => !trace.logs.is_empty(),
+        })
+        .collect();
+    if selected.is_empty() {
+        match trace_id {
+            Some(id) => println!("Trace {} not found in {}", id, trace_file.display()),
+            None => println!("No log lines in {} mention a trace of {}", log_file.display(), trace_file.display()),
+        }
+        return;
+    }
+
+    for trace in selected {
+        println!(
+            "{} {} ({} calls, {} log lines)",
+            "🔗 Trace".cyan().bold(),
+            trace.trace_id.yellow(),
+            trace.call_count(),
+            trace.logs.len()
+        );
+        for row in trace.render() {
+            println!("{}", row);
+        }
+        println!();
+    }
+}
+
+fn convert_command(input: &Path, to: ConvertFormat, output: Option<&Path>, push: Option<&str>) {
+    if output == Some(input) {
+        eprintln!("{} Output must differ from the input file", "❌ Error:".red().bold());
+        std::process::exit(1);
+    }
+
+    let result = match to {
+        ConvertFormat::Otlp => otlp::convert(input, output, push),
+    };
+    match result {
+        Ok(report) => {
+            if let Some(output) = output {
+                println!(
+                    "{} Wrote {} spans of {} traces to {}",
+                    "📦".green(),
+                    report.spans.to_string().yellow(),
+                    report.traces,
+                    output.display()
+                );
+            }
+            if let Some(endpoint) = push {
+                println!(
+                    "{} Pushed {} spans of {} traces to {} in {} requests",
+                    "📤".green(),
+                    report.spans.to_string().yellow(),
+                    report.traces,
+                    endpoint,
+                    report.requests
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("{} {}", "❌ Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        value.to_string()
+    } else {
+        format!("{}…", value.chars().take(max).collect::<String>())
+    }
+}
+
+fn validate_command() {
+    println!("{}", "🔍 Validating FlowTrace setup...".cyan().bold());
+    println!();
+
+    let mut all_ok = true;
+
+    // Check Cargo.toml
+    if std::path::Path::new("Cargo.toml").exists() {
+        println!("{} Cargo.toml found", "✅".green());
+
+        // Try to read and parse Cargo.toml
+        if let Ok(content) = std::fs::read_to_string("Cargo.toml") {
+            if content.contains("flowtrace-agent") {
+                println!("{} flowtrace-agent dependency found", "✅".green());
+            } else {
+                println!("{} flowtrace-agent dependency missing", "⚠️".yellow());
+                println!("   Add: flowtrace-agent = \"1.0\"");
+                all_ok = false;
+            }
+
+            if content.contains("flowtrace-derive") {
+                println!("{} flowtrace-derive dependency found", "✅".green());
+            } else {
+                println!("{} flowtrace-derive dependency missing", "⚠️".yellow());
+                println!("   Add: flowtrace-derive = \"1.0\"");
+                all_ok = false;
+            }
+        }
+    } else {
+        println!("{} Cargo.toml not found", "❌".red());
+        all_ok = false;
+    }
+
+    // Check src directory
+    if std::path::Path::new("src").exists() {
+        println!("{} src/ directory found", "✅".green());
+    } else {
+        println!("{} src/ directory not found", "❌".red());
+        all_ok = false;
+    }
+
+    println!();
+    if all_ok {
+        println!("{}", "✅ FlowTrace setup looks good!".green().bold());
+    } else {
+        println!(
+            "{}",
+            "⚠️ FlowTrace setup needs attention".yellow().bold()
+        );
+        std::process::exit(1);
+    }
+}
+
+fn doctor_command(path: PathBuf, no_build: bool) {
+    println!("{}", "🩺 Running FlowTrace diagnostics...".cyan().bold());
+
+    let mut sections = vec![
+        ("Manifest", doctor::check_manifest(&path)),
+        ("Sources", doctor::check_sources(&path)),
+    ];
+    if !no_build {
+        println!("   Building a #[trace] smoke test, this may take a while");
+        sections.push(("Runtime", doctor::check_runtime(&path)));
+    }
+
+    let mut failures = 0;
+    let mut warnings = 0;
+
+    for (title, checks) in &sections {
+        println!();
+        println!("{}", title.bold());
+        for check in checks {
+            let icon = match check.status {
+                doctor::Status::Ok => "✅".green(),
+                doctor::Status::Warn => {
+                    warnings += 1;
+                    "⚠️".yellow()
+                }
+                doctor::Status::Fail => {
+                    failures += 1;
+                    "❌".red()
+                }
+            };
+            println!("{} {}", icon, check.message);
+            if let Some(fix) = &check.fix {
+                println!("   {} {}", "Fix:".cyan(), fix);
+            }
+        }
+    }
+
+    println!();
+    if failures > 0 {
+        println!(
+            "{}",
+            format!("❌ {} problem(s), {} warning(s)", failures, warnings).red().bold()
+        );
+        std::process::exit(1);
+    } else if warnings > 0 {
+        println!(
+            "{}",
+            format!("⚠️ Setup works with {} warning(s)", warnings).yellow().bold()
+        );
+    } else {
+        println!("{}", "✅ FlowTrace setup is healthy!".green().bold());
     }
 }
 
@@ -234,7 +1761,21 @@ fn version_command() {
     println!("FlowTrace CLI tool for Rust");
     println!();
     println!("Features:");
+    println!("  • Initialize projects for tracing");
     println!("  • Analyze Rust projects");
+    println!("  • Watch coverage while editing");
+    println!("  • Measure tracing overhead");
+    println!("  • Diagnose setup problems");
+    println!("  • Search trace payloads");
+    println!("  • Render per-thread timelines");
+    println!("  • Find critical paths in traces");
+    println!("  • Summarize runs for CI (JUnit, GitHub)");
+    println!("  • Prune large trace files");
+    #[cfg(feature = "tui")]
+    println!("  • Browse traces in the terminal");
     println!("  • Instrument code with #[trace]");
+    println!("  • Remove instrumentation with uninstrument");
+    println!("  • Preview #[trace] expansions");
+    println!("  • Follow live events from a running agent");
     println!("  • Validate FlowTrace setup");
 }
@@ -0,0 +1,127 @@
+//! Cargo.toml helpers for wiring FlowTrace dependencies
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Dependencies required by code instrumented with `#[trace]`
+pub const FLOWTRACE_DEPENDENCIES: [(&str, &str); 2] =
+    [("flowtrace-agent", "1.0"), ("flowtrace-derive", "1.0")];
+
+/// Find the closest Cargo.toml at or above `path`
+pub fn find_manifest(path: &Path) -> Option<PathBuf> {
+    let start = path.canonicalize().ok()?;
+    let mut dir = if start.is_file() { start.parent()? } else { start.as_path() };
+
+    loop {
+        let manifest = dir.join("Cargo.toml");
+        if manifest.is_file() {
+            return Some(manifest);
+        }
+        dir = dir.parent()?;
+    }
+}
+
+/// Add missing FlowTrace dependencies to a Cargo.toml
+///
+/// Edits the file textually so existing formatting and comments are kept.
+/// Returns the names of the dependencies that were added.
+pub fn add_dependencies(manifest: &Path) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(manifest)
+        .map_err(|e| format!("Failed to read {}: {}", manifest.display(), e))?;
+
+    let (updated, added) = with_dependencies(&content);
+
+    if !added.is_empty() {
+        fs::write(manifest, updated)
+            .map_err(|e| format!("Failed to write {}: {}", manifest.display(), e))?;
+    }
+
+    Ok(added)
+}
+
+/// Return `content` with the missing FlowTrace dependencies inserted
+fn with_dependencies(content: &str) -> (String, Vec<String>) {
+    let missing: Vec<(&str, &str)> = FLOWTRACE_DEPENDENCIES
+        .iter()
+        .filter(|(name, _)| !has_dependency(content, name))
+        .copied()
+        .collect();
+
+    if missing.is_empty() {
+        return (content.to_string(), Vec::new());
+    }
+
+    let lines: String = missing
+        .iter()
+        .map(|(name, version)| format!("{} = \"{}\"\n", name, version))
+        .collect();
+    let added = missing.iter().map(|(name, _)| name.to_string()).collect();
+
+    let mut output = String::with_capacity(content.len() + lines.len());
+    let mut inserted = false;
+
+    for line in content.split_inclusive('\n') {
+        output.push_str(line);
+        if !inserted && line.trim() == "[dependencies]" {
+            if !line.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str(&lines);
+            inserted = true;
+        }
+    }
+
+    if !inserted {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str("\n[dependencies]\n");
+        output.push_str(&lines);
+    }
+
+    (output, added)
+}
+
+/// Whether a dependency key is declared anywhere in the manifest
+pub fn has_dependency(content: &str, name: &str) -> bool {
+    content.lines().any(|line| {
+        let line = line.trim();
+        line.strip_prefix(name)
+            .map(|rest| rest.trim_start().starts_with('=') || rest.starts_with(']'))
+            .unwrap_or(false)
+            || line == format!("[dependencies.{}]", name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_dependencies_inserts_under_section() {
+        let manifest = "[package]\nname = \"app\"\n\n[dependencies]\nserde = \"1.0\"\n";
+
+        let (updated, added) = with_dependencies(manifest);
+        assert_eq!(added, vec!["flowtrace-agent", "flowtrace-derive"]);
+        assert_eq!(
+            updated,
+            "[package]\nname = \"app\"\n\n[dependencies]\nflowtrace-agent = \"1.0\"\nflowtrace-derive = \"1.0\"\nserde = \"1.0\"\n"
+        );
+    }
+
+    #[test]
+    fn test_with_dependencies_keeps_existing() {
+        let manifest = "[dependencies]\nflowtrace-agent = { path = \"../agent\" }\n";
+
+        let (updated, added) = with_dependencies(manifest);
+        assert_eq!(added, vec!["flowtrace-derive"]);
+        assert!(updated.contains("flowtrace-agent = { path = \"../agent\" }"));
+        assert!(updated.contains("flowtrace-derive = \"1.0\""));
+    }
+
+    #[test]
+    fn test_with_dependencies_adds_section() {
+        let (updated, _) = with_dependencies("[package]\nname = \"app\"");
+        assert!(updated.ends_with("\n\n[dependencies]\nflowtrace-agent = \"1.0\"\nflowtrace-derive = \"1.0\"\n"));
+    }
+}
@@ -0,0 +1,344 @@
+//! OTLP export for `flowctl-rs convert`
+//!
+//! Call trees rebuilt from a recorded trace become OTLP/JSON spans (an
+//! `ExportTraceServiceRequest`), written to a file or POSTed to the OTLP/HTTP
+//! endpoint of a collector such as Jaeger or Tempo. Span IDs are derived from
+//! the log, so pushing the same file twice yields the same spans.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::trace::{self, Call};
+
+/// Spans sent per request, well below the default body limits of common collectors
+pub const SPANS_PER_REQUEST: usize = 1000;
+
+/// Path OTLP/HTTP collectors accept traces on
+const TRACES_PATH: &str = "/v1/traces";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `STATUS_CODE_ERROR` of an OTLP span status
+const STATUS_ERROR: u8 = 2;
+
+/// `SPAN_KIND_INTERNAL`
+const KIND_INTERNAL: u8 = 1;
+
+/// What a conversion wrote and sent
+#[derive(Debug, Default)]
+pub struct ConvertReport {
+    pub traces: usize,
+    pub spans: usize,
+    /// Requests accepted by the `--push` endpoint
+    pub requests: usize,
+}
+
+/// Convert `input` to OTLP/JSON, writing it to `output` and/or pushing it to `endpoint`
+pub fn convert(input: &Path, output: Option<&Path>, endpoint: Option<&str>) -> Result<ConvertReport, String> {
+    let events = trace::read_events(input)?;
+    let agents = trace::read_agent_info(input)?;
+    let roots = trace::build_calls(&events);
+    let seed = events.first().map_or(0, |event| event.timestamp);
+    let spans = spans(&roots, seed);
+    let resource = resource(agents.first());
+
+    let traces: HashSet<&str> = spans.iter().filter_map(|span| span["traceId"].as_str()).collect();
+    let mut report = ConvertReport {
+        traces: traces.len(),
+        spans: spans.len(),
+        requests: 0,
+    };
+
+    if let Some(output) = output {
+        let body = request(&resource, &spans).to_string();
+        std::fs::write(output, body).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    }
+    if let Some(endpoint) = endpoint {
+        for batch in spans.chunks(SPANS_PER_REQUEST) {
+            push(endpoint, &request(&resource, batch).to_string())?;
+            report.requests += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Resource attributes of the recording agent, with `service.name` always set
+fn resource(agent: Option<&trace::AgentInfo>) -> BTreeMap<String, String> {
+    let mut attributes = agent.map(|agent| agent.resource.clone()).unwrap_or_default();
+    attributes
+        .entry("service.name".to_string())
+        .or_insert_with(|| "unknown_service".to_string());
+    if let Some(agent) = agent {
+        attributes.insert("telemetry.sdk.language".to_string(), agent.language.clone());
+    }
+    attributes
+}
+
+/// An `ExportTraceServiceRequest` holding `spans`
+pub fn request(resource: &BTreeMap<String, String>, spans: &[Value]) -> Value {
+    let attributes: Vec<Value> = resource.iter().map(|(key, value)| string_attribute(key, value)).collect();
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": attributes },
+            "scopeSpans": [{
+                "scope": { "name": "flowtrace" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// OTLP spans of the calls in `roots` and their descendants
+///
+/// `seed` (the first timestamp of the log) keeps IDs generated for one log
+/// apart from those of another.
+pub fn spans(roots: &[Call], seed: i64) -> Vec<Value> {
+    let mut spans = Vec::new();
+    for root in roots {
+        let trace_id = match root.trace_id.as_deref() {
+            Some(id) => otlp_trace_id(id),
+            None => format!("{:016x}{:016x}", seed as u64, root.line as u64),
+        };
+        push_span(root, &trace_id, None, seed, &mut spans);
+    }
+    spans
+}
+
+fn push_span(call: &Call, trace_id: &str, parent: Option<&str>, seed: i64, spans: &mut Vec<Value>) {
+    let span_id = format!("{:016x}", fnv1a(&[&seed.to_le_bytes(), &(call.line as u64).to_le_bytes()]).max(1));
+
+    let mut attributes = vec![
+        string_attribute("code.namespace", &call.module),
+        string_attribute("code.function", &call.function),
+        string_attribute("thread.name", &call.thread),
+    ];
+    for (key, value) in [
+        ("flowtrace.args", &call.args),
+        ("flowtrace.result", &call.result),
+        ("flowtrace.fingerprint", &call.fingerprint),
+    ] {
+        if let Some(value) = value {
+            attributes.push(string_attribute(key, value));
+        }
+    }
+    if let Some(rate) = call.sample_rate {
+        attributes.push(json!({ "key": "flowtrace.sample_rate", "value": { "doubleValue": rate } }));
+    }
+    if call.end.is_none() {
+        attributes.push(json!({ "key": "flowtrace.unfinished", "value": { "boolValue": true } }));
+    }
+
+    let end_nanos = (call.finish() * 1000).to_string();
+    let mut span = json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "name": call.name(),
+        "kind": KIND_INTERNAL,
+        "startTimeUnixNano": (call.start * 1000).to_string(),
+        "endTimeUnixNano": end_nanos,
+        "attributes": attributes,
+    });
+    if let Some(parent) = parent {
+        span["parentSpanId"] = json!(parent);
+    }
+    if let Some(exception) = &call.exception {
+        span["status"] = json!({ "code": STATUS_ERROR, "message": exception });
+        span["events"] = json!([{
+            "name": "exception",
+            "timeUnixNano": end_nanos,
+            "attributes": [string_attribute("exception.message", exception)],
+        }]);
+    }
+    spans.push(span);
+
+    for child in &call.children {
+        push_span(child, trace_id, Some(&span_id), seed, spans);
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// A 32-hex-digit trace ID: a W3C-style ID as is, anything else hashed
+fn otlp_trace_id(id: &str) -> String {
+    let hex: String = id.chars().filter(|c| *c != '-').collect();
+    if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return hex.to_ascii_lowercase();
+    }
+    let high = fnv1a(&[b"high", id.as_bytes()]);
+    let low = fnv1a(&[b"low", id.as_bytes()]);
+    format!("{:016x}{:016x}", high, low)
+}
+
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// `host:port` and request path of an `http://` OTLP endpoint
+///
+/// An endpoint without a path gets `/v1/traces`.
+fn parse_endpoint(endpoint: &str) -> Result<(String, String), String> {
+    let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+        if endpoint.starts_with("https://") {
+            format!("{}: https is not supported, push to the collector's plain http port", endpoint)
+        } else {
+            format!("{}: expected an http:// URL", endpoint)
+        }
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) if rest[index..].len() > 1 => (&rest[..index], rest[index..].to_string()),
+        Some(index) => (&rest[..index], TRACES_PATH.to_string()),
+        None => (rest, TRACES_PATH.to_string()),
+    };
+    if authority.is_empty() {
+        return Err(format!("{}: missing host", endpoint));
+    }
+    let authority = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Ok((authority, path))
+}
+
+/// POST one OTLP/JSON request body to `endpoint`
+pub fn push(endpoint: &str, body: &str) -> Result<(), String> {
+    let (authority, path) = parse_endpoint(endpoint)?;
+    let error = |e: std::io::Error| format!("Failed to push to {}: {}", endpoint, e);
+
+    let addr = authority
+        .to_socket_addrs()
+        .map_err(error)?
+        .next()
+        .ok_or_else(|| format!("Failed to push to {}: no address for {}", endpoint, authority))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(error)?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(error)?;
+    stream.set_write_timeout(Some(TIMEOUT)).map_err(error)?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    )
+    .map_err(error)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(error)?;
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        _ => {
+            let detail = response.split("\r\n\r\n").nth(1).unwrap_or_default().trim();
+            let detail: String = detail.chars().take(200).collect();
+            Err(format!("{} rejected the traces: {} {}", endpoint, status_line, detail).trim_end().to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn call(function: &str, line: usize, start: i64, end: i64, children: Vec<Call>) -> Call {
+        Call {
+            module: "shop".to_string(),
+            function: function.to_string(),
+            thread: "main".to_string(),
+            start,
+            end: Some(end),
+            line,
+            children,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let mut failed = call("charge", 2, 20, 40, vec![]);
+        failed.exception = Some("declined".to_string());
+        let mut root = call("checkout", 1, 10, 50, vec![failed]);
+        root.trace_id = Some("4BF92F35-77B3-4DA6-A3CE-929D0E0E4736".to_string());
+        let mut other = call("health", 3, 60, 70, vec![]);
+        other.end = None;
+
+        let spans = spans(&[root, other], 7);
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0]["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(spans[0]["name"], "shop::checkout");
+        assert_eq!(spans[0]["startTimeUnixNano"], "10000");
+        assert_eq!(spans[0]["endTimeUnixNano"], "50000");
+        assert!(spans[0]["parentSpanId"].is_null());
+
+        assert_eq!(spans[1]["traceId"], spans[0]["traceId"]);
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(spans[1]["status"]["code"], STATUS_ERROR);
+        assert_eq!(spans[1]["status"]["message"], "declined");
+
+        assert_eq!(spans[2]["traceId"], format!("{:016x}{:016x}", 7, 3));
+        assert_eq!(spans[2]["attributes"][3]["key"], "flowtrace.unfinished");
+        assert_ne!(spans[2]["spanId"], spans[0]["spanId"]);
+        assert_eq!(otlp_trace_id("order-42").len(), 32);
+        assert_eq!(otlp_trace_id("order-42"), otlp_trace_id("order-42"));
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            parse_endpoint("http://jaeger:4318").unwrap(),
+            ("jaeger:4318".to_string(), TRACES_PATH.to_string())
+        );
+        assert_eq!(
+            parse_endpoint("http://tempo/otlp/v1/traces").unwrap(),
+            ("tempo:80".to_string(), "/otlp/v1/traces".to_string())
+        );
+        assert!(parse_endpoint("https://tempo:4318").unwrap_err().contains("https"));
+        assert!(parse_endpoint("tempo:4318").is_err());
+    }
+
+    #[test]
+    fn test_push() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in ["HTTP/1.1 200 OK", "HTTP/1.1 400 Bad Request"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !String::from_utf8_lossy(&request).ends_with('}') {
+                    let read = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                write!(stream, "{}\r\nContent-Length: 9\r\n\r\nbad spans", reply).unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let body = request(&resource(None), &[]).to_string();
+        push(&endpoint, &body).unwrap();
+        let error = push(&endpoint, &body).unwrap_err();
+        assert!(error.contains("400 Bad Request") && error.ends_with("bad spans"), "{}", error);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        assert!(requests[0].ends_with(&body));
+        assert!(body.contains(r#""key":"service.name""#));
+    }
+}
@@ -0,0 +1,133 @@
+//! Throwaway crates compiled against a project's FlowTrace dependencies
+//!
+//! `bench-overhead` and `doctor` generate a small program under the
+//! project's `target/` directory, build it with the same `flowtrace-agent`
+//! and `flowtrace-derive` specs the project declares and run it.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::manifest::FLOWTRACE_DEPENDENCIES;
+
+/// A generated crate living in `<project>/target/<name>`
+pub struct Probe {
+    pub dir: PathBuf,
+}
+
+impl Probe {
+    /// Write the probe's manifest and `src/main.rs`
+    pub fn create(project: &Path, name: &str, main: &str) -> Result<Self, String> {
+        let manifest_path = project.join("Cargo.toml");
+        let content = fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+
+        let project_dir = project
+            .canonicalize()
+            .map_err(|e| format!("Failed to resolve {}: {}", project.display(), e))?;
+        let dir = project_dir.join("target").join(name);
+        fs::create_dir_all(dir.join("src"))
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+        fs::write(dir.join("Cargo.toml"), probe_manifest(name, &content, &project_dir)?)
+            .map_err(|e| format!("Failed to write {} manifest: {}", name, e))?;
+        fs::write(dir.join("src").join("main.rs"), main)
+            .map_err(|e| format!("Failed to write {} source: {}", name, e))?;
+
+        Ok(Self { dir })
+    }
+
+    /// Build and run the probe in release mode, returning its stdout
+    pub fn run<I, S>(&self, args: I) -> Result<String, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let output = Command::new("cargo")
+            .args(["run", "--release", "--quiet", "--manifest-path"])
+            .arg(self.dir.join("Cargo.toml"))
+            .arg("--")
+            .args(args)
+            .output()
+            .map_err(|e| format!("Failed to run cargo: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "Probe failed to build or run:\n{}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+/// Build the probe manifest, reusing the project's FlowTrace dependency
+/// specs so the probe exercises what the project ships
+fn probe_manifest(name: &str, project_manifest: &str, project_dir: &Path) -> Result<String, String> {
+    let manifest: toml::Table = project_manifest
+        .parse()
+        .map_err(|e| format!("Failed to parse Cargo.toml: {}", e))?;
+
+    let mut dependencies = toml::Table::new();
+    for (dependency, version) in FLOWTRACE_DEPENDENCIES {
+        let spec = ["dependencies", "dev-dependencies"]
+            .iter()
+            .find_map(|section| manifest.get(*section)?.get(dependency).cloned())
+            .map(|spec| absolute_spec(spec, project_dir))
+            .unwrap_or_else(|| toml::Value::String(version.to_string()));
+        dependencies.insert(dependency.to_string(), spec);
+    }
+
+    let mut package = toml::Table::new();
+    package.insert("name".into(), name.into());
+    package.insert("version".into(), "0.0.0".into());
+    package.insert("edition".into(), "2021".into());
+    package.insert("publish".into(), false.into());
+
+    let mut probe = toml::Table::new();
+    probe.insert("package".into(), package.into());
+    probe.insert("dependencies".into(), dependencies.into());
+    // Keep the generated crate out of any enclosing workspace
+    probe.insert("workspace".into(), toml::Table::new().into());
+
+    toml::to_string(&probe).map_err(|e| format!("Failed to write {} manifest: {}", name, e))
+}
+
+/// Resolve a relative `path = ...` against the project and drop `optional`
+fn absolute_spec(spec: toml::Value, project_dir: &Path) -> toml::Value {
+    match spec {
+        toml::Value::Table(mut table) => {
+            table.remove("optional");
+            if let Some(path) = table.get("path").and_then(|p| p.as_str()) {
+                let absolute: PathBuf = project_dir.join(path);
+                table.insert("path".into(), absolute.display().to_string().into());
+            }
+            toml::Value::Table(table)
+        }
+        spec => spec,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_manifest_reuses_project_specs() {
+        let project = "[package]\nname = \"app\"\n\n[dependencies]\nflowtrace-agent = { path = \"../agent\", optional = true }\n";
+
+        let manifest: toml::Table = probe_manifest("flowtrace-bench", project, Path::new("/work/app"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let dependencies = manifest["dependencies"].as_table().unwrap();
+
+        assert_eq!(manifest["package"]["name"].as_str(), Some("flowtrace-bench"));
+        assert_eq!(dependencies["flowtrace-agent"]["path"].as_str(), Some("/work/app/../agent"));
+        assert!(dependencies["flowtrace-agent"].get("optional").is_none());
+        assert_eq!(dependencies["flowtrace-derive"].as_str(), Some("1.0"));
+        assert!(manifest.contains_key("workspace"));
+    }
+}
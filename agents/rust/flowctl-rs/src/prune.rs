@@ -0,0 +1,332 @@
+//! Trace file slicing for `flowctl-rs prune`
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::trace::{self, Call, Event};
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+/// A `--from`/`--to` bound before it is resolved against the trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSpec {
+    /// Microseconds since the Unix epoch
+    Absolute(i64),
+    /// Microseconds after midnight UTC on the day of the first event
+    TimeOfDay(i64),
+}
+
+impl TimeSpec {
+    pub fn resolve(self, first_timestamp: i64) -> i64 {
+        match self {
+            TimeSpec::Absolute(micros) => micros,
+            TimeSpec::TimeOfDay(micros) => first_timestamp.div_euclid(MICROS_PER_DAY) * MICROS_PER_DAY + micros,
+        }
+    }
+}
+
+/// What to keep from the input trace
+#[derive(Debug, Clone, Default)]
+pub struct PruneOptions {
+    pub from: Option<TimeSpec>,
+    pub to: Option<TimeSpec>,
+    pub trace_id: Option<String>,
+    /// Keep events with this `#[trace(target = "...")]`
+    pub target: Option<String>,
+    /// Keep whole top-level call trees that contain a call to this function
+    pub function: Option<String>,
+    /// Keep only the most recent events fitting in this many bytes
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PruneReport {
+    pub lines_read: usize,
+    pub lines_kept: usize,
+    pub bytes_written: u64,
+}
+
+/// Copy the lines of `input` selected by `options` into `output`
+///
+/// Lines are copied verbatim, so the result is a valid trace file that other
+/// tools read exactly like the original. Lines that are not trace events are
+/// always kept.
+pub fn prune(input: &Path, output: &Path, options: &PruneOptions) -> Result<PruneReport, String> {
+    let function_lines = match &options.function {
+        Some(function) => Some(tree_lines(&trace::build_calls(&trace::read_events(input)?), function)),
+        None => None,
+    };
+
+    // First pass: decide which lines survive the filters and remember their sizes
+    let mut kept: Vec<(usize, u64)> = Vec::new();
+    let mut first_timestamp = None;
+    let mut lines_read = 0;
+
+    for (index, line) in lines(input)?.enumerate() {
+        let line = line?;
+        let number = index + 1;
+        lines_read += 1;
+
+        let keep = match serde_json::from_str::<Event>(&line) {
+            Ok(event) => {
+                let first = *first_timestamp.get_or_insert(event.timestamp);
+                keeps(&event, number, first, options, function_lines.as_ref())
+            }
+            Err(_) => true,
+        };
+        if keep {
+            kept.push((number, line.len() as u64 + 1));
+        }
+    }
+
+    if let Some(max_bytes) = options.max_bytes {
+        let mut total = 0;
+        let newest = kept
+            .iter()
+            .rev()
+            .take_while(|(_, size)| {
+                total += size;
+                total <= max_bytes
+            })
+            .count();
+        kept.drain(..kept.len() - newest);
+    }
+
+    // Second pass: copy the surviving lines
+    let file = File::create(output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    let mut report = PruneReport {
+        lines_read,
+        ..Default::default()
+    };
+    let mut wanted = kept.iter().map(|(number, _)| *number).peekable();
+
+    for (index, line) in lines(input)?.enumerate() {
+        let Some(&next) = wanted.peek() else { break };
+        let line = line?;
+        if index + 1 == next {
+            wanted.next();
+            writeln!(writer, "{}", line).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+            report.lines_kept += 1;
+            report.bytes_written += line.len() as u64 + 1;
+        }
+    }
+
+    writer
+        .flush()
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    Ok(report)
+}
+
+fn lines(path: &Path) -> Result<impl Iterator<Item = Result<String, String>> + '_, String> {
+    Ok(trace::open_log(path)?
+        .lines()
+        .map(move |line| line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))))
+}
+
+fn keeps(
+    event: &Event,
+    line: usize,
+    first_timestamp: i64,
+    options: &PruneOptions,
+    function_lines: Option<&HashSet<usize>>,
+) -> bool {
+    let after_from = options
+        .from
+        .is_none_or(|from| event.timestamp >= from.resolve(first_timestamp));
+    let before_to = options
+        .to
+        .is_none_or(|to| event.timestamp <= to.resolve(first_timestamp));
+    let in_trace = options
+        .trace_id
+        .as_ref()
+        .is_none_or(|id| event.trace_id.as_ref() == Some(id));
+    let in_target = options
+        .target
+        .as_ref()
+        .is_none_or(|target| event.target.as_ref() == Some(target));
+    let in_tree = function_lines.is_none_or(|lines| lines.contains(&line));
+
+    after_from && before_to && in_trace && in_target && in_tree
+}
+
+/// Log lines of every top-level tree containing a call to `function`
+fn tree_lines(roots: &[Call], function: &str) -> HashSet<usize> {
+    fn contains(call: &Call, function: &str) -> bool {
+        call.function == function || call.name() == function || call.children.iter().any(|c| contains(c, function))
+    }
+
+    fn collect(call: &Call, lines: &mut HashSet<usize>) {
+        lines.insert(call.line);
+        lines.extend(call.exit_line);
+        for child in &call.children {
+            collect(child, lines);
+        }
+    }
+
+    let mut lines = HashSet::new();
+    for root in roots.iter().filter(|root| contains(root, function)) {
+        collect(root, &mut lines);
+    }
+    lines
+}
+
+/// Parse `HH:MM[:SS]` (UTC), `YYYY-MM-DDTHH:MM[:SS][Z]` (UTC) or epoch microseconds
+pub fn parse_time(value: &str) -> Result<TimeSpec, String> {
+    let invalid = || format!("Invalid time '{}': expected HH:MM[:SS], YYYY-MM-DDTHH:MM[:SS] or epoch microseconds", value);
+    let value = value.trim();
+
+    if let Ok(micros) = value.parse::<i64>() {
+        return Ok(TimeSpec::Absolute(micros));
+    }
+
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (Some(date), time.trim_end_matches('Z')),
+        None => (None, value),
+    };
+
+    let mut parts = time.split(':');
+    let hours: i64 = parts.next().and_then(|h| h.parse().ok()).ok_or_else(invalid)?;
+    let minutes: i64 = parts.next().and_then(|m| m.parse().ok()).ok_or_else(invalid)?;
+    let seconds: f64 = match parts.next() {
+        Some(s) => s.parse().map_err(|_| invalid())?,
+        None => 0.0,
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || !(0.0..60.0).contains(&seconds) {
+        return Err(invalid());
+    }
+    let time_micros = (hours * 3600 + minutes * 60) * MICROS_PER_SECOND + (seconds * 1_000_000.0) as i64;
+
+    match date {
+        None => Ok(TimeSpec::TimeOfDay(time_micros)),
+        Some(date) => {
+            let fields: Vec<i64> = date.split('-').map(|f| f.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+            let [year, month, day] = fields[..] else {
+                return Err(invalid());
+            };
+            if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                return Err(invalid());
+            }
+            Ok(TimeSpec::Absolute(days_from_civil(year, month, day) * MICROS_PER_DAY + time_micros))
+        }
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parse a size such as `100MB`, `512KiB` or `2048`
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size '{}'", value))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("Invalid size unit in '{}': use B, KB, MB or GB", value)),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_trace(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let lines = [
+            r#"{"event":"ENTER","timestamp":1000,"class":"app","method":"handle","thread":"t1","traceId":"a"}"#,
+            r#"{"event":"ENTER","timestamp":1100,"class":"app","method":"checkout","target":"db","thread":"t1","traceId":"a"}"#,
+            r#"{"event":"EXIT","timestamp":1200,"class":"app","method":"checkout","target":"db","thread":"t1","traceId":"a"}"#,
+            r#"{"event":"EXIT","timestamp":1300,"class":"app","method":"handle","thread":"t1","traceId":"a"}"#,
+            r#"{"event":"ENTER","timestamp":2000,"class":"app","method":"handle","thread":"t1","traceId":"b"}"#,
+            r#"{"event":"EXIT","timestamp":2100,"class":"app","method":"handle","thread":"t1","traceId":"b"}"#,
+        ];
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        path
+    }
+
+    fn pruned(name: &str, options: PruneOptions) -> Vec<usize> {
+        let input = write_trace(&format!("{}.jsonl", name));
+        let output = std::env::temp_dir().join(format!("{}.out.jsonl", name));
+        prune(&input, &output, &options).unwrap();
+
+        let kept = trace::read_events(&output).unwrap().iter().map(|e| e.timestamp as usize).collect();
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+        kept
+    }
+
+    #[test]
+    fn test_prune_filters() {
+        let by_time = PruneOptions {
+            from: Some(TimeSpec::Absolute(1100)),
+            to: Some(TimeSpec::Absolute(2000)),
+            ..Default::default()
+        };
+        assert_eq!(pruned("flowctl_prune_time", by_time), vec![1100, 1200, 1300, 2000]);
+
+        let by_trace = PruneOptions {
+            trace_id: Some("b".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pruned("flowctl_prune_trace", by_trace), vec![2000, 2100]);
+
+        let by_target = PruneOptions {
+            target: Some("db".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pruned("flowctl_prune_target", by_target), vec![1100, 1200]);
+
+        let by_function = PruneOptions {
+            function: Some("checkout".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pruned("flowctl_prune_function", by_function), vec![1000, 1100, 1200, 1300]);
+
+        // Lines are about 95 bytes; room for the newest two only
+        let by_size = PruneOptions {
+            max_bytes: Some(200),
+            ..Default::default()
+        };
+        assert_eq!(pruned("flowctl_prune_size", by_size), vec![2000, 2100]);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("12:05"), Ok(TimeSpec::TimeOfDay((12 * 3600 + 5 * 60) * MICROS_PER_SECOND)));
+        assert_eq!(parse_time("1970-01-02T00:00:01Z"), Ok(TimeSpec::Absolute(MICROS_PER_DAY + MICROS_PER_SECOND)));
+        assert_eq!(parse_time("1700000000000000"), Ok(TimeSpec::Absolute(1_700_000_000_000_000)));
+        assert!(parse_time("25:00").is_err());
+
+        // 2023-11-14T22:13:20Z is 1_700_000_000 seconds after the epoch
+        assert_eq!(
+            parse_time("2023-11-14 22:13:20"),
+            Ok(TimeSpec::Absolute(1_700_000_000 * MICROS_PER_SECOND))
+        );
+        assert_eq!(TimeSpec::TimeOfDay(0).resolve(MICROS_PER_DAY + 5), MICROS_PER_DAY);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100MB"), Ok(100 << 20));
+        assert_eq!(parse_size("1.5k"), Ok(1536));
+        assert_eq!(parse_size("2048"), Ok(2048));
+        assert!(parse_size("10 parsecs").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Heuristics ranking functions by how useful tracing them would be
+
+use serde::Serialize;
+use syn::visit::Visit;
+use syn::{Block, ExprAwait, ExprCall, ExprMethodCall, ExprPath, ReturnType, Signature, Type};
+
+/// Method names that usually perform IO
+const IO_METHODS: [&str; 16] = [
+    "read", "read_to_string", "read_to_end", "write", "write_all", "flush", "send", "recv",
+    "query", "execute", "fetch", "fetch_one", "fetch_all", "connect", "request", "send_request",
+];
+
+/// Path segments that usually denote IO APIs
+const IO_PATHS: [&str; 9] = [
+    "fs", "File", "TcpStream", "UdpSocket", "Command", "reqwest", "sqlx", "redis", "hyper",
+];
+
+/// A function worth instrumenting, with the reasons it scored
+#[derive(Debug, Clone, Serialize)]
+pub struct Recommendation {
+    pub function: String,
+    pub file: String,
+    pub line: usize,
+    pub score: u32,
+    pub reasons: Vec<String>,
+}
+
+/// Score a function body; higher means more valuable to trace
+pub fn score(sig: &Signature, block: &Block, is_public: bool, lines: usize) -> (u32, Vec<String>) {
+    let mut score = 0;
+    let mut reasons = Vec::new();
+
+    let mut body = BodyVisitor::default();
+    body.visit_block(block);
+
+    if let Some(call) = body.io_calls.first() {
+        score += 3;
+        reasons.push(if body.io_calls.len() > 1 {
+            format!("performs IO ({} and {} more)", call, body.io_calls.len() - 1)
+        } else {
+            format!("performs IO ({})", call)
+        });
+    }
+
+    if sig.asyncness.is_some() || body.awaits > 0 {
+        score += 2;
+        reasons.push(match body.awaits {
+            0 => "async".to_string(),
+            1 => "awaits 1 future".to_string(),
+            n => format!("awaits {} futures", n),
+        });
+    }
+
+    if returns_result(&sig.output) {
+        score += 2;
+        reasons.push("returns Result".to_string());
+    }
+
+    if lines >= 10 {
+        score += (lines / 10).min(5) as u32;
+        reasons.push(format!("long function ({} lines)", lines));
+    }
+
+    if is_public {
+        score += 1;
+        reasons.push("public API".to_string());
+    }
+
+    (score, reasons)
+}
+
+/// Sort by descending score, keeping source order among equals
+pub fn rank(recommendations: &mut [Recommendation]) {
+    recommendations.sort_by_key(|r| std::cmp::Reverse(r.score));
+}
+
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Path(type_path) => type_path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident.to_string().ends_with("Result")),
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
+
+#[derive(Default)]
+struct BodyVisitor {
+    awaits: usize,
+    io_calls: Vec<String>,
+}
+
+impl BodyVisitor {
+    fn record_io(&mut self, call: String) {
+        if !self.io_calls.contains(&call) {
+            self.io_calls.push(call);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for BodyVisitor {
+    fn visit_expr_await(&mut self, node: &'ast ExprAwait) {
+        self.awaits += 1;
+        syn::visit::visit_expr_await(self, node);
+    }
+
+    fn visit_expr_method_call(&mut self, node: &'ast ExprMethodCall) {
+        let method = node.method.to_string();
+        if IO_METHODS.contains(&method.as_str()) {
+            self.record_io(format!(".{}()", method));
+        }
+        syn::visit::visit_expr_method_call(self, node);
+    }
+
+    fn visit_expr_call(&mut self, node: &'ast ExprCall) {
+        if let syn::Expr::Path(ExprPath { path, .. }) = &*node.func {
+            let segments: Vec<String> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+            if segments.len() > 1 && segments.iter().any(|s| IO_PATHS.contains(&s.as_str())) {
+                self.record_io(segments.join("::"));
+            }
+        }
+        syn::visit::visit_expr_call(self, node);
+    }
+
+    // Nested items are scored on their own
+    fn visit_item(&mut self, _node: &'ast syn::Item) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score_fn(source: &str, is_public: bool, lines: usize) -> (u32, Vec<String>) {
+        let func = syn::parse_str::<syn::ItemFn>(source).unwrap();
+        score(&func.sig, &func.block, is_public, lines)
+    }
+
+    #[test]
+    fn test_io_and_result_score_higher() {
+        let (io_score, reasons) = score_fn(
+            "async fn load(id: u32) -> Result<User, Error> { let raw = fs::read_to_string(path).await?; db.query(raw).await }",
+            true,
+            3,
+        );
+        assert_eq!(
+            reasons,
+            vec![
+                "performs IO (fs::read_to_string and 1 more)",
+                "awaits 2 futures",
+                "returns Result",
+                "public API"
+            ]
+        );
+
+        let (plain_score, reasons) = score_fn("fn add(a: u32, b: u32) -> u32 { a + b }", false, 1);
+        assert!(reasons.is_empty());
+        assert!(io_score > plain_score);
+    }
+
+    #[test]
+    fn test_rank_is_stable() {
+        let rec = |function: &str, score| Recommendation {
+            function: function.to_string(),
+            file: String::new(),
+            line: 1,
+            score,
+            reasons: Vec::new(),
+        };
+        let mut recommendations = vec![rec("a", 1), rec("b", 5), rec("c", 1)];
+
+        rank(&mut recommendations);
+        let order: Vec<&str> = recommendations.iter().map(|r| r.function.as_str()).collect();
+        assert_eq!(order, vec!["b", "a", "c"]);
+    }
+}
@@ -0,0 +1,154 @@
+//! Project scaffolding for `flowctl-rs init`
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::{parse_file, Item};
+
+use crate::config::{CONFIG_FILE, DEFAULT_CONFIG};
+use crate::manifest;
+
+/// Statement inserted at the top of `main` by `init --with-main`
+const START_TRACING: &str =
+    "flowtrace_agent::start_tracing(flowtrace_agent::Config::from_env()).expect(\"failed to start FlowTrace\");";
+
+/// What `init` changed in the project
+#[derive(Debug, Default)]
+pub struct InitReport {
+    pub config_path: Option<PathBuf>,
+    pub dependencies_added: Vec<String>,
+    pub main_updated: Option<PathBuf>,
+}
+
+/// Create `flowtrace.toml`, add the FlowTrace dependencies and optionally
+/// start tracing from `main`
+///
+/// Every step is skipped when already done, so running `init` twice is safe.
+pub fn init_project(dir: &Path, with_main: bool) -> Result<InitReport, String> {
+    let manifest_path = dir.join("Cargo.toml");
+    if !manifest_path.is_file() {
+        return Err(format!("No Cargo.toml found in {}", dir.display()));
+    }
+
+    let mut report = InitReport::default();
+
+    let config_path = dir.join(CONFIG_FILE);
+    if !config_path.exists() {
+        fs::write(&config_path, DEFAULT_CONFIG)
+            .map_err(|e| format!("Failed to write {}: {}", config_path.display(), e))?;
+        report.config_path = Some(config_path);
+    }
+
+    report.dependencies_added = manifest::add_dependencies(&manifest_path)?;
+
+    if with_main {
+        let main_path = dir.join("src").join("main.rs");
+        if !main_path.is_file() {
+            return Err(format!("{} not found", main_path.display()));
+        }
+
+        let content = fs::read_to_string(&main_path)
+            .map_err(|e| format!("Failed to read {}: {}", main_path.display(), e))?;
+
+        if let Some(updated) = insert_start_tracing(&content)? {
+            fs::write(&main_path, updated)
+                .map_err(|e| format!("Failed to write {}: {}", main_path.display(), e))?;
+            report.main_updated = Some(main_path);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Insert the `start_tracing` call as the first statement of `fn main`
+///
+/// Returns `None` when `main` already starts tracing.
+fn insert_start_tracing(content: &str) -> Result<Option<String>, String> {
+    let syntax = parse_file(content).map_err(|e| format!("Failed to parse main.rs: {}", e))?;
+
+    let main = syntax
+        .items
+        .iter()
+        .find_map(|item| match item {
+            Item::Fn(func) if func.sig.ident == "main" => Some(func),
+            _ => None,
+        })
+        .ok_or_else(|| "No fn main found in main.rs".to_string())?;
+
+    if content.contains("start_tracing") {
+        return Ok(None);
+    }
+
+    let brace = main.block.brace_token.span.open().start();
+    let line_start = content
+        .split_inclusive('\n')
+        .take(brace.line - 1)
+        .map(str::len)
+        .sum::<usize>();
+    let line = &content[line_start..];
+    let brace_offset = line_start
+        + line
+            .char_indices()
+            .nth(brace.column)
+            .map(|(index, _)| index)
+            .ok_or_else(|| "Failed to locate the body of fn main".to_string())?;
+
+    let indent: String = line.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+
+    let mut updated = String::with_capacity(content.len() + START_TRACING.len() + 8);
+    updated.push_str(&content[..=brace_offset]);
+    updated.push('\n');
+    updated.push_str(&indent);
+    updated.push_str("    ");
+    updated.push_str(START_TRACING);
+    if !content[brace_offset + 1..].starts_with('\n') {
+        updated.push('\n');
+        updated.push_str(&indent);
+    }
+    updated.push_str(&content[brace_offset + 1..]);
+
+    Ok(Some(updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_start_tracing() {
+        let code = "#[tokio::main]\nasync fn main() {\n    run().await;\n}\n";
+
+        let updated = insert_start_tracing(code).unwrap().unwrap();
+        assert_eq!(
+            updated,
+            format!("#[tokio::main]\nasync fn main() {{\n    {}\n    run().await;\n}}\n", START_TRACING)
+        );
+
+        assert!(insert_start_tracing(&updated).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_insert_start_tracing_requires_main() {
+        assert!(insert_start_tracing("fn helper() {}").is_err());
+    }
+
+    #[test]
+    fn test_init_project() {
+        let dir = std::env::temp_dir().join("flowctl_init_project");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"app\"\n\n[dependencies]\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+
+        let report = init_project(&dir, true).unwrap();
+        assert!(report.config_path.is_some());
+        assert_eq!(report.dependencies_added.len(), 2);
+        assert!(report.main_updated.is_some());
+
+        let again = init_project(&dir, true).unwrap();
+        assert!(again.config_path.is_none());
+        assert!(again.dependencies_added.is_empty());
+        assert!(again.main_updated.is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+//! Run summaries for `flowctl-rs summary`, as text, JUnit XML or GitHub annotations
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::trace::Call;
+
+/// A call that ended with an EXCEPTION event
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub name: String,
+    pub exception: String,
+    pub line: usize,
+}
+
+/// One of the slowest finished calls
+#[derive(Debug, Clone)]
+pub struct Slow {
+    pub name: String,
+    pub duration_micros: i64,
+    pub line: usize,
+}
+
+/// Calls of one function that exceeded its configured budget
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub name: String,
+    pub budget_micros: i64,
+    pub worst_micros: i64,
+    pub count: usize,
+    /// Log line of the worst call
+    pub line: usize,
+}
+
+/// Per-function totals, used for JUnit test cases
+#[derive(Debug, Clone, Default)]
+pub struct FunctionStats {
+    pub calls: usize,
+    pub failures: usize,
+    pub total_micros: i64,
+    /// Calls and total duration scaled back up by the sampling rate of each call
+    pub estimated_calls: f64,
+    pub estimated_total_micros: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub total_calls: usize,
+    pub unfinished_calls: usize,
+    pub failures: Vec<Failure>,
+    pub slowest: Vec<Slow>,
+    pub violations: Vec<Violation>,
+    pub functions: BTreeMap<String, FunctionStats>,
+    /// Calls the run is estimated to have made, counting each sampled call as `1 / sampleRate`
+    pub estimated_calls: f64,
+    /// Whether any call was kept by sampling, making the counts above partial
+    pub sampled: bool,
+}
+
+impl Summary {
+    /// Whether the run had errors or budget violations
+    pub fn has_problems(&self) -> bool {
+        !self.failures.is_empty() || !self.violations.is_empty()
+    }
+}
+
+/// Summarize every call in `roots`
+///
+/// `budgets` maps a function name or `module::function` to its budget in
+/// milliseconds, as in the `[budgets]` section of `flowtrace.toml`.
+pub fn summarize(roots: &[Call], budgets: &BTreeMap<String, f64>, slowest: usize) -> Summary {
+    let mut summary = Summary::default();
+    let mut violations: BTreeMap<String, Violation> = BTreeMap::new();
+    let mut finished = Vec::new();
+
+    let mut stack: Vec<&Call> = roots.iter().rev().collect();
+    while let Some(call) = stack.pop() {
+        stack.extend(call.children.iter().rev());
+
+        let name = call.name();
+        let weight = call.weight();
+        summary.total_calls += 1;
+        summary.estimated_calls += weight;
+        summary.sampled |= weight > 1.0;
+        let stats = summary.functions.entry(name.clone()).or_default();
+        stats.calls += 1;
+        stats.estimated_calls += weight;
+
+        if let Some(exception) = &call.exception {
+            stats.failures += 1;
+            summary.failures.push(Failure {
+                name: name.clone(),
+                exception: exception.clone(),
+                line: call.line,
+            });
+        }
+
+        let Some(duration) = call.duration_micros.filter(|_| call.end.is_some()) else {
+            summary.unfinished_calls += 1;
+            continue;
+        };
+        stats.total_micros += duration;
+        stats.estimated_total_micros += duration as f64 * weight;
+        finished.push(Slow {
+            name: name.clone(),
+            duration_micros: duration,
+            line: call.line,
+        });
+
+        let budget = budgets.get(&name).or_else(|| budgets.get(&call.function));
+        if let Some(budget) = budget {
+            let budget_micros = (budget * 1000.0) as i64;
+            if duration > budget_micros {
+                let violation = violations.entry(name.clone()).or_insert_with(|| Violation {
+                    name: name.clone(),
+                    budget_micros,
+                    worst_micros: 0,
+                    count: 0,
+                    line: call.line,
+                });
+                violation.count += 1;
+                if duration > violation.worst_micros {
+                    violation.worst_micros = duration;
+                    violation.line = call.line;
+                }
+            }
+        }
+    }
+
+    finished.sort_by_key(|slow| std::cmp::Reverse(slow.duration_micros));
+    finished.truncate(slowest);
+    summary.slowest = finished;
+    summary.violations = violations.into_values().collect();
+    summary
+}
+
+/// JUnit XML with one test case per traced function
+///
+/// A function's test case fails when any of its calls raised an exception or
+/// exceeded its latency budget.
+pub fn to_junit(summary: &Summary, suite: &str) -> String {
+    let failing = summary
+        .functions
+        .keys()
+        .filter(|name| function_problems(summary, name).is_some())
+        .count();
+    let total_seconds: f64 = summary
+        .functions
+        .values()
+        .map(|stats| stats.total_micros as f64 / 1_000_000.0)
+        .sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"flowtrace\" tests=\"{}\" failures=\"{}\">\n",
+        summary.functions.len(),
+        failing
+    ));
+    xml.push_str(&format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.6}\">\n",
+        escape_xml(suite),
+        summary.functions.len(),
+        failing,
+        total_seconds
+    ));
+
+    for (name, stats) in &summary.functions {
+        let (classname, function) = name.rsplit_once("::").unwrap_or(("", name));
+        xml.push_str(&format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.6}\"",
+            escape_xml(classname),
+            escape_xml(function),
+            stats.total_micros as f64 / 1_000_000.0
+        ));
+
+        match function_problems(summary, name) {
+            Some((message, details)) => {
+                xml.push_str(">\n");
+                xml.push_str(&format!(
+                    "      <failure message=\"{}\">{}</failure>\n",
+                    escape_xml(&message),
+                    escape_xml(&details)
+                ));
+                xml.push_str("    </testcase>\n");
+            }
+            None => xml.push_str("/>\n"),
+        }
+    }
+
+    if !summary.slowest.is_empty() {
+        let slowest: Vec<String> = summary
+            .slowest
+            .iter()
+            .map(|slow| format!("{} {}µs (line {})", slow.name, slow.duration_micros, slow.line))
+            .collect();
+        xml.push_str(&format!(
+            "    <system-out>Slowest calls:\n{}</system-out>\n",
+            escape_xml(&slowest.join("\n"))
+        ));
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+fn function_problems(summary: &Summary, name: &str) -> Option<(String, String)> {
+    let failures: Vec<&Failure> = summary.failures.iter().filter(|f| f.name == name).collect();
+    let violation = summary.violations.iter().find(|v| v.name == name);
+
+    let mut messages = Vec::new();
+    let mut details = Vec::new();
+
+    if let Some(first) = failures.first() {
+        messages.push(format!("{} call(s) raised an exception", failures.len()));
+        details.push(format!("line {}: {}", first.line, first.exception));
+    }
+    if let Some(violation) = violation {
+        messages.push(format!(
+            "{} call(s) exceeded the {}µs budget",
+            violation.count, violation.budget_micros
+        ));
+        details.push(format!(
+            "line {}: worst call took {}µs",
+            violation.line, violation.worst_micros
+        ));
+    }
+
+    (!messages.is_empty()).then(|| (messages.join("; "), details.join("\n")))
+}
+
+/// One GitHub check-run annotation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Annotation {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub annotation_level: &'static str,
+    pub title: String,
+    pub message: String,
+}
+
+/// Annotations pointing at the trace file lines of failures and budget violations
+pub fn to_annotations(summary: &Summary, path: &str) -> Vec<Annotation> {
+    let failures = summary.failures.iter().map(|failure| Annotation {
+        path: path.to_string(),
+        start_line: failure.line,
+        end_line: failure.line,
+        annotation_level: "failure",
+        title: format!("{} raised an exception", failure.name),
+        message: failure.exception.clone(),
+    });
+
+    let violations = summary.violations.iter().map(|violation| Annotation {
+        path: path.to_string(),
+        start_line: violation.line,
+        end_line: violation.line,
+        annotation_level: "warning",
+        title: format!("{} exceeded its latency budget", violation.name),
+        message: format!(
+            "{} call(s) over {}µs; worst took {}µs",
+            violation.count, violation.budget_micros, violation.worst_micros
+        ),
+    });
+
+    failures.chain(violations).collect()
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::build_calls;
+    use crate::trace::tests::event;
+
+    fn summary() -> Summary {
+        let mut failed = event("EXCEPTION", "load", 300, "t1");
+        failed.exception = Some("user <42> missing".to_string());
+        let roots = build_calls(&[
+            event("ENTER", "handle", 0, "t1"),
+            event("ENTER", "load", 100, "t1"),
+            failed,
+            event("EXIT", "handle", 5_000, "t1"),
+            event("ENTER", "handle", 6_000, "t1"),
+            event("EXIT", "handle", 6_500, "t1"),
+        ]);
+
+        let budgets = BTreeMap::from([("app::handle".to_string(), 1.0)]);
+        summarize(&roots, &budgets, 2)
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summary = summary();
+        assert_eq!(summary.total_calls, 3);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.slowest[0].duration_micros, 5_000);
+        assert_eq!(summary.slowest.len(), 2);
+
+        assert_eq!(summary.violations.len(), 1);
+        assert_eq!(summary.violations[0].count, 1);
+        assert_eq!(summary.violations[0].worst_micros, 5_000);
+        assert!(summary.has_problems());
+    }
+
+    #[test]
+    fn test_summarize_scales_sampled_calls() {
+        let mut events = vec![
+            event("ENTER", "handle", 0, "t1"),
+            event("EXIT", "handle", 1_000, "t1"),
+            event("ENTER", "load", 2_000, "t1"),
+            event("EXIT", "load", 2_500, "t1"),
+        ];
+        for event in &mut events[..2] {
+            event.sample_rate = Some(0.25);
+        }
+        let summary = summarize(&build_calls(&events), &BTreeMap::new(), 1);
+
+        assert_eq!(summary.total_calls, 2);
+        assert_eq!(summary.estimated_calls, 5.0);
+        assert!(summary.sampled);
+        let handle = &summary.functions["app::handle"];
+        assert_eq!((handle.calls, handle.estimated_calls), (1, 4.0));
+        assert_eq!(handle.estimated_total_micros, 4_000.0);
+        assert_eq!(summary.functions["app::load"].estimated_total_micros, 500.0);
+    }
+
+    #[test]
+    fn test_to_junit() {
+        let xml = to_junit(&summary(), "flowtrace.jsonl");
+        assert!(xml.contains("<testsuites name=\"flowtrace\" tests=\"2\" failures=\"2\">"));
+        assert!(xml.contains("<testcase classname=\"app\" name=\"handle\" time=\"0.005500\">"));
+        assert!(xml.contains("1 call(s) exceeded the 1000µs budget"));
+        assert!(xml.contains("user &lt;42&gt; missing"));
+    }
+
+    #[test]
+    fn test_to_annotations() {
+        let annotations = to_annotations(&summary(), "flowtrace.jsonl");
+        let levels: Vec<&str> = annotations.iter().map(|a| a.annotation_level).collect();
+        assert_eq!(levels, vec!["failure", "warning"]);
+        assert_eq!(annotations[0].title, "app::load raised an exception");
+    }
+}
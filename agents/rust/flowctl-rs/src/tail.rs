@@ -0,0 +1,86 @@
+//! Following a running agent's live WebSocket stream, for `flowctl-rs tail`
+
+use colored::*;
+
+use crate::trace::{self, AgentInfo, Event};
+
+/// Add `module`/`level` filter parameters to a `ws://` URL
+pub fn stream_url(url: &str, modules: &[String], level: Option<&str>) -> String {
+    let params: Vec<String> = modules
+        .iter()
+        .map(|module| format!("module={}", module))
+        .chain(level.map(|level| format!("level={}", level)))
+        .collect();
+
+    if params.is_empty() {
+        return url.to_string();
+    }
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", url, separator, params.join("&"))
+}
+
+/// Render one streamed JSON message; `None` for anything that isn't a record
+pub fn format_line(line: &str) -> Option<String> {
+    if let Ok(agent) = serde_json::from_str::<AgentInfo>(line) {
+        if agent.event == "AGENT_INFO" {
+            let pid = agent.pid.map(|pid| format!(", pid {}", pid)).unwrap_or_default();
+            return Some(format!(
+                "{} {} agent {} (schema v{}{})",
+                "📡 Connected to".green().bold(),
+                agent.language.cyan(),
+                agent.agent_version,
+                agent.schema_version,
+                pid
+            ));
+        }
+    }
+
+    let event: Event = serde_json::from_str(line).ok()?;
+    let name = format!("{}::{}", event.module, event.function);
+    let duration = event
+        .duration_micros
+        .or(event.duration_millis.map(|millis| millis * 1000))
+        .map(|micros| format!(" ({})", trace::format_micros(micros)))
+        .unwrap_or_default();
+    let thread = event.thread.dimmed();
+
+    Some(match event.event.as_str() {
+        "ENTER" => format!("{} → {}", thread, name),
+        "EXIT" => format!("{} ← {}{}", thread, name, duration.dimmed()),
+        "EXCEPTION" => format!(
+            "{} 💥 {}{} {}",
+            thread,
+            name.red(),
+            duration.dimmed(),
+            event.exception.unwrap_or_default()
+        ),
+        other => format!("{} {} {}", thread, other, name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_url() {
+        let modules = vec!["billing".to_string(), "auth".to_string()];
+        assert_eq!(stream_url("ws://127.0.0.1:7878", &[], None), "ws://127.0.0.1:7878");
+        assert_eq!(
+            stream_url("ws://127.0.0.1:7878/", &modules, Some("warn")),
+            "ws://127.0.0.1:7878/?module=billing&module=auth&level=warn"
+        );
+        assert_eq!(stream_url("ws://host/?token=1", &[], Some("error")), "ws://host/?token=1&level=error");
+    }
+
+    #[test]
+    fn test_format_line() {
+        colored::control::set_override(false);
+        let info = r#"{"event":"AGENT_INFO","timestamp":1,"language":"rust","agentVersion":"1.0.0","schemaVersion":1,"pid":7}"#;
+        assert_eq!(format_line(info).unwrap(), "📡 Connected to rust agent 1.0.0 (schema v1, pid 7)");
+
+        let exit = r#"{"event":"EXIT","timestamp":1,"class":"shop","method":"pay","durationMicros":1500,"thread":"main"}"#;
+        assert_eq!(format_line(exit).unwrap(), "main ← shop::pay (1.50ms)");
+        assert_eq!(format_line("not json"), None);
+    }
+}
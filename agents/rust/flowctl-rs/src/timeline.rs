@@ -0,0 +1,234 @@
+//! ASCII Gantt rendering for `flowctl-rs timeline`
+
+use crate::trace::Call;
+
+/// Time range shown by the timeline, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Window {
+    /// The span from the first call start to the last call end
+    pub fn of(roots: &[Call]) -> Option<Self> {
+        let start = roots.iter().map(|call| call.start).min()?;
+        let end = roots.iter().map(Call::finish).max()?;
+        Some(Self { start, end: end.max(start + 1) })
+    }
+
+    /// The span of the first root call named `name` (`function` or `module::function`)
+    pub fn of_call(roots: &[Call], name: &str) -> Option<Self> {
+        roots
+            .iter()
+            .find(|call| call.function == name || call.name() == name)
+            .map(|call| Self {
+                start: call.start,
+                end: call.finish().max(call.start + 1),
+            })
+    }
+
+    /// Narrow to `[from_ms, to_ms]` milliseconds after the window start
+    pub fn select(self, from_ms: Option<f64>, to_ms: Option<f64>) -> Self {
+        let offset = |ms: f64| self.start + (ms * 1000.0) as i64;
+        let start = from_ms.map(offset).unwrap_or(self.start).clamp(self.start, self.end);
+        let end = to_ms.map(offset).unwrap_or(self.end).clamp(start + 1, self.end.max(start + 1));
+        Self { start, end }
+    }
+
+    pub fn duration(&self) -> i64 {
+        self.end - self.start
+    }
+
+    fn overlaps(&self, start: i64, end: i64) -> bool {
+        start < self.end && end > self.start
+    }
+
+    /// Column range `[from, to)` covering `[start, end]` on a `width`-column axis
+    fn columns(&self, start: i64, end: i64, width: usize) -> (usize, usize) {
+        let column = |t: i64| {
+            let t = t.clamp(self.start, self.end);
+            ((t - self.start) as f64 * width as f64 / self.duration() as f64) as usize
+        };
+        let from = column(start).min(width.saturating_sub(1));
+        let to = column(end).clamp(from + 1, width);
+        (from, to)
+    }
+}
+
+/// One call drawn as a bar
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub depth: usize,
+    pub name: String,
+    pub bar: String,
+    pub duration_micros: Option<i64>,
+    pub failed: bool,
+}
+
+/// All calls of one thread plus its busy/idle summary line
+#[derive(Debug, Clone)]
+pub struct Lane {
+    pub thread: String,
+    /// `█` where the thread is inside a root call, `·` where it is idle
+    pub activity: String,
+    pub rows: Vec<Row>,
+    /// Idle intervals between root calls, longest first
+    pub gaps: Vec<(i64, i64)>,
+}
+
+/// A rendered timeline
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub lanes: Vec<Lane>,
+    /// `▲` in columns where two or more threads are busy at once
+    pub overlap: String,
+}
+
+/// Lay out the calls of `roots` within `window`, grouped by thread
+pub fn render(roots: &[Call], window: Window, width: usize, max_depth: Option<usize>) -> Timeline {
+    let width = width.max(1);
+    let mut threads: Vec<&str> = Vec::new();
+    for root in roots {
+        if !threads.contains(&root.thread.as_str()) {
+            threads.push(&root.thread);
+        }
+    }
+
+    let mut busy_count = vec![0usize; width];
+    let mut lanes = Vec::new();
+
+    for thread in threads {
+        let calls: Vec<&Call> = roots
+            .iter()
+            .filter(|root| root.thread == thread && window.overlaps(root.start, root.finish()))
+            .collect();
+        if calls.is_empty() {
+            continue;
+        }
+
+        let mut activity = vec!['·'; width];
+        let mut rows = Vec::new();
+        for call in &calls {
+            let (from, to) = window.columns(call.start, call.finish(), width);
+            activity[from..to].fill('█');
+            collect_rows(call, 0, window, width, max_depth, &mut rows);
+        }
+
+        for (column, busy) in activity.iter().enumerate() {
+            if *busy == '█' {
+                busy_count[column] += 1;
+            }
+        }
+
+        let mut gaps: Vec<(i64, i64)> = calls
+            .windows(2)
+            .filter_map(|pair| {
+                let idle_from = pair[0].finish();
+                let idle_to = pair[1].start;
+                (idle_to > idle_from).then_some((idle_from, idle_to))
+            })
+            .collect();
+        gaps.sort_by_key(|(from, to)| std::cmp::Reverse(to - from));
+
+        lanes.push(Lane {
+            thread: thread.to_string(),
+            activity: activity.into_iter().collect(),
+            rows,
+            gaps,
+        });
+    }
+
+    let overlap = busy_count
+        .iter()
+        .map(|count| if *count > 1 { '▲' } else { ' ' })
+        .collect();
+
+    Timeline { lanes, overlap }
+}
+
+fn collect_rows(
+    call: &Call,
+    depth: usize,
+    window: Window,
+    width: usize,
+    max_depth: Option<usize>,
+    rows: &mut Vec<Row>,
+) {
+    if max_depth.is_some_and(|max| depth > max) || !window.overlaps(call.start, call.finish()) {
+        return;
+    }
+
+    let (from, to) = window.columns(call.start, call.finish(), width);
+    let mut bar = vec![' '; width];
+    bar[from..to].fill(if call.end.is_some() { '█' } else { '▒' });
+
+    rows.push(Row {
+        depth,
+        name: call.name(),
+        bar: bar.into_iter().collect(),
+        duration_micros: call.duration_micros,
+        failed: call.failed(),
+    });
+
+    for child in &call.children {
+        collect_rows(child, depth + 1, window, width, max_depth, rows);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::build_calls;
+    use crate::trace::tests::event;
+
+    fn roots() -> Vec<Call> {
+        build_calls(&[
+            event("ENTER", "a", 0, "t1"),
+            event("ENTER", "b", 0, "t1"),
+            event("EXIT", "b", 10, "t1"),
+            event("EXIT", "a", 20, "t1"),
+            event("ENTER", "c", 15, "t2"),
+            event("EXIT", "c", 30, "t2"),
+            event("ENTER", "d", 30, "t1"),
+            event("EXIT", "d", 40, "t1"),
+        ])
+    }
+
+    #[test]
+    fn test_render_lanes_gaps_and_overlap() {
+        let roots = roots();
+        let window = Window::of(&roots).unwrap();
+        assert_eq!(window, Window { start: 0, end: 40 });
+
+        let timeline = render(&roots, window, 8, None);
+        assert_eq!(timeline.lanes.len(), 2);
+
+        let t1 = &timeline.lanes[0];
+        assert_eq!(t1.activity, "████··██");
+        let bars: Vec<(usize, &str)> = t1.rows.iter().map(|row| (row.depth, row.bar.as_str())).collect();
+        assert_eq!(bars, vec![(0, "████    "), (1, "██      "), (0, "      ██")]);
+        assert_eq!(t1.gaps, vec![(20, 30)]);
+
+        assert_eq!(timeline.lanes[1].activity, "···███··");
+        assert_eq!(timeline.overlap, "   ▲    ");
+    }
+
+    #[test]
+    fn test_select_window() {
+        let roots = roots();
+        let window = Window::of(&roots).unwrap().select(Some(0.025), None);
+        assert_eq!(window, Window { start: 25, end: 40 });
+
+        let timeline = render(&roots, window, 4, Some(0));
+        let names: Vec<&str> = timeline
+            .lanes
+            .iter()
+            .flat_map(|lane| lane.rows.iter().map(|row| row.name.as_str()))
+            .collect();
+        assert_eq!(names, vec!["app::d", "app::c"]);
+
+        assert_eq!(Window::of_call(&roots, "app::c"), Some(Window { start: 15, end: 30 }));
+        assert_eq!(Window::of_call(&roots, "b"), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! Reading FlowTrace JSONL logs and rebuilding call trees
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// One line of a FlowTrace log, as written by `flowtrace-agent`
+///
+/// A lenient view of `flowtrace-agent/schema/trace-event.schema.json`:
+/// fields flowctl-rs doesn't need are ignored and most are optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Event {
+    pub event: String,
+    /// Wall-clock time in microseconds, whatever `timestamp_format` the agent used
+    #[serde(deserialize_with = "timestamp_micros")]
+    pub timestamp: i64,
+    #[serde(rename = "class", default)]
+    pub module: String,
+    #[serde(rename = "method", default)]
+    pub function: String,
+    /// Logical subsystem from `#[trace(target = "...")]`
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Hash of the function's code from `#[trace]`, when the agent records one
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    #[serde(default)]
+    pub args: Option<String>,
+    #[serde(default)]
+    pub result: Option<String>,
+    #[serde(default)]
+    pub exception: Option<String>,
+    #[serde(rename = "durationMicros", default)]
+    pub duration_micros: Option<i64>,
+    #[serde(rename = "durationMillis", default)]
+    pub duration_millis: Option<i64>,
+    #[serde(default)]
+    pub thread: String,
+    /// Request/trace identifier, when the agent propagates one
+    #[serde(rename = "traceId", default)]
+    pub trace_id: Option<String>,
+    /// Fraction of calls the agent kept when it sampled; absent when it kept all of them
+    #[serde(rename = "sampleRate", default)]
+    pub sample_rate: Option<f64>,
+    /// 1-based line in the log file
+    #[serde(skip)]
+    pub line: usize,
+}
+
+/// The `AGENT_INFO` record a FlowTrace agent writes at the start of each stream
+///
+/// See `flowtrace-agent/schema/agent-info.schema.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentInfo {
+    pub event: String,
+    pub language: String,
+    #[serde(default)]
+    pub agent_version: String,
+    #[serde(default)]
+    pub schema_version: u32,
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// OpenTelemetry resource attributes, e.g. `service.name`
+    #[serde(default)]
+    pub resource: BTreeMap<String, String>,
+    /// 1-based line in the log file
+    #[serde(skip)]
+    pub line: usize,
+}
+
+/// Newest event schema version flowctl-rs understands
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Integer values above this are epoch nanoseconds rather than microseconds
+const NANOS_THRESHOLD: i64 = 100_000_000_000_000_000;
+
+/// Accept epoch microseconds, epoch nanoseconds or an RFC 3339 string
+fn timestamp_micros<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Epoch(i64),
+        Text(String),
+    }
+
+    match Timestamp::deserialize(deserializer)? {
+        Timestamp::Epoch(value) if value > NANOS_THRESHOLD => Ok(value / 1000),
+        Timestamp::Epoch(value) => Ok(value),
+        Timestamp::Text(text) => match crate::prune::parse_time(&text) {
+            Ok(crate::prune::TimeSpec::Absolute(micros)) => Ok(micros),
+            _ => Err(serde::de::Error::custom(format!("invalid timestamp '{}'", text))),
+        },
+    }
+}
+
+/// First bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Open a trace file for reading lines, decompressing it if it is zstd
+///
+/// The agent writes compressed logs as a series of frames, so a file whose
+/// writer died mid-frame is read up to the last complete one.
+pub fn open_log(path: &Path) -> Result<Box<dyn BufRead>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    let start = reader
+        .fill_buf()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    if !start.starts_with(&ZSTD_MAGIC) {
+        return Ok(Box::new(reader));
+    }
+    let decoder = zstd::stream::read::Decoder::with_buffer(reader)
+        .map_err(|e| format!("Failed to decompress {}: {}", path.display(), e))?;
+    Ok(Box::new(BufReader::new(UntilTruncated(decoder))))
+}
+
+/// Ends the stream at the first decoding error instead of failing
+struct UntilTruncated<R>(R);
+
+impl<R: Read> Read for UntilTruncated<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(self.0.read(buf).unwrap_or(0))
+    }
+}
+
+/// Read every event in a JSONL trace file, skipping lines that are not events
+pub fn read_events(path: &Path) -> Result<Vec<Event>, String> {
+    let mut events = Vec::new();
+    for (index, line) in open_log(path)?.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if let Ok(mut event) = serde_json::from_str::<Event>(&line) {
+            event.line = index + 1;
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+/// Read the `AGENT_INFO` records of a trace file, one per stream appended to it
+pub fn read_agent_info(path: &Path) -> Result<Vec<AgentInfo>, String> {
+    let mut agents = Vec::new();
+    for (index, line) in open_log(path)?.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        match serde_json::from_str::<AgentInfo>(&line) {
+            Ok(mut agent) if agent.event == "AGENT_INFO" => {
+                agent.line = index + 1;
+                agents.push(agent);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(agents)
+}
+
+/// A function call rebuilt from its ENTER and EXIT/EXCEPTION events
+#[derive(Debug, Clone, Default)]
+pub struct Call {
+    pub module: String,
+    pub function: String,
+    pub thread: String,
+    /// Trace ID of the ENTER event, when the agent propagates one
+    pub trace_id: Option<String>,
+    /// Hash of the function's code when it was traced
+    pub fingerprint: Option<String>,
+    pub args: Option<String>,
+    pub result: Option<String>,
+    pub exception: Option<String>,
+    /// Timestamp of the ENTER event, in microseconds
+    pub start: i64,
+    /// Timestamp of the closing event; `None` when the call never returned
+    pub end: Option<i64>,
+    pub duration_micros: Option<i64>,
+    /// Sampling rate the call was kept at, if the agent sampled
+    pub sample_rate: Option<f64>,
+    /// Log line of the ENTER event
+    pub line: usize,
+    /// Log line of the closing event
+    pub exit_line: Option<usize>,
+    pub children: Vec<Call>,
+}
+
+impl Call {
+    fn open(event: &Event) -> Self {
+        Self {
+            module: event.module.clone(),
+            function: event.function.clone(),
+            thread: event.thread.clone(),
+            trace_id: event.trace_id.clone(),
+            fingerprint: event.fingerprint.clone(),
+            args: event.args.clone(),
+            start: event.timestamp,
+            sample_rate: event.sample_rate,
+            line: event.line,
+            ..Default::default()
+        }
+    }
+
+    fn close(&mut self, event: &Event) {
+        self.end = Some(event.timestamp);
+        self.exit_line = Some(event.line);
+        self.result = event.result.clone();
+        self.exception = event.exception.clone();
+        self.sample_rate = self.sample_rate.or(event.sample_rate);
+        self.duration_micros = event
+            .duration_micros
+            .or(event.duration_millis.map(|ms| ms * 1000))
+            .or(Some(event.timestamp - self.start));
+    }
+
+    /// `module::function`, or just the function when the module is empty
+    pub fn name(&self) -> String {
+        if self.module.is_empty() {
+            self.function.clone()
+        } else {
+            format!("{}::{}", self.module, self.function)
+        }
+    }
+
+    /// How many calls this one stands for: the inverse of its sampling rate
+    pub fn weight(&self) -> f64 {
+        match self.sample_rate {
+            Some(rate) if rate > 0.0 && rate < 1.0 => 1.0 / rate,
+            _ => 1.0,
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exception.is_some()
+    }
+
+    /// End of the call; unfinished calls extend to their last finished descendant
+    pub fn finish(&self) -> i64 {
+        self.end.unwrap_or_else(|| {
+            self.children
+                .iter()
+                .map(Call::finish)
+                .max()
+                .unwrap_or(self.start)
+                .max(self.start + 1)
+        })
+    }
+}
+
+/// Pair ENTER events with their EXIT/EXCEPTION per thread and nest calls
+///
+/// Returns root calls in the order they started. Calls whose closing event
+/// is missing are kept with `end: None`.
+pub fn build_calls(events: &[Event]) -> Vec<Call> {
+    let mut stacks: HashMap<&str, Vec<Call>> = HashMap::new();
+    let mut roots = Vec::new();
+
+    for event in events {
+        let stack = stacks.entry(event.thread.as_str()).or_default();
+
+        match event.event.as_str() {
+            "ENTER" => stack.push(Call::open(event)),
+            "EXIT" | "EXCEPTION" => {
+                let Some(depth) = stack
+                    .iter()
+                    .rposition(|call| call.function == event.function && call.module == event.module)
+                else {
+                    continue;
+                };
+
+                // Calls above the match never logged their exit; close them as unfinished
+                while stack.len() > depth + 1 {
+                    let unfinished = stack.pop().unwrap();
+                    attach(stack, &mut roots, unfinished);
+                }
+
+                let mut call = stack.pop().unwrap();
+                call.close(event);
+                attach(stack, &mut roots, call);
+            }
+            _ => {}
+        }
+    }
+
+    for (_, mut stack) in stacks {
+        while let Some(call) = stack.pop() {
+            attach(&mut stack, &mut roots, call);
+        }
+    }
+
+    roots.sort_by_key(|call| (call.start, call.line));
+    roots
+}
+
+fn attach(stack: &mut [Call], roots: &mut Vec<Call>, call: Call) {
+    match stack.last_mut() {
+        Some(parent) => {
+            let key = (call.start, call.line);
+            let index = parent
+                .children
+                .partition_point(|child| (child.start, child.line) <= key);
+            parent.children.insert(index, call);
+        }
+        None => roots.push(call),
+    }
+}
+
+/// Human-readable duration from microseconds
+pub fn format_micros(micros: i64) -> String {
+    if micros >= 1_000_000 {
+        format!("{:.2}s", micros as f64 / 1_000_000.0)
+    } else if micros >= 1_000 {
+        format!("{:.2}ms", micros as f64 / 1_000.0)
+    } else {
+        format!("{}µs", micros)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn event(kind: &str, function: &str, timestamp: i64, thread: &str) -> Event {
+        Event {
+            event: kind.to_string(),
+            timestamp,
+            module: "app".to_string(),
+            function: function.to_string(),
+            thread: thread.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_calls_nests_per_thread() {
+        let events = vec![
+            event("ENTER", "handle", 0, "t1"),
+            event("ENTER", "other", 5, "t2"),
+            event("ENTER", "load", 10, "t1"),
+            event("EXIT", "load", 30, "t1"),
+            event("EXIT", "other", 40, "t2"),
+            event("EXCEPTION", "handle", 50, "t1"),
+        ];
+
+        let roots = build_calls(&events);
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].function, "handle");
+        assert_eq!(roots[0].duration_micros, Some(50));
+        assert_eq!(roots[0].children[0].function, "load");
+        assert_eq!(roots[0].children[0].duration_micros, Some(20));
+        assert_eq!(roots[1].function, "other");
+    }
+
+    #[test]
+    fn test_timestamp_formats() {
+        let parse = |timestamp: &str| {
+            let line = format!(r#"{{"event":"ENTER","timestamp":{},"class":"app","method":"a"}}"#, timestamp);
+            serde_json::from_str::<Event>(&line).map(|event| event.timestamp)
+        };
+
+        assert_eq!(parse("1700000000123456").unwrap(), 1_700_000_000_123_456);
+        assert_eq!(parse("1700000000123456789").unwrap(), 1_700_000_000_123_456);
+        assert_eq!(parse(r#""2023-11-14T22:13:20.123456Z""#).unwrap(), 1_700_000_000_123_456);
+        assert!(parse(r#""yesterday""#).is_err());
+    }
+
+    #[test]
+    fn test_build_calls_keeps_unfinished() {
+        let events = vec![
+            event("ENTER", "outer", 0, "t1"),
+            event("ENTER", "panicked", 1, "t1"),
+            event("EXIT", "outer", 9, "t1"),
+            event("ENTER", "dangling", 10, "t1"),
+        ];
+
+        let roots = build_calls(&events);
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].children[0].function, "panicked");
+        assert_eq!(roots[0].children[0].end, None);
+        assert_eq!(roots[1].function, "dangling");
+        assert_eq!(roots[1].end, None);
+    }
+
+    #[test]
+    fn test_read_agent_info() {
+        let path = std::env::temp_dir().join("flowctl_agent_info.jsonl");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"eventId":"01","event":"AGENT_INFO","timestamp":1,"language":"rust","agentVersion":"1.0.0","schemaVersion":1,"pid":42}"#,
+                "\n",
+                r#"{"event":"ENTER","timestamp":2,"class":"app","method":"a","thread":"t1"}"#,
+                "\n",
+                r#"{"event":"AGENT_INFO","timestamp":3,"language":"java","schemaVersion":2}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        let agents = read_agent_info(&path).unwrap();
+        let events = read_events(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(agents.len(), 2);
+        assert_eq!((agents[0].language.as_str(), agents[0].pid, agents[0].line), ("rust", Some(42), 1));
+        assert_eq!((agents[1].language.as_str(), agents[1].schema_version), ("java", 2));
+        assert!(build_calls(&events).iter().all(|call| call.function == "a"));
+    }
+
+    #[test]
+    fn test_read_compressed_log() {
+        let path = std::env::temp_dir().join("flowctl_compressed.jsonl.zst");
+        let frame = |line: &str| zstd::encode_all(format!("{}\n", line).as_bytes(), 3).unwrap();
+        let mut content = frame(r#"{"event":"ENTER","timestamp":1,"class":"app","method":"a","thread":"t1"}"#);
+        content.extend(frame(r#"{"event":"EXIT","timestamp":2,"class":"app","method":"a","thread":"t1"}"#));
+        // A frame cut short by a crash
+        let last = frame(r#"{"event":"ENTER","timestamp":3,"class":"app","method":"b","thread":"t1"}"#);
+        content.extend(&last[..last.len() / 2]);
+        std::fs::write(&path, content).unwrap();
+
+        let events = read_events(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].event, "EXIT");
+        assert_eq!(events[1].line, 2);
+    }
+
+    #[test]
+    fn test_format_micros() {
+        assert_eq!(format_micros(250), "250µs");
+        assert_eq!(format_micros(1_500), "1.50ms");
+        assert_eq!(format_micros(2_000_000), "2.00s");
+    }
+}
@@ -0,0 +1,466 @@
+//! Interactive trace browser for `flowctl-rs tui`
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table, Wrap};
+use ratatui::Frame;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::trace::{format_micros, Call};
+
+/// Aggregated timings of one function across all loaded traces
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionStats {
+    pub name: String,
+    pub calls: usize,
+    pub failures: usize,
+    pub total_micros: i64,
+    pub max_micros: i64,
+}
+
+impl FunctionStats {
+    pub fn average_micros(&self) -> i64 {
+        self.total_micros / self.calls.max(1) as i64
+    }
+}
+
+/// Per-function stats for every call under `roots`, most total time first
+pub fn function_stats(roots: &[Call]) -> Vec<FunctionStats> {
+    let mut by_name: BTreeMap<String, FunctionStats> = BTreeMap::new();
+    let mut stack: Vec<&Call> = roots.iter().collect();
+
+    while let Some(call) = stack.pop() {
+        stack.extend(&call.children);
+
+        let name = call.name();
+        let stats = by_name.entry(name.clone()).or_insert_with(|| FunctionStats {
+            name,
+            ..Default::default()
+        });
+        let duration = call.duration_micros.unwrap_or(0);
+        stats.calls += 1;
+        stats.failures += usize::from(call.failed());
+        stats.total_micros += duration;
+        stats.max_micros = stats.max_micros.max(duration);
+    }
+
+    let mut stats: Vec<FunctionStats> = by_name.into_values().collect();
+    stats.sort_by_key(|s| std::cmp::Reverse(s.total_micros));
+    stats
+}
+
+/// A visible line of the call tree; `path` holds child indices from the root list
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeRow {
+    pub path: Vec<usize>,
+    pub depth: usize,
+    pub has_children: bool,
+    pub expanded: bool,
+}
+
+/// Browser state, independent of the terminal so it can be unit tested
+pub struct App {
+    roots: Vec<Call>,
+    stats: Vec<FunctionStats>,
+    expanded: HashSet<Vec<usize>>,
+    selected: usize,
+    filter: String,
+    editing_filter: bool,
+}
+
+impl App {
+    pub fn new(roots: Vec<Call>) -> Self {
+        Self {
+            stats: function_stats(&roots),
+            roots,
+            expanded: HashSet::new(),
+            selected: 0,
+            filter: String::new(),
+            editing_filter: false,
+        }
+    }
+
+    fn call(&self, path: &[usize]) -> &Call {
+        let mut call = &self.roots[path[0]];
+        for &index in &path[1..] {
+            call = &call.children[index];
+        }
+        call
+    }
+
+    fn matches(&self, call: &Call) -> bool {
+        self.filter.is_empty()
+            || call.name().to_lowercase().contains(&self.filter.to_lowercase())
+            || call.children.iter().any(|child| self.matches(child))
+    }
+
+    /// Rows currently visible; with a filter, branches leading to matches are expanded
+    pub fn rows(&self) -> Vec<TreeRow> {
+        let mut rows = Vec::new();
+        for (index, root) in self.roots.iter().enumerate() {
+            self.push_rows(root, vec![index], &mut rows);
+        }
+        rows
+    }
+
+    fn push_rows(&self, call: &Call, path: Vec<usize>, rows: &mut Vec<TreeRow>) {
+        if !self.matches(call) {
+            return;
+        }
+
+        let expanded = !self.filter.is_empty() || self.expanded.contains(&path);
+        rows.push(TreeRow {
+            depth: path.len() - 1,
+            has_children: !call.children.is_empty(),
+            expanded,
+            path: path.clone(),
+        });
+
+        if expanded {
+            for (index, child) in call.children.iter().enumerate() {
+                let mut child_path = path.clone();
+                child_path.push(index);
+                self.push_rows(child, child_path, rows);
+            }
+        }
+    }
+
+    fn selected_row(&self, rows: &[TreeRow]) -> Option<TreeRow> {
+        rows.get(self.selected.min(rows.len().saturating_sub(1))).cloned()
+    }
+
+    /// Apply a key press; returns `false` when the browser should exit
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.editing_filter {
+            match key {
+                KeyCode::Enter => self.editing_filter = false,
+                KeyCode::Esc => {
+                    self.editing_filter = false;
+                    self.filter.clear();
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => {}
+            }
+            self.selected = 0;
+            return true;
+        }
+
+        let rows = self.rows();
+        match key {
+            KeyCode::Char('q') => return false,
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(rows.len().saturating_sub(1));
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::PageDown => self.selected = (self.selected + 20).min(rows.len().saturating_sub(1)),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(20),
+            KeyCode::Home | KeyCode::Char('g') => self.selected = 0,
+            KeyCode::End | KeyCode::Char('G') => self.selected = rows.len().saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => {
+                if let Some(row) = self.selected_row(&rows) {
+                    self.expanded.insert(row.path);
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                if let Some(row) = self.selected_row(&rows) {
+                    if row.expanded && row.has_children {
+                        self.expanded.remove(&row.path);
+                    } else if row.depth > 0 {
+                        // Jump to the parent row
+                        let parent = &row.path[..row.path.len() - 1];
+                        if let Some(index) = rows.iter().position(|r| r.path == parent) {
+                            self.selected = index;
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.selected = 0;
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+/// Take over the terminal and browse `roots` until the user quits
+pub fn run(roots: Vec<Call>) -> Result<(), String> {
+    let mut app = App::new(roots);
+    let mut terminal = ratatui::init();
+
+    let result = loop {
+        if let Err(e) = terminal.draw(|frame| draw(frame, &app)) {
+            break Err(format!("Failed to draw: {}", e));
+        }
+
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if !app.handle_key(key.code) {
+                    break Ok(());
+                }
+            }
+            Ok(_) => {}
+            Err(e) => break Err(format!("Failed to read input: {}", e)),
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [tree_area, side] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(body);
+    let [details_area, stats_area] =
+        Layout::vertical([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(side);
+
+    let rows = app.rows();
+    let selected = app.selected_row(&rows);
+    let selected_call = selected.as_ref().map(|row| app.call(&row.path));
+
+    let filter = if app.editing_filter {
+        format!("/{}▏", app.filter)
+    } else if app.filter.is_empty() {
+        String::new()
+    } else {
+        format!("filter: {}", app.filter)
+    };
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled(" FlowTrace ", Style::new().fg(Color::Black).bg(Color::Cyan)),
+            Span::raw(format!(" {} top-level calls  ", app.roots.len())),
+            Span::styled(filter, Style::new().fg(Color::Yellow)),
+        ])),
+        header,
+    );
+
+    draw_tree(frame, tree_area, app, &rows);
+    draw_details(frame, details_area, selected_call);
+    draw_stats(frame, stats_area, app, selected_call);
+
+    frame.render_widget(
+        Paragraph::new(" ↑↓/jk move  →/l expand  ←/h collapse  / filter  Esc clear  q quit")
+            .style(Style::new().fg(Color::DarkGray)),
+        footer,
+    );
+}
+
+fn draw_tree(frame: &mut Frame, area: Rect, app: &App, rows: &[TreeRow]) {
+    let items: Vec<ListItem> = rows
+        .iter()
+        .map(|row| {
+            let call = app.call(&row.path);
+            let marker = match (row.has_children, row.expanded) {
+                (false, _) => "  ",
+                (true, true) => "▾ ",
+                (true, false) => "▸ ",
+            };
+            let duration = call
+                .duration_micros
+                .map(format_micros)
+                .unwrap_or_else(|| "unfinished".to_string());
+            let name_style = if call.failed() {
+                Style::new().fg(Color::Red)
+            } else {
+                Style::new()
+            };
+
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{}{}", "  ".repeat(row.depth), marker)),
+                Span::styled(call.name(), name_style),
+                Span::styled(format!(" {}", duration), Style::new().fg(Color::Cyan)),
+            ]))
+        })
+        .collect();
+
+    let mut state = ListState::default().with_selected((!rows.is_empty()).then_some(app.selected.min(rows.len() - 1)));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(" Calls "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        area,
+        &mut state,
+    );
+}
+
+fn draw_details(frame: &mut Frame, area: Rect, call: Option<&Call>) {
+    let lines = match call {
+        Some(call) => {
+            let field = |label: &str, value: String| {
+                Line::from(vec![
+                    Span::styled(format!("{:<10}", label), Style::new().fg(Color::DarkGray)),
+                    Span::raw(value),
+                ])
+            };
+            let mut lines = vec![
+                field("function", call.name()),
+                field("thread", call.thread.clone()),
+                field(
+                    "duration",
+                    call.duration_micros
+                        .map(format_micros)
+                        .unwrap_or_else(|| "unfinished".to_string()),
+                ),
+                field("line", call.line.to_string()),
+            ];
+            if let Some(args) = &call.args {
+                lines.push(field("args", args.clone()));
+            }
+            if let Some(result) = &call.result {
+                lines.push(field("result", result.clone()));
+            }
+            if let Some(exception) = &call.exception {
+                lines.push(Line::styled(
+                    format!("{:<10}{}", "exception", exception),
+                    Style::new().fg(Color::Red),
+                ));
+            }
+            lines
+        }
+        None => vec![Line::raw("No calls")],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title(" Details ")),
+        area,
+    );
+}
+
+fn draw_stats(frame: &mut Frame, area: Rect, app: &App, selected: Option<&Call>) {
+    let selected_name = selected.map(Call::name);
+    let rows: Vec<Row> = app
+        .stats
+        .iter()
+        .map(|stats| {
+            let style = if selected_name.as_deref() == Some(stats.name.as_str()) {
+                Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+            } else {
+                Style::new()
+            };
+            Row::new(vec![
+                Cell::from(stats.name.clone()),
+                Cell::from(stats.calls.to_string()),
+                Cell::from(stats.failures.to_string()),
+                Cell::from(format_micros(stats.average_micros())),
+                Cell::from(format_micros(stats.max_micros)),
+                Cell::from(format_micros(stats.total_micros)),
+            ])
+            .style(style)
+        })
+        .collect();
+
+    let widths = [
+        Constraint::Min(16),
+        Constraint::Length(6),
+        Constraint::Length(5),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+    ];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(
+                Row::new(vec!["function", "calls", "err", "avg", "max", "total"])
+                    .style(Style::new().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title(" Functions ")),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::build_calls;
+    use crate::trace::tests::event;
+
+    fn app() -> App {
+        App::new(build_calls(&[
+            event("ENTER", "handle", 0, "t1"),
+            event("ENTER", "load", 1, "t1"),
+            event("EXIT", "load", 5, "t1"),
+            event("EXIT", "handle", 10, "t1"),
+            event("ENTER", "handle", 20, "t1"),
+            event("EXIT", "handle", 24, "t1"),
+        ]))
+    }
+
+    fn visible(app: &App) -> Vec<String> {
+        app.rows()
+            .iter()
+            .map(|row| format!("{}{}", "-".repeat(row.depth), app.call(&row.path).function))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_and_collapse() {
+        let mut app = app();
+        assert_eq!(visible(&app), vec!["handle", "handle"]);
+
+        app.handle_key(KeyCode::Right);
+        assert_eq!(visible(&app), vec!["handle", "-load", "handle"]);
+
+        app.handle_key(KeyCode::Down);
+        app.handle_key(KeyCode::Left);
+        assert_eq!(app.selected, 0);
+        app.handle_key(KeyCode::Left);
+        assert_eq!(visible(&app), vec!["handle", "handle"]);
+        assert!(!app.handle_key(KeyCode::Char('q')));
+    }
+
+    #[test]
+    fn test_filter_expands_matching_branches() {
+        let mut app = app();
+        for key in [KeyCode::Char('/'), KeyCode::Char('l'), KeyCode::Char('o'), KeyCode::Enter] {
+            app.handle_key(key);
+        }
+        assert_eq!(visible(&app), vec!["handle", "-load"]);
+
+        app.handle_key(KeyCode::Esc);
+        assert_eq!(visible(&app), vec!["handle", "handle"]);
+    }
+
+    #[test]
+    fn test_draw_renders_panes() {
+        let backend = ratatui::backend::TestBackend::new(100, 20);
+        let mut terminal = ratatui::Terminal::new(backend).unwrap();
+        let app = app();
+        terminal.draw(|frame| draw(frame, &app)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Calls"));
+        assert!(screen.contains("app::handle"));
+        assert!(screen.contains("Functions"));
+    }
+
+    #[test]
+    fn test_function_stats() {
+        let stats = function_stats(&app().roots);
+        assert_eq!(stats[0].name, "app::handle");
+        assert_eq!(stats[0].calls, 2);
+        assert_eq!(stats[0].total_micros, 14);
+        assert_eq!(stats[0].max_micros, 10);
+        assert_eq!(stats[0].average_micros(), 7);
+        assert_eq!(stats[1].name, "app::load");
+    }
+}
@@ -0,0 +1,145 @@
+//! Polling file watcher for `flowctl-rs watch`
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::analyzer::AnalysisStats;
+use crate::workspace;
+
+/// Modification times of every Rust source under a path
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Snapshot {
+    files: HashMap<PathBuf, SystemTime>,
+}
+
+impl Snapshot {
+    /// Record the current state of `path` (a file or directory)
+    pub fn take(path: &Path) -> Self {
+        let files = if path.is_file() {
+            vec![path.to_path_buf()]
+        } else {
+            workspace::rust_files(path)
+        };
+
+        Self {
+            files: files
+                .into_iter()
+                .filter_map(|file| {
+                    let modified = fs::metadata(&file).and_then(|m| m.modified()).ok()?;
+                    Some((file, modified))
+                })
+                .collect(),
+        }
+    }
+
+    /// Files added, modified or removed since `previous`, sorted
+    pub fn changed_since(&self, previous: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(file, modified)| previous.files.get(*file) != Some(modified))
+            .map(|(file, _)| file.clone())
+            .chain(
+                previous
+                    .files
+                    .keys()
+                    .filter(|file| !self.files.contains_key(*file))
+                    .cloned(),
+            )
+            .collect();
+
+        changed.sort();
+        changed
+    }
+}
+
+/// Human-readable changes between two analysis runs, empty if nothing moved
+pub fn stats_delta(before: &AnalysisStats, after: &AnalysisStats) -> Vec<String> {
+    let mut deltas = Vec::new();
+
+    let counts = [
+        ("files", before.total_files, after.total_files),
+        ("functions", before.total_functions, after.total_functions),
+        (
+            "instrumentable",
+            before.instrumentable_functions,
+            after.instrumentable_functions,
+        ),
+        (
+            "instrumented",
+            before.instrumented_functions,
+            after.instrumented_functions,
+        ),
+    ];
+
+    for (label, old, new) in counts {
+        if old != new {
+            deltas.push(format!("{} {:+}", label, new as i64 - old as i64));
+        }
+    }
+
+    if (before.coverage - after.coverage).abs() >= 0.05 {
+        deltas.push(format!("coverage {:.1}% → {:.1}%", before.coverage, after.coverage));
+    }
+
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_changed_since() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+
+        let before = Snapshot {
+            files: HashMap::from([
+                (PathBuf::from("a.rs"), t0),
+                (PathBuf::from("b.rs"), t0),
+                (PathBuf::from("c.rs"), t0),
+            ]),
+        };
+        let after = Snapshot {
+            files: HashMap::from([
+                (PathBuf::from("a.rs"), t0),
+                (PathBuf::from("b.rs"), t1),
+                (PathBuf::from("d.rs"), t0),
+            ]),
+        };
+
+        assert_eq!(
+            after.changed_since(&before),
+            vec![PathBuf::from("b.rs"), PathBuf::from("c.rs"), PathBuf::from("d.rs")]
+        );
+        assert!(after.changed_since(&after).is_empty());
+    }
+
+    #[test]
+    fn test_stats_delta() {
+        let before = AnalysisStats {
+            total_functions: 10,
+            instrumented_functions: 2,
+            instrumentable_functions: 8,
+            coverage: 20.0,
+            ..Default::default()
+        };
+        let after = AnalysisStats {
+            total_functions: 11,
+            instrumented_functions: 4,
+            instrumentable_functions: 7,
+            coverage: 36.4,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stats_delta(&before, &after),
+            vec!["functions +1", "instrumentable -1", "instrumented +2", "coverage 20.0% → 36.4%"]
+        );
+        assert!(stats_delta(&after, &after).is_empty());
+    }
+}
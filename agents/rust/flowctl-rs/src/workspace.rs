@@ -0,0 +1,205 @@
+//! Cargo workspace discovery for directory-wide operations
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::{DirEntry, WalkDir};
+
+/// A crate discovered in a directory or cargo workspace
+#[derive(Debug, Clone)]
+pub struct CrateInfo {
+    pub name: String,
+    pub root: PathBuf,
+}
+
+/// Rust source files grouped by the crate they belong to
+#[derive(Debug, Clone)]
+pub struct CrateSources {
+    pub name: String,
+    pub files: Vec<PathBuf>,
+}
+
+/// Discover workspace member crates via `cargo metadata`
+///
+/// Returns an empty list when `dir` is not part of a cargo project or cargo
+/// is not available.
+pub fn discover_crates(dir: &Path) -> Vec<CrateInfo> {
+    if !dir.join("Cargo.toml").exists() {
+        return Vec::new();
+    }
+
+    let output = match Command::new("cargo")
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .current_dir(dir)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+
+    let metadata: serde_json::Value = match serde_json::from_slice(&output.stdout) {
+        Ok(metadata) => metadata,
+        Err(_) => return Vec::new(),
+    };
+
+    metadata["packages"]
+        .as_array()
+        .map(|packages| {
+            packages
+                .iter()
+                .filter_map(|package| {
+                    let name = package["name"].as_str()?;
+                    let manifest = Path::new(package["manifest_path"].as_str()?);
+                    Some(CrateInfo {
+                        name: name.to_string(),
+                        root: manifest.parent()?.to_path_buf(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Collect the Rust source files under `dir`, grouped per crate
+///
+/// Files are attributed to the innermost crate containing them, so a root
+/// package does not claim the sources of nested workspace members. When `dir`
+/// is not a cargo project all files form a single group named after it.
+pub fn crate_sources(dir: &Path) -> Vec<CrateSources> {
+    let files = rust_files(dir);
+    let crates = discover_crates(dir);
+
+    if crates.is_empty() {
+        let name = dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| dir.file_name().map(|n| n.to_string_lossy().to_string()))
+            .unwrap_or_else(|| dir.display().to_string());
+        return vec![CrateSources { name, files }];
+    }
+
+    let mut groups: Vec<CrateSources> = crates
+        .iter()
+        .map(|info| CrateSources {
+            name: info.name.clone(),
+            files: Vec::new(),
+        })
+        .collect();
+
+    for file in files {
+        let absolute = file.canonicalize().unwrap_or_else(|_| file.clone());
+        let owner = crates
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| absolute.starts_with(&info.root))
+            .max_by_key(|(_, info)| info.root.components().count())
+            .map(|(index, _)| index);
+
+        if let Some(index) = owner {
+            groups[index].files.push(file);
+        }
+    }
+
+    groups.retain(|group| !group.files.is_empty());
+    groups
+}
+
+/// Recursively list `.rs` files under `dir`
+///
+/// Build output, hidden directories and generated files (see [`is_generated`])
+/// are skipped.
+pub fn rust_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| !is_ignored_dir(e))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "rs"))
+        .map(|e| e.into_path())
+        .filter(|path| !is_generated(path))
+        .collect();
+
+    files.sort();
+    files
+}
+
+/// Whether a file's header marks it as generated code
+///
+/// Recognizes the `@generated` marker (prost, protobuf, sqlx, ...) and
+/// bindgen's "automatically generated" banner in the first few lines.
+pub fn is_generated(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+
+    BufReader::new(file)
+        .lines()
+        .take(5)
+        .map_while(Result::ok)
+        .any(|line| line.contains("@generated") || line.contains("automatically generated"))
+}
+
+fn is_ignored_dir(entry: &DirEntry) -> bool {
+    if entry.depth() == 0 || !entry.file_type().is_dir() {
+        return false;
+    }
+
+    let name = entry.file_name().to_string_lossy();
+    name == "target" || name.starts_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_files_skips_target() {
+        let dir = std::env::temp_dir().join("flowctl_workspace_files");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "fn a() {}").unwrap();
+        std::fs::write(dir.join("target/debug/build.rs"), "fn b() {}").unwrap();
+
+        let files = rust_files(&dir);
+        assert_eq!(files, vec![dir.join("src/lib.rs")]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rust_files_skips_generated() {
+        let dir = std::env::temp_dir().join("flowctl_workspace_generated");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.rs"), "fn a() {}").unwrap();
+        std::fs::write(dir.join("proto.rs"), "// This file is @generated by prost-build.\nfn b() {}").unwrap();
+        std::fs::write(dir.join("bindings.rs"), "/* automatically generated by rust-bindgen */\nfn c() {}").unwrap();
+
+        assert_eq!(rust_files(&dir), vec![dir.join("lib.rs")]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_crate_sources_per_member() {
+        let dir = std::env::temp_dir().join("flowctl_workspace_members");
+        let _ = std::fs::remove_dir_all(&dir);
+        for member in ["api", "core"] {
+            std::fs::create_dir_all(dir.join(member).join("src")).unwrap();
+            std::fs::write(
+                dir.join(member).join("Cargo.toml"),
+                format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n", member),
+            )
+            .unwrap();
+            std::fs::write(dir.join(member).join("src/lib.rs"), "fn a() {}").unwrap();
+        }
+        std::fs::write(dir.join("Cargo.toml"), "[workspace]\nmembers = [\"api\", \"core\"]\n").unwrap();
+
+        let names: Vec<String> = crate_sources(&dir).into_iter().map(|sources| sources.name).collect();
+        assert_eq!(names, vec!["api", "core"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+/*
+ * FlowTrace C API
+ *
+ * Link against libflowtrace, built by `cargo build --release` in agents/rust/flowtrace-ffi,
+ * to write events into the same trace stream as the Rust agent. All functions return 0 on success and a
+ * negative FLOWTRACE_* code on failure. Strings are NUL-terminated UTF-8.
+ */
+#ifndef FLOWTRACE_H
+#define FLOWTRACE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A required argument was NULL or not valid UTF-8 */
+#define FLOWTRACE_INVALID_ARGUMENT (-1)
+/* Tracing was already started or the log file could not be opened */
+#define FLOWTRACE_INIT_FAILED (-2)
+
+/* Start tracing from FLOWTRACE_* env vars; non-NULL log_file overrides FLOWTRACE_LOGFILE */
+int flowtrace_init(const char *log_file);
+
+/* args may be NULL */
+int flowtrace_log_enter(const char *module, const char *function, const char *args);
+
+/* result may be NULL; negative duration_micros means unknown */
+int flowtrace_log_exit(const char *module, const char *function, const char *result, int64_t duration_micros);
+
+/* negative duration_micros means unknown */
+int flowtrace_log_exception(const char *module, const char *function, const char *error, int64_t duration_micros);
+
+int flowtrace_flush(void);
+
+/* Stop tracing and close the log file */
+int flowtrace_shutdown(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FLOWTRACE_H */
@@ -0,0 +1,159 @@
+//! The `AGENT_INFO` record that opens every trace stream
+//!
+//! Each FlowTrace language agent writes one before its first event, so tools
+//! merging logs from several processes know which agent, and which version
+//! of the event schema, produced each stream.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ParseError, TimestampFormat};
+
+/// Value of the `event` field of an [`AgentInfo`] record
+pub const AGENT_INFO: &str = "AGENT_INFO";
+
+/// Version of the event schema in `schema/trace-event.schema.json`
+///
+/// Bumped when fields change meaning or are removed; new optional fields
+/// don't require a bump.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Metadata about the agent writing a trace stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AgentInfo {
+    #[serde(default)]
+    pub event_id: String,
+    /// Always [`AGENT_INFO`]
+    pub event: String,
+    /// When the stream was started, in microseconds since the Unix epoch
+    ///
+    /// Written according to `Config::timestamp_format`, like event timestamps.
+    #[serde(deserialize_with = "crate::parse::timestamp_micros")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "crate::schema::timestamp_schema"))]
+    pub timestamp: i64,
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    /// `rust` for this agent; `java`, `node`, ... for the others
+    pub language: String,
+    pub agent_version: String,
+    pub schema_version: u32,
+    pub pid: u32,
+    /// Process this one was forked from, for the header a forked child writes
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub parent_pid: Option<u32>,
+    /// Environment variables named in `Config::env_allowlist` that were set
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub environment: BTreeMap<String, String>,
+    /// Attributes of `Config::resource`, keyed by their OpenTelemetry names
+    /// (`service.name`, `service.version`, `deployment.environment`, ...)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub resource: BTreeMap<String, String>,
+}
+
+impl AgentInfo {
+    /// Describe this agent and process, timestamped now
+    pub fn current() -> Self {
+        let nanos = crate::wall_clock_nanos();
+        Self {
+            event_id: crate::ulid::generate(),
+            event: AGENT_INFO.to_string(),
+            timestamp: nanos / 1000,
+            timestamp_nanos: nanos,
+            language: "rust".to_string(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: SCHEMA_VERSION,
+            pid: std::process::id(),
+            parent_pid: None,
+            environment: BTreeMap::new(),
+            resource: BTreeMap::new(),
+        }
+    }
+
+    /// The same record with a new event ID, timestamped now
+    pub fn restamped(&self) -> Self {
+        let current = Self::current();
+        Self {
+            event_id: current.event_id,
+            timestamp: current.timestamp,
+            timestamp_nanos: current.timestamp_nanos,
+            ..self.clone()
+        }
+    }
+
+    /// Identify the traced service by `resource`
+    pub fn with_resource(mut self, resource: &crate::Resource) -> Self {
+        self.resource = resource.to_attributes();
+        self
+    }
+
+    /// Record the values of the environment variables in `allowlist`
+    ///
+    /// Only listed variables are read, so secrets elsewhere in the
+    /// environment never reach the trace; unset ones are left out.
+    pub fn with_environment(mut self, allowlist: &[String]) -> Self {
+        self.environment = allowlist
+            .iter()
+            .filter_map(|name| Some((name.clone(), std::env::var(name).ok()?)))
+            .collect();
+        self
+    }
+
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(self.timestamp_nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Parse an `AGENT_INFO` line, as written by any FlowTrace agent
+    pub fn from_json_line(line: &str) -> Result<AgentInfo, ParseError> {
+        let info: AgentInfo = crate::parse::from_line(line)?;
+        if info.event != AGENT_INFO {
+            return Err(ParseError::Schema {
+                column: 0,
+                message: format!("expected an {} record, found `{}`", AGENT_INFO, info.event),
+            });
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_info_roundtrip() {
+        let info = AgentInfo::current();
+        let line = info.to_json(TimestampFormat::Rfc3339).unwrap();
+        assert!(line.starts_with(r#"{"eventId":"#), "{}", line);
+        assert!(line.contains(r#""event":"AGENT_INFO""#));
+        assert!(line.contains(r#""language":"rust""#));
+
+        let parsed = AgentInfo::from_json_line(&line).unwrap();
+        assert_eq!(parsed.timestamp, info.timestamp);
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+        assert_eq!(parsed.pid, std::process::id());
+
+        let event = crate::TraceEvent::enter("app", "work", None);
+        let not_info = AgentInfo::from_json_line(&serde_json::to_string(&event).unwrap());
+        assert!(not_info.is_err());
+        assert!(!line.contains("environment"));
+    }
+
+    #[test]
+    fn test_with_environment() {
+        std::env::set_var("FLOWTRACE_TEST_REGION", "eu-west-1");
+        let allowlist = ["FLOWTRACE_TEST_REGION".to_string(), "FLOWTRACE_TEST_UNSET".to_string()];
+        let info = AgentInfo::current().with_environment(&allowlist);
+        std::env::remove_var("FLOWTRACE_TEST_REGION");
+
+        let line = info.to_json(TimestampFormat::EpochMicros).unwrap();
+        let parsed = AgentInfo::from_json_line(&line).unwrap();
+        assert_eq!(parsed.environment.len(), 1);
+        assert_eq!(parsed.environment["FLOWTRACE_TEST_REGION"], "eu-west-1");
+    }
+}
@@ -0,0 +1,186 @@
+//! Periodic per-function aggregates
+//!
+//! With `Config::aggregate_interval_secs` set (or `Config::aggregate_only`),
+//! the logger sums up the calls that finished per function, before any
+//! sampling, and writes one `AGGREGATE` record per function at the end of
+//! each interval. In aggregate-only mode raw events are not written at all,
+//! which makes the agent a low-overhead profiler that can be left on and
+//! switched to full tracing with [`set_aggregate_only`](crate::set_aggregate_only).
+//! Records are written with the next event after the interval ends, and for
+//! the last partial interval when the logger is dropped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EventType, ParseError, TimestampFormat, TraceEvent};
+
+/// Value of the `event` field of an [`Aggregate`] record
+pub const AGGREGATE: &str = "AGGREGATE";
+
+/// Interval used by aggregate-only mode when `Config::aggregate_interval_secs` is unset
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Calls, failures and durations of one function over one interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aggregate {
+    #[serde(default)]
+    pub event_id: String,
+    /// Always [`AGGREGATE`]
+    pub event: String,
+    /// End of the interval, in microseconds since the Unix epoch, written like event timestamps
+    #[serde(deserialize_with = "crate::parse::timestamp_micros")]
+    pub timestamp: i64,
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    #[serde(rename = "class")]
+    pub module: String,
+    #[serde(rename = "method")]
+    pub function: String,
+    /// Length of the interval
+    pub window_seconds: f64,
+    /// Calls that finished (EXIT or EXCEPTION) in the interval
+    pub calls: u64,
+    /// Calls that finished with an EXCEPTION
+    pub errors: u64,
+    /// Sum, minimum and maximum of the durations of calls that reported one
+    pub total_micros: i64,
+    pub min_micros: i64,
+    pub max_micros: i64,
+}
+
+impl Aggregate {
+    /// Mean duration of the calls, in microseconds
+    pub fn mean_micros(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.total_micros as f64 / self.calls as f64
+        }
+    }
+
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(self.timestamp_nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Parse an `AGGREGATE` line
+    pub fn from_json_line(line: &str) -> Result<Aggregate, ParseError> {
+        let aggregate: Aggregate = crate::parse::from_line(line)?;
+        if aggregate.event != AGGREGATE {
+            return Err(ParseError::Schema {
+                column: 0,
+                message: format!("expected an {} record, found `{}`", AGGREGATE, aggregate.event),
+            });
+        }
+        Ok(aggregate)
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    calls: u64,
+    errors: u64,
+    total_micros: i64,
+    min_micros: Option<i64>,
+    max_micros: i64,
+}
+
+/// Sums up calls per function for the current interval
+pub(crate) struct Aggregator {
+    interval: Duration,
+    window_start: Instant,
+    /// Totals by module and function
+    totals: BTreeMap<(String, String), Totals>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            window_start: Instant::now(),
+            totals: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: &TraceEvent) {
+        let failed = match event.event_type {
+            EventType::Enter => return,
+            EventType::Exit => false,
+            EventType::Exception => true,
+        };
+        let totals = self
+            .totals
+            .entry((event.module.clone(), event.function.clone()))
+            .or_default();
+        totals.calls += 1;
+        totals.errors += u64::from(failed);
+        if let Some(duration) = event.duration_micros {
+            totals.total_micros += duration;
+            totals.min_micros = Some(totals.min_micros.map_or(duration, |min| min.min(duration)));
+            totals.max_micros = totals.max_micros.max(duration);
+        }
+    }
+
+    /// Whether the current interval has ended
+    pub(crate) fn is_due(&self) -> bool {
+        self.window_start.elapsed() >= self.interval
+    }
+
+    /// End the current interval, returning a record per function called in it
+    pub(crate) fn take(&mut self) -> Vec<Aggregate> {
+        let window_seconds = self.window_start.elapsed().as_secs_f64();
+        self.window_start = Instant::now();
+        let nanos = crate::wall_clock_nanos();
+
+        std::mem::take(&mut self.totals)
+            .into_iter()
+            .map(|((module, function), totals)| Aggregate {
+                event_id: crate::ulid::generate(),
+                event: AGGREGATE.to_string(),
+                timestamp: nanos / 1000,
+                timestamp_nanos: nanos,
+                module,
+                function,
+                window_seconds,
+                calls: totals.calls,
+                errors: totals.errors,
+                total_micros: totals.total_micros,
+                min_micros: totals.min_micros.unwrap_or(0),
+                max_micros: totals.max_micros,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregates_per_function() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60));
+        aggregator.record(&TraceEvent::enter("app", "charge", None));
+        aggregator.record(&TraceEvent::exit("app", "charge", None, Some(100)));
+        aggregator.record(&TraceEvent::exception("app", "charge", "declined", Some(300)));
+        aggregator.record(&TraceEvent::exit("app", "login", None, Some(20)));
+        assert!(!aggregator.is_due());
+
+        let records = aggregator.take();
+        let charge = &records[0];
+        assert_eq!((charge.function.as_str(), charge.calls, charge.errors), ("charge", 2, 1));
+        assert_eq!((charge.total_micros, charge.min_micros, charge.max_micros), (400, 100, 300));
+        assert_eq!(charge.mean_micros(), 200.0);
+        assert_eq!(records[1].function, "login");
+        assert!(aggregator.take().is_empty());
+
+        let line = charge.to_json(TimestampFormat::Rfc3339).unwrap();
+        let parsed = Aggregate::from_json_line(&line).unwrap();
+        assert_eq!((parsed.module.as_str(), parsed.max_micros), ("app", 300));
+        assert!(Aggregate::from_json_line(&serde_json::to_string(&TraceEvent::enter("app", "a", None)).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Circuit breaker for network sinks
+//!
+//! A sink whose connection attempts keep failing trips the breaker after a few
+//! consecutive failures. While open, events bypass the sink (going to its
+//! fallback, or dropped and counted) and the sink only probes the connection
+//! at a slowly growing interval, so an unreachable collector costs neither CPU
+//! in retry loops nor memory in queued events. A successful probe closes the
+//! breaker again.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::spool::Spool;
+
+/// Tracks consecutive connection failures of one sink
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    failures: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+}
+
+impl CircuitBreaker {
+    /// Trip after `threshold` consecutive failures; retry (then probe) from
+    /// `initial_backoff`, doubling up to `max_backoff`
+    pub(crate) fn new(threshold: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            threshold,
+            failures: 0,
+            initial_backoff,
+            max_backoff,
+            backoff: initial_backoff,
+        }
+    }
+
+    /// Whether events should bypass the sink
+    pub(crate) fn is_open(&self) -> bool {
+        self.failures >= self.threshold
+    }
+
+    /// The sink connected: close the breaker
+    pub(crate) fn record_success(&mut self) {
+        self.failures = 0;
+        self.backoff = self.initial_backoff;
+    }
+
+    /// The sink failed to connect; returns how long to wait before the next attempt
+    pub(crate) fn record_failure(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        let wait = self.backoff;
+        self.backoff = (self.backoff * 2).min(self.max_backoff);
+        wait
+    }
+}
+
+/// Where a sink's events go while its breaker is open, shared with the logging threads
+pub(crate) struct Fallback {
+    /// Spool replayed once the sink recovers, used instead of `path` when set
+    spool: Option<Spool>,
+    /// JSONL file appended to, or `None` to drop events
+    path: Option<String>,
+    /// First line written to a newly opened file, e.g. `AGENT_INFO`
+    header: String,
+    file: Mutex<Option<File>>,
+    open: AtomicBool,
+    diverted: AtomicU64,
+    spooled: AtomicU64,
+    replayed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Fallback {
+    pub(crate) fn new(spool: Option<Spool>, path: Option<String>, header: String) -> Self {
+        Self {
+            spool,
+            path,
+            header,
+            file: Mutex::new(None),
+            open: AtomicBool::new(false),
+            diverted: AtomicU64::new(0),
+            spooled: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the sink's breaker is open
+    pub(crate) fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_open(&self, open: bool) {
+        self.open.store(open, Ordering::Release);
+    }
+
+    /// Spool `json`, append it to the fallback file, or count it as dropped
+    pub(crate) fn divert(&self, json: &str) {
+        if let Some(spool) = &self.spool {
+            let (written, evicted) = spool.append(json);
+            if written {
+                self.spooled.fetch_add(1, Ordering::Relaxed);
+            }
+            self.dropped.fetch_add(evicted + u64::from(!written), Ordering::Relaxed);
+            return;
+        }
+
+        let written = self.path.as_ref().is_some_and(|path| {
+            let Ok(mut file) = self.file.lock() else {
+                return false;
+            };
+            if file.is_none() {
+                *file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut opened| writeln!(opened, "{}", self.header).map(|_| opened))
+                    .ok();
+            }
+            file.as_mut().is_some_and(|file| writeln!(file, "{}", json).is_ok())
+        });
+
+        let counter = if written { &self.diverted } else { &self.dropped };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The spool to replay once the sink recovers
+    pub(crate) fn spool(&self) -> Option<&Spool> {
+        self.spool.as_ref()
+    }
+
+    /// Count a spooled event as sent again
+    pub(crate) fn record_replayed(&self) {
+        self.replayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Events written to the fallback file so far
+    pub(crate) fn diverted(&self) -> u64 {
+        self.diverted.load(Ordering::Relaxed)
+    }
+
+    /// Events written to the spool so far
+    pub(crate) fn spooled(&self) -> u64 {
+        self.spooled.load(Ordering::Relaxed)
+    }
+
+    /// Spooled events sent again so far
+    pub(crate) fn replayed(&self) -> u64 {
+        self.replayed.load(Ordering::Relaxed)
+    }
+
+    /// Events lost so far
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trips_and_recovers() {
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(1), Duration::from_secs(5));
+        assert!(!breaker.is_open());
+
+        let waits: Vec<u64> = (0..5).map(|_| breaker.record_failure().as_secs()).collect();
+        assert_eq!(waits, [1, 2, 4, 5, 5]);
+        assert!(breaker.is_open());
+
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        assert_eq!(breaker.record_failure(), Duration::from_secs(1));
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn test_fallback_accounts_for_events() {
+        let path = std::env::temp_dir().join("flowtrace_breaker_fallback.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let fallback = Fallback::new(None, Some(path.display().to_string()), "header".to_string());
+        fallback.divert("one");
+        fallback.divert("two");
+        assert_eq!((fallback.diverted(), fallback.dropped()), (2, 0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "header\none\ntwo\n");
+
+        let dropping = Fallback::new(None, None, String::new());
+        dropping.divert("one");
+        assert_eq!((dropping.diverted(), dropping.dropped()), (0, 1));
+        std::fs::remove_file(path).unwrap();
+
+        let dir = std::env::temp_dir().join("flowtrace_breaker_spool");
+        let _ = std::fs::remove_dir_all(&dir);
+        let spooling = Fallback::new(Some(Spool::open(&dir, 6).unwrap()), None, String::new());
+        spooling.divert("one");
+        spooling.divert("two");
+        assert_eq!((spooling.spooled(), spooling.dropped()), (2, 1));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
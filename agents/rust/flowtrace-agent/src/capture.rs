@@ -0,0 +1,105 @@
+//! Keeping a request's events in memory
+//!
+//! An [`EventCapture`] attached to a trace context (see
+//! [`TraceContext::with_capture`]) receives a copy of every event logged in
+//! that context, and in the contexts of work bound from it, up to
+//! [`MAX_CAPTURED_EVENTS`]. A handler can then show the timing of the request
+//! so far, e.g. in the response to a debug flag. Events are copied before
+//! sampling and sink filters, so dropped events are captured too; nothing is
+//! captured while the tracer is stopped.
+
+use std::sync::{Arc, Mutex};
+
+use crate::TraceEvent;
+
+/// Events an [`EventCapture`] keeps; later ones are counted in [`EventCapture::dropped`]
+pub const MAX_CAPTURED_EVENTS: usize = 1000;
+
+/// Events logged in one trace context, shared by its clones
+#[derive(Debug, Clone, Default)]
+pub struct EventCapture {
+    inner: Arc<Mutex<Captured>>,
+}
+
+#[derive(Debug, Default)]
+struct Captured {
+    events: Vec<TraceEvent>,
+    dropped: usize,
+}
+
+impl EventCapture {
+    /// An empty capture, to attach with [`TraceContext::with_capture`](crate::TraceContext::with_capture)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events captured so far, in the order they were logged
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.inner.lock().map(|captured| captured.events.clone()).unwrap_or_default()
+    }
+
+    /// Events left out past [`MAX_CAPTURED_EVENTS`]
+    pub fn dropped(&self) -> usize {
+        self.inner.lock().map(|captured| captured.dropped).unwrap_or_default()
+    }
+
+    fn record(&self, event: &TraceEvent) {
+        let Ok(mut captured) = self.inner.lock() else {
+            return;
+        };
+        if captured.events.len() == MAX_CAPTURED_EVENTS {
+            captured.dropped += 1;
+            return;
+        }
+        let mut event = event.clone();
+        // The context holds this capture, which must not hold itself
+        event.context = None;
+        captured.events.push(event);
+    }
+}
+
+/// Copy `event` into the capture of its context, if any
+pub(crate) fn record(event: &TraceEvent) {
+    if let Some(capture) = event.context.as_ref().and_then(|context| context.capture()) {
+        capture.record(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_follows_context() {
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: String::new(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let capture = EventCapture::new();
+        let context = crate::TraceContext::new_root().with_capture(capture.clone());
+        crate::with_tracer(&tracer, || {
+            context.scope(|| {
+                crate::log_event(TraceEvent::enter("app", "handle", None));
+                crate::log_event(TraceEvent::exit("app", "handle", None, Some(10)));
+            });
+            crate::log_event(TraceEvent::enter("app", "elsewhere", None));
+            crate::log_event(TraceEvent::exit("app", "elsewhere", None, Some(10)));
+            for _ in 0..MAX_CAPTURED_EVENTS {
+                context.scope(|| crate::log_event(TraceEvent::enter("app", "loop", None)));
+            }
+        });
+        tracer.stop();
+
+        let events = capture.events();
+        assert_eq!(events.len(), MAX_CAPTURED_EVENTS);
+        assert_eq!(capture.dropped(), 2);
+        assert_eq!(events[1].function, "handle");
+        assert_eq!(events[1].trace_id.as_deref(), Some(context.trace_id()));
+        assert!(events.iter().all(|event| event.function != "elsewhere" && event.context.is_none()));
+    }
+}
@@ -0,0 +1,94 @@
+//! Per-second cap on captured argument and result bytes
+//!
+//! With `Config::max_capture_bytes_per_sec` set, the `args` and `result` of
+//! the events written in each second share that many bytes. The first event
+//! that would go over it, and every later one in the same second, has its
+//! payloads replaced with [`CAPTURE_SUPPRESSED`]: calls are still recorded,
+//! with their timing and outcome, only without their values. Exception
+//! messages are never suppressed.
+
+use std::time::{Duration, Instant};
+
+use crate::TraceEvent;
+
+/// What `args` and `result` read once the capture budget is spent
+pub const CAPTURE_SUPPRESSED: &str = "<capture suppressed>";
+
+/// Length of the window the budget covers
+const WINDOW: Duration = Duration::from_secs(1);
+
+pub(crate) struct CaptureBudget {
+    max_bytes: u64,
+    window_start: Instant,
+    used_bytes: u64,
+    /// Set once an event went over the budget; cleared with the next window
+    spent: bool,
+}
+
+impl CaptureBudget {
+    pub(crate) fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            window_start: Instant::now(),
+            used_bytes: 0,
+            spent: false,
+        }
+    }
+
+    /// Charge the payloads of `event` to the budget, suppressing them when it is spent
+    pub(crate) fn apply(&mut self, event: &mut TraceEvent, now: Instant) {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.used_bytes = 0;
+            self.spent = false;
+        }
+
+        let bytes = [&event.args, &event.result].into_iter().flatten().map(|payload| payload.len() as u64).sum::<u64>();
+        if bytes == 0 {
+            return;
+        }
+        if !self.spent && self.used_bytes + bytes <= self.max_bytes {
+            self.used_bytes += bytes;
+            return;
+        }
+        self.spent = true;
+        for payload in [&mut event.args, &mut event.result].into_iter().flatten() {
+            *payload = CAPTURE_SUPPRESSED.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(args: &str) -> TraceEvent {
+        TraceEvent::enter("app", "work", Some(args.to_string()))
+    }
+
+    #[test]
+    fn test_budget_suppresses_until_next_window() {
+        let start = Instant::now();
+        let mut budget = CaptureBudget::new(10);
+        budget.window_start = start;
+
+        let mut first = call("123456");
+        budget.apply(&mut first, start);
+        assert_eq!(first.args.as_deref(), Some("123456"));
+
+        let mut over = call("123456");
+        budget.apply(&mut over, start);
+        assert_eq!(over.args.as_deref(), Some(CAPTURE_SUPPRESSED));
+        // Still within the budget, but after it was spent
+        let mut small = TraceEvent::exit("app", "work", Some("1".to_string()), Some(5));
+        budget.apply(&mut small, start + Duration::from_millis(500));
+        assert_eq!(small.result.as_deref(), Some(CAPTURE_SUPPRESSED));
+        let mut exception = TraceEvent::exception("app", "work", "failed", Some(5));
+        budget.apply(&mut exception, start);
+        assert_eq!(exception.exception.as_deref(), Some("failed"));
+
+        let mut next = call("123456");
+        budget.apply(&mut next, start + WINDOW);
+        assert_eq!(next.args.as_deref(), Some("123456"));
+    }
+}
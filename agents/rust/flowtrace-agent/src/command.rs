@@ -0,0 +1,261 @@
+//! Tracing subprocesses
+//!
+//! [`TracedCommand`] runs a [`std::process::Command`] as one call: ENTER with
+//! the program and its arguments when it is spawned, then EXIT with its exit
+//! code, or EXCEPTION when it could not be started, exited with a non-zero
+//! code or was killed by a signal. With
+//! [`propagate_context`](TracedCommand::propagate_context) the child also gets
+//! the trace in its `TRACEPARENT` environment variable, which the agent reads
+//! at startup, so a child tracing with FlowTrace logs into the same trace.
+
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output};
+
+use crate::{TraceContext, TraceEvent};
+
+/// Environment variable a child process inherits its trace from
+pub const TRACEPARENT_ENV: &str = "TRACEPARENT";
+
+/// A [`Command`] whose runs are traced as calls of `module::<program>`
+///
+/// ```rust,no_run
+/// use std::process::Command;
+/// use flowtrace_agent::TracedCommand;
+///
+/// let mut git = Command::new("git");
+/// git.args(["status", "--short"]);
+/// let output = TracedCommand::new(module_path!(), git).propagate_context().output()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TracedCommand {
+    command: Command,
+    module: String,
+    propagate: bool,
+}
+
+impl TracedCommand {
+    /// Wrap `command`, logging its runs in `module`
+    pub fn new(module: &str, command: Command) -> Self {
+        Self {
+            command,
+            module: module.to_string(),
+            propagate: false,
+        }
+    }
+
+    /// Pass the trace to the child in [`TRACEPARENT_ENV`]
+    pub fn propagate_context(mut self) -> Self {
+        self.propagate = true;
+        self
+    }
+
+    /// Run the command to completion, like [`Command::status`]
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        let call = self.start();
+        let status = self.command.status();
+        call.finish(status.as_ref().map_err(ToString::to_string).copied());
+        status
+    }
+
+    /// Run the command to completion collecting its output, like [`Command::output`]
+    pub fn output(&mut self) -> io::Result<Output> {
+        let call = self.start();
+        let output = self.command.output();
+        call.finish(output.as_ref().map(|output| output.status).map_err(ToString::to_string));
+        output
+    }
+
+    /// Start the command, like [`Command::spawn`]; the call ends when the child is waited for
+    pub fn spawn(&mut self) -> io::Result<TracedChild> {
+        let call = self.start();
+        match self.command.spawn() {
+            Ok(child) => Ok(TracedChild {
+                child: Some(child),
+                call: Some(call),
+            }),
+            Err(error) => {
+                call.finish(Err(error.to_string()));
+                Err(error)
+            }
+        }
+    }
+
+    /// Log ENTER and pass the trace on, returning the call to finish
+    fn start(&mut self) -> Call {
+        let program = self.command.get_program().to_string_lossy().into_owned();
+        let function = std::path::Path::new(&program)
+            .file_name()
+            .map_or_else(|| program.clone(), |name| name.to_string_lossy().into_owned());
+        let args: Vec<String> = self.command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        let args = serde_json::json!({ "program": program, "args": args }).to_string();
+
+        // The ENTER starts a trace if the caller isn't in one
+        let context = TraceContext::current().unwrap_or_else(TraceContext::new_or_inherited);
+        context.scope(|| crate::log_event(TraceEvent::enter(&self.module, &function, Some(args))));
+        if self.propagate {
+            self.command.env(TRACEPARENT_ENV, context.traceparent());
+        }
+
+        Call {
+            module: self.module.clone(),
+            function,
+            context,
+            start: crate::monotonic_micros(),
+        }
+    }
+}
+
+/// A running child process, see [`TracedCommand::spawn`]
+///
+/// Its call ends when it is waited for; a child dropped before then is
+/// logged as EXIT with the result `"detached"`.
+pub struct TracedChild {
+    /// Taken by `wait_with_output`, which consumes it
+    child: Option<Child>,
+    call: Option<Call>,
+}
+
+impl TracedChild {
+    /// The child process, e.g. for its pid or pipes
+    pub fn child(&mut self) -> &mut Child {
+        self.child.as_mut().expect("child is only taken when consumed")
+    }
+
+    /// Wait for the child to exit, like [`Child::wait`]
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child().wait();
+        if let Some(call) = self.call.take() {
+            call.finish(status.as_ref().map_err(ToString::to_string).copied());
+        }
+        status
+    }
+
+    /// Wait for the child to exit collecting its output, like [`Child::wait_with_output`]
+    pub fn wait_with_output(mut self) -> io::Result<Output> {
+        let child = self.child.take().expect("child is only taken when consumed");
+        let output = child.wait_with_output();
+        if let Some(call) = self.call.take() {
+            call.finish(output.as_ref().map(|output| output.status).map_err(ToString::to_string));
+        }
+        output
+    }
+}
+
+impl Drop for TracedChild {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            let detached = Some("\"detached\"".to_string());
+            call.log(TraceEvent::exit(&call.module, &call.function, detached, Some(call.elapsed())));
+        }
+    }
+}
+
+/// A run of a command between its ENTER and closing event
+struct Call {
+    module: String,
+    function: String,
+    context: TraceContext,
+    /// Start on the `monotonic_micros` clock
+    start: i64,
+}
+
+impl Call {
+    fn elapsed(&self) -> i64 {
+        crate::monotonic_micros() - self.start
+    }
+
+    /// Log EXIT for a successful run, EXCEPTION otherwise
+    fn finish(self, status: Result<ExitStatus, String>) {
+        let duration = Some(self.elapsed());
+        let event = match status {
+            Ok(status) if status.success() => {
+                TraceEvent::exit(&self.module, &self.function, Some(r#"{"exitCode": 0}"#.to_string()), duration)
+            }
+            Ok(status) => TraceEvent::exception(&self.module, &self.function, &describe(status), duration),
+            Err(error) => TraceEvent::exception(&self.module, &self.function, &error, duration),
+        };
+        self.log(event);
+    }
+
+    fn log(&self, event: TraceEvent) {
+        self.context.scope(|| crate::log_event(event));
+    }
+}
+
+/// Why a run failed: its exit code, or the signal that ended it
+fn describe(status: ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exit code {}", code);
+    }
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return format!("killed by signal {}", signal);
+    }
+    status.to_string()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Events logged by `f` to a tracer of its own
+    fn traced<R>(name: &str, f: impl FnOnce() -> R) -> (R, Vec<TraceEvent>) {
+        let path = std::env::temp_dir().join(format!("flowtrace_command_{}.jsonl", name));
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+        let result = crate::with_tracer(&tracer, f);
+        tracer.stop();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (result, log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect())
+    }
+
+    fn sh(script: &str) -> TracedCommand {
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", script]);
+        TracedCommand::new("app", command)
+    }
+
+    #[test]
+    fn test_status() {
+        let (status, events) = traced("status", || sh("exit 3").status().unwrap());
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].function, "sh");
+        assert_eq!(events[0].args.as_deref(), Some(r#"{"program":"/bin/sh","args":["-c","exit 3"]}"#));
+        assert_eq!(events[1].exception.as_deref(), Some("exit code 3"));
+        assert_eq!(events[0].trace_id, events[1].trace_id);
+
+        let (_, events) = traced("missing", || TracedCommand::new("app", Command::new("/nonexistent/tool")).status());
+        assert_eq!(events[1].function, "tool");
+        assert_eq!(events[1].status, Some(crate::Status::Error));
+    }
+
+    #[test]
+    fn test_propagate_context() {
+        let (output, events) = traced("propagate", || sh("echo $TRACEPARENT").propagate_context().output().unwrap());
+        let header = String::from_utf8(output.stdout).unwrap();
+        let context = TraceContext::from_traceparent(&header).unwrap();
+        assert_eq!(Some(context.trace_id()), events[0].trace_id.as_deref());
+        assert_eq!(events[1].result.as_deref(), Some(r#"{"exitCode": 0}"#));
+    }
+
+    #[test]
+    fn test_spawn() {
+        let (_, events) = traced("spawn", || {
+            sh("exit 0").spawn().unwrap().wait().unwrap();
+            drop(sh("sleep 0").spawn().unwrap());
+        });
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[1].status, Some(crate::Status::Ok));
+        assert_eq!(events[3].result.as_deref(), Some(r#""detached""#));
+    }
+}
@@ -1,18 +1,132 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::runtime::Runtime;
+use crate::{FlowTraceError, Level, Resource, SinkFormats, TraceEvent};
 
 /// Configuration for FlowTrace agent
 #[derive(Debug, Clone)]
 pub struct Config {
+    /// Whether tracing is off, a dry run, or on
+    pub mode: Mode,
     pub package_prefix: String,
     pub log_file: String,
     pub stdout: bool,
     pub max_arg_length: usize,
+    /// Cut captured arguments and results past this many levels of nesting
+    /// to `{...}`, `[...]` or `(...)`; `#[trace(max_depth = N)]` overrides it
+    pub max_arg_depth: Option<usize>,
+    /// Bytes of `args` and `result` written per second; past it, payloads read
+    /// `"<capture suppressed>"` until the next second
+    pub max_capture_bytes_per_sec: Option<u64>,
+    /// Only write events at this level or above (see `TraceEvent::effective_level`)
+    pub min_level: Option<Level>,
+    /// Minimum levels of individual sinks, overriding `min_level`
+    pub sink_levels: SinkLevels,
+    /// Module and duration filters of individual sinks, applied after the global ones
+    pub sink_filters: SinkFilters,
+    /// How the log file and stdout render each record
+    pub formats: SinkFormats,
+    /// How event timestamps are written
+    pub timestamp_format: TimestampFormat,
+    /// How the log file is compressed
+    pub compression: Compression,
+    /// Cap on the bytes used by the log file and its rotations
+    pub max_total_disk_bytes: Option<u64>,
+    /// What to do when `max_total_disk_bytes` is reached
+    pub quota_action: QuotaAction,
+    /// Keep this fraction of root calls, in `(0, 1]`; ignored when
+    /// `max_events_per_second` is set
+    pub sample_rate: Option<f64>,
+    /// Sample root calls to log at most about this many events per second
+    pub max_events_per_second: Option<u32>,
+    /// File of per-function overrides, one per line like
+    /// `billing::charge_card capture for=10m`, checked for changes every few seconds
+    pub control_file: Option<String>,
+    /// Write per-function `ERROR_RATE` records at this interval
+    pub error_rate_interval_secs: Option<u64>,
+    /// Write only the first of identical exceptions in each window of this
+    /// many seconds, then a `REPEATED` record counting the rest
+    pub coalesce_window_secs: Option<u64>,
+    /// Write per-function `AGGREGATE` records at this interval
+    pub aggregate_interval_secs: Option<u64>,
+    /// Write only `AGGREGATE` records (every 60 seconds unless
+    /// `aggregate_interval_secs` is set), no raw events
+    pub aggregate_only: bool,
+    /// Measure the agent's own cost and write an `OVERHEAD` record at this interval
+    pub overhead_interval_secs: Option<u64>,
+    /// Flush the log file and write due periodic records on a timer, rather
+    /// than only when the next event is logged
+    pub flush_interval_ms: Option<u64>,
+    /// Where timers run; a std thread ([`ThreadRuntime`](crate::runtime::ThreadRuntime)) if unset
+    pub runtime: Option<Arc<dyn Runtime>>,
+    /// Only log events from these modules (and their submodules); all if empty
+    pub modules: Vec<String>,
+    /// Never log events from these modules (and their submodules)
+    pub exclude_modules: Vec<String>,
+    /// Open the stream with an `AGENT_INFO` record
+    pub agent_info: bool,
+    /// Environment variables (e.g. `DEPLOY_ENV`, `REGION`) recorded in `AGENT_INFO`;
+    /// no others are read
+    pub env_allowlist: Vec<String>,
+    /// Identity of the traced service, recorded in `AGENT_INFO` with OTel attribute names
+    pub resource: Resource,
+    /// Also stream events to WebSocket clients connecting to this address
+    /// (requires the `websocket` feature)
+    pub websocket_addr: Option<String>,
+    /// Also stream events to the gRPC collector at this URL
+    /// (requires the `grpc` feature)
+    pub grpc_endpoint: Option<String>,
+    /// Where gRPC events go while the collector is unreachable; dropped (and counted) if unset
+    pub grpc_fallback_file: Option<String>,
+    /// How network exporters reconnect after failures
+    pub export_retry: RetryPolicy,
+    /// Connections each network exporter sends over in parallel; events of
+    /// one trace (or, without a trace ID, one thread) always share a connection
+    pub exporter_workers: usize,
+    /// Callbacks run for every event passing the filters, sampling and `min_level`
+    pub hooks: Vec<EventHook>,
+    /// Spool records network exporters cannot deliver in this directory, replaying
+    /// them once the collector is back; takes precedence over `grpc_fallback_file`
+    pub spool_dir: Option<String>,
+    /// Size cap of `spool_dir`, beyond which the oldest spooled records are dropped
+    pub spool_max_bytes: u64,
 }
 
 impl Config {
+    /// Start building a configuration from the defaults
+    ///
+    /// ```rust
+    /// use flowtrace_agent::{Config, Sink};
+    ///
+    /// let config = Config::builder()
+    ///     .log_file("traces/app.jsonl")
+    ///     .sample_rate(0.1)
+    ///     .sink(Sink::Stdout)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(config.sample_rate, Some(0.1));
+    /// ```
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
     /// Create configuration from environment variables
     pub fn from_env() -> Self {
         Self {
+            mode: env::var("FLOWTRACE_MODE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
             package_prefix: env::var("FLOWTRACE_PACKAGE_PREFIX").unwrap_or_default(),
             log_file: env::var("FLOWTRACE_LOGFILE").unwrap_or_else(|_| "flowtrace.jsonl".to_string()),
             stdout: env::var("FLOWTRACE_STDOUT").map(|v| v == "true").unwrap_or(false),
@@ -20,17 +134,1332 @@ impl Config {
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(1000),
+            max_arg_depth: env::var("FLOWTRACE_MAX_ARG_DEPTH").ok().and_then(|v| v.parse().ok()),
+            max_capture_bytes_per_sec: env::var("FLOWTRACE_MAX_CAPTURE_BYTES_PER_SEC")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            min_level: env::var("FLOWTRACE_MIN_LEVEL").ok().and_then(|v| v.parse().ok()),
+            sink_levels: SinkLevels::from_env(),
+            formats: SinkFormats::from_env(),
+            timestamp_format: env::var("FLOWTRACE_TIMESTAMP_FORMAT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            compression: env::var("FLOWTRACE_COMPRESSION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            max_total_disk_bytes: env::var("FLOWTRACE_MAX_TOTAL_DISK_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            quota_action: env::var("FLOWTRACE_QUOTA_ACTION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            sample_rate: env::var("FLOWTRACE_SAMPLE_RATE")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            max_events_per_second: env::var("FLOWTRACE_MAX_EVENTS_PER_SECOND")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            control_file: env::var("FLOWTRACE_CONTROL_FILE").ok(),
+            error_rate_interval_secs: env::var("FLOWTRACE_ERROR_RATE_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs| secs > 0),
+            coalesce_window_secs: env::var("FLOWTRACE_COALESCE_WINDOW_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs| secs > 0),
+            aggregate_interval_secs: env::var("FLOWTRACE_AGGREGATE_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs| secs > 0),
+            aggregate_only: env::var("FLOWTRACE_AGGREGATE_ONLY").map(|v| v == "true").unwrap_or(false),
+            overhead_interval_secs: env::var("FLOWTRACE_OVERHEAD_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs| secs > 0),
+            flush_interval_ms: env::var("FLOWTRACE_FLUSH_INTERVAL_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&millis| millis > 0),
+            agent_info: env::var("FLOWTRACE_AGENT_INFO").map(|v| v != "false").unwrap_or(true),
+            env_allowlist: env::var("FLOWTRACE_ENV_ALLOWLIST")
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            resource: Resource::from_env(),
+            websocket_addr: env::var("FLOWTRACE_WEBSOCKET_ADDR").ok().filter(|v| !v.is_empty()),
+            grpc_endpoint: env::var("FLOWTRACE_GRPC_ENDPOINT").ok().filter(|v| !v.is_empty()),
+            grpc_fallback_file: env::var("FLOWTRACE_GRPC_FALLBACK_FILE").ok().filter(|v| !v.is_empty()),
+            export_retry: RetryPolicy::from_env(),
+            exporter_workers: env::var("FLOWTRACE_EXPORTER_WORKERS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&workers| workers > 0)
+                .unwrap_or(1),
+            hooks: Vec::new(),
+            spool_dir: env::var("FLOWTRACE_SPOOL_DIR").ok().filter(|v| !v.is_empty()),
+            spool_max_bytes: env::var("FLOWTRACE_SPOOL_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_SPOOL_MAX_BYTES),
+            ..Default::default()
+        }
+    }
+
+    /// Load configuration from a `flowtrace.toml` file
+    ///
+    /// Reads the `[agent]` section and applies the profile named by the
+    /// `FLOWTRACE_PROFILE` environment variable, or by `profile` in `[agent]`.
+    /// Profiles are shared with `flowctl-rs instrument --profile`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, FlowTraceError> {
+        let profile = env::var("FLOWTRACE_PROFILE").ok();
+        Self::from_file_with_profile(path, profile.as_deref())
+    }
+
+    /// Load configuration from a `flowtrace.toml` file using a specific profile
+    ///
+    /// With `profile` set to `None`, the `profile` key of `[agent]` is used if present.
+    pub fn from_file_with_profile(path: impl AsRef<Path>, profile: Option<&str>) -> Result<Self, FlowTraceError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)))?;
+        let file: FileConfig = toml::from_str(&content).map_err(|e| ConfigError::Invalid {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+
+        let mut config = Self::default();
+        file.agent.settings.apply(&mut config);
+
+        if let Some(name) = profile.or(file.agent.profile.as_deref()) {
+            let profile = file
+                .profiles
+                .get(name)
+                .ok_or_else(|| ConfigError::UnknownProfile {
+                    profile: name.to_string(),
+                    path: path.display().to_string(),
+                })?;
+            profile.settings.apply(&mut config);
+            config.modules = profile.modules.clone();
+            config.exclude_modules = profile.exclude_modules.clone();
         }
+
+        Ok(config)
+    }
+
+    /// Whether events from `module` pass the `modules`/`exclude_modules` filters
+    ///
+    /// Patterns match a module and its submodules, with or without the leading
+    /// crate name, so `billing` matches `shop::billing::invoice`.
+    pub fn allows_module(&self, module: &str) -> bool {
+        let matches = |pattern: &String| module_matches(module, pattern);
+
+        (self.modules.is_empty() || self.modules.iter().any(matches))
+            && !self.exclude_modules.iter().any(matches)
+    }
+
+    /// Whether a sink with the minimum level `sink_level` writes events at `level`
+    pub(crate) fn sink_allows(&self, sink_level: Option<Level>, level: Level) -> bool {
+        sink_level.or(self.min_level).is_none_or(|min_level| level >= min_level)
+    }
+
+    /// Check the configuration before tracing starts
+    ///
+    /// Besides the checks of [`ConfigBuilder::build`], fails if `log_file` or
+    /// `grpc_fallback_file` could not be written, e.g. because its directory
+    /// does not exist. `Tracer::start` runs this, so a bad path is reported
+    /// up front instead of events silently going nowhere.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.check_options()?;
+        check_writable("log_file", &self.log_file)?;
+        if let Some(path) = &self.grpc_fallback_file {
+            check_writable("grpc_fallback_file", path)?;
+        }
+        Ok(())
+    }
+
+    /// Connect to the collector at `grpc_endpoint`, failing if it does not answer
+    ///
+    /// Optional: the exporter otherwise rides out a collector that is down at
+    /// startup by retrying, spooling or falling back.
+    pub fn probe_exporters(&self) -> Result<(), ConfigError> {
+        match &self.grpc_endpoint {
+            Some(endpoint) => probe("grpc_endpoint", endpoint),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks of the options alone, without touching the file system
+    fn check_options(&self) -> Result<(), ConfigError> {
+        if let Some(rate) = self.sample_rate {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(ConfigError::InvalidSampleRate(rate));
+            }
+            if self.max_events_per_second.is_some() {
+                return Err(ConfigError::ConflictingSampling);
+            }
+        }
+        if self.max_events_per_second == Some(0) {
+            return Err(ConfigError::Zero("max_events_per_second"));
+        }
+        if self.max_capture_bytes_per_sec == Some(0) {
+            return Err(ConfigError::Zero("max_capture_bytes_per_sec"));
+        }
+        if self.error_rate_interval_secs == Some(0) {
+            return Err(ConfigError::Zero("error_rate_interval_secs"));
+        }
+        if self.coalesce_window_secs == Some(0) {
+            return Err(ConfigError::Zero("coalesce_window_secs"));
+        }
+        if self.aggregate_interval_secs == Some(0) {
+            return Err(ConfigError::Zero("aggregate_interval_secs"));
+        }
+        if self.overhead_interval_secs == Some(0) {
+            return Err(ConfigError::Zero("overhead_interval_secs"));
+        }
+        if self.flush_interval_ms == Some(0) {
+            return Err(ConfigError::Zero("flush_interval_ms"));
+        }
+        if self.exporter_workers == 0 {
+            return Err(ConfigError::Zero("exporter_workers"));
+        }
+
+        if self.log_file.is_empty() {
+            if self.compression != Compression::None {
+                return Err(ConfigError::NeedsLogFile("compression"));
+            }
+            if self.max_total_disk_bytes.is_some() {
+                return Err(ConfigError::NeedsLogFile("max_total_disk_bytes"));
+            }
+        }
+        if self.grpc_endpoint.is_none() {
+            if self.grpc_fallback_file.is_some() {
+                return Err(ConfigError::NeedsGrpcEndpoint("grpc_fallback_file"));
+            }
+            if self.spool_dir.is_some() {
+                return Err(ConfigError::NeedsGrpcEndpoint("spool_dir"));
+            }
+        }
+
+        let missing_feature = |option, feature| Err(ConfigError::MissingFeature { option, feature });
+        if cfg!(not(feature = "zstd")) && self.compression == Compression::Zstd {
+            return missing_feature("compression", "zstd");
+        }
+        if cfg!(not(feature = "websocket")) && self.websocket_addr.is_some() {
+            return missing_feature("websocket_addr", "websocket");
+        }
+        if cfg!(not(feature = "grpc")) && self.grpc_endpoint.is_some() {
+            return missing_feature("grpc_endpoint", "grpc");
+        }
+
+        check_patterns("modules", &self.modules)?;
+        check_patterns("exclude_modules", &self.exclude_modules)?;
+        self.sink_filters.check()
+    }
+}
+
+/// Module patterns are paths like `billing` or `shop::billing`
+fn check_patterns(option: &'static str, patterns: &[String]) -> Result<(), ConfigError> {
+    let is_path = |pattern: &String| {
+        pattern
+            .split("::")
+            .all(|segment| !segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_'))
+    };
+    match patterns.iter().find(|pattern| !is_path(pattern)) {
+        Some(pattern) => Err(ConfigError::InvalidModulePattern {
+            option,
+            pattern: pattern.clone(),
+        }),
+        None => Ok(()),
     }
 }
 
+/// Whether the logger could open `path` for appending; an empty path disables the file
+fn check_writable(option: &'static str, path: &str) -> Result<(), ConfigError> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    let not_writable = |reason: String| ConfigError::NotWritable {
+        option,
+        path: path.to_string(),
+        reason,
+    };
+    let file = Path::new(path);
+    if file.is_dir() {
+        return Err(not_writable("it is a directory".to_string()));
+    }
+    let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if !dir.is_dir() {
+        return Err(not_writable(format!("directory {} does not exist", dir.display())));
+    }
+    if file.exists() {
+        fs::OpenOptions::new()
+            .append(true)
+            .open(file)
+            .map_err(|e| not_writable(e.to_string()))?;
+    } else if fs::metadata(dir).is_ok_and(|metadata| metadata.permissions().readonly()) {
+        return Err(not_writable(format!("directory {} is read-only", dir.display())));
+    }
+    Ok(())
+}
+
+/// How long [`Config::probe_exporters`] waits for each address to accept
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Open a TCP connection to the host of an `http(s)://host[:port]` endpoint
+fn probe(option: &'static str, endpoint: &str) -> Result<(), ConfigError> {
+    let unreachable = |reason: String| ConfigError::Unreachable {
+        option,
+        endpoint: endpoint.to_string(),
+        reason,
+    };
+    let (scheme, rest) = endpoint.split_once("://").unwrap_or(("http", endpoint));
+    let authority = rest.split('/').next().unwrap_or_default();
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+    let authority = match (has_port, scheme) {
+        (true, _) => authority.to_string(),
+        (false, "https") => format!("{}:443", authority),
+        (false, _) => format!("{}:80", authority),
+    };
+
+    let mut reason = format!("no address found for {}", authority);
+    for addr in authority.to_socket_addrs().map_err(|e| unreachable(e.to_string()))? {
+        match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(e) => reason = e.to_string(),
+        }
+    }
+    Err(unreachable(reason))
+}
+
+pub(crate) fn module_matches(module: &str, pattern: &str) -> bool {
+    let within = |module: &str| {
+        module == pattern || module.strip_prefix(pattern).is_some_and(|rest| rest.starts_with("::"))
+    };
+
+    within(module) || module.split_once("::").is_some_and(|(_, rest)| within(rest))
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
+            mode: Mode::default(),
             package_prefix: String::new(),
             log_file: "flowtrace.jsonl".to_string(),
             stdout: false,
             max_arg_length: 1000,
+            max_arg_depth: None,
+            max_capture_bytes_per_sec: None,
+            min_level: None,
+            sink_levels: SinkLevels::default(),
+            sink_filters: SinkFilters::default(),
+            formats: SinkFormats::default(),
+            timestamp_format: TimestampFormat::default(),
+            compression: Compression::default(),
+            max_total_disk_bytes: None,
+            quota_action: QuotaAction::default(),
+            sample_rate: None,
+            max_events_per_second: None,
+            control_file: None,
+            error_rate_interval_secs: None,
+            coalesce_window_secs: None,
+            aggregate_interval_secs: None,
+            aggregate_only: false,
+            overhead_interval_secs: None,
+            flush_interval_ms: None,
+            runtime: None,
+            modules: Vec::new(),
+            exclude_modules: Vec::new(),
+            agent_info: true,
+            env_allowlist: Vec::new(),
+            resource: Resource::default(),
+            websocket_addr: None,
+            grpc_endpoint: None,
+            grpc_fallback_file: None,
+            export_retry: RetryPolicy::default(),
+            exporter_workers: 1,
+            hooks: Vec::new(),
+            spool_dir: None,
+            spool_max_bytes: DEFAULT_SPOOL_MAX_BYTES,
+        }
+    }
+}
+
+const DEFAULT_SPOOL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A callback run for each event before it is serialized, see [`ConfigBuilder::on_event`]
+#[derive(Clone)]
+pub struct EventHook(Arc<dyn Fn(&TraceEvent) + Send + Sync>);
+
+impl EventHook {
+    /// Wrap `hook` for `Config::hooks`, as [`ConfigBuilder::on_event`] does
+    ///
+    /// The hook runs on the thread logging the event, while the logger is
+    /// locked, so it should be quick and must not log events itself.
+    pub fn new(hook: impl Fn(&TraceEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Run the hook; a panic in it is caught so the event still reaches the sinks
+    pub(crate) fn call(&self, event: &TraceEvent) {
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| (self.0)(event)));
+    }
+}
+
+impl fmt::Debug for EventHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHook")
+    }
+}
+
+/// An output events are written to, besides the defaults of `Config`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    /// JSONL log file, replacing `Config::log_file`
+    File(String),
+    /// Every line also printed to stdout
+    Stdout,
+    /// WebSocket clients connecting to this address (requires the `websocket` feature)
+    WebSocket(String),
+    /// gRPC collector at this URL (requires the `grpc` feature)
+    Grpc(String),
+}
+
+/// Why a configuration was rejected, by [`ConfigBuilder::build`], [`Config::validate`],
+/// [`Config::probe_exporters`] or [`Config::from_file`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// `sample_rate` is not within `(0, 1]`
+    InvalidSampleRate(f64),
+    /// Both `sample_rate` and `max_events_per_second` are set
+    ConflictingSampling,
+    /// A count or interval that must be positive is zero
+    Zero(&'static str),
+    /// An option that needs a log file is set while `log_file` is empty
+    NeedsLogFile(&'static str),
+    /// An option of the gRPC exporter is set without `grpc_endpoint`
+    NeedsGrpcEndpoint(&'static str),
+    /// An option needs a cargo feature this build does not have
+    MissingFeature { option: &'static str, feature: &'static str },
+    /// A module filter is not a module path
+    InvalidModulePattern { option: &'static str, pattern: String },
+    /// A duration threshold is negative or not a number
+    InvalidDuration(&'static str, f64),
+    /// A file the logger writes cannot be opened for appending
+    NotWritable { option: &'static str, path: String, reason: String },
+    /// An exporter endpoint did not accept a connection (see [`Config::probe_exporters`])
+    Unreachable { option: &'static str, endpoint: String, reason: String },
+    /// A `flowtrace.toml` file is not valid TOML or has unknown values
+    Invalid { path: String, message: String },
+    /// The selected profile is not defined in the `flowtrace.toml` file
+    UnknownProfile { profile: String, path: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSampleRate(rate) => write!(f, "sample_rate must be within (0, 1], got {}", rate),
+            Self::ConflictingSampling => write!(f, "set either sample_rate or max_events_per_second, not both"),
+            Self::Zero(option) => write!(f, "{} must be greater than 0", option),
+            Self::NeedsLogFile(option) => write!(f, "{} requires a log_file", option),
+            Self::NeedsGrpcEndpoint(option) => write!(f, "{} requires a grpc_endpoint", option),
+            Self::MissingFeature { option, feature } => {
+                write!(f, "{} requires the `{}` feature", option, feature)
+            }
+            Self::InvalidModulePattern { option, pattern } => write!(
+                f,
+                "{} pattern '{}' is not a module path like `billing` or `shop::billing`; \
+                 patterns already match submodules, so wildcards are not needed",
+                option, pattern
+            ),
+            Self::InvalidDuration(option, millis) => {
+                write!(f, "{} must be a non-negative number of milliseconds, got {}", option, millis)
+            }
+            Self::NotWritable { option, path, reason } => {
+                write!(f, "{} '{}' cannot be written: {}", option, path, reason)
+            }
+            Self::Unreachable { option, endpoint, reason } => write!(
+                f,
+                "{} {} is unreachable: {}; check the address or start the collector first",
+                option, endpoint, reason
+            ),
+            Self::Invalid { path, message } => write!(f, "invalid {}: {}", path, message),
+            Self::UnknownProfile { profile, path } => write!(f, "profile '{}' not found in {}", profile, path),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Fluent construction of a validated [`Config`], see [`Config::builder`]
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Turn tracing off, or make it a dry run counting events without writing them
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    pub fn package_prefix(mut self, package_prefix: impl Into<String>) -> Self {
+        self.config.package_prefix = package_prefix.into();
+        self
+    }
+
+    /// Write events to `log_file`; an empty path disables the file
+    pub fn log_file(mut self, log_file: impl Into<String>) -> Self {
+        self.config.log_file = log_file.into();
+        self
+    }
+
+    pub fn max_arg_length(mut self, max_arg_length: usize) -> Self {
+        self.config.max_arg_length = max_arg_length;
+        self
+    }
+
+    /// Cut captured arguments and results past `max_arg_depth` levels of nesting
+    pub fn max_arg_depth(mut self, max_arg_depth: usize) -> Self {
+        self.config.max_arg_depth = Some(max_arg_depth);
+        self
+    }
+
+    /// Write at most `max_bytes` of captured arguments and results per second
+    pub fn max_capture_bytes_per_sec(mut self, max_bytes: u64) -> Self {
+        self.config.max_capture_bytes_per_sec = Some(max_bytes);
+        self
+    }
+
+    /// Only write events at `level` or above
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.config.min_level = Some(level);
+        self
+    }
+
+    /// Give individual sinks their own minimum level
+    pub fn sink_levels(mut self, sink_levels: SinkLevels) -> Self {
+        self.config.sink_levels = sink_levels;
+        self
+    }
+
+    /// Give individual sinks their own module and duration filters
+    pub fn sink_filters(mut self, sink_filters: SinkFilters) -> Self {
+        self.config.sink_filters = sink_filters;
+        self
+    }
+
+    /// Render the log file and stdout in these formats
+    pub fn formats(mut self, formats: SinkFormats) -> Self {
+        self.config.formats = formats;
+        self
+    }
+
+    pub fn timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.config.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    /// Cap the log file and its rotations at `max_bytes`, applying `action` when reached
+    pub fn disk_quota(mut self, max_bytes: u64, action: QuotaAction) -> Self {
+        self.config.max_total_disk_bytes = Some(max_bytes);
+        self.config.quota_action = action;
+        self
+    }
+
+    /// Keep this fraction of root calls
+    pub fn sample_rate(mut self, sample_rate: f64) -> Self {
+        self.config.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Sample root calls to log at most about this many events per second
+    pub fn max_events_per_second(mut self, max_events_per_second: u32) -> Self {
+        self.config.max_events_per_second = Some(max_events_per_second);
+        self
+    }
+
+    /// Apply the per-function overrides in `path`, re-reading it when it changes
+    pub fn control_file(mut self, path: impl Into<String>) -> Self {
+        self.config.control_file = Some(path.into());
+        self
+    }
+
+    /// Write per-function `ERROR_RATE` records every `secs` seconds
+    pub fn error_rate_interval_secs(mut self, secs: u64) -> Self {
+        self.config.error_rate_interval_secs = Some(secs);
+        self
+    }
+
+    /// Coalesce identical exceptions into `REPEATED` records every `secs` seconds
+    pub fn coalesce_window_secs(mut self, secs: u64) -> Self {
+        self.config.coalesce_window_secs = Some(secs);
+        self
+    }
+
+    /// Write per-function `AGGREGATE` records every `secs` seconds
+    pub fn aggregate_interval_secs(mut self, secs: u64) -> Self {
+        self.config.aggregate_interval_secs = Some(secs);
+        self
+    }
+
+    /// Write only `AGGREGATE` records, no raw events
+    pub fn aggregate_only(mut self, aggregate_only: bool) -> Self {
+        self.config.aggregate_only = aggregate_only;
+        self
+    }
+
+    /// Measure the agent's own cost, writing an `OVERHEAD` record every `secs` seconds
+    pub fn overhead_interval_secs(mut self, secs: u64) -> Self {
+        self.config.overhead_interval_secs = Some(secs);
+        self
+    }
+
+    /// Flush and write due periodic records every `millis` milliseconds
+    pub fn flush_interval_ms(mut self, millis: u64) -> Self {
+        self.config.flush_interval_ms = Some(millis);
+        self
+    }
+
+    /// Run timers on `runtime`, e.g. `TokioRuntime::current()` in a tokio application
+    pub fn runtime(mut self, runtime: impl Runtime + 'static) -> Self {
+        self.config.runtime = Some(Arc::new(runtime));
+        self
+    }
+
+    /// Only log events from `module` and the other modules added this way
+    pub fn module(mut self, module: impl Into<String>) -> Self {
+        self.config.modules.push(module.into());
+        self
+    }
+
+    /// Never log events from `module`
+    pub fn exclude_module(mut self, module: impl Into<String>) -> Self {
+        self.config.exclude_modules.push(module.into());
+        self
+    }
+
+    pub fn agent_info(mut self, agent_info: bool) -> Self {
+        self.config.agent_info = agent_info;
+        self
+    }
+
+    /// Identify the traced service by `resource`
+    pub fn resource(mut self, resource: Resource) -> Self {
+        self.config.resource = resource;
+        self
+    }
+
+    /// Record the environment variable `name` in `AGENT_INFO`
+    pub fn capture_env(mut self, name: impl Into<String>) -> Self {
+        self.config.env_allowlist.push(name.into());
+        self
+    }
+
+    /// Also write events to `sink`
+    pub fn sink(mut self, sink: Sink) -> Self {
+        match sink {
+            Sink::File(path) => self.config.log_file = path,
+            Sink::Stdout => self.config.stdout = true,
+            Sink::WebSocket(addr) => self.config.websocket_addr = Some(addr),
+            Sink::Grpc(endpoint) => self.config.grpc_endpoint = Some(endpoint),
+        }
+        self
+    }
+
+    /// Run `hook` for every event that passes the filters, sampling and `min_level`
+    ///
+    /// Hooks run on the thread logging the event, while the logger is locked,
+    /// so they should be quick and must not log events themselves.
+    ///
+    /// ```rust
+    /// use flowtrace_agent::{Config, EventType};
+    ///
+    /// let config = Config::builder()
+    ///     .on_event(|event| {
+    ///         if matches!(event.event_type, EventType::Exception) {
+    ///             eprintln!("{}::{} failed", event.module, event.function);
+    ///         }
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// # assert_eq!(config.hooks.len(), 1);
+    /// ```
+    pub fn on_event(mut self, hook: impl Fn(&TraceEvent) + Send + Sync + 'static) -> Self {
+        self.config.hooks.push(EventHook::new(hook));
+        self
+    }
+
+    pub fn grpc_fallback_file(mut self, path: impl Into<String>) -> Self {
+        self.config.grpc_fallback_file = Some(path.into());
+        self
+    }
+
+    pub fn export_retry(mut self, export_retry: RetryPolicy) -> Self {
+        self.config.export_retry = export_retry;
+        self
+    }
+
+    /// Send over `workers` connections in parallel, partitioned by trace
+    pub fn exporter_workers(mut self, workers: usize) -> Self {
+        self.config.exporter_workers = workers;
+        self
+    }
+
+    /// Spool undeliverable records in `dir`, capped at `max_bytes`
+    pub fn spool(mut self, dir: impl Into<String>, max_bytes: u64) -> Self {
+        self.config.spool_dir = Some(dir.into());
+        self.config.spool_max_bytes = max_bytes;
+        self
+    }
+
+    /// Validate the settings and return the configuration
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.config.check_options()?;
+        Ok(self.config)
+    }
+}
+
+/// Minimum event levels of individual sinks; `None` falls back to `Config::min_level`
+///
+/// ```toml
+/// [agent]
+/// min_level = "info"
+/// sink_levels = { stdout = "warn", file = "debug" }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SinkLevels {
+    #[serde(deserialize_with = "level")]
+    pub file: Option<Level>,
+    #[serde(deserialize_with = "level")]
+    pub stdout: Option<Level>,
+    #[serde(deserialize_with = "level")]
+    pub websocket: Option<Level>,
+    #[serde(deserialize_with = "level")]
+    pub grpc: Option<Level>,
+}
+
+impl SinkLevels {
+    fn from_env() -> Self {
+        let var = |name: &str| env::var(name).ok().and_then(|v| v.parse().ok());
+        Self {
+            file: var("FLOWTRACE_FILE_MIN_LEVEL"),
+            stdout: var("FLOWTRACE_STDOUT_MIN_LEVEL"),
+            websocket: var("FLOWTRACE_WEBSOCKET_MIN_LEVEL"),
+            grpc: var("FLOWTRACE_GRPC_MIN_LEVEL"),
+        }
+    }
+}
+
+/// Filters of individual sinks, e.g. to export only the slow calls while the
+/// log file keeps everything
+///
+/// ```toml
+/// [agent.sink_filters.grpc]
+/// min_duration_ms = 500
+/// exclude_modules = ["health"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SinkFilters {
+    pub file: SinkFilter,
+    pub stdout: SinkFilter,
+    pub websocket: SinkFilter,
+    pub grpc: SinkFilter,
+}
+
+impl SinkFilters {
+    fn check(&self) -> Result<(), ConfigError> {
+        macro_rules! check {
+            ($sink:ident) => {
+                let filter = &self.$sink;
+                check_patterns(concat!("sink_filters.", stringify!($sink), ".modules"), &filter.modules)?;
+                check_patterns(
+                    concat!("sink_filters.", stringify!($sink), ".exclude_modules"),
+                    &filter.exclude_modules,
+                )?;
+                if let Some(millis) = filter.min_duration_ms.filter(|millis| millis.is_nan() || *millis < 0.0) {
+                    let option = concat!("sink_filters.", stringify!($sink), ".min_duration_ms");
+                    return Err(ConfigError::InvalidDuration(option, millis));
+                }
+            };
+        }
+        check!(file);
+        check!(stdout);
+        check!(websocket);
+        check!(grpc);
+        Ok(())
+    }
+}
+
+/// Which events one sink writes, on top of `Config::modules` and its level
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SinkFilter {
+    /// Only events from these modules (and their submodules); all if empty
+    pub modules: Vec<String>,
+    /// No events from these modules (and their submodules)
+    pub exclude_modules: Vec<String>,
+    /// Only exceptions and the EXIT events of calls at least this slow; ENTER
+    /// events are left out too
+    pub min_duration_ms: Option<f64>,
+}
+
+/// A level written in any case, e.g. `"warn"` as well as `"WARN"`
+fn level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Level>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Reconnection policy of network exporters
+///
+/// Failed connection attempts are retried after `initial_backoff_ms`,
+/// doubling up to `max_backoff_ms`. After `max_attempts` failures in a row
+/// the exporter's circuit breaker opens and records go to the spool or
+/// fallback while the collector keeps being probed at `max_backoff_ms` at most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            max_attempts: 3,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn from_env() -> Self {
+        let var = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        let default = Self::default();
+        Self {
+            initial_backoff_ms: var("FLOWTRACE_EXPORT_RETRY_INITIAL_MS").unwrap_or(default.initial_backoff_ms),
+            max_backoff_ms: var("FLOWTRACE_EXPORT_RETRY_MAX_MS").unwrap_or(default.max_backoff_ms),
+            max_attempts: var("FLOWTRACE_EXPORT_RETRY_ATTEMPTS")
+                .and_then(|v| u32::try_from(v).ok())
+                .unwrap_or(default.max_attempts),
+        }
+    }
+}
+
+/// Format of the `timestamp` field in written events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// Integer microseconds since the Unix epoch
+    #[default]
+    EpochMicros,
+    /// Integer nanoseconds since the Unix epoch
+    EpochNanos,
+    /// RFC 3339 string in UTC with microsecond precision
+    Rfc3339,
+}
+
+impl TimestampFormat {
+    /// Render a wall-clock time given in nanoseconds since the Unix epoch
+    pub fn render(self, nanos: i64) -> serde_json::Value {
+        match self {
+            Self::EpochMicros => (nanos / 1000).into(),
+            Self::EpochNanos => nanos.into(),
+            Self::Rfc3339 => chrono::DateTime::from_timestamp_nanos(nanos)
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+                .into(),
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "epoch_micros" => Ok(Self::EpochMicros),
+            "epoch_nanos" => Ok(Self::EpochNanos),
+            "rfc3339" => Ok(Self::Rfc3339),
+            _ => Err(format!(
+                "Unknown timestamp format '{}': use epoch_micros, epoch_nanos or rfc3339",
+                value
+            )),
+        }
+    }
+}
+
+/// Compression of the log file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Plain JSONL, written line by line
+    #[default]
+    None,
+    /// Batches of lines written as independent zstd frames (requires the `zstd` feature)
+    ///
+    /// A frame is written every 64 KiB of events, once a batch is a second
+    /// old, and on flush, so everything up to the last frame stays readable
+    /// if the process dies. `zstd -d` and `flowctl-rs` read the file directly.
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown compression '{}': use none or zstd", value)),
+        }
+    }
+}
+
+/// Whether a tracer logs events, set with `FLOWTRACE_MODE=off|dry|on|on_demand`
+///
+/// Lets a deployment verify or disable tracing without code changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Start nothing: no sinks are opened and events are dropped
+    Off,
+    /// Set everything up, opening the sinks, but count events instead of writing them
+    Dry,
+    #[default]
+    On,
+    /// Set everything up, but drop events outside windows opened with
+    /// [`record_for`](crate::record_for)
+    OnDemand,
+}
+
+impl Mode {
+    /// Whether events are written, always or in recording windows
+    pub(crate) fn writes(self) -> bool {
+        matches!(self, Self::On | Self::OnDemand)
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(Self::Off),
+            "dry" => Ok(Self::Dry),
+            "on" => Ok(Self::On),
+            "on_demand" => Ok(Self::OnDemand),
+            _ => Err(format!("Unknown mode '{}': use off, dry, on or on_demand", value)),
         }
     }
 }
+
+/// What the agent does when it reaches `Config::max_total_disk_bytes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Rotate the log file and delete the oldest rotations
+    #[default]
+    Rotate,
+    /// Keep every file, logging only EXCEPTION events from then on
+    ErrorsOnly,
+}
+
+impl FromStr for QuotaAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rotate" => Ok(Self::Rotate),
+            "errors_only" => Ok(Self::ErrorsOnly),
+            _ => Err(format!("Unknown quota action '{}': use rotate or errors_only", value)),
+        }
+    }
+}
+
+/// The parts of `flowtrace.toml` the agent reads; other sections are ignored
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FileConfig {
+    agent: AgentSection,
+    profiles: HashMap<String, ProfileSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AgentSection {
+    profile: Option<String>,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ProfileSection {
+    modules: Vec<String>,
+    exclude_modules: Vec<String>,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+/// Optional overrides of the basic `Config` fields
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Settings {
+    mode: Option<Mode>,
+    package_prefix: Option<String>,
+    log_file: Option<String>,
+    stdout: Option<bool>,
+    max_arg_length: Option<usize>,
+    max_arg_depth: Option<usize>,
+    max_capture_bytes_per_sec: Option<u64>,
+    #[serde(deserialize_with = "level")]
+    min_level: Option<Level>,
+    sink_levels: Option<SinkLevels>,
+    sink_filters: Option<SinkFilters>,
+    formats: Option<SinkFormats>,
+    timestamp_format: Option<TimestampFormat>,
+    compression: Option<Compression>,
+    max_total_disk_bytes: Option<u64>,
+    quota_action: Option<QuotaAction>,
+    sample_rate: Option<f64>,
+    max_events_per_second: Option<u32>,
+    control_file: Option<String>,
+    error_rate_interval_secs: Option<u64>,
+    coalesce_window_secs: Option<u64>,
+    aggregate_interval_secs: Option<u64>,
+    aggregate_only: Option<bool>,
+    overhead_interval_secs: Option<u64>,
+    flush_interval_ms: Option<u64>,
+    agent_info: Option<bool>,
+    env_allowlist: Option<Vec<String>>,
+    resource: Option<Resource>,
+    websocket_addr: Option<String>,
+    grpc_endpoint: Option<String>,
+    grpc_fallback_file: Option<String>,
+    export_retry: Option<RetryPolicy>,
+    exporter_workers: Option<usize>,
+    spool_dir: Option<String>,
+    spool_max_bytes: Option<u64>,
+}
+
+impl Settings {
+    fn apply(&self, config: &mut Config) {
+        if let Some(mode) = self.mode {
+            config.mode = mode;
+        }
+        if let Some(package_prefix) = &self.package_prefix {
+            config.package_prefix = package_prefix.clone();
+        }
+        if let Some(log_file) = &self.log_file {
+            config.log_file = log_file.clone();
+        }
+        if let Some(stdout) = self.stdout {
+            config.stdout = stdout;
+        }
+        if let Some(max_arg_length) = self.max_arg_length {
+            config.max_arg_length = max_arg_length;
+        }
+        if let Some(max_arg_depth) = self.max_arg_depth {
+            config.max_arg_depth = Some(max_arg_depth);
+        }
+        if let Some(max_capture_bytes_per_sec) = self.max_capture_bytes_per_sec {
+            config.max_capture_bytes_per_sec = Some(max_capture_bytes_per_sec);
+        }
+        if let Some(min_level) = self.min_level {
+            config.min_level = Some(min_level);
+        }
+        if let Some(sink_levels) = self.sink_levels {
+            config.sink_levels = sink_levels;
+        }
+        if let Some(sink_filters) = &self.sink_filters {
+            config.sink_filters = sink_filters.clone();
+        }
+        if let Some(formats) = self.formats {
+            config.formats = formats;
+        }
+        if let Some(timestamp_format) = self.timestamp_format {
+            config.timestamp_format = timestamp_format;
+        }
+        if let Some(compression) = self.compression {
+            config.compression = compression;
+        }
+        if let Some(max_total_disk_bytes) = self.max_total_disk_bytes {
+            config.max_total_disk_bytes = Some(max_total_disk_bytes);
+        }
+        if let Some(quota_action) = self.quota_action {
+            config.quota_action = quota_action;
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            config.sample_rate = Some(sample_rate);
+        }
+        if let Some(max_events_per_second) = self.max_events_per_second {
+            config.max_events_per_second = Some(max_events_per_second);
+        }
+        if let Some(control_file) = &self.control_file {
+            config.control_file = Some(control_file.clone());
+        }
+        if let Some(error_rate_interval_secs) = self.error_rate_interval_secs {
+            config.error_rate_interval_secs = Some(error_rate_interval_secs);
+        }
+        if let Some(coalesce_window_secs) = self.coalesce_window_secs {
+            config.coalesce_window_secs = Some(coalesce_window_secs);
+        }
+        if let Some(aggregate_interval_secs) = self.aggregate_interval_secs {
+            config.aggregate_interval_secs = Some(aggregate_interval_secs);
+        }
+        if let Some(aggregate_only) = self.aggregate_only {
+            config.aggregate_only = aggregate_only;
+        }
+        if let Some(overhead_interval_secs) = self.overhead_interval_secs {
+            config.overhead_interval_secs = Some(overhead_interval_secs);
+        }
+        if let Some(flush_interval_ms) = self.flush_interval_ms {
+            config.flush_interval_ms = Some(flush_interval_ms);
+        }
+        if let Some(agent_info) = self.agent_info {
+            config.agent_info = agent_info;
+        }
+        if let Some(env_allowlist) = &self.env_allowlist {
+            config.env_allowlist = env_allowlist.clone();
+        }
+        if let Some(resource) = &self.resource {
+            config.resource = resource.clone();
+        }
+        if let Some(websocket_addr) = &self.websocket_addr {
+            config.websocket_addr = Some(websocket_addr.clone());
+        }
+        if let Some(grpc_endpoint) = &self.grpc_endpoint {
+            config.grpc_endpoint = Some(grpc_endpoint.clone());
+        }
+        if let Some(grpc_fallback_file) = &self.grpc_fallback_file {
+            config.grpc_fallback_file = Some(grpc_fallback_file.clone());
+        }
+        if let Some(export_retry) = self.export_retry {
+            config.export_retry = export_retry;
+        }
+        if let Some(exporter_workers) = self.exporter_workers.filter(|&workers| workers > 0) {
+            config.exporter_workers = exporter_workers;
+        }
+        if let Some(spool_dir) = &self.spool_dir {
+            config.spool_dir = Some(spool_dir.clone());
+        }
+        if let Some(spool_max_bytes) = self.spool_max_bytes {
+            config.spool_max_bytes = spool_max_bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [instrument]
+        exclude = ["**/generated/**"]
+
+        [agent]
+        log_file = "traces/app.jsonl"
+        timestamp_format = "rfc3339"
+        env_allowlist = ["DEPLOY_ENV", "REGION"]
+        export_retry = { max_attempts = 5 }
+
+        [agent.sink_filters.grpc]
+        min_duration_ms = 250
+        exclude_modules = ["health"]
+
+        [agent.resource]
+        service_name = "billing-api"
+        deployment_environment = "staging"
+
+        [profiles.debug-billing]
+        include = ["src/billing/**"]
+        modules = ["billing"]
+        exclude_modules = ["billing::metrics"]
+        stdout = true
+        min_level = "info"
+        sink_levels = { stdout = "WARN" }
+        formats = { stdout = "logfmt" }
+    "#;
+
+    fn write_config(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, CONFIG).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_from_file_with_profile() {
+        let path = write_config("flowtrace_agent_profile.toml");
+
+        let config = Config::from_file_with_profile(&path, Some("debug-billing")).unwrap();
+        assert_eq!(config.log_file, "traces/app.jsonl");
+        assert_eq!(config.timestamp_format, TimestampFormat::Rfc3339);
+        assert!(config.stdout);
+        assert_eq!(config.modules, vec!["billing"]);
+        assert_eq!(config.min_level, Some(Level::Info));
+        assert_eq!(config.sink_levels.stdout, Some(Level::Warn));
+        assert_eq!(config.formats.stdout, crate::OutputFormat::Logfmt);
+        assert_eq!(config.formats.file, crate::OutputFormat::Json);
+        assert_eq!(config.sink_filters.grpc.min_duration_ms, Some(250.0));
+        assert_eq!(config.sink_filters.grpc.exclude_modules, vec!["health"]);
+        assert!(config.sink_filters.file.modules.is_empty());
+        assert!(config.sink_allows(config.sink_levels.stdout, Level::Error));
+        assert!(!config.sink_allows(config.sink_levels.stdout, Level::Info));
+        assert!(!config.sink_allows(config.sink_levels.file, Level::Debug));
+        assert_eq!(config.export_retry.max_attempts, 5);
+        assert_eq!(config.env_allowlist, vec!["DEPLOY_ENV", "REGION"]);
+        assert_eq!(config.resource.service_name.as_deref(), Some("billing-api"));
+        assert_eq!(config.resource.deployment_environment.as_deref(), Some("staging"));
+        assert_eq!(config.export_retry.max_backoff_ms, RetryPolicy::default().max_backoff_ms);
+
+        let plain = Config::from_file_with_profile(&path, None).unwrap();
+        assert!(!plain.stdout);
+        assert!(plain.modules.is_empty());
+
+        assert!(matches!(
+            Config::from_file_with_profile(&path, Some("missing")),
+            Err(FlowTraceError::Config(ConfigError::UnknownProfile { .. }))
+        ));
+        assert!(matches!(
+            Config::from_file_with_profile(path.with_extension("absent"), None),
+            Err(FlowTraceError::Io(_))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_builder() {
+        let config = Config::builder()
+            .log_file("traces/app.jsonl")
+            .sample_rate(0.25)
+            .sink(Sink::Stdout)
+            .module("billing")
+            .build()
+            .unwrap();
+        assert_eq!(config.log_file, "traces/app.jsonl");
+        assert_eq!(config.sample_rate, Some(0.25));
+        assert!(config.stdout);
+        assert_eq!(config.modules, vec!["billing"]);
+        assert_eq!(config.spool_max_bytes, DEFAULT_SPOOL_MAX_BYTES);
+
+        let build = |builder: ConfigBuilder| builder.build().unwrap_err();
+        assert_eq!(build(Config::builder().sample_rate(1.5)), ConfigError::InvalidSampleRate(1.5));
+        assert_eq!(build(Config::builder().exporter_workers(0)), ConfigError::Zero("exporter_workers"));
+        assert_eq!(
+            build(Config::builder().sample_rate(0.5).max_events_per_second(100)),
+            ConfigError::ConflictingSampling
+        );
+        assert_eq!(build(Config::builder().max_events_per_second(0)), ConfigError::Zero("max_events_per_second"));
+        assert_eq!(
+            build(Config::builder().max_capture_bytes_per_sec(0)),
+            ConfigError::Zero("max_capture_bytes_per_sec")
+        );
+        assert_eq!(
+            build(Config::builder().log_file("").disk_quota(1024, QuotaAction::Rotate)),
+            ConfigError::NeedsLogFile("max_total_disk_bytes")
+        );
+        assert_eq!(
+            build(Config::builder().spool("/tmp/spool", 1024)),
+            ConfigError::NeedsGrpcEndpoint("spool_dir")
+        );
+        assert_eq!(
+            build(Config::builder().module("billing*")),
+            ConfigError::InvalidModulePattern {
+                option: "modules",
+                pattern: "billing*".to_string()
+            }
+        );
+        let filters = SinkFilters {
+            grpc: SinkFilter {
+                min_duration_ms: Some(-1.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            build(Config::builder().sink_filters(filters)),
+            ConfigError::InvalidDuration("sink_filters.grpc.min_duration_ms", -1.0)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = std::env::temp_dir().join("flowtrace_config_validate");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config = |log_file: &Path| Config {
+            log_file: log_file.display().to_string(),
+            ..Default::default()
+        };
+
+        assert!(config(&dir.join("app.jsonl")).validate().is_ok());
+        assert!(config(Path::new("")).validate().is_ok());
+        let missing = config(&dir.join("missing").join("app.jsonl")).validate().unwrap_err();
+        assert!(matches!(missing, ConfigError::NotWritable { option: "log_file", .. }));
+        assert!(missing.to_string().contains("does not exist"), "{}", missing);
+        let directory = config(&dir).validate().unwrap_err();
+        assert!(directory.to_string().ends_with("it is a directory"), "{}", directory);
+        let invalid = Config {
+            exclude_modules: vec!["shop::".to_string()],
+            ..config(&dir.join("app.jsonl"))
+        };
+        assert!(matches!(invalid.validate(), Err(ConfigError::InvalidModulePattern { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = |addr| Config {
+            grpc_endpoint: Some(format!("http://{}", addr)),
+            ..Default::default()
+        };
+        assert!(endpoint(listener.local_addr().unwrap()).probe_exporters().is_ok());
+        let closed = listener.local_addr().unwrap();
+        drop(listener);
+        let error = endpoint(closed).probe_exporters().unwrap_err();
+        assert!(matches!(error, ConfigError::Unreachable { option: "grpc_endpoint", .. }));
+        assert!(Config::default().probe_exporters().is_ok());
+    }
+
+    #[test]
+    fn test_parse_timestamp_format() {
+        assert_eq!("epoch_nanos".parse(), Ok(TimestampFormat::EpochNanos));
+        assert!("iso".parse::<TimestampFormat>().is_err());
+    }
+
+    #[test]
+    fn test_allows_module() {
+        let config = Config {
+            modules: vec!["billing".to_string()],
+            exclude_modules: vec!["billing::metrics".to_string()],
+            ..Default::default()
+        };
+
+        assert!(config.allows_module("shop::billing"));
+        assert!(config.allows_module("shop::billing::invoice"));
+        assert!(config.allows_module("billing"));
+        assert!(!config.allows_module("shop::billing::metrics::counter"));
+        assert!(!config.allows_module("shop::billingx"));
+        assert!(!config.allows_module("shop::orders"));
+        assert!(Config::default().allows_module("anything"));
+    }
+}
@@ -0,0 +1,414 @@
+//! Trace contexts shared by every call of one trace
+//!
+//! A trace starts at a root call: an ENTER logged on a thread with no context,
+//! or a request entering the framework middleware. Its [`TraceContext`]
+//! carries the `traceId` stamped on every event and the trace's sampling
+//! decision, made once at the root. Nested calls on the same thread pick the
+//! context up automatically; work moved to other threads or tasks takes it
+//! along with [`bind`] and [`bind_future`], and other services receive it as a
+//! W3C `traceparent` header, so sampling never keeps part of a trace. Messages
+//! carry the same header, added by [`inject_context`] and read back by
+//! [`extract_context`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+
+use crate::{EventCapture, EventType, TraceEvent};
+
+/// Header carrying the trace, in HTTP requests and message headers
+pub const TRACEPARENT: &str = "traceparent";
+
+thread_local! {
+    /// Contexts attached with `attach`, `scope` or `instrument`, innermost last
+    static ATTACHED: RefCell<Vec<TraceContext>> = const { RefCell::new(Vec::new()) };
+    /// Context of the root call entered with nothing attached, and its call depth
+    static IMPLICIT: RefCell<Option<(TraceContext, usize)>> = const { RefCell::new(None) };
+}
+
+/// Identity and sampling decision of one trace
+#[derive(Debug, Clone)]
+pub struct TraceContext {
+    trace_id: String,
+    /// Rate the trace is kept at, or `None` when dropped; unset until decided
+    decision: Arc<OnceLock<Option<f64>>>,
+    /// Receives a copy of every event logged in the context
+    capture: Option<EventCapture>,
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new_root()
+    }
+}
+
+impl TraceContext {
+    /// Start a new trace with a random ID and no sampling decision yet
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: format!("{:016x}{:016x}", crate::ulid::random_u64(), crate::ulid::random_u64()),
+            decision: Arc::new(OnceLock::new()),
+            capture: None,
+        }
+    }
+
+    /// The trace the parent process passed down in `TRACEPARENT` (see
+    /// [`TracedCommand::propagate_context`](crate::TracedCommand::propagate_context)),
+    /// or else a new one
+    pub(crate) fn new_or_inherited() -> Self {
+        static INHERITED: OnceLock<Option<TraceContext>> = OnceLock::new();
+        INHERITED
+            .get_or_init(|| {
+                let header = std::env::var(crate::command::TRACEPARENT_ENV).ok()?;
+                Self::from_traceparent(&header)
+            })
+            .clone()
+            .unwrap_or_else(Self::new_root)
+    }
+
+    /// The context of the calling thread, if it is inside a trace
+    pub fn current() -> Option<Self> {
+        ATTACHED
+            .with(|attached| attached.borrow().last().cloned())
+            .or_else(|| IMPLICIT.with(|implicit| implicit.borrow().as_ref().map(|(context, _)| context.clone())))
+    }
+
+    /// 32 lowercase hex digits, as in W3C trace context
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Whether the trace is being kept; `None` until the agent has decided
+    pub fn sampled(&self) -> Option<bool> {
+        self.decision.get().map(Option::is_some)
+    }
+
+    /// Continue a trace from a W3C `traceparent` header, honoring its sampled flag
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let (version, trace_id, parent_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let hex = |value: &str, len: usize| value.len() == len && value.bytes().all(|b| b.is_ascii_hexdigit());
+        if version != "00" || parts.next().is_some() || !hex(trace_id, 32) || !hex(parent_id, 16) || !hex(flags, 2) {
+            return None;
+        }
+        if trace_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+
+        let sampled = u8::from_str_radix(flags, 16).ok()? & 1 == 1;
+        let decision = OnceLock::new();
+        let _ = decision.set(sampled.then_some(1.0));
+        Some(Self {
+            trace_id: trace_id.to_ascii_lowercase(),
+            decision: Arc::new(decision),
+            capture: None,
+        })
+    }
+
+    /// Adopt a gateway's request or correlation ID, e.g. an `X-Request-Id`
+    /// header, as the trace ID
+    ///
+    /// A UUID or 32 hex digits is used as is, without dashes; any other ID is
+    /// hashed, so each service receiving it lands in the same trace.
+    pub fn from_correlation_id(id: &str) -> Option<Self> {
+        let id = id.trim();
+        if id.is_empty() {
+            return None;
+        }
+        let hex: String = id.chars().filter(|&c| c != '-').collect();
+        let trace_id = if hex.len() == 32 && hex.bytes().all(|b| b.is_ascii_hexdigit()) && hex.bytes().any(|b| b != b'0') {
+            hex.to_ascii_lowercase()
+        } else {
+            // Two FNV-1a hashes with different offsets, stable across runs and services
+            let fnv = |offset: u64| {
+                id.bytes().fold(offset, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3))
+            };
+            format!("{:016x}{:016x}", fnv(0xcbf2_9ce4_8422_2325), fnv(0x8422_2325_cbf2_9ce4) | 1)
+        };
+        Some(Self {
+            trace_id,
+            decision: Arc::new(OnceLock::new()),
+            capture: None,
+        })
+    }
+
+    /// A W3C `traceparent` header for calls to other services
+    ///
+    /// Flagged as sampled unless the trace has been dropped.
+    pub fn traceparent(&self) -> String {
+        let flags = if self.sampled() == Some(false) { "00" } else { "01" };
+        format!("00-{}-{:016x}-{}", self.trace_id, crate::ulid::random_u64() | 1, flags)
+    }
+
+    /// This context, also copying every event logged in it into `capture`
+    ///
+    /// Contexts carried along from it with [`bind`], [`bind_future`] and
+    /// [`instrument`](Self::instrument) share the capture; a `traceparent`
+    /// passed to another service does not.
+    pub fn with_capture(mut self, capture: EventCapture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// The capture attached with [`with_capture`](Self::with_capture)
+    pub fn capture(&self) -> Option<&EventCapture> {
+        self.capture.as_ref()
+    }
+
+    /// Make this the calling thread's context until the guard is dropped
+    pub fn attach(&self) -> ContextGuard {
+        ATTACHED.with(|attached| attached.borrow_mut().push(self.clone()));
+        ContextGuard { _private: () }
+    }
+
+    /// Run `f` inside this context
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.attach();
+        f()
+    }
+
+    /// Poll `future` inside this context, whichever thread polls it
+    pub fn instrument<F: Future>(&self, future: F) -> WithContext<F> {
+        WithContext {
+            context: Some(self.clone()),
+            future: Box::pin(future),
+        }
+    }
+
+    /// Decide whether to keep the trace, unless it already was
+    pub(crate) fn decide(&self, decide: impl FnOnce() -> Option<f64>) -> Option<f64> {
+        *self.decision.get_or_init(decide)
+    }
+}
+
+/// Restores the previous context when dropped
+pub struct ContextGuard {
+    _private: (),
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        ATTACHED.with(|attached| attached.borrow_mut().pop());
+    }
+}
+
+/// A future polled inside a trace context, see [`TraceContext::instrument`]
+pub struct WithContext<F> {
+    context: Option<TraceContext>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithContext<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let _guard = self.context.as_ref().map(TraceContext::attach);
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Wrap `f` to run in the caller's trace context, e.g. on a spawned thread
+///
+/// ```rust
+/// use flowtrace_agent::{bind, trace};
+///
+/// #[trace]
+/// fn handle() {
+///     // The worker's events join this trace and follow its sampling decision
+///     std::thread::spawn(bind(|| work())).join().unwrap();
+/// }
+///
+/// #[trace]
+/// fn work() {}
+/// ```
+pub fn bind<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let context = TraceContext::current();
+    move || match context {
+        Some(context) => context.scope(f),
+        None => f(),
+    }
+}
+
+/// Wrap `future` to be polled in the caller's trace context, e.g. for `tokio::spawn`
+pub fn bind_future<F: Future>(future: F) -> WithContext<F> {
+    WithContext {
+        context: TraceContext::current(),
+        future: Box::pin(future),
+    }
+}
+
+/// Add the calling thread's trace to the headers of a message about to be
+/// produced (Kafka, RabbitMQ, SQS, ...), as a `traceparent`
+///
+/// Nothing is added outside a trace.
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use flowtrace_agent::{extract_context, inject_context, TraceContext};
+///
+/// let producer = TraceContext::new_root();
+/// let mut headers = HashMap::new();
+/// producer.scope(|| inject_context(&mut headers));
+///
+/// // On the consumer, calls made inside the context join the producer's trace
+/// let consumer = extract_context(&headers).unwrap_or_default();
+/// assert_eq!(consumer.trace_id(), producer.trace_id());
+/// ```
+pub fn inject_context(headers: &mut HashMap<String, String>) {
+    if let Some(context) = TraceContext::current() {
+        headers.insert(TRACEPARENT.to_string(), context.traceparent());
+    }
+}
+
+/// The trace a consumed message was produced in, from its `traceparent`
+/// header, honoring its sampled flag
+///
+/// The header name is matched case-insensitively, as some brokers change it.
+pub fn extract_context(headers: &HashMap<String, String>) -> Option<TraceContext> {
+    let header = headers.get(TRACEPARENT).or_else(|| {
+        headers
+            .iter()
+            .find_map(|(name, value)| name.eq_ignore_ascii_case(TRACEPARENT).then_some(value))
+    })?;
+    TraceContext::from_traceparent(header)
+}
+
+/// Attach the calling thread's context to `event` just before it is logged
+///
+/// An ENTER with no context starts an implicit root call, which ends with
+/// the matching EXIT or EXCEPTION.
+pub(crate) fn stamp(event: &mut TraceEvent) {
+    if event.context.is_none() {
+        event.context = ATTACHED.with(|attached| attached.borrow().last().cloned()).or_else(|| {
+            IMPLICIT.with(|implicit| {
+                let mut implicit = implicit.borrow_mut();
+                match event.event_type {
+                    EventType::Enter => {
+                        let (context, depth) = implicit.get_or_insert_with(|| (TraceContext::new_or_inherited(), 0));
+                        *depth += 1;
+                        Some(context.clone())
+                    }
+                    EventType::Exit | EventType::Exception => {
+                        let (context, depth) = implicit.as_mut()?;
+                        let context = context.clone();
+                        *depth -= 1;
+                        if *depth == 0 {
+                            *implicit = None;
+                        }
+                        Some(context)
+                    }
+                }
+            })
+        });
+    }
+
+    if event.trace_id.is_none() {
+        event.trace_id = event.context.as_ref().map(|context| context.trace_id.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implicit_root_spans_nested_calls() {
+        let mut events = [
+            TraceEvent::enter("app", "root", None),
+            TraceEvent::enter("app", "child", None),
+            TraceEvent::exit("app", "child", None, None),
+            TraceEvent::exit("app", "root", None, None),
+            TraceEvent::enter("app", "next", None),
+        ];
+        for event in &mut events {
+            stamp(event);
+        }
+
+        let ids: Vec<&str> = events.iter().map(|event| event.trace_id.as_deref().unwrap()).collect();
+        assert!(ids[..4].iter().all(|id| *id == ids[0]));
+        assert_ne!(ids[4], ids[0]);
+        assert!(TraceContext::current().is_some());
+
+        stamp(&mut TraceEvent::exit("app", "next", None, None));
+        assert!(TraceContext::current().is_none());
+    }
+
+    #[test]
+    fn test_bind_carries_context_to_threads() {
+        let context = TraceContext::new_root();
+        let (inside, worker) = context.scope(|| {
+            let mut event = TraceEvent::enter("app", "inside", None);
+            stamp(&mut event);
+            let worker = std::thread::spawn(bind(|| {
+                let mut event = TraceEvent::enter("app", "worker", None);
+                stamp(&mut event);
+                event
+            }));
+            (event, worker.join().unwrap())
+        });
+
+        assert_eq!(inside.trace_id.as_deref(), Some(context.trace_id()));
+        assert_eq!(worker.trace_id.as_deref(), Some(context.trace_id()));
+        // The decision is shared by every copy of the context
+        worker.context.unwrap().decide(|| None);
+        assert_eq!(context.sampled(), Some(false));
+        assert!(TraceContext::current().is_none());
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let context = TraceContext::from_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.sampled(), Some(true));
+
+        let dropped = TraceContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").unwrap();
+        assert_eq!(dropped.sampled(), Some(false));
+        assert!(dropped.traceparent().ends_with("-00"));
+
+        let root = TraceContext::new_root();
+        let header = root.traceparent();
+        assert!(header.starts_with(&format!("00-{}-", root.trace_id())) && header.ends_with("-01"));
+        assert_eq!(TraceContext::from_traceparent(&header).unwrap().trace_id(), root.trace_id());
+
+        for invalid in ["", "00-xyz-00f067aa0ba902b7-01", "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"] {
+            assert!(TraceContext::from_traceparent(invalid).is_none(), "{}", invalid);
+        }
+        assert!(TraceContext::from_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+    }
+
+    #[test]
+    fn test_from_correlation_id() {
+        let uuid = TraceContext::from_correlation_id("3F2504E0-4F89-11D3-9A0C-0305E82C3301").unwrap();
+        assert_eq!(uuid.trace_id(), "3f2504e04f8911d39a0c0305e82c3301");
+        assert_eq!(uuid.sampled(), None);
+
+        let opaque = TraceContext::from_correlation_id("req-42").unwrap();
+        assert_eq!(opaque.trace_id().len(), 32);
+        assert_eq!(opaque.trace_id(), TraceContext::from_correlation_id("req-42").unwrap().trace_id());
+        assert_ne!(opaque.trace_id(), TraceContext::from_correlation_id("req-43").unwrap().trace_id());
+        assert!(TraceContext::from_traceparent(&opaque.traceparent()).is_some());
+        assert!(TraceContext::from_correlation_id(" ").is_none());
+    }
+
+    #[test]
+    fn test_message_headers() {
+        let mut headers = HashMap::new();
+        inject_context(&mut headers);
+        assert!(headers.is_empty());
+
+        let producer = TraceContext::new_root();
+        producer.decide(|| None);
+        producer.scope(|| inject_context(&mut headers));
+        let consumer = extract_context(&headers).unwrap();
+        assert_eq!(consumer.trace_id(), producer.trace_id());
+        assert_eq!(consumer.sampled(), Some(false));
+
+        let header = headers.remove(TRACEPARENT).unwrap();
+        headers.insert("TraceParent".to_string(), header);
+        assert_eq!(extract_context(&headers).unwrap().trace_id(), producer.trace_id());
+        assert!(extract_context(&HashMap::new()).is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Per-function overrides read from a control file
+//!
+//! With `Config::control_file` set, the logger checks that file every few
+//! seconds and re-reads it when it changed, so tracing of single functions can
+//! be turned up or down in a running service. Each line maps a pattern to
+//! one or more actions:
+//!
+//! ```text
+//! # pattern              actions
+//! billing::charge_card   capture for=10m
+//! billing::health        off
+//! orders                 sample=1.0
+//! ```
+//!
+//! Patterns match `module::function` names the way module filters match
+//! modules: `orders` covers every function in and below the `orders` module.
+//! The first line matching an event applies:
+//!
+//! - `off` drops the function's events
+//! - `sample=R` keeps its calls with probability `R` instead of the configured
+//!   sampling; with `1.0`, also within traces sampled out
+//! - `capture` keeps all its calls, past module filters and sampling, with
+//!   arguments and results whole, ignoring `max_arg_depth` and
+//!   `max_capture_bytes_per_sec`
+//! - `for=N` with an `s`, `m` or `h` suffix ends the line's effect `N` after
+//!   the file was last modified
+//!
+//! A missing file means no overrides. A file that fails to parse after tracing
+//! started is reported on stderr, and the overrides read before stay in effect.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::module_matches;
+use crate::sampling::{self, CallSampler};
+use crate::{ConfigError, TraceEvent};
+
+/// How often the file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What an override does to the events of a function
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Action {
+    pub off: bool,
+    pub capture: bool,
+    pub sample: Option<f64>,
+}
+
+/// One line of the file
+#[derive(Debug, Clone, PartialEq)]
+struct Override {
+    pattern: String,
+    action: Action,
+    expires: Option<SystemTime>,
+}
+
+pub(crate) struct ControlFile {
+    path: PathBuf,
+    overrides: Vec<Override>,
+    /// Modification time of the file as last read; `None` while it is missing
+    modified: Option<SystemTime>,
+    checked: Instant,
+    /// Decisions of `sample=R` overrides, one per call
+    calls: CallSampler,
+}
+
+impl ControlFile {
+    /// Read the overrides in `path`, if it exists
+    pub(crate) fn open(path: &str) -> Result<Self, ConfigError> {
+        let mut control = Self {
+            path: PathBuf::from(path),
+            overrides: Vec::new(),
+            modified: None,
+            checked: Instant::now(),
+            calls: CallSampler::default(),
+        };
+        control.reload().map_err(|message| ConfigError::Invalid {
+            path: path.to_string(),
+            message,
+        })?;
+        Ok(control)
+    }
+
+    /// Re-read the file if it changed, at most once per `RELOAD_INTERVAL`
+    pub(crate) fn refresh(&mut self, now: Instant) {
+        if now.duration_since(self.checked) < RELOAD_INTERVAL {
+            return;
+        }
+        self.checked = now;
+        if let Err(message) = self.reload() {
+            eprintln!(
+                "flowtrace: invalid {}: {}; keeping the previous overrides",
+                self.path.display(),
+                message
+            );
+        }
+    }
+
+    fn reload(&mut self) -> Result<(), String> {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if modified == self.modified {
+            return Ok(());
+        }
+        // Noted before parsing, so a broken file is reported once rather than on every check
+        self.modified = modified;
+        self.overrides = match modified {
+            Some(modified) => {
+                let content = fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+                parse(&content, modified)?
+            }
+            None => Vec::new(),
+        };
+        Ok(())
+    }
+
+    /// The action of the first live override matching the function of `event`
+    pub(crate) fn action(&self, event: &TraceEvent, now: SystemTime) -> Option<Action> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+        let name = format!("{}::{}", event.module, event.function);
+        self.overrides
+            .iter()
+            .find(|rule| rule.expires.is_none_or(|expires| now < expires) && module_matches(&name, &rule.pattern))
+            .map(|rule| rule.action)
+    }
+
+    /// Whether `event` is kept by a `sample=rate` override, deciding once per call
+    pub(crate) fn sample(&mut self, event: &TraceEvent, rate: f64) -> Option<f64> {
+        self.calls.sample(event, || sampling::with_probability(rate))
+    }
+}
+
+/// Parse the lines of a control file last modified at `modified`
+fn parse(content: &str, modified: SystemTime) -> Result<Vec<Override>, String> {
+    let mut overrides = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(pattern) = words.next() else {
+            continue;
+        };
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+
+        let mut action = Action::default();
+        let mut expires = None;
+        for word in words {
+            match word.split_once('=') {
+                None if word == "off" => action.off = true,
+                None if word == "capture" => action.capture = true,
+                Some(("sample", rate)) => match rate.parse::<f64>() {
+                    Ok(rate) if (0.0..=1.0).contains(&rate) => action.sample = Some(rate),
+                    _ => return Err(error(format!("sample rate '{}' is not between 0 and 1", rate))),
+                },
+                Some(("for", duration)) => {
+                    let duration = parse_duration(duration)
+                        .ok_or_else(|| error(format!("'{}' is not a duration like 30s, 10m or 2h", duration)))?;
+                    expires = Some(modified + duration);
+                }
+                _ => return Err(error(format!("unknown action '{}', expected off, capture, sample=R or for=N", word))),
+            }
+        }
+        if action == Action::default() {
+            return Err(error(format!("no action for '{}'", pattern)));
+        }
+        if action.off && (action.capture || action.sample.is_some()) {
+            return Err(error("`off` can't be combined with `capture` or `sample`".to_string()));
+        }
+        overrides.push(Override {
+            pattern: pattern.to_string(),
+            action,
+            expires,
+        });
+    }
+    Ok(overrides)
+}
+
+/// `30s`, `10m` or `2h`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.len().checked_sub(1)?;
+    let (count, unit) = value.split_at(split);
+    let count: u64 = count.parse().ok()?;
+    let secs = match unit {
+        "s" => count,
+        "m" => count * 60,
+        "h" => count * 3600,
+        _ => return None,
+    };
+    Some(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let content = "# pattern  actions\n\
+                       billing::charge_card capture for=10m\n\
+                       \n\
+                       billing::health off  # noisy\n\
+                       orders sample=0.25\n";
+        let overrides = parse(content, modified).unwrap();
+        assert_eq!(overrides.len(), 3);
+        assert!(overrides[0].action.capture);
+        assert_eq!(overrides[0].expires, Some(modified + Duration::from_secs(600)));
+        assert!(overrides[1].action.off);
+        assert_eq!(overrides[2].action.sample, Some(0.25));
+
+        assert_eq!(parse("orders", modified).unwrap_err(), "line 1: no action for 'orders'");
+        assert!(parse("orders sample=2", modified).unwrap_err().contains("not between 0 and 1"));
+        assert!(parse("orders for=10d capture", modified).unwrap_err().contains("not a duration"));
+        assert!(parse("\norders verbose", modified).unwrap_err().starts_with("line 2: unknown action 'verbose'"));
+        assert!(parse("orders off capture", modified).is_err());
+    }
+
+    #[test]
+    fn test_action_matches_and_expires() {
+        let modified = SystemTime::now();
+        let control = ControlFile {
+            path: PathBuf::new(),
+            overrides: parse("billing::charge_card capture for=1m\nbilling off\n", modified).unwrap(),
+            modified: Some(modified),
+            checked: Instant::now(),
+            calls: CallSampler::default(),
+        };
+        let charge = TraceEvent::enter("app::billing", "charge_card", None);
+        let refund = TraceEvent::enter("billing", "refund", None);
+        let other = TraceEvent::enter("orders", "charge_card", None);
+
+        let capture = control.action(&charge, modified).unwrap();
+        assert!(capture.capture && !capture.off);
+        assert!(control.action(&refund, modified).unwrap().off);
+        assert_eq!(control.action(&other, modified), None);
+        // Past its `for`, the first line no longer applies and the next one matches
+        assert!(control.action(&charge, modified + Duration::from_secs(61)).unwrap().off);
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join("flowtrace_control_reload.txt");
+        let _ = fs::remove_file(&path);
+        let mut control = ControlFile::open(&path.display().to_string()).unwrap();
+        assert!(control.overrides.is_empty());
+
+        fs::write(&path, "orders off\n").unwrap();
+        let start = control.checked;
+        control.refresh(start);
+        assert!(control.overrides.is_empty());
+        control.refresh(start + RELOAD_INTERVAL);
+        assert_eq!(control.overrides.len(), 1);
+
+        // A broken edit keeps the overrides already in effect
+        fs::write(&path, "orders loud\n").unwrap();
+        control.modified = None;
+        control.refresh(start + RELOAD_INTERVAL * 2);
+        assert_eq!(control.overrides.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+        control.refresh(start + RELOAD_INTERVAL * 3);
+        assert!(control.overrides.is_empty());
+
+        fs::write(&path, "orders sample=1.5\n").unwrap();
+        let error = ControlFile::open(&path.display().to_string()).err().unwrap();
+        assert!(matches!(error, ConfigError::Invalid { .. }));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Summarizing deeply nested captures
+//!
+//! Captured arguments and results are `{:?}` strings. Past a nesting depth
+//! (`Config::max_arg_depth`, or `#[trace(max_depth = N)]` for one function)
+//! each struct, list or tuple is cut to `{...}`, `[...]` or `(...)`, so a
+//! capture ends on whole values instead of being truncated mid-structure.
+
+use std::borrow::Cow;
+
+/// Cut `debug` to `max_depth` levels of nesting
+///
+/// Brackets inside string and char literals are left alone.
+pub(crate) fn summarize(debug: &str, max_depth: usize) -> Cow<'_, str> {
+    if !debug.contains(['{', '[', '(']) {
+        return Cow::Borrowed(debug);
+    }
+
+    let mut out = String::with_capacity(debug.len());
+    let mut depth = 0;
+    let mut chars = debug.chars();
+    while let Some(c) = chars.next() {
+        let shown = depth <= max_depth;
+        // A literal in full, escapes included; an apostrophe only opens a char literal, `'x'` or `'\n'`
+        if c == '"' || (c == '\'' && is_char_literal(chars.as_str())) {
+            if shown {
+                out.push(c);
+            }
+            while let Some(next) = chars.next() {
+                if shown {
+                    out.push(next);
+                }
+                if next == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        if shown {
+                            out.push(escaped);
+                        }
+                    }
+                } else if next == c {
+                    break;
+                }
+            }
+            continue;
+        }
+        match c {
+            '{' | '[' | '(' => {
+                depth += 1;
+                if shown {
+                    out.push(c);
+                    if depth > max_depth {
+                        out.push_str("...");
+                    }
+                }
+            }
+            '}' | ']' | ')' => {
+                depth = depth.saturating_sub(1);
+                if depth <= max_depth {
+                    out.push(c);
+                }
+            }
+            _ if shown => out.push(c),
+            _ => {}
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Whether `rest`, following an apostrophe, continues a char literal
+fn is_char_literal(rest: &str) -> bool {
+    let mut chars = rest.chars();
+    match chars.next() {
+        Some('\\') => chars.any(|c| c == '\''),
+        Some(_) => chars.next() == Some('\''),
+        None => false,
+    }
+}
+
+/// Cut the `{"name": value, ...}` object of captured arguments, counting depth within each value
+pub(crate) fn summarize_args(args: &str, max_depth: usize) -> Cow<'_, str> {
+    summarize(args, max_depth + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let debug = r#"Order { id: 7, customer: Customer { name: "a {b}", tags: ["x"] }, lines: [Line { sku: 'x' }] }"#;
+        assert_eq!(summarize(debug, 0), "Order {...}");
+        assert_eq!(summarize(debug, 1), r#"Order { id: 7, customer: Customer {...}, lines: [...] }"#);
+        assert_eq!(summarize(debug, 2), r#"Order { id: 7, customer: Customer { name: "a {b}", tags: [...] }, lines: [Line {...}] }"#);
+        assert_eq!(summarize(debug, 3), debug);
+        assert!(matches!(summarize("42", 0), Cow::Borrowed(_)));
+        assert_eq!(summarize("Note { text: don't (yet) }", 1), "Note { text: don't (...) }");
+        assert_eq!(summarize(r"('\'', ['('])", 1), r"('\'', [...])");
+
+        let args = r#"{"order": Order { id: 7, customer: Customer { name: "a\"(" } }, "n": 3}"#;
+        assert_eq!(summarize_args(args, 1), r#"{"order": Order { id: 7, customer: Customer {...} }, "n": 3}"#);
+    }
+}
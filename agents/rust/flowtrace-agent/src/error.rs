@@ -0,0 +1,52 @@
+//! Errors of starting and configuring tracing
+
+use std::fmt;
+use std::io;
+
+use crate::ConfigError;
+
+/// Why tracing could not be started, see [`start_tracing`](crate::start_tracing)
+#[derive(Debug)]
+pub enum FlowTraceError {
+    /// The log file (or a config file) could not be opened, read or written
+    Io(io::Error),
+    /// The tracer was already started; stop it first
+    AlreadyInitialized,
+    /// The configuration was rejected
+    Config(ConfigError),
+    /// A network exporter could not be set up, e.g. its address is in use
+    Exporter { exporter: &'static str, source: io::Error },
+}
+
+impl fmt::Display for FlowTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::AlreadyInitialized => write!(f, "tracer already initialized"),
+            Self::Config(e) => write!(f, "invalid configuration: {}", e),
+            Self::Exporter { exporter, source } => write!(f, "{} exporter failed to start: {}", exporter, source),
+        }
+    }
+}
+
+impl std::error::Error for FlowTraceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) | Self::Exporter { source: e, .. } => Some(e),
+            Self::Config(e) => Some(e),
+            Self::AlreadyInitialized => None,
+        }
+    }
+}
+
+impl From<io::Error> for FlowTraceError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ConfigError> for FlowTraceError {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
+}
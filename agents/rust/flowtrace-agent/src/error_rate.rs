@@ -0,0 +1,151 @@
+//! Periodic per-function error-rate records
+//!
+//! With `Config::error_rate_interval_secs` set, the logger counts the calls
+//! that finished and failed per function, before any sampling, and writes one
+//! `ERROR_RATE` record per function at the end of each interval, so alerting
+//! can follow a small summary stream however heavily raw events are sampled.
+//! Records are written with the next event after the interval ends, and for
+//! the last partial interval when the logger is dropped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EventType, ParseError, TimestampFormat, TraceEvent};
+
+/// Value of the `event` field of an [`ErrorRate`] record
+pub const ERROR_RATE: &str = "ERROR_RATE";
+
+/// Calls and failures of one function over one interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorRate {
+    #[serde(default)]
+    pub event_id: String,
+    /// Always [`ERROR_RATE`]
+    pub event: String,
+    /// End of the interval, in microseconds since the Unix epoch, written like event timestamps
+    #[serde(deserialize_with = "crate::parse::timestamp_micros")]
+    pub timestamp: i64,
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    #[serde(rename = "class")]
+    pub module: String,
+    #[serde(rename = "method")]
+    pub function: String,
+    /// Length of the interval
+    pub window_seconds: f64,
+    /// Calls that finished (EXIT or EXCEPTION) in the interval
+    pub calls: u64,
+    /// Calls that finished with an EXCEPTION
+    pub errors: u64,
+}
+
+impl ErrorRate {
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(self.timestamp_nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Parse an `ERROR_RATE` line
+    pub fn from_json_line(line: &str) -> Result<ErrorRate, ParseError> {
+        let rate: ErrorRate = crate::parse::from_line(line)?;
+        if rate.event != ERROR_RATE {
+            return Err(ParseError::Schema {
+                column: 0,
+                message: format!("expected an {} record, found `{}`", ERROR_RATE, rate.event),
+            });
+        }
+        Ok(rate)
+    }
+}
+
+/// Counts calls and errors per function for the current interval
+pub(crate) struct ErrorRates {
+    interval: Duration,
+    window_start: Instant,
+    /// Calls and errors by module and function
+    counts: BTreeMap<(String, String), (u64, u64)>,
+}
+
+impl ErrorRates {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            window_start: Instant::now(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: &TraceEvent) {
+        let failed = match event.event_type {
+            EventType::Enter => return,
+            EventType::Exit => false,
+            EventType::Exception => true,
+        };
+        let (calls, errors) = self
+            .counts
+            .entry((event.module.clone(), event.function.clone()))
+            .or_default();
+        *calls += 1;
+        *errors += u64::from(failed);
+    }
+
+    /// Whether the current interval has ended
+    pub(crate) fn is_due(&self) -> bool {
+        self.window_start.elapsed() >= self.interval
+    }
+
+    /// End the current interval, returning a record per function called in it
+    pub(crate) fn take(&mut self) -> Vec<ErrorRate> {
+        let window_seconds = self.window_start.elapsed().as_secs_f64();
+        self.window_start = Instant::now();
+        let nanos = crate::wall_clock_nanos();
+
+        std::mem::take(&mut self.counts)
+            .into_iter()
+            .map(|((module, function), (calls, errors))| ErrorRate {
+                event_id: crate::ulid::generate(),
+                event: ERROR_RATE.to_string(),
+                timestamp: nanos / 1000,
+                timestamp_nanos: nanos,
+                module,
+                function,
+                window_seconds,
+                calls,
+                errors,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_per_function() {
+        let mut rates = ErrorRates::new(Duration::from_secs(60));
+        rates.record(&TraceEvent::enter("app", "charge", None));
+        rates.record(&TraceEvent::exit("app", "charge", None, Some(1)));
+        rates.record(&TraceEvent::exception("app", "charge", "declined", Some(1)));
+        rates.record(&TraceEvent::exit("app", "login", None, Some(1)));
+        assert!(!rates.is_due());
+
+        let records = rates.take();
+        let counts: Vec<(&str, u64, u64)> = records
+            .iter()
+            .map(|rate| (rate.function.as_str(), rate.calls, rate.errors))
+            .collect();
+        assert_eq!(counts, [("charge", 2, 1), ("login", 1, 0)]);
+        assert!(rates.take().is_empty());
+
+        let line = records[0].to_json(TimestampFormat::Rfc3339).unwrap();
+        let parsed = ErrorRate::from_json_line(&line).unwrap();
+        assert_eq!((parsed.module.as_str(), parsed.errors), ("app", 1));
+        assert!(ErrorRate::from_json_line(&serde_json::to_string(&TraceEvent::enter("app", "a", None)).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! C API for writing into the global trace stream
+//!
+//! Enabled by the `ffi` feature, and exported from the shared and static
+//! libraries the `flowtrace-ffi` crate builds, so C and C++ components of a
+//! mixed-language process log the same events, with the same schema, as the
+//! Rust code around them. Declarations live in `include/flowtrace.h`.
+//!
+//! Every function returns `0` on success and a negative value on failure;
+//! strings are NUL-terminated UTF-8 and may be NULL where optional.
+//!
+//! # Safety
+//!
+//! Non-NULL string pointers must point to valid NUL-terminated strings that
+//! stay alive for the duration of the call.
+
+use std::ffi::{c_char, c_int, CStr};
+
+use crate::{Config, TraceEvent};
+
+/// A required argument was NULL or not valid UTF-8
+pub const FLOWTRACE_INVALID_ARGUMENT: c_int = -1;
+/// Tracing could not be started, e.g. it already was or the log file failed to open
+pub const FLOWTRACE_INIT_FAILED: c_int = -2;
+
+/// Start global tracing, configured from the `FLOWTRACE_*` environment variables
+///
+/// A non-NULL `log_file` overrides `FLOWTRACE_LOGFILE`.
+///
+/// # Safety
+///
+/// See the [module documentation](self).
+#[no_mangle]
+pub unsafe extern "C" fn flowtrace_init(log_file: *const c_char) -> c_int {
+    let mut config = Config::from_env();
+    match optional(log_file) {
+        Ok(Some(log_file)) => config.log_file = log_file.to_string(),
+        Ok(None) => {}
+        Err(code) => return code,
+    }
+
+    match crate::start_tracing(config) {
+        Ok(()) => 0,
+        Err(_) => FLOWTRACE_INIT_FAILED,
+    }
+}
+
+/// Log an ENTER event; `args` may be NULL
+///
+/// # Safety
+///
+/// See the [module documentation](self).
+#[no_mangle]
+pub unsafe extern "C" fn flowtrace_log_enter(
+    module: *const c_char,
+    function: *const c_char,
+    args: *const c_char,
+) -> c_int {
+    log(|| {
+        Ok(TraceEvent::enter(
+            required(module)?,
+            required(function)?,
+            optional(args)?.map(str::to_string),
+        ))
+    })
+}
+
+/// Log an EXIT event; `result` may be NULL, a negative `duration_micros` means unknown
+///
+/// # Safety
+///
+/// See the [module documentation](self).
+#[no_mangle]
+pub unsafe extern "C" fn flowtrace_log_exit(
+    module: *const c_char,
+    function: *const c_char,
+    result: *const c_char,
+    duration_micros: i64,
+) -> c_int {
+    log(|| {
+        Ok(TraceEvent::exit(
+            required(module)?,
+            required(function)?,
+            optional(result)?.map(str::to_string),
+            duration(duration_micros),
+        ))
+    })
+}
+
+/// Log an EXCEPTION event; a negative `duration_micros` means unknown
+///
+/// # Safety
+///
+/// See the [module documentation](self).
+#[no_mangle]
+pub unsafe extern "C" fn flowtrace_log_exception(
+    module: *const c_char,
+    function: *const c_char,
+    error: *const c_char,
+    duration_micros: i64,
+) -> c_int {
+    log(|| {
+        Ok(TraceEvent::exception(
+            required(module)?,
+            required(function)?,
+            required(error)?,
+            duration(duration_micros),
+        ))
+    })
+}
+
+/// Flush events logged so far
+#[no_mangle]
+pub extern "C" fn flowtrace_flush() -> c_int {
+    crate::flush_tracing();
+    0
+}
+
+/// Stop global tracing and close the log file
+#[no_mangle]
+pub extern "C" fn flowtrace_shutdown() -> c_int {
+    crate::stop_tracing();
+    0
+}
+
+fn log(event: impl FnOnce() -> Result<TraceEvent, c_int>) -> c_int {
+    match event() {
+        Ok(event) => {
+            crate::log_event(event);
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+fn duration(duration_micros: i64) -> Option<i64> {
+    (duration_micros >= 0).then_some(duration_micros)
+}
+
+unsafe fn required<'a>(value: *const c_char) -> Result<&'a str, c_int> {
+    optional(value)?.ok_or(FLOWTRACE_INVALID_ARGUMENT)
+}
+
+unsafe fn optional<'a>(value: *const c_char) -> Result<Option<&'a str>, c_int> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map(Some)
+        .map_err(|_| FLOWTRACE_INVALID_ARGUMENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_through_c_api() {
+        let path = std::env::temp_dir().join("flowtrace_ffi.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(Config {
+                log_file: path.display().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let codes = crate::with_tracer(&tracer, || unsafe {
+            [
+                flowtrace_log_enter(c"native".as_ptr(), c"decode".as_ptr(), std::ptr::null()),
+                flowtrace_log_exit(c"native".as_ptr(), c"decode".as_ptr(), c"7".as_ptr(), 120),
+                flowtrace_log_exception(c"native".as_ptr(), c"decode".as_ptr(), c"bad frame".as_ptr(), -1),
+                flowtrace_log_enter(std::ptr::null(), c"decode".as_ptr(), std::ptr::null()),
+                flowtrace_log_exit(c"native".as_ptr(), c"\xff".as_ptr(), std::ptr::null(), 0),
+            ]
+        });
+        tracer.stop();
+        assert_eq!(codes, [0, 0, 0, FLOWTRACE_INVALID_ARGUMENT, FLOWTRACE_INVALID_ARGUMENT]);
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .skip(1) // AGENT_INFO
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].module, "native");
+        assert_eq!(events[1].result.as_deref(), Some("7"));
+        assert_eq!(events[1].duration_micros, Some(120));
+        assert_eq!(events[2].exception.as_deref(), Some("bad frame"));
+        assert_eq!(events[2].duration_micros, None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Per-sink filtering
+//!
+//! `Config::sink_levels` and `Config::sink_filters` are resolved into one
+//! [`Rule`] per sink when the logger starts, so routing an event costs a
+//! level comparison for sinks without module or duration filters.
+
+use crate::config::{module_matches, SinkFilter};
+use crate::{Config, EventType, Level, TraceEvent};
+
+/// Rules of every sink
+pub(crate) struct SinkRules {
+    pub file: Rule,
+    pub stdout: Rule,
+    #[cfg(feature = "websocket")]
+    pub websocket: Rule,
+    #[cfg(feature = "grpc")]
+    pub grpc: Rule,
+}
+
+impl SinkRules {
+    pub fn new(config: &Config) -> Self {
+        let levels = config.sink_levels;
+        let filters = &config.sink_filters;
+        let rule = |level: Option<Level>, filter: &SinkFilter| Rule::new(level.or(config.min_level), filter);
+        Self {
+            file: rule(levels.file, &filters.file),
+            stdout: rule(levels.stdout, &filters.stdout),
+            #[cfg(feature = "websocket")]
+            websocket: rule(levels.websocket, &filters.websocket),
+            #[cfg(feature = "grpc")]
+            grpc: rule(levels.grpc, &filters.grpc),
+        }
+    }
+}
+
+/// Which events one sink writes
+pub(crate) struct Rule {
+    min_level: Option<Level>,
+    modules: Vec<String>,
+    exclude_modules: Vec<String>,
+    min_duration_micros: Option<i64>,
+}
+
+impl Rule {
+    fn new(min_level: Option<Level>, filter: &SinkFilter) -> Self {
+        Self {
+            min_level,
+            modules: filter.modules.clone(),
+            exclude_modules: filter.exclude_modules.clone(),
+            min_duration_micros: filter.min_duration_ms.map(|millis| (millis * 1000.0) as i64),
+        }
+    }
+
+    /// Whether the sink writes `event`, whose effective level is `level`
+    pub fn allows(&self, event: &TraceEvent, level: Level) -> bool {
+        if self.min_level.is_some_and(|min_level| level < min_level) {
+            return false;
+        }
+        if let Some(min_duration) = self.min_duration_micros {
+            let slow = event.duration_micros.is_some_and(|duration| duration >= min_duration);
+            if !(slow || matches!(event.event_type, EventType::Exception)) {
+                return false;
+            }
+        }
+        let matches = |pattern: &String| module_matches(&event.module, pattern);
+        (self.modules.is_empty() || self.modules.iter().any(matches)) && !self.exclude_modules.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let config = Config {
+            min_level: Some(Level::Info),
+            sink_levels: crate::SinkLevels {
+                stdout: Some(Level::Error),
+                ..Default::default()
+            },
+            sink_filters: crate::SinkFilters {
+                file: SinkFilter {
+                    exclude_modules: vec!["health".to_string()],
+                    min_duration_ms: Some(500.0),
+                    ..Default::default()
+                },
+                stdout: SinkFilter {
+                    modules: vec!["orders".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let rules = SinkRules::new(&config);
+        let route = |rule: &Rule, event: &TraceEvent| rule.allows(event, event.effective_level());
+
+        let fast = TraceEvent::exit("shop::billing", "charge", None, Some(1_000));
+        let slow = TraceEvent::exit("shop::billing", "charge", None, Some(600_000));
+        let failed = TraceEvent::exception("shop::orders", "ship", "timeout", Some(1_000));
+        let probe = TraceEvent::exit("shop::health", "check", None, Some(900_000));
+
+        assert!(!route(&rules.file, &fast) && route(&rules.file, &slow) && route(&rules.file, &failed));
+        assert!(!route(&rules.file, &probe));
+        assert!(!route(&rules.file, &TraceEvent::enter("shop::billing", "charge", None)));
+        assert!(route(&rules.stdout, &failed));
+        assert!(!route(&rules.stdout, &TraceEvent::exit("shop::orders", "ship", None, Some(1_000))));
+        assert!(!route(&rules.stdout, &TraceEvent::exception("shop::billing", "charge", "declined", None)));
+    }
+}
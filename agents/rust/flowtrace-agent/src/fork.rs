@@ -0,0 +1,76 @@
+//! Fork awareness
+//!
+//! A forked child inherits the logger as it was in the parent: the log
+//! file's open description, whose lock it then shares with the parent, the
+//! pending zstd batch and the counts of the periodic records, which the
+//! parent writes too, and exporter threads that did not survive the fork.
+//! On Unix a `pthread_atfork` handler, registered with the first logger,
+//! bumps a fork generation in the child; a logger seeing a new generation
+//! resets itself before its next event (see `Logger::after_fork`) and stamps
+//! the child's events with its `pid` and `parentPid`.
+//!
+//! The handler only touches an atomic, so forking and then calling `exec`,
+//! as `std::process::Command` does, costs nothing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Forks this process has gone through, counted in each child
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Register the fork handler, once
+pub(crate) fn install() {
+    #[cfg(unix)]
+    {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| unsafe {
+            sys::pthread_atfork(None, None, Some(forked));
+        });
+    }
+}
+
+/// Current fork generation: changes in a child each time it is forked
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// This process's ID and its parent's, as recorded on a child's events
+pub(crate) fn process_ids() -> (u32, Option<u32>) {
+    #[cfg(unix)]
+    let parent = u32::try_from(unsafe { sys::getppid() }).ok();
+    #[cfg(not(unix))]
+    let parent = None;
+    (std::process::id(), parent)
+}
+
+/// Runs in the child, where only async-signal-safe work is allowed
+#[cfg(unix)]
+extern "C" fn forked() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+
+    extern "C" {
+        pub fn pthread_atfork(
+            prepare: Option<extern "C" fn()>,
+            parent: Option<extern "C" fn()>,
+            child: Option<extern "C" fn()>,
+        ) -> c_int;
+        pub fn getppid() -> c_int;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_ids() {
+        install();
+        let (pid, parent) = process_ids();
+        assert_eq!(pid, std::process::id());
+        assert!(parent.is_some_and(|parent| parent != pid));
+    }
+}
@@ -0,0 +1,264 @@
+//! Output formats of the log file and stdout
+//!
+//! Records are built as JSON; sinks configured with another
+//! [`OutputFormat`] render each line from it. Network sinks always send JSON.
+
+use std::borrow::Cow;
+use std::env;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Short keys of [`OutputFormat::CompactJson`], by full key
+///
+/// `TraceEvent` accepts both when parsing, through serde aliases.
+const COMPACT_KEYS: &[(&str, &str)] = &[
+    ("eventId", "id"),
+    ("event", "e"),
+    ("timestamp", "ts"),
+    ("monotonicMicros", "mono"),
+    ("class", "c"),
+    ("method", "m"),
+    ("target", "tg"),
+    ("fingerprint", "fp"),
+    ("args", "a"),
+    ("result", "r"),
+    ("exception", "x"),
+    ("status", "st"),
+    ("durationMicros", "d"),
+    ("thread", "th"),
+    ("level", "l"),
+    ("budgetExceeded", "be"),
+    ("attempt", "at"),
+    ("sampleRate", "sr"),
+    ("traceId", "tid"),
+    ("ioBytesRead", "ior"),
+    ("ioBytesWritten", "iow"),
+    ("ioWaitMicros", "iot"),
+    ("parentPid", "ppid"),
+];
+
+/// Columns of [`OutputFormat::Csv`], in order
+pub const CSV_COLUMNS: &[&str] = &["timestamp", "event", "class", "method", "duration_micros", "thread", "exception"];
+
+/// Characters kept of a CSV field; longer ones are cut and end in `...`
+const CSV_MAX_FIELD_CHARS: usize = 256;
+
+/// How a sink renders each record
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One JSON object per line, with the field names of the event schema
+    #[default]
+    Json,
+    /// JSON with short keys (`c`, `m`, `d`, ...) and without `durationMillis`
+    CompactJson,
+    /// `key=value` pairs, quoted where needed, as read by Heroku-style log pipelines
+    Logfmt,
+    /// Comma-separated [`CSV_COLUMNS`] under a header row, for spreadsheets
+    Csv,
+}
+
+impl OutputFormat {
+    /// Render a record serialized as the JSON object `json`
+    pub fn render(self, json: &str) -> Cow<'_, str> {
+        let fields = || match serde_json::from_str(json) {
+            Ok(Value::Object(fields)) => Some(fields),
+            _ => None,
+        };
+
+        match self {
+            Self::Json => Cow::Borrowed(json),
+            Self::CompactJson => fields().map_or(Cow::Borrowed(json), |fields| {
+                Cow::Owned(Value::Object(compact(fields)).to_string())
+            }),
+            Self::Logfmt => fields().map_or(Cow::Borrowed(json), |fields| Cow::Owned(logfmt(&fields))),
+            Self::Csv => fields().map_or(Cow::Borrowed(json), |fields| Cow::Owned(csv(&fields))),
+        }
+    }
+
+    /// Line written before the first record of a file or stream
+    pub fn header(self) -> Option<String> {
+        (self == Self::Csv).then(|| CSV_COLUMNS.join(","))
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Self::Json),
+            "compact_json" => Ok(Self::CompactJson),
+            "logfmt" => Ok(Self::Logfmt),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("Unknown format '{}': use json, compact_json, logfmt or csv", value)),
+        }
+    }
+}
+
+/// Output formats of the sinks writing text
+///
+/// ```toml
+/// [agent]
+/// formats = { stdout = "logfmt" }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SinkFormats {
+    pub file: OutputFormat,
+    pub stdout: OutputFormat,
+}
+
+impl SinkFormats {
+    pub(crate) fn from_env() -> Self {
+        let var = |name: &str| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or_default();
+        Self {
+            file: var("FLOWTRACE_FILE_FORMAT"),
+            stdout: var("FLOWTRACE_STDOUT_FORMAT"),
+        }
+    }
+}
+
+fn compact(fields: Map<String, Value>) -> Map<String, Value> {
+    fields
+        .into_iter()
+        .filter(|(key, _)| key != "durationMillis")
+        .map(|(key, value)| match COMPACT_KEYS.iter().find(|(full, _)| *full == key) {
+            Some((_, short)) => (short.to_string(), value),
+            None => (key, value),
+        })
+        .collect()
+}
+
+fn logfmt(fields: &Map<String, Value>) -> String {
+    let pairs: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            format!("{}={}", key, logfmt_value(&value))
+        })
+        .collect();
+    pairs.join(" ")
+}
+
+fn csv(fields: &Map<String, Value>) -> String {
+    let json_keys = ["timestamp", "event", "class", "method", "durationMicros", "thread", "exception"];
+    let cells: Vec<String> = json_keys
+        .iter()
+        .map(|key| match fields.get(*key) {
+            Some(Value::String(text)) => csv_field(text),
+            Some(Value::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        })
+        .collect();
+    cells.join(",")
+}
+
+/// One text cell: kept on one line, cut to [`CSV_MAX_FIELD_CHARS`], quoted
+/// when needed, and never read as a spreadsheet formula
+fn csv_field(text: &str) -> String {
+    let mut cell: String = text
+        .chars()
+        .take(CSV_MAX_FIELD_CHARS)
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if text.chars().nth(CSV_MAX_FIELD_CHARS).is_some() {
+        cell.push_str("...");
+    }
+    if cell.starts_with(['=', '+', '-', '@']) {
+        cell.insert(0, '\'');
+    }
+
+    if cell.contains([',', '"']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell
+    }
+}
+
+/// Quote `value` when it is empty or contains spaces, quotes, `=` or control characters
+fn logfmt_value(value: &str) -> Cow<'_, str> {
+    let plain = !value.is_empty() && !value.chars().any(|c| c == ' ' || c == '"' || c == '=' || c.is_control());
+    if plain {
+        return Cow::Borrowed(value);
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TimestampFormat, TraceEvent};
+
+    #[test]
+    fn test_compact_json_parses_back() {
+        let event = TraceEvent::exit("shop::billing", "charge", Some("Ok(3)".to_string()), Some(1_500));
+        let json = event.to_json(TimestampFormat::EpochMicros).unwrap();
+
+        let compact = OutputFormat::CompactJson.render(&json);
+        assert!(compact.starts_with(r#"{"id":"#), "{}", compact);
+        assert!(compact.contains(r#""c":"shop::billing","m":"charge""#));
+        assert!(!compact.contains("durationMillis"));
+
+        let parsed = TraceEvent::from_json_line(&compact).unwrap();
+        assert_eq!(parsed.function, "charge");
+        assert_eq!(parsed.duration_micros, Some(1_500));
+        assert_eq!(parsed.result.as_deref(), Some("Ok(3)"));
+    }
+
+    #[test]
+    fn test_logfmt() {
+        let event = TraceEvent::exception("shop::billing", "charge", "card \"declined\"", Some(7));
+        let json = event.to_json(TimestampFormat::EpochMicros).unwrap();
+        let line = OutputFormat::Logfmt.render(&json);
+
+        assert!(line.starts_with("eventId="), "{}", line);
+        assert!(line.contains(" event=EXCEPTION "));
+        assert!(line.contains(" class=shop::billing method=charge "));
+        assert!(line.contains(r#" exception="card \"declined\"" "#));
+        assert!(line.contains(" durationMicros=7 "));
+        assert_eq!(logfmt_value(""), r#""""#);
+        assert_eq!(logfmt_value("a=b"), r#""a=b""#);
+        assert_eq!(OutputFormat::Json.render(&json), json);
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut event = TraceEvent::exception("shop::billing", "charge", "declined, \"retry\"\nlater", Some(7));
+        event.timestamp = 1_700_000_000_123_456;
+        let json = event.to_json(TimestampFormat::EpochMicros).unwrap();
+        let row = OutputFormat::Csv.render(&json);
+        assert_eq!(
+            row,
+            format!(
+                r#"1700000000123456,EXCEPTION,shop::billing,charge,7,{},"declined, ""retry"" later""#,
+                event.thread
+            )
+        );
+
+        let enter = TraceEvent::enter("app", "work", None).to_json(TimestampFormat::EpochMicros).unwrap();
+        assert_eq!(OutputFormat::Csv.render(&enter).split(',').count(), CSV_COLUMNS.len());
+        assert_eq!(OutputFormat::Csv.header().unwrap(), "timestamp,event,class,method,duration_micros,thread,exception");
+        assert_eq!(csv_field("=HYPERLINK(1)"), "'=HYPERLINK(1)");
+        assert_eq!(csv_field(&"x".repeat(300)).len(), CSV_MAX_FIELD_CHARS + 3);
+    }
+}
@@ -0,0 +1,203 @@
+//! Tracing hand-written futures
+//!
+//! [`TracedFuture`] logs the events `#[trace]` logs for an `async fn` around
+//! any future: ENTER when it is first polled, EXIT (or EXCEPTION for an
+//! `Err`, with [`fallible`](TracedFuture::fallible)) when it completes. It
+//! keeps the trace context it was created in, so the events stay in one trace
+//! whichever threads poll it, e.g. inside `FuturesUnordered` or `tokio::spawn`.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{TraceContext, TraceEvent};
+
+/// How a completed future is logged
+enum Outcome {
+    Exit(Option<String>),
+    Exception(String),
+}
+
+/// A future traced as one call, see [`trace_future!`](crate::trace_future)
+pub struct TracedFuture<F: Future> {
+    future: Pin<Box<F>>,
+    module: String,
+    function: String,
+    args: Option<String>,
+    context: TraceContext,
+    outcome: fn(&F::Output) -> Outcome,
+    /// Start on the `monotonic_micros` clock, once first polled
+    start: Option<i64>,
+    /// Set once the closing event has been logged
+    finished: bool,
+    /// Ties the closing event to the ENTER, whichever thread polls the future last
+    call_id: u64,
+}
+
+impl<F: Future> TracedFuture<F> {
+    /// Wrap `future`, logging its events as `module::function`
+    ///
+    /// The output is not logged; see [`with_result`](Self::with_result) and
+    /// [`fallible`](Self::fallible).
+    pub fn new(module: &str, function: &str, future: F) -> Self {
+        Self {
+            future: Box::pin(future),
+            module: module.to_string(),
+            function: function.to_string(),
+            args: None,
+            context: TraceContext::current().unwrap_or_default(),
+            outcome: |_| Outcome::Exit(None),
+            start: None,
+            finished: false,
+            call_id: TraceEvent::next_call_id(),
+        }
+    }
+
+    /// Log `args` with the ENTER event
+    pub fn with_args(mut self, args: impl Into<String>) -> Self {
+        self.args = Some(args.into());
+        self
+    }
+
+    /// Log the output as the EXIT event's result
+    pub fn with_result(mut self) -> Self
+    where
+        F::Output: Debug,
+    {
+        self.outcome = |output| Outcome::Exit(Some(format!("{:?}", output)));
+        self
+    }
+}
+
+impl<F, T, E> TracedFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    T: Debug,
+    E: Debug,
+{
+    /// Log `Ok` values as the EXIT result and `Err` values as an EXCEPTION
+    pub fn fallible(mut self) -> Self {
+        self.outcome = |output| match output {
+            Ok(value) => Outcome::Exit(Some(format!("{:?}", value))),
+            Err(error) => Outcome::Exception(format!("{:?}", error)),
+        };
+        self
+    }
+}
+
+impl<F: Future> TracedFuture<F> {
+    fn log(&self, mut event: TraceEvent) {
+        event.context = Some(self.context.clone());
+        event.call_id = Some(self.call_id);
+        crate::log_event(event);
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        let Some(start) = self.start.filter(|_| !self.finished) else {
+            return;
+        };
+        self.finished = true;
+        let duration = crate::monotonic_micros() - start;
+        let event = match outcome {
+            Outcome::Exit(result) => TraceEvent::exit(&self.module, &self.function, result, Some(duration)),
+            Outcome::Exception(error) => TraceEvent::exception(&self.module, &self.function, &error, Some(duration)),
+        };
+        self.log(event);
+    }
+}
+
+impl<F: Future> Future for TracedFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        let _guard = this.context.attach();
+        if this.start.is_none() {
+            this.log(TraceEvent::enter(&this.module, &this.function, this.args.clone()));
+            this.start = Some(crate::monotonic_micros());
+        }
+
+        let output = this.future.as_mut().poll(cx);
+        if let Poll::Ready(output) = &output {
+            let outcome = (this.outcome)(output);
+            this.finish(outcome);
+        }
+        output
+    }
+}
+
+impl<F: Future> Drop for TracedFuture<F> {
+    fn drop(&mut self) {
+        // Dropped before completing, e.g. the losing branch of a `select!`
+        if !std::thread::panicking() {
+            self.finish(Outcome::Exit(Some("cancelled".to_string())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_traced_future_events() {
+        let path = std::env::temp_dir().join("flowtrace_future_traced.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        crate::with_tracer(&tracer, || {
+            runtime.block_on(async {
+                let found = TracedFuture::new("app", "find", async { Ok::<u32, String>(7) })
+                    .with_args("{\"id\": 7}")
+                    .fallible();
+                assert_eq!(found.await, Ok(7));
+
+                let missing = TracedFuture::new("app", "find", async { Err::<u32, _>("missing") }).fallible();
+                assert!(missing.await.is_err());
+
+                let count = TracedFuture::new("app", "count", async { 3 }).with_result();
+                assert_eq!(count.await, 3);
+
+                let slow = TracedFuture::new("app", "slow", std::future::pending::<()>());
+                assert!(tokio::time::timeout(Duration::from_millis(1), slow).await.is_err());
+            })
+        });
+        tracer.stop();
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .skip(1) // AGENT_INFO
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let closes: Vec<(&str, Option<&str>, Option<&str>)> = events
+            .iter()
+            .filter(|event| !matches!(event.event_type, crate::EventType::Enter))
+            .map(|event| (event.function.as_str(), event.result.as_deref(), event.exception.as_deref()))
+            .collect();
+        assert_eq!(
+            closes,
+            [
+                ("find", Some("7"), None),
+                ("find", None, Some("\"missing\"")),
+                ("count", Some("3"), None),
+                ("slow", Some("cancelled"), None),
+            ]
+        );
+        assert_eq!(events[0].args.as_deref(), Some("{\"id\": 7}"));
+        // Each future is its own trace, shared by its ENTER and EXIT
+        assert_eq!(events[0].trace_id, events[1].trace_id);
+        assert_ne!(events[0].trace_id, events[2].trace_id);
+    }
+}
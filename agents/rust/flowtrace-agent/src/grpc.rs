@@ -0,0 +1,632 @@
+//! Streaming export of events to a gRPC collector
+//!
+//! With `Config::grpc_endpoint` set, the logger also streams every record to
+//! the collector's `flowtrace.v1.Collector/Export` call (see
+//! `proto/flowtrace.proto`). The collector answers on the same call with
+//! `Control` messages that change the module filters and sampling rate of the
+//! export, so it can turn verbosity up or down without restarting the service.
+//!
+//! The exporter runs on its own thread and reconnects with backoff when the
+//! connection drops, following `Config::export_retry`. After a few failed
+//! attempts in a row its circuit breaker opens: events go to the spool in
+//! `Config::spool_dir`, to `Config::grpc_fallback_file`, or are dropped and
+//! counted, instead of queueing, and the collector is only probed at a slowly
+//! growing interval until it answers. Spooled events are replayed first once
+//! it does. Events logged faster than they can be sent take the same fallback.
+//!
+//! With `Config::exporter_workers` above one, that many export threads each
+//! keep their own call open. Events are partitioned between them by trace ID,
+//! or by thread for events outside a trace, so each call tree still arrives
+//! in order.
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use futures_util::future::Either;
+use tokio::sync::{mpsc, oneshot};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Endpoint;
+
+use crate::breaker::{CircuitBreaker, Fallback};
+use crate::sampling::{self, CallSampler};
+use crate::spool::{self, Spool};
+use crate::{Config, RetryPolicy, TimestampFormat, TraceEvent};
+
+/// Records buffered before new ones are dropped
+const BUFFER: usize = 4096;
+/// How long records queued at shutdown may take to send
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+const EXPORT_PATH: &str = "/flowtrace.v1.Collector/Export";
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// One log line, exactly as written to the JSONL file
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(string, tag = "1")]
+    pub json: String,
+}
+
+/// Export settings pushed by the collector
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Control {
+    #[prost(string, repeated, tag = "1")]
+    pub modules: Vec<String>,
+    #[prost(string, repeated, tag = "2")]
+    pub exclude_modules: Vec<String>,
+    #[prost(double, optional, tag = "3")]
+    pub sample_rate: Option<f64>,
+}
+
+impl Control {
+    /// Whether events from `module` pass the collector's filters
+    fn allows_module(&self, module: &str) -> bool {
+        let matches = |pattern: &String| crate::config::module_matches(module, pattern);
+
+        (self.modules.is_empty() || self.modules.iter().any(matches))
+            && !self.exclude_modules.iter().any(matches)
+    }
+}
+
+/// Health of an export, see [`GrpcExporter::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportStats {
+    /// Whether the circuit breaker is open, bypassing the collector
+    pub breaker_open: bool,
+    /// Events written to the fallback file instead of the collector
+    pub diverted: u64,
+    /// Events written to the spool while the collector was unreachable
+    pub spooled: u64,
+    /// Spooled events sent to the collector after it recovered
+    pub replayed: u64,
+    /// Bytes waiting in the spool
+    pub spool_bytes: u64,
+    /// Events lost, with no fallback file or when writing it failed
+    pub dropped: u64,
+}
+
+/// A connection to a gRPC collector
+pub struct GrpcExporter {
+    /// One per export worker
+    senders: Vec<mpsc::Sender<Record>>,
+    /// Worker the next record outside a call tree goes to
+    next_worker: AtomicUsize,
+    control: Arc<RwLock<Control>>,
+    calls: Mutex<CallSampler>,
+    fallback: Arc<Fallback>,
+    /// For events re-serialized with the export's sampling rate
+    timestamp_format: TimestampFormat,
+    /// Dropped with the exporter to stop the export threads
+    _shutdown: Vec<oneshot::Sender<()>>,
+}
+
+impl GrpcExporter {
+    /// Start exporting to `endpoint` (e.g. `http://collector:4317`)
+    ///
+    /// `agent_info` is the `AGENT_INFO` line sent first on every connection,
+    /// and written first to the fallback file when the breaker first opens.
+    /// Retries, spooling and the fallback file follow `config`.
+    pub fn connect(endpoint: &str, agent_info: String, config: &Config) -> io::Result<Self> {
+        let endpoint = Endpoint::from_shared(endpoint.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let control = Arc::new(RwLock::new(Control::default()));
+        let spool = match &config.spool_dir {
+            Some(dir) => Some(Spool::open(dir, config.spool_max_bytes)?),
+            None => None,
+        };
+        let fallback = Arc::new(Fallback::new(spool, config.grpc_fallback_file.clone(), agent_info.clone()));
+
+        let workers = config.exporter_workers.max(1);
+        let mut senders = Vec::with_capacity(workers);
+        let mut shutdown = Vec::with_capacity(workers);
+        for worker in 0..workers {
+            let (sender, receiver) = mpsc::channel(BUFFER / workers);
+            let (stop, stopped) = oneshot::channel();
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            let export = Export {
+                endpoint: endpoint.clone(),
+                control: Arc::clone(&control),
+                fallback: Arc::clone(&fallback),
+                agent_info: agent_info.clone(),
+                retry: config.export_retry,
+            };
+            let name = if workers == 1 {
+                "flowtrace-grpc".to_string()
+            } else {
+                format!("flowtrace-grpc-{}", worker)
+            };
+            thread::Builder::new()
+                .name(name)
+                .spawn(move || runtime.block_on(run(export, receiver, stopped)))?;
+            senders.push(sender);
+            shutdown.push(stop);
+        }
+
+        Ok(Self {
+            senders,
+            next_worker: AtomicUsize::new(0),
+            control,
+            calls: Mutex::new(CallSampler::default()),
+            fallback,
+            timestamp_format: config.timestamp_format,
+            _shutdown: shutdown,
+        })
+    }
+
+    /// Circuit breaker state and fallback accounting
+    pub fn stats(&self) -> ExportStats {
+        ExportStats {
+            breaker_open: self.fallback.is_open(),
+            diverted: self.fallback.diverted(),
+            spooled: self.fallback.spooled(),
+            replayed: self.fallback.replayed(),
+            spool_bytes: self.fallback.spool().map_or(0, Spool::bytes),
+            dropped: self.fallback.dropped(),
+        }
+    }
+
+    /// The settings most recently pushed by the collector
+    pub fn control(&self) -> Control {
+        self.control.read().map(|control| control.clone()).unwrap_or_default()
+    }
+
+    /// Queue `event`, already serialized as `json`, if the collector's filters and sampling keep it
+    pub fn publish(&self, event: &TraceEvent, json: &str) {
+        let Ok(control) = self.control.read() else {
+            return;
+        };
+        let rate = control.sample_rate.unwrap_or(1.0);
+        let sampled = match (&event.trace_id, self.calls.lock()) {
+            (Some(trace_id), _) => sampling::by_trace_id(trace_id, rate),
+            (None, Ok(mut calls)) => calls.sample(event, || sampling::with_probability(rate)),
+            (None, Err(_)) => sampling::with_probability(rate),
+        };
+
+        // Whole traces (or root calls) are sampled so exported call trees are never partial
+        match sampled {
+            Some(export_rate) if control.allows_module(&event.module) => {
+                let worker = self.worker_for(event);
+                if export_rate >= 1.0 {
+                    self.send(worker, json);
+                    return;
+                }
+                // Sampled again on the way out: the collector sees the combined rate
+                let mut event = event.clone();
+                event.sampled = true;
+                event.sample_rate = Some(event.sample_rate.unwrap_or(1.0) * export_rate);
+                if let Ok(json) = event.to_json(self.timestamp_format) {
+                    self.send(worker, &json);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Queue a record that is not a trace event (e.g. `ERROR_RATE`), bypassing filters and sampling
+    pub fn publish_record(&self, json: &str) {
+        let worker = self.next_worker.fetch_add(1, Ordering::Relaxed) % self.senders.len();
+        self.send(worker, json);
+    }
+
+    /// Worker exporting `event`: the same for every event of its trace, or of its thread
+    fn worker_for(&self, event: &TraceEvent) -> usize {
+        if self.senders.len() == 1 {
+            return 0;
+        }
+        let key = event.trace_id.as_deref().unwrap_or(&event.thread);
+        // FNV-1a, stable across runs unlike `DefaultHasher`'s random keys
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        (hash % self.senders.len() as u64) as usize
+    }
+
+    fn send(&self, worker: usize, json: &str) {
+        if self.fallback.is_open() || self.senders[worker].try_send(Record { json: json.to_string() }).is_err() {
+            self.fallback.divert(json);
+        }
+    }
+}
+
+/// What the export thread shares with the exporter
+struct Export {
+    endpoint: Endpoint,
+    control: Arc<RwLock<Control>>,
+    fallback: Arc<Fallback>,
+    agent_info: String,
+    retry: RetryPolicy,
+}
+
+/// Keep an export call open, reconnecting until the exporter is dropped
+async fn run(export: Export, receiver: mpsc::Receiver<Record>, stopped: oneshot::Receiver<()>) {
+    let exporting = Box::pin(async move {
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let mut breaker = CircuitBreaker::new(
+            export.retry.max_attempts,
+            Duration::from_millis(export.retry.initial_backoff_ms),
+            Duration::from_millis(export.retry.max_backoff_ms),
+        );
+        loop {
+            let mut connected = false;
+            let _ = export_call(&export, &receiver, &mut connected).await;
+            if receiver.lock().await.is_closed() {
+                return;
+            }
+            if connected {
+                // The call was accepted before it ended; reconnect right away
+                breaker.record_success();
+                continue;
+            }
+
+            let wait = breaker.record_failure();
+            if breaker.is_open() && !export.fallback.is_open() {
+                // Stop holding records for a collector that is not answering
+                let mut receiver = receiver.lock().await;
+                while let Ok(record) = receiver.try_recv() {
+                    export.fallback.divert(&record.json);
+                }
+                export.fallback.set_open(true);
+            }
+            tokio::time::sleep(wait).await;
+        }
+    });
+
+    if let Either::Right((_, exporting)) = futures_util::future::select(exporting, stopped).await {
+        // The record stream ends once drained; give the call a moment to send it
+        let _ = tokio::time::timeout(SHUTDOWN_GRACE, exporting).await;
+    }
+}
+
+/// One export call: stream records up, apply `Control`s coming down
+///
+/// Sets `connected` and closes the breaker once the collector accepts the call.
+async fn export_call(
+    export: &Export,
+    receiver: &Arc<tokio::sync::Mutex<mpsc::Receiver<Record>>>,
+    connected: &mut bool,
+) -> Result<(), Error> {
+    let mut client = tonic::client::Grpc::new(export.endpoint.connect().await?);
+    client.ready().await?;
+
+    let handshake = futures_util::stream::once(std::future::ready(Record {
+        json: export.agent_info.clone(),
+    }));
+    let records = futures_util::stream::unfold(Arc::clone(receiver), |receiver| async move {
+        let record = receiver.lock().await.recv().await?;
+        Some((record, receiver))
+    });
+    // Spooled records go first, each segment deleted once all of it was taken;
+    // one interrupted mid-segment is sent again in full (deduplicate by `eventId`)
+    let replay = futures_util::stream::unfold(
+        (Arc::clone(&export.fallback), None::<(std::path::PathBuf, std::vec::IntoIter<String>)>),
+        |(fallback, mut segment)| async move {
+            loop {
+                let spool = fallback.spool()?;
+                if let Some((path, mut records)) = segment.take() {
+                    if let Some(json) = records.next() {
+                        fallback.record_replayed();
+                        return Some((Record { json }, (fallback, Some((path, records)))));
+                    }
+                    spool.remove(&path);
+                }
+                let path = spool.take_oldest()?;
+                let records = spool::read_segment(&path).into_iter();
+                segment = Some((path, records));
+            }
+        },
+    );
+
+    let response = client
+        .streaming(
+            tonic::Request::new(handshake.chain(replay).chain(records)),
+            PathAndQuery::from_static(EXPORT_PATH),
+            tonic::codec::ProstCodec::<Record, Control>::default(),
+        )
+        .await?;
+
+    *connected = true;
+    export.fallback.set_open(false);
+
+    let mut controls = response.into_inner();
+    while let Some(update) = controls.message().await? {
+        if let Ok(mut control) = export.control.write() {
+            *control = update;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exporter() -> (GrpcExporter, mpsc::Receiver<Record>) {
+        let (exporter, mut receivers) = exporter_with_workers(1);
+        (exporter, receivers.remove(0))
+    }
+
+    fn exporter_with_workers(workers: usize) -> (GrpcExporter, Vec<mpsc::Receiver<Record>>) {
+        let (senders, receivers) = (0..workers).map(|_| mpsc::channel(16)).unzip();
+        let exporter = GrpcExporter {
+            senders,
+            next_worker: AtomicUsize::new(0),
+            control: Arc::new(RwLock::new(Control::default())),
+            calls: Mutex::new(CallSampler::default()),
+            fallback: Arc::new(Fallback::new(None, None, String::new())),
+            timestamp_format: TimestampFormat::EpochMicros,
+            _shutdown: Vec::new(),
+        };
+        (exporter, receivers)
+    }
+
+    fn publish(exporter: &GrpcExporter, event: TraceEvent) {
+        let json = serde_json::to_string(&event).unwrap();
+        exporter.publish(&event, &json);
+    }
+
+    #[test]
+    fn test_control_filters_modules() {
+        let (exporter, mut receiver) = exporter();
+        *exporter.control.write().unwrap() = Control {
+            modules: vec!["billing".to_string()],
+            exclude_modules: vec!["billing::metrics".to_string()],
+            sample_rate: None,
+        };
+
+        publish(&exporter, TraceEvent::enter("shop::billing", "charge", None));
+        publish(&exporter, TraceEvent::enter("shop::billing::metrics", "record", None));
+        publish(&exporter, TraceEvent::enter("shop::auth", "login", None));
+
+        assert!(receiver.try_recv().unwrap().json.contains(r#""method":"charge""#));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_sampling_keeps_whole_calls() {
+        let (exporter, mut receiver) = exporter();
+        exporter.control.write().unwrap().sample_rate = Some(0.0);
+
+        publish(&exporter, TraceEvent::enter("app", "dropped", None));
+        exporter.control.write().unwrap().sample_rate = Some(1.0);
+        // Still inside the unsampled root call
+        publish(&exporter, TraceEvent::enter("app", "child", None));
+        publish(&exporter, TraceEvent::exit("app", "child", None, Some(1)));
+        publish(&exporter, TraceEvent::exit("app", "dropped", None, Some(2)));
+        assert!(receiver.try_recv().is_err());
+
+        publish(&exporter, TraceEvent::enter("app", "kept", None));
+        publish(&exporter, TraceEvent::exit("app", "kept", None, Some(1)));
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_sampling_by_trace_id() {
+        let (exporter, mut receiver) = exporter();
+        exporter.control.write().unwrap().sample_rate = Some(0.5);
+        let in_trace = |trace_id: &str, event: TraceEvent| TraceEvent {
+            trace_id: Some(trace_id.to_string()),
+            ..event
+        };
+
+        // Decided by the trace ID alone, whichever thread logs the event
+        for _ in 0..3 {
+            publish(&exporter, in_trace("0fffffffffffffff0000000000000000", TraceEvent::enter("app", "kept", None)));
+            publish(&exporter, in_trace("ffffffffffffffff0000000000000000", TraceEvent::enter("app", "dropped", None)));
+        }
+        for _ in 0..3 {
+            let json = receiver.try_recv().unwrap().json;
+            assert!(json.contains(r#""method":"kept""#));
+            assert!(json.contains(r#""sampled":true"#) && json.contains(r#""sampleRate":0.5"#), "{}", json);
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_workers_partition_by_trace() {
+        let (exporter, mut receivers) = exporter_with_workers(4);
+        let in_trace = |trace_id: String, event: TraceEvent| TraceEvent {
+            trace_id: Some(trace_id),
+            ..event
+        };
+
+        for trace in 0..32 {
+            let trace_id = format!("{:032x}", trace);
+            publish(&exporter, in_trace(trace_id.clone(), TraceEvent::enter("app", "handle", None)));
+            publish(&exporter, in_trace(trace_id, TraceEvent::exit("app", "handle", None, Some(1))));
+        }
+
+        let mut busy = 0;
+        for receiver in &mut receivers {
+            let mut records = Vec::new();
+            while let Ok(record) = receiver.try_recv() {
+                records.push(TraceEvent::from_json_line(&record.json).unwrap());
+            }
+            busy += usize::from(!records.is_empty());
+            // Each trace's ENTER and EXIT arrive together, in order, on one worker
+            for pair in records.chunks(2) {
+                assert_eq!(pair[0].trace_id, pair[1].trace_id);
+                assert!(matches!(pair[1].event_type, crate::EventType::Exit));
+            }
+        }
+        assert!(busy > 1);
+    }
+
+    #[test]
+    fn test_open_breaker_diverts_to_fallback() {
+        let path = std::env::temp_dir().join("flowtrace_grpc_fallback.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let (mut exporter, mut receiver) = exporter();
+        exporter.fallback = Arc::new(Fallback::new(None, Some(path.display().to_string()), "AGENT_INFO line".to_string()));
+
+        exporter.fallback.set_open(true);
+        publish(&exporter, TraceEvent::enter("app", "diverted", None));
+        exporter.fallback.set_open(false);
+        publish(&exporter, TraceEvent::enter("app", "exported", None));
+
+        assert!(receiver.try_recv().unwrap().json.contains(r#""method":"exported""#));
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().next(), Some("AGENT_INFO line"));
+        assert!(log.lines().nth(1).unwrap().contains(r#""method":"diverted""#));
+        assert_eq!(exporter.stats(), ExportStats { diverted: 1, ..Default::default() });
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_breaker_opens_while_collector_is_down() {
+        // Nothing listens on the port of a dropped listener
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel(16);
+        let fallback = Arc::new(Fallback::new(None, None, String::new()));
+        let export = Export {
+            endpoint: Endpoint::from_shared(format!("http://{}", addr)).unwrap(),
+            control: Arc::new(RwLock::new(Control::default())),
+            fallback: Arc::clone(&fallback),
+            agent_info: String::new(),
+            retry: RetryPolicy {
+                initial_backoff_ms: 10,
+                ..Default::default()
+            },
+        };
+        sender.send(Record { json: "queued".to_string() }).await.unwrap();
+        let (_shutdown, stopped) = oneshot::channel();
+        tokio::spawn(run(export, receiver, stopped));
+
+        // Opens on the third failed attempt
+        while !fallback.is_open() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        // The queued record was released rather than held for the collector
+        assert_eq!(fallback.dropped(), 1);
+    }
+
+    mod collector {
+        //! A minimal `Collector` server, hand-written like the client
+        use super::*;
+        use std::convert::Infallible;
+        use tonic::body::BoxBody;
+        use tonic::codegen::{http, BoxFuture, Context, Poll, Service};
+        use tonic::server::NamedService;
+        use tonic::Streaming;
+
+        type Controls = std::pin::Pin<Box<dyn futures_util::Stream<Item = Result<Control, tonic::Status>> + Send>>;
+
+        /// Forwards received records and pushes one `Control` per call
+        #[derive(Clone)]
+        pub struct Collector {
+            pub received: mpsc::UnboundedSender<String>,
+            pub control: Control,
+        }
+
+        impl NamedService for Collector {
+            const NAME: &'static str = "flowtrace.v1.Collector";
+        }
+
+        impl Service<http::Request<BoxBody>> for Collector {
+            type Response = http::Response<BoxBody>;
+            type Error = Infallible;
+            type Future = BoxFuture<Self::Response, Self::Error>;
+
+            fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+                let export = Export(self.clone());
+                Box::pin(async move {
+                    let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::<Control, Record>::default());
+                    Ok(grpc.streaming(export, request).await)
+                })
+            }
+        }
+
+        struct Export(Collector);
+
+        impl Service<tonic::Request<Streaming<Record>>> for Export {
+            type Response = tonic::Response<Controls>;
+            type Error = tonic::Status;
+            type Future = BoxFuture<Self::Response, Self::Error>;
+
+            fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, request: tonic::Request<Streaming<Record>>) -> Self::Future {
+                let Collector { received, control } = self.0.clone();
+                let mut records = request.into_inner();
+                tokio::spawn(async move {
+                    while let Ok(Some(record)) = records.message().await {
+                        let _ = received.send(record.json);
+                    }
+                });
+
+                let controls = futures_util::stream::once(std::future::ready(Ok(control)))
+                    .chain(futures_util::stream::pending());
+                Box::pin(async move { Ok(tonic::Response::new(Box::pin(controls) as Controls)) })
+            }
+        }
+    }
+
+    /// Serve a collector pushing `control`, returning its URL and the records it receives
+    async fn serve_collector(control: Control) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received, records) = mpsc::unbounded_channel();
+        let collector = collector::Collector { received, control };
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(collector)
+                .serve_with_incoming(incoming),
+        );
+        (format!("http://{}", addr), records)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_to_collector() {
+        let (url, mut records) = serve_collector(Control {
+            modules: vec!["billing".to_string()],
+            ..Default::default()
+        })
+        .await;
+
+        let exporter = GrpcExporter::connect(&url, "AGENT_INFO line".to_string(), &Config::default()).unwrap();
+        assert_eq!(records.recv().await.unwrap(), "AGENT_INFO line");
+        while exporter.control().modules.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        publish(&exporter, TraceEvent::enter("shop::auth", "login", None));
+        publish(&exporter, TraceEvent::enter("shop::billing", "charge", None));
+        assert!(records.recv().await.unwrap().contains(r#""method":"charge""#));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replays_spool_to_collector() {
+        let dir = std::env::temp_dir().join("flowtrace_grpc_spool");
+        let _ = std::fs::remove_dir_all(&dir);
+        // Left behind by an earlier run that could not reach the collector
+        let spool = Spool::open(&dir, 1024).unwrap();
+        spool.append("spooled record");
+        drop(spool);
+
+        let (url, mut records) = serve_collector(Control::default()).await;
+        let config = Config {
+            spool_dir: Some(dir.display().to_string()),
+            ..Default::default()
+        };
+        let exporter = GrpcExporter::connect(&url, "AGENT_INFO line".to_string(), &config).unwrap();
+        assert_eq!(records.recv().await.unwrap(), "AGENT_INFO line");
+        assert_eq!(records.recv().await.unwrap(), "spooled record");
+
+        publish(&exporter, TraceEvent::enter("app", "live", None));
+        assert!(records.recv().await.unwrap().contains(r#""method":"live""#));
+        let stats = exporter.stats();
+        assert_eq!((stats.replayed, stats.spool_bytes), (1, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
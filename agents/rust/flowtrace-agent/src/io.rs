@@ -0,0 +1,332 @@
+//! IO accounting for traced calls
+//!
+//! [`TracedReader`] and [`TracedWriter`] wrap a reader or writer (and, with
+//! the `tokio` feature, an `AsyncRead` or `AsyncWrite`) and add the bytes
+//! they move and the time spent waiting on them to the innermost call open on
+//! the thread doing the IO. That call's EXIT or EXCEPTION then carries
+//! `ioBytesRead`, `ioBytesWritten` and `ioWaitMicros`, telling a call slow on
+//! its disk or network from one slow on its own work. Calls without traced IO
+//! get none of the fields.
+//!
+//! Totals are kept per thread, like implicit trace contexts: IO done on
+//! another thread, or by a future polled on one, counts towards the call open
+//! there.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::time::Instant;
+
+use crate::{EventType, TraceEvent};
+
+/// Open calls tracked per thread; beyond it the outermost are forgotten, e.g.
+/// calls of futures that were entered on this thread and closed on another
+const MAX_FRAMES: usize = 256;
+
+thread_local! {
+    /// IO of the calls open on this thread, innermost last
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// IO done during one call
+struct Frame {
+    module: String,
+    function: String,
+    bytes_read: u64,
+    bytes_written: u64,
+    wait_micros: i64,
+}
+
+/// Open a frame on ENTER and close it on EXIT or EXCEPTION, adding its totals to the event
+pub(crate) fn stamp(event: &mut TraceEvent) {
+    FRAMES.with(|frames| {
+        let mut frames = frames.borrow_mut();
+        if matches!(event.event_type, EventType::Enter) {
+            if frames.len() == MAX_FRAMES {
+                frames.remove(0);
+            }
+            frames.push(Frame {
+                module: event.module.clone(),
+                function: event.function.clone(),
+                bytes_read: 0,
+                bytes_written: 0,
+                wait_micros: 0,
+            });
+            return;
+        }
+
+        // Normally the innermost frame; a call closed out of order leaves the others open
+        let Some(index) = frames
+            .iter()
+            .rposition(|frame| frame.module == event.module && frame.function == event.function)
+        else {
+            return;
+        };
+        let frame = frames.remove(index);
+        if frame.bytes_read > 0 || frame.bytes_written > 0 || frame.wait_micros > 0 {
+            event.io_bytes_read = Some(frame.bytes_read);
+            event.io_bytes_written = Some(frame.bytes_written);
+            event.io_wait_micros = Some(frame.wait_micros);
+        }
+    });
+}
+
+/// Add IO to the innermost open call, if any
+fn record(bytes_read: usize, bytes_written: usize, started: Instant) {
+    let wait_micros = started.elapsed().as_micros() as i64;
+    FRAMES.with(|frames| {
+        if let Some(frame) = frames.borrow_mut().last_mut() {
+            frame.bytes_read += bytes_read as u64;
+            frame.bytes_written += bytes_written as u64;
+            frame.wait_micros += wait_micros;
+        }
+    });
+}
+
+/// A reader whose reads count towards the enclosing call's IO
+///
+/// ```rust,no_run
+/// use std::io::Read;
+/// use flowtrace_agent::{trace, TracedReader};
+///
+/// #[trace]
+/// fn load(path: &str) -> std::io::Result<String> {
+///     // EXIT: "ioBytesRead": 5120, "ioBytesWritten": 0, "ioWaitMicros": 830
+///     let mut text = String::new();
+///     TracedReader::new(std::fs::File::open(path)?).read_to_string(&mut text)?;
+///     Ok(text)
+/// }
+/// ```
+pub struct TracedReader<R> {
+    inner: R,
+    /// When the pending async read was first polled
+    #[cfg(feature = "tokio")]
+    pending: Option<Instant>,
+}
+
+impl<R> TracedReader<R> {
+    /// Wrap `inner`
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            #[cfg(feature = "tokio")]
+            pending: None,
+        }
+    }
+
+    /// The wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The wrapped reader; IO done on it directly is not counted
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap the reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for TracedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let result = self.inner.read(buf);
+        record(*result.as_ref().unwrap_or(&0), 0, started);
+        result
+    }
+}
+
+/// A writer whose writes and flushes count towards the enclosing call's IO
+pub struct TracedWriter<W> {
+    inner: W,
+    /// When the pending async write or flush was first polled
+    #[cfg(feature = "tokio")]
+    pending: Option<Instant>,
+}
+
+impl<W> TracedWriter<W> {
+    /// Wrap `inner`
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            #[cfg(feature = "tokio")]
+            pending: None,
+        }
+    }
+
+    /// The wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// The wrapped writer; IO done on it directly is not counted
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap the writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for TracedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let result = self.inner.write(buf);
+        record(0, *result.as_ref().unwrap_or(&0), started);
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let started = Instant::now();
+        let result = self.inner.flush();
+        record(0, 0, started);
+        result
+    }
+}
+
+/// Async IO is timed from the first poll of an operation until it completes
+#[cfg(feature = "tokio")]
+mod tokio_io {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Instant;
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::{record, TracedReader, TracedWriter};
+
+    /// Record an operation started at `pending` once it completes
+    fn finish<T>(poll: Poll<T>, pending: &mut Option<Instant>, bytes: impl FnOnce(&T) -> (usize, usize)) -> Poll<T> {
+        if let Poll::Ready(result) = &poll {
+            if let Some(started) = pending.take() {
+                let (read, written) = bytes(result);
+                record(read, written, started);
+            }
+        }
+        poll
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for TracedReader<R> {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            let this = &mut *self;
+            let filled = buf.filled().len();
+            this.pending.get_or_insert_with(Instant::now);
+            let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+            let read = buf.filled().len() - filled;
+            finish(poll, &mut this.pending, |_| (read, 0))
+        }
+    }
+
+    impl<W: AsyncWrite + Unpin> AsyncWrite for TracedWriter<W> {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            let this = &mut *self;
+            this.pending.get_or_insert_with(Instant::now);
+            let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+            finish(poll, &mut this.pending, |result| (0, *result.as_ref().unwrap_or(&0)))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            let this = &mut *self;
+            this.pending.get_or_insert_with(Instant::now);
+            let poll = Pin::new(&mut this.inner).poll_flush(cx);
+            finish(poll, &mut this.pending, |_| (0, 0))
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            let this = &mut *self;
+            this.pending.get_or_insert_with(Instant::now);
+            let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+            finish(poll, &mut this.pending, |_| (0, 0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_counts_towards_innermost_call() {
+        let path = std::env::temp_dir().join("flowtrace_io.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+
+        crate::with_tracer(&tracer, || {
+            crate::log_event(TraceEvent::enter("app", "copy", None));
+            let mut reader = TracedReader::new(&b"hello world"[..]);
+            let mut writer = TracedWriter::new(Vec::new());
+            crate::log_event(TraceEvent::enter("app", "read", None));
+            let mut buf = [0; 5];
+            reader.read_exact(&mut buf).unwrap();
+            crate::log_event(TraceEvent::exit("app", "read", None, None));
+            io::copy(&mut reader, &mut writer).unwrap();
+            writer.flush().unwrap();
+            crate::log_event(TraceEvent::exit("app", "copy", None, None));
+            assert_eq!(writer.into_inner(), b" world");
+
+            crate::log_event(TraceEvent::enter("app", "idle", None));
+            crate::log_event(TraceEvent::exit("app", "idle", None, None));
+        });
+        tracer.stop();
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        let io = |event: &TraceEvent| (event.io_bytes_read, event.io_bytes_written);
+        assert_eq!(io(&events[2]), (Some(5), Some(0)));
+        assert_eq!(io(&events[3]), (Some(6), Some(6)));
+        assert!(events[3].io_wait_micros.is_some());
+        assert_eq!(io(&events[5]), (None, None));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_io() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join("flowtrace_io_async.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        crate::with_tracer(&tracer, || {
+            runtime.block_on(async {
+                crate::log_event(TraceEvent::enter("app", "echo", None));
+                let mut text = String::new();
+                // Named in full, as the std traits are in scope too
+                AsyncReadExt::read_to_string(&mut TracedReader::new(&b"ping"[..]), &mut text).await.unwrap();
+                let mut writer = TracedWriter::new(Vec::new());
+                AsyncWriteExt::write_all(&mut writer, text.as_bytes()).await.unwrap();
+                AsyncWriteExt::flush(&mut writer).await.unwrap();
+                crate::log_event(TraceEvent::exit("app", "echo", None, None));
+            })
+        });
+        tracer.stop();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let exit = TraceEvent::from_json_line(log.lines().last().unwrap()).unwrap();
+        assert_eq!((exit.io_bytes_read, exit.io_bytes_written), (Some(4), Some(4)));
+    }
+}
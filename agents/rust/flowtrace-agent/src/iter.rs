@@ -0,0 +1,184 @@
+//! Iterator tracing for batch-processing loops
+//!
+//! [`Traced`] wraps an iterator as one call: ENTER when the first item is
+//! pulled, EXIT with the item count and total time once it is exhausted or
+//! dropped. With [`Traced::sample_every`], every Nth item also becomes a child
+//! call timed from the moment it is yielded until the loop asks for the next
+//! one, i.e. how long the loop body spent on it.
+
+use crate::TraceEvent;
+
+/// An iterator traced as one call, see [`trace_iter!`](crate::trace_iter)
+pub struct Traced<I> {
+    iter: I,
+    module: String,
+    function: String,
+    /// Name of the child calls of sampled items
+    item_function: String,
+    /// Trace every Nth item as a child call
+    every: Option<u64>,
+    items: u64,
+    /// Start on the `monotonic_micros` clock, once the first item is pulled
+    start: Option<i64>,
+    /// Start of the sampled item being processed
+    item_start: Option<i64>,
+    /// Set once the closing EXIT event has been logged
+    finished: bool,
+}
+
+impl<I: Iterator> Traced<I> {
+    /// Wrap `iter`, logging its events as `module::function`
+    pub fn new(module: &str, function: &str, iter: I) -> Self {
+        Self {
+            iter,
+            module: module.to_string(),
+            function: function.to_string(),
+            item_function: format!("{}::item", function),
+            every: None,
+            items: 0,
+            start: None,
+            item_start: None,
+            finished: false,
+        }
+    }
+
+    /// Also trace the 1st, (N+1)th, (2N+1)th... item as a child call; 0 turns it off
+    pub fn sample_every(mut self, n: u64) -> Self {
+        self.every = (n > 0).then_some(n);
+        self
+    }
+}
+
+impl<I> Traced<I> {
+    /// Items yielded so far
+    pub fn items(&self) -> u64 {
+        self.items
+    }
+
+    fn close_item(&mut self) {
+        if let Some(start) = self.item_start.take() {
+            let duration = crate::monotonic_micros() - start;
+            crate::log_event(TraceEvent::exit(&self.module, &self.item_function, None, Some(duration)));
+        }
+    }
+
+    fn finish(&mut self) {
+        self.close_item();
+        if let Some(start) = self.start.filter(|_| !self.finished) {
+            self.finished = true;
+            let duration = crate::monotonic_micros() - start;
+            let result = format!("{{\"items\": {}}}", self.items);
+            crate::log_event(TraceEvent::exit(&self.module, &self.function, Some(result), Some(duration)));
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for Traced<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        if self.finished {
+            return self.iter.next();
+        }
+        if self.start.is_none() {
+            crate::log_event(TraceEvent::enter(&self.module, &self.function, None));
+            self.start = Some(crate::monotonic_micros());
+        }
+        self.close_item();
+
+        let item = self.iter.next();
+        match &item {
+            Some(_) => {
+                if self.every.is_some_and(|every| self.items.is_multiple_of(every)) {
+                    let args = format!("{{\"item\": {}}}", self.items);
+                    crate::log_event(TraceEvent::enter(&self.module, &self.item_function, Some(args)));
+                    self.item_start = Some(crate::monotonic_micros());
+                }
+                self.items += 1;
+            }
+            None => self.finish(),
+        }
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I> Drop for Traced<I> {
+    fn drop(&mut self) {
+        // A loop that stopped early (`break`, `take`, `find`) still logs its EXIT
+        if !std::thread::panicking() {
+            self.finish();
+        }
+    }
+}
+
+/// `.traced(module, function)` on any iterator
+pub trait TraceIterExt: Iterator + Sized {
+    /// Trace this iterator as one call, see [`Traced`]
+    fn traced(self, module: &str, function: &str) -> Traced<Self> {
+        Traced::new(module, function, self)
+    }
+}
+
+impl<I: Iterator> TraceIterExt for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventType;
+
+    #[test]
+    fn test_traced_iterator_events() {
+        let path = std::env::temp_dir().join("flowtrace_iter_traced.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let (total, early) = crate::with_tracer(&tracer, || {
+            let total: u32 = (1..=5).traced("app", "load_rows").sample_every(2).sum();
+            let early = (1..=100).traced("app", "scan").find(|&n| n == 3);
+            (total, early)
+        });
+        tracer.stop();
+        assert_eq!((total, early), (15, Some(3)));
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .skip(1) // AGENT_INFO
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let calls: Vec<(&str, &str, Option<&str>)> = events
+            .iter()
+            .map(|event| {
+                let kind = if matches!(event.event_type, EventType::Enter) { "ENTER" } else { "EXIT" };
+                (kind, event.function.as_str(), event.args.as_deref().or(event.result.as_deref()))
+            })
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("ENTER", "load_rows", None),
+                ("ENTER", "load_rows::item", Some(r#"{"item": 0}"#)),
+                ("EXIT", "load_rows::item", None),
+                ("ENTER", "load_rows::item", Some(r#"{"item": 2}"#)),
+                ("EXIT", "load_rows::item", None),
+                ("ENTER", "load_rows::item", Some(r#"{"item": 4}"#)),
+                ("EXIT", "load_rows::item", None),
+                ("EXIT", "load_rows", Some(r#"{"items": 5}"#)),
+                ("ENTER", "scan", None),
+                ("EXIT", "scan", Some(r#"{"items": 3}"#)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Tracing scheduled background jobs
+//!
+//! [`traced_job`] runs a periodic task on a tokio interval, each run as the
+//! root call of a trace of its own, so cron-style work shows up like requests
+//! do. A run that takes longer than the interval records by how much in
+//! `overrunMicros`, and a failed run counts the failures in a row, ending
+//! that streak included, in `consecutiveFailures`:
+//!
+//! ```json
+//! {"event":"EXCEPTION","module":"job","method":"sync_accounts","exception":"\"timeout\"","result":"{\"run\": 7, \"overrunMicros\": 1500000, \"consecutiveFailures\": 3}",...}
+//! ```
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+use crate::{TraceContext, TraceEvent};
+
+/// Module of the events of job runs
+const MODULE: &str = "job";
+
+/// Run `job` every `interval`, tracing each run as a call of `job::<name>`
+///
+/// The first run starts right away. A run that overruns the interval is
+/// followed by the next one as soon as it ends; runs never overlap. The
+/// returned future never completes: spawn it, and abort it to stop the job.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use flowtrace_agent::traced_job;
+///
+/// # async fn purge_expired_sessions() -> Result<(), std::io::Error> { Ok(()) }
+/// # async fn schedule() {
+/// tokio::spawn(traced_job("purge_sessions", Duration::from_secs(60), || async {
+///     purge_expired_sessions().await
+/// }));
+/// # }
+/// ```
+pub async fn traced_job<F, Fut, E>(name: &str, interval: Duration, mut job: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Debug,
+{
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut failures = 0;
+
+    for run in 1.. {
+        ticks.tick().await;
+        let mut call = Run::start(name, run, interval);
+        let result = call.context.instrument(job()).await;
+        failures = if result.is_ok() { 0 } else { failures + 1 };
+        call.finish(result.err().map(|error| format!("{:?}", error)), failures);
+    }
+}
+
+/// One run of a job between its ENTER and closing event
+struct Run {
+    function: String,
+    run: u64,
+    interval: Duration,
+    context: TraceContext,
+    /// Start on the `monotonic_micros` clock
+    start: i64,
+    /// Set once the closing event has been logged
+    finished: bool,
+}
+
+impl Run {
+    /// Log ENTER in a new trace
+    fn start(name: &str, run: u64, interval: Duration) -> Self {
+        let context = TraceContext::new_root();
+        let args = format!("{{\"run\": {}, \"intervalMicros\": {}}}", run, interval.as_micros());
+        context.scope(|| crate::log_event(TraceEvent::enter(MODULE, name, Some(args))));
+        Self {
+            function: name.to_string(),
+            run,
+            interval,
+            context,
+            start: crate::monotonic_micros(),
+            finished: false,
+        }
+    }
+
+    /// Log EXIT, or EXCEPTION with `error` after `failures` failed runs in a row
+    fn finish(&mut self, error: Option<String>, failures: u32) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        let duration = crate::monotonic_micros() - self.start;
+
+        let mut fields = vec![format!("\"run\": {}", self.run)];
+        let overrun = duration - self.interval.as_micros() as i64;
+        if overrun > 0 {
+            fields.push(format!("\"overrunMicros\": {}", overrun));
+        }
+        let mut event = match error {
+            Some(error) => {
+                fields.push(format!("\"consecutiveFailures\": {}", failures));
+                TraceEvent::exception(MODULE, &self.function, &error, Some(duration))
+            }
+            None => TraceEvent::exit(MODULE, &self.function, None, Some(duration)),
+        };
+        event.result = Some(format!("{{{}}}", fields.join(", ")));
+        self.context.scope(|| crate::log_event(event));
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        // The job was aborted mid-run
+        if !std::thread::panicking() && !self.finished {
+            self.finished = true;
+            let duration = Some(crate::monotonic_micros() - self.start);
+            let event = TraceEvent::exit(MODULE, &self.function, Some("cancelled".to_string()), duration);
+            self.context.scope(|| crate::log_event(event));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traced_job() {
+        let path = std::env::temp_dir().join("flowtrace_job.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        crate::with_tracer(&tracer, || {
+            runtime.block_on(async {
+                let (done, mut finished) = tokio::sync::mpsc::unbounded_channel();
+                let mut run = 0;
+                let job = tokio::spawn(traced_job("sync", Duration::from_millis(20), move || {
+                    run += 1;
+                    let done = done.clone();
+                    async move {
+                        match run {
+                            1 => tokio::time::sleep(Duration::from_millis(30)).await,
+                            2 | 3 => return Err("timeout"),
+                            _ => done.send(()).unwrap(),
+                        }
+                        Ok(())
+                    }
+                }));
+                finished.recv().await;
+                job.abort();
+                let _ = job.await;
+            })
+        });
+        tracer.stop();
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(events.len(), 8);
+        assert_eq!(events[0].args.as_deref(), Some(r#"{"run": 1, "intervalMicros": 20000}"#));
+        assert!(events[1].result.as_deref().unwrap().starts_with(r#"{"run": 1, "overrunMicros": "#));
+        assert_eq!(events[3].result.as_deref(), Some(r#"{"run": 2, "consecutiveFailures": 1}"#));
+        assert_eq!(events[5].result.as_deref(), Some(r#"{"run": 3, "consecutiveFailures": 2}"#));
+        assert_eq!(events[7].status, Some(crate::Status::Ok));
+        assert_eq!(events[7].result.as_deref(), Some(r#"{"run": 4}"#));
+        assert_ne!(events[0].trace_id, events[2].trace_id);
+        assert_eq!(events[2].trace_id, events[3].trace_id);
+    }
+}
@@ -22,24 +22,96 @@
 //! }
 //! ```
 
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
 
+mod aggregate;
+mod agent_info;
+#[cfg(feature = "grpc")]
+mod breaker;
+pub mod capture;
+mod capture_budget;
+pub mod command;
 mod config;
+mod control;
+mod error;
+mod error_rate;
+mod filter;
+mod fork;
+mod format;
+pub mod context;
+mod depth;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod future;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod io;
+pub mod iter;
+#[cfg(feature = "tokio")]
+pub mod job;
 mod logger;
+pub mod net;
+mod overhead;
+mod parse;
+pub mod pool;
+mod quota;
+mod recording;
+mod repeated;
+pub mod resource;
+pub mod runtime;
+mod sampling;
+#[cfg(feature = "schema")]
+pub mod schema;
 pub mod span;
+#[cfg(feature = "grpc")]
+mod spool;
+mod tracer;
+mod ulid;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 pub mod middleware;
 
-pub use config::Config;
+pub use aggregate::{Aggregate, AGGREGATE};
+pub use agent_info::{AgentInfo, AGENT_INFO, SCHEMA_VERSION};
+pub use config::{
+    Compression, Config, ConfigBuilder, ConfigError, EventHook, Mode, QuotaAction, RetryPolicy, Sink, SinkFilter,
+    SinkFilters, SinkLevels, TimestampFormat,
+};
+pub use capture::{EventCapture, MAX_CAPTURED_EVENTS};
+pub use capture_budget::CAPTURE_SUPPRESSED;
+pub use command::{TracedChild, TracedCommand, TRACEPARENT_ENV};
+pub use context::{bind, bind_future, extract_context, inject_context, TraceContext, TRACEPARENT};
+pub use error::FlowTraceError;
+pub use error_rate::{ErrorRate, ERROR_RATE};
+pub use format::{OutputFormat, SinkFormats, CSV_COLUMNS};
+pub use future::TracedFuture;
+pub use io::{TracedReader, TracedWriter};
+pub use iter::{TraceIterExt, Traced};
+#[cfg(feature = "tokio")]
+pub use job::traced_job;
 pub use logger::Logger;
-pub use span::{Span, start_span};
+pub use net::RequestPhases;
+pub use overhead::{Overhead, OVERHEAD};
+pub use parse::ParseError;
+pub use pool::traced_task;
+pub use quota::{QuotaEvent, QUOTA};
+pub use repeated::{Repeated, REPEATED};
+pub use resource::Resource;
+#[cfg(feature = "async-std")]
+pub use runtime::AsyncStdRuntime;
+#[cfg(feature = "tokio")]
+pub use runtime::TokioRuntime;
+pub use runtime::{Runtime, ThreadRuntime};
+pub use span::{current_span, retry, Span, SpanBuilder, SpanKind, SpanRef, start_span};
+pub use tracer::{with_tracer, Tracer};
 
 /// Trace event type
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "UPPERCASE")]
 pub enum EventType {
     Enter,
@@ -47,128 +119,432 @@ pub enum EventType {
     Exception,
 }
 
+/// Event severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("Unknown level '{}': use debug, info, warn or error", value)),
+        }
+    }
+}
+
+/// Outcome of a call, on its closing event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// The call returned normally, or with `Ok` for functions returning `Result`
+    Ok,
+    /// The call returned `Err`, panicked, or its span was marked with `Span::set_error`;
+    /// the message is in `exception`
+    Error,
+}
+
 /// Trace event structure
+// Published as schema/trace-event.schema.json, see the `schema` module
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct TraceEvent {
-    #[serde(rename = "event")]
+    /// Unique, time-sortable ULID for deduplicating retransmitted events
+    #[serde(rename = "eventId", alias = "id", default)]
+    pub event_id: String,
+    #[serde(rename = "event", alias = "e")]
     pub event_type: EventType,
+    /// Wall-clock time in microseconds since the Unix epoch
+    ///
+    /// Written according to `Config::timestamp_format`; any format is accepted when parsing.
+    #[serde(alias = "ts", deserialize_with = "parse::timestamp_micros")]
+    #[cfg_attr(feature = "schema", schemars(schema_with = "schema::timestamp_schema"))]
     pub timestamp: i64,
-    #[serde(rename = "class")]
+    /// Wall-clock time in nanoseconds, used for `TimestampFormat::EpochNanos`
+    /// (0 for events read back from JSON)
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    /// Microseconds on the process-relative monotonic clock (see [`monotonic_micros`])
+    #[serde(rename = "monotonicMicros", alias = "mono", default)]
+    pub monotonic_micros: i64,
+    #[serde(rename = "class", alias = "c")]
     pub module: String,
-    #[serde(rename = "method")]
+    #[serde(rename = "method", alias = "m")]
     pub function: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Logical subsystem set with `#[trace(target = "...")]`, independent of the module path
+    #[serde(skip_serializing_if = "Option::is_none", alias = "tg", default)]
+    pub target: Option<String>,
+    /// Hash of the traced function's signature (and body, with `fingerprint_body`)
+    /// at compile time, to tell code changes from environmental drift
+    #[serde(skip_serializing_if = "Option::is_none", alias = "fp", default)]
+    pub fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", alias = "a")]
     pub args: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "Option::is_none", alias = "r")]
     pub result: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "Option::is_none", alias = "x")]
     pub exception: Option<String>,
+    /// Outcome of the call: `ok` on EXIT, `error` on EXCEPTION; absent on ENTER
+    #[serde(skip_serializing_if = "Option::is_none", alias = "st", default)]
+    pub status: Option<Status>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "durationMillis")]
     pub duration_millis: Option<i64>,
-    #[serde(skip_serializing_if = "Option::is_none", rename = "durationMicros")]
+    #[serde(skip_serializing_if = "Option::is_none", rename = "durationMicros", alias = "d")]
     pub duration_micros: Option<i64>,
+    #[serde(alias = "th")]
     pub thread: String,
+    /// Severity, set when an event is escalated (e.g. an exceeded latency budget)
+    #[serde(skip_serializing_if = "Option::is_none", alias = "l", default)]
+    pub level: Option<Level>,
+    /// Whether the call took longer than its `#[trace(warn_over_ms = ...)]` budget
+    #[serde(skip_serializing_if = "std::ops::Not::not", rename = "budgetExceeded", alias = "be", default)]
+    pub budget_exceeded: bool,
+    /// 1-based attempt number of a retried operation; on the outer call, the attempts it took
+    #[serde(skip_serializing_if = "Option::is_none", alias = "at", default)]
+    pub attempt: Option<u32>,
+    /// Whether the event was kept by a head-sampling decision, made by this
+    /// agent or upstream (a sampled `traceparent`), so its trace may be one of many
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub sampled: bool,
+    /// Fraction of root calls kept when the event was sampled; absent means all of them
+    #[serde(skip_serializing_if = "Option::is_none", rename = "sampleRate", alias = "sr", default)]
+    pub sample_rate: Option<f64>,
+    /// ID of the trace the event belongs to, shared across threads, tasks and services
+    #[serde(skip_serializing_if = "Option::is_none", rename = "traceId", alias = "tid", default)]
+    pub trace_id: Option<String>,
+    /// Bytes read through a `TracedReader` during the call; set on EXIT and
+    /// EXCEPTION of calls that did traced IO
+    #[serde(skip_serializing_if = "Option::is_none", rename = "ioBytesRead", default)]
+    pub io_bytes_read: Option<u64>,
+    /// Bytes written through a `TracedWriter` during the call
+    #[serde(skip_serializing_if = "Option::is_none", rename = "ioBytesWritten", default)]
+    pub io_bytes_written: Option<u64>,
+    /// Microseconds spent waiting on traced reads, writes and flushes during the call
+    #[serde(skip_serializing_if = "Option::is_none", rename = "ioWaitMicros", default)]
+    pub io_wait_micros: Option<i64>,
+    /// Process that logged the event, set in a forked child (see `AgentInfo::pid` otherwise)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pid: Option<u32>,
+    /// Process the child was forked from
+    #[serde(skip_serializing_if = "Option::is_none", rename = "parentPid", alias = "ppid", default)]
+    pub parent_pid: Option<u32>,
+    /// Role of a manual span in its trace, set on the ENTER of spans built with `Span::builder`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub kind: Option<SpanKind>,
+    /// Attributes of a manual span: on ENTER those set with `Span::builder`,
+    /// on EXIT or EXCEPTION all of them
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tags: Option<BTreeMap<String, String>>,
+    /// Context the event was logged in, carrying its trace's sampling decision
+    #[serde(skip)]
+    pub context: Option<TraceContext>,
+    /// Nesting depth `args` and `result` are cut to, overriding `Config::max_arg_depth`
+    #[serde(skip)]
+    pub max_depth: Option<usize>,
+    /// Call the event belongs to, the same on its ENTER and its EXIT or
+    /// EXCEPTION whichever thread logs them; see `TraceEvent::with_call_id`
+    #[serde(skip)]
+    pub call_id: Option<u64>,
 }
 
 impl TraceEvent {
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        if format == TimestampFormat::EpochMicros {
+            return serde_json::to_string(self);
+        }
+
+        let nanos = if self.timestamp_nanos != 0 {
+            self.timestamp_nanos
+        } else {
+            self.timestamp * 1000
+        };
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Flag the event when its duration is over `budget_micros`
+    ///
+    /// With `escalate`, a flagged event is also raised to `Level::Warn`.
+    pub fn with_budget(mut self, budget_micros: i64, escalate: bool) -> Self {
+        if self.duration_micros.is_some_and(|duration| duration > budget_micros) {
+            self.budget_exceeded = true;
+            if escalate {
+                self.level = Some(Level::Warn);
+            }
+        }
+        self
+    }
+
+    /// Severity the event is filtered at: its `level` if set, otherwise
+    /// ERROR for exceptions and INFO for everything else
+    pub fn effective_level(&self) -> Level {
+        self.level.unwrap_or(match self.event_type {
+            EventType::Exception => Level::Error,
+            _ => Level::Info,
+        })
+    }
+
+    /// Record the logical subsystem the event belongs to, e.g. `"db"`
+    pub fn with_target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// Set the captured arguments, e.g. those `#[trace(echo_args(...))]` repeats on EXIT
+    pub fn with_args(mut self, args: String) -> Self {
+        self.args = Some(args);
+        self
+    }
+
+    /// Cut `args` and `result` to `max_depth` levels of nesting, see `#[trace(max_depth = N)]`
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// A new ID for [`with_call_id`](Self::with_call_id), unique within the process
+    pub fn next_call_id() -> u64 {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Tie the event to one call, e.g. an `async fn` that may resume on another thread
+    pub fn with_call_id(mut self, call_id: u64) -> Self {
+        self.call_id = Some(call_id);
+        self
+    }
+
+    /// Record the fingerprint `#[trace]` computed for the function
+    pub fn with_fingerprint(mut self, fingerprint: &str) -> Self {
+        self.fingerprint = Some(fingerprint.to_string());
+        self
+    }
+
     /// Create a new ENTER event
     pub fn enter(module: &str, function: &str, args: Option<String>) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros() as i64;
+        let now = wall_clock_nanos();
 
         Self {
+            event_id: ulid::generate(),
             event_type: EventType::Enter,
-            timestamp: now,
+            timestamp: now / 1000,
+            timestamp_nanos: now,
+            monotonic_micros: monotonic_micros(),
             module: module.to_string(),
             function: function.to_string(),
+            target: None,
+            fingerprint: None,
             args,
             result: None,
             exception: None,
+            status: None,
             duration_millis: None,
             duration_micros: None,
             thread: format!("{:?}", std::thread::current().id()),
+            level: None,
+            budget_exceeded: false,
+            attempt: None,
+            sampled: false,
+            sample_rate: None,
+            trace_id: None,
+            io_bytes_read: None,
+            io_bytes_written: None,
+            io_wait_micros: None,
+            pid: None,
+            parent_pid: None,
+            kind: None,
+            tags: None,
+            context: None,
+            max_depth: None,
+            call_id: None,
         }
     }
 
     /// Create a new EXIT event
     pub fn exit(module: &str, function: &str, result: Option<String>, duration_micros: Option<i64>) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros() as i64;
+        let now = wall_clock_nanos();
 
         let duration_millis = duration_micros.map(|d| d / 1000);
 
         Self {
+            event_id: ulid::generate(),
             event_type: EventType::Exit,
-            timestamp: now,
+            timestamp: now / 1000,
+            timestamp_nanos: now,
+            monotonic_micros: monotonic_micros(),
             module: module.to_string(),
             function: function.to_string(),
+            target: None,
+            fingerprint: None,
             args: None,
             result,
             exception: None,
+            status: Some(Status::Ok),
             duration_millis,
             duration_micros,
             thread: format!("{:?}", std::thread::current().id()),
+            level: None,
+            budget_exceeded: false,
+            attempt: None,
+            sampled: false,
+            sample_rate: None,
+            trace_id: None,
+            io_bytes_read: None,
+            io_bytes_written: None,
+            io_wait_micros: None,
+            pid: None,
+            parent_pid: None,
+            kind: None,
+            tags: None,
+            context: None,
+            max_depth: None,
+            call_id: None,
         }
     }
 
     /// Create a new EXCEPTION event
     pub fn exception(module: &str, function: &str, error: &str, duration_micros: Option<i64>) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros() as i64;
+        let now = wall_clock_nanos();
 
         let duration_millis = duration_micros.map(|d| d / 1000);
 
         Self {
+            event_id: ulid::generate(),
             event_type: EventType::Exception,
-            timestamp: now,
+            timestamp: now / 1000,
+            timestamp_nanos: now,
+            monotonic_micros: monotonic_micros(),
             module: module.to_string(),
             function: function.to_string(),
+            target: None,
+            fingerprint: None,
             args: None,
             result: None,
             exception: Some(error.to_string()),
+            status: Some(Status::Error),
             duration_millis,
             duration_micros,
             thread: format!("{:?}", std::thread::current().id()),
+            level: None,
+            budget_exceeded: false,
+            attempt: None,
+            sampled: false,
+            sample_rate: None,
+            trace_id: None,
+            io_bytes_read: None,
+            io_bytes_written: None,
+            io_wait_micros: None,
+            pid: None,
+            parent_pid: None,
+            kind: None,
+            tags: None,
+            context: None,
+            max_depth: None,
+            call_id: None,
         }
     }
 }
 
+/// Wall-clock time in nanoseconds since the Unix epoch
+fn wall_clock_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as i64
+}
+
+/// Start of the monotonic clock, fixed by the first reading
+static CLOCK_START: OnceLock<Instant> = OnceLock::new();
+
+/// Microseconds elapsed on a process-relative monotonic clock
+///
+/// Unlike `timestamp`, this never jumps when the system clock is adjusted.
+/// Event durations are measured on the same clock, so they always agree with
+/// the difference between ENTER and EXIT `monotonicMicros`.
+pub fn monotonic_micros() -> i64 {
+    CLOCK_START.get_or_init(Instant::now).elapsed().as_micros() as i64
+}
+
 /// Global tracer instance
-static mut GLOBAL_TRACER: Option<Arc<Mutex<Logger>>> = None;
+static GLOBAL_TRACER: Tracer = Tracer::new();
 
 /// Initialize global tracing
-pub fn start_tracing(config: Config) -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        if GLOBAL_TRACER.is_some() {
-            return Err("Tracer already initialized".into());
-        }
-        let logger = Logger::new(config)?;
-        GLOBAL_TRACER = Some(Arc::new(Mutex::new(logger)));
-    }
-    Ok(())
+pub fn start_tracing(config: Config) -> Result<(), FlowTraceError> {
+    GLOBAL_TRACER.start(config)
 }
 
 /// Stop global tracing
 pub fn stop_tracing() {
-    unsafe {
-        GLOBAL_TRACER = None;
+    GLOBAL_TRACER.stop();
+}
+
+/// Flush events logged to the global tracer so far
+pub fn flush_tracing() {
+    GLOBAL_TRACER.flush();
+}
+
+/// Switch the global tracer between aggregate-only mode and full tracing
+///
+/// See `Config::aggregate_only`.
+pub fn set_aggregate_only(aggregate_only: bool) {
+    GLOBAL_TRACER.set_aggregate_only(aggregate_only);
+}
+
+/// Record events of the global tracer for `window`, started in `Mode::OnDemand`
+///
+/// Calls entered before the window ends are logged whole, with their EXIT or
+/// EXCEPTION, even when it comes later.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// // e.g. from an admin endpoint, after an alert fired
+/// flowtrace_agent::record_for(Duration::from_secs(60));
+/// ```
+pub fn record_for(window: Duration) {
+    GLOBAL_TRACER.record_for(window);
+}
+
+/// Stops global tracing when dropped, see [`init_from_env`]
+pub struct TracingGuard {
+    _private: (),
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        stop_tracing();
+    }
+}
+
+/// Start global tracing with `Config::from_env()` until the returned guard is dropped
+///
+/// This is what `#[trace(main)]` calls. If tracing can't start, the error
+/// is printed to stderr and the program runs untraced.
+pub fn init_from_env() -> TracingGuard {
+    if let Err(e) = start_tracing(Config::from_env()) {
+        eprintln!("flowtrace: tracing disabled: {}", e);
     }
+    TracingGuard { _private: () }
 }
 
-/// Log a trace event
+/// Log a trace event to the global tracer, or to the `with_tracer` override of this thread
 pub fn log_event(event: TraceEvent) {
-    unsafe {
-        if let Some(tracer) = &GLOBAL_TRACER {
-            if let Ok(mut logger) = tracer.lock() {
-                logger.log(event);
-            }
-        }
+    if let Some(event) = tracer::log_override(event) {
+        GLOBAL_TRACER.log(event);
     }
 }
 
@@ -176,12 +552,12 @@ pub fn log_event(event: TraceEvent) {
 #[macro_export]
 macro_rules! trace_function {
     ($module:expr, $function:expr, $body:expr) => {{
-        let start = std::time::Instant::now();
+        let start = $crate::monotonic_micros();
         $crate::log_event($crate::TraceEvent::enter($module, $function, None));
 
         let result = (|| $body)();
 
-        let duration = start.elapsed().as_secs_f64() * 1000.0;
+        let duration = $crate::monotonic_micros() - start;
         $crate::log_event($crate::TraceEvent::exit(
             $module,
             $function,
@@ -193,6 +569,69 @@ macro_rules! trace_function {
     }};
 }
 
+/// Retry a fallible operation, tracing every attempt
+///
+/// Expands to [`retry`] with the caller's module path:
+///
+/// ```rust
+/// let mut failures = 2;
+/// let result: Result<u32, String> = flowtrace_agent::trace_retry!("fetch_user", 5, |attempt| {
+///     if failures > 0 {
+///         failures -= 1;
+///         Err(format!("timeout on attempt {}", attempt))
+///     } else {
+///         Ok(42)
+///     }
+/// });
+/// assert_eq!(result, Ok(42));
+/// ```
+#[macro_export]
+macro_rules! trace_retry {
+    ($function:expr, $max_attempts:expr, $op:expr) => {
+        $crate::retry(module_path!(), $function, $max_attempts, $op)
+    };
+}
+
+/// Trace an iterator as one call, optionally timing every Nth item
+///
+/// Expands to [`Traced::new`] with the caller's module path:
+///
+/// ```rust
+/// let rows = vec![3, 1, 4, 1, 5];
+/// let mut total = 0;
+/// for row in flowtrace_agent::trace_iter!("import_rows", rows, every = 2) {
+///     total += row;
+/// }
+/// assert_eq!(total, 14);
+/// ```
+#[macro_export]
+macro_rules! trace_iter {
+    ($function:expr, $iter:expr) => {
+        $crate::Traced::new(module_path!(), $function, ::std::iter::IntoIterator::into_iter($iter))
+    };
+    ($function:expr, $iter:expr, every = $every:expr) => {
+        $crate::trace_iter!($function, $iter).sample_every($every)
+    };
+}
+
+/// Trace a future as one call
+///
+/// Expands to [`TracedFuture::new`] with the caller's module path:
+///
+/// ```rust
+/// # async fn fetch(id: u32) -> Result<u32, String> { Ok(id) }
+/// # async fn run() -> Result<u32, String> {
+/// let user = flowtrace_agent::trace_future!("fetch_user", fetch(7)).fallible().await?;
+/// # Ok(user)
+/// # }
+/// ```
+#[macro_export]
+macro_rules! trace_future {
+    ($function:expr, $future:expr) => {
+        $crate::TracedFuture::new(module_path!(), $function, $future)
+    };
+}
+
 /// Procedural macro attribute for automatic tracing (placeholder)
 ///
 /// Note: This would require a separate proc-macro crate
@@ -204,3 +643,70 @@ pub use flowtrace_agent_attribute::trace;
 pub mod flowtrace_agent_attribute {
     pub use flowtrace_derive::trace;
 }
+
+pub use flowtrace_derive::trace_block;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_carry_monotonic_time() {
+        let enter = TraceEvent::enter("app", "work", None);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let exit = TraceEvent::exit("app", "work", None, Some(2_000));
+
+        assert!(exit.monotonic_micros - enter.monotonic_micros >= 2_000);
+        assert!(monotonic_micros() >= exit.monotonic_micros);
+
+        let json = serde_json::to_value(&exit).unwrap();
+        assert_eq!(json["monotonicMicros"], exit.monotonic_micros);
+    }
+
+    #[test]
+    fn test_with_budget() {
+        let fast = TraceEvent::exit("app", "work", None, Some(1_000)).with_budget(2_000, true);
+        assert!(!fast.budget_exceeded);
+        assert!(!serde_json::to_string(&fast).unwrap().contains("budgetExceeded"));
+
+        let slow = TraceEvent::exit("app", "work", None, Some(3_000)).with_budget(2_000, false);
+        assert!(slow.budget_exceeded);
+        assert_eq!(slow.level, None);
+
+        let escalated = TraceEvent::exit("app", "work", None, Some(3_000)).with_budget(2_000, true);
+        let json = serde_json::to_value(&escalated).unwrap();
+        assert_eq!(json["budgetExceeded"], true);
+        assert_eq!(json["level"], "WARN");
+    }
+
+    #[test]
+    fn test_status() {
+        let exit = serde_json::to_value(TraceEvent::exit("app", "work", None, Some(1))).unwrap();
+        let exception = serde_json::to_value(TraceEvent::exception("app", "work", "boom", Some(1))).unwrap();
+        assert_eq!((exit["status"].as_str(), exception["status"].as_str()), (Some("ok"), Some("error")));
+        assert!(!serde_json::to_string(&TraceEvent::enter("app", "work", None)).unwrap().contains("status"));
+    }
+
+    #[test]
+    fn test_with_target() {
+        let event = TraceEvent::enter("shop::orders::repo", "find", None).with_target("db");
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!((json["class"].as_str(), json["target"].as_str()), (Some("shop::orders::repo"), Some("db")));
+        assert!(!serde_json::to_string(&TraceEvent::enter("app", "work", None)).unwrap().contains("target"));
+    }
+
+    #[test]
+    fn test_timestamp_formats() {
+        let mut event = TraceEvent::enter("app", "work", None);
+        event.timestamp = 1_700_000_000_123_456;
+        event.timestamp_nanos = 1_700_000_000_123_456_789;
+
+        let json = |format| serde_json::from_str::<serde_json::Value>(&event.to_json(format).unwrap()).unwrap();
+        assert_eq!(json(TimestampFormat::EpochMicros)["timestamp"], 1_700_000_000_123_456_i64);
+        assert_eq!(json(TimestampFormat::EpochNanos)["timestamp"], 1_700_000_000_123_456_789_i64);
+        assert_eq!(json(TimestampFormat::Rfc3339)["timestamp"], "2023-11-14T22:13:20.123456Z");
+
+        let line = event.to_json(TimestampFormat::EpochNanos).unwrap();
+        assert!(line.starts_with(r#"{"eventId":"#), "field order kept: {}", line);
+    }
+}
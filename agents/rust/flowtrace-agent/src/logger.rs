@@ -1,16 +1,67 @@
 use std::fs::OpenOptions;
 use std::io::Write;
-use crate::{Config, TraceEvent};
+use crate::capture_budget::CaptureBudget;
+use crate::control::ControlFile;
+use crate::depth;
+use crate::aggregate::{self, Aggregator};
+use crate::error_rate::ErrorRates;
+use crate::filter::SinkRules;
+use crate::overhead::{OverheadMeter, Stopwatch};
+use crate::quota::DiskQuota;
+use crate::recording::Recording;
+use crate::repeated::Coalescer;
+use crate::sampling::Sampler;
+use crate::{AgentInfo, Compression, Config, EventType, FlowTraceError, Mode, TraceEvent};
+
+/// Uncompressed bytes collected before a zstd frame is written
+#[cfg(feature = "zstd")]
+const ZSTD_BATCH_BYTES: usize = 64 * 1024;
+/// Age after which a partial batch is written anyway
+#[cfg(feature = "zstd")]
+const ZSTD_BATCH_AGE: std::time::Duration = std::time::Duration::from_secs(1);
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
 
 /// Thread-safe JSONL logger
 pub struct Logger {
     config: Config,
+    /// Which events each sink writes
+    rules: SinkRules,
     file: Option<std::fs::File>,
+    /// Written again at the top of each rotated-in log file
+    agent_info: Option<String>,
+    quota: Option<DiskQuota>,
+    /// Set while the quota is applied, so writes it makes don't apply it again
+    enforcing_quota: bool,
+    sampler: Option<Sampler>,
+    control: Option<ControlFile>,
+    /// Recording windows, in `Mode::OnDemand`
+    recording: Option<Recording>,
+    capture_budget: Option<CaptureBudget>,
+    error_rates: Option<ErrorRates>,
+    coalescer: Option<Coalescer>,
+    aggregator: Option<Aggregator>,
+    /// Whether raw events are left out, leaving only `AGGREGATE` records
+    aggregate_only: bool,
+    overhead: Option<OverheadMeter>,
+    /// Events counted and dropped in `Mode::Dry`
+    dry_run_events: u64,
+    /// Fork generation the logger's state belongs to
+    fork_generation: u64,
+    /// Process and parent IDs stamped on events, once the logger runs in a forked child
+    forked: Option<(u32, Option<u32>)>,
+    /// Lines waiting to be written as the next zstd frame, and when the first arrived
+    #[cfg(feature = "zstd")]
+    batch: (Vec<u8>, Option<std::time::Instant>),
+    #[cfg(feature = "websocket")]
+    websocket: Option<crate::websocket::WebSocketSink>,
+    #[cfg(feature = "grpc")]
+    grpc: Option<crate::grpc::GrpcExporter>,
 }
 
 impl Logger {
     /// Create a new logger
-    pub fn new(config: Config) -> Result<Self, std::io::Error> {
+    pub fn new(config: Config) -> Result<Self, FlowTraceError> {
         let file = if !config.log_file.is_empty() {
             Some(
                 OpenOptions::new()
@@ -22,32 +73,1017 @@ impl Logger {
             None
         };
 
-        Ok(Self { config, file })
+        #[cfg(not(feature = "zstd"))]
+        if config.compression == Compression::Zstd {
+            return Err(crate::ConfigError::MissingFeature {
+                option: "compression",
+                feature: "zstd",
+            }
+            .into());
+        }
+
+        #[cfg(not(feature = "websocket"))]
+        if config.websocket_addr.is_some() {
+            return Err(crate::ConfigError::MissingFeature {
+                option: "websocket_addr",
+                feature: "websocket",
+            }
+            .into());
+        }
+
+        let header = AgentInfo::current()
+            .with_environment(&config.env_allowlist)
+            .with_resource(&config.resource);
+        let agent_info = header.to_json(config.timestamp_format).ok();
+
+        #[cfg(feature = "websocket")]
+        let websocket = match &config.websocket_addr {
+            Some(addr) => Some(
+                crate::websocket::WebSocketSink::bind(addr, header, config.timestamp_format)
+                    .map_err(|source| FlowTraceError::Exporter { exporter: "websocket", source })?,
+            ),
+            None => None,
+        };
+
+        #[cfg(feature = "grpc")]
+        let grpc = match &config.grpc_endpoint {
+            Some(endpoint) => Some(
+                crate::grpc::GrpcExporter::connect(endpoint, agent_info.clone().unwrap_or_default(), &config)
+                    .map_err(|source| FlowTraceError::Exporter { exporter: "grpc", source })?,
+            ),
+            None => None,
+        };
+        #[cfg(not(feature = "grpc"))]
+        if config.grpc_endpoint.is_some() {
+            return Err(crate::ConfigError::MissingFeature {
+                option: "grpc_endpoint",
+                feature: "grpc",
+            }
+            .into());
+        }
+
+        let quota = match config.max_total_disk_bytes {
+            Some(max_bytes) if file.is_some() => Some(DiskQuota::new(&config.log_file, max_bytes, config.quota_action)?),
+            _ => None,
+        };
+
+        let control = config.control_file.as_deref().map(ControlFile::open).transpose()?;
+
+        let mut logger = Self {
+            sampler: Sampler::from_config(&config),
+            control,
+            recording: (config.mode == Mode::OnDemand).then(Recording::default),
+            capture_budget: config.max_capture_bytes_per_sec.map(CaptureBudget::new),
+            error_rates: config
+                .error_rate_interval_secs
+                .map(|secs| ErrorRates::new(std::time::Duration::from_secs(secs))),
+            coalescer: config
+                .coalesce_window_secs
+                .map(|secs| Coalescer::new(std::time::Duration::from_secs(secs))),
+            aggregator: match (config.aggregate_interval_secs, config.aggregate_only) {
+                (Some(secs), _) => Some(Aggregator::new(std::time::Duration::from_secs(secs))),
+                (None, true) => Some(Aggregator::new(aggregate::DEFAULT_INTERVAL)),
+                (None, false) => None,
+            },
+            aggregate_only: config.aggregate_only,
+            overhead: config
+                .overhead_interval_secs
+                .map(|secs| OverheadMeter::new(std::time::Duration::from_secs(secs))),
+            dry_run_events: 0,
+            fork_generation: crate::fork::generation(),
+            forked: None,
+            rules: SinkRules::new(&config),
+            config,
+            file,
+            agent_info: agent_info.clone(),
+            quota,
+            enforcing_quota: false,
+            #[cfg(feature = "zstd")]
+            batch: (Vec::new(), None),
+            #[cfg(feature = "websocket")]
+            websocket,
+            #[cfg(feature = "grpc")]
+            grpc,
+        };
+        crate::fork::install();
+        if !logger.config.mode.writes() {
+            return Ok(logger);
+        }
+        logger.write_headers();
+        if logger.config.agent_info {
+            if let Some(json) = &agent_info {
+                logger.write_line(json);
+            }
+        }
+        Ok(logger)
     }
 
     /// Log a trace event
-    pub fn log(&mut self, event: TraceEvent) {
-        if let Ok(json) = serde_json::to_string(&event) {
-            let line = format!("{}\n", json);
+    pub fn log(&mut self, mut event: TraceEvent) {
+        // Outside recording windows, dropped before anything else looks at the event
+        if let Some(recording) = &mut self.recording {
+            if !recording.admit(&event, std::time::Instant::now()) {
+                return;
+            }
+        }
+        let action = self.control.as_mut().and_then(|control| {
+            control.refresh(std::time::Instant::now());
+            control.action(&event, std::time::SystemTime::now())
+        });
+        if action.is_some_and(|action| action.off) {
+            return;
+        }
+        // A `capture` override keeps every call of the function, whole
+        let capture = action.is_some_and(|action| action.capture);
+        if !capture && !self.config.allows_module(&event.module) {
+            return;
+        }
+        if self.fork_generation != crate::fork::generation() {
+            self.after_fork();
+        }
+        match self.config.mode {
+            Mode::On | Mode::OnDemand => {}
+            Mode::Dry => {
+                self.dry_run_events += 1;
+                return;
+            }
+            Mode::Off => return,
+        }
+        // Counted before sampling, so the rates cover every call
+        if let Some(error_rates) = &mut self.error_rates {
+            error_rates.record(&event);
+        }
+        if let Some(aggregator) = &mut self.aggregator {
+            aggregator.record(&event);
+        }
+        self.write_due();
+        if self.aggregate_only {
+            return;
+        }
+        if self.quota.as_ref().is_some_and(DiskQuota::errors_only) && !matches!(event.event_type, EventType::Exception) {
+            return;
+        }
+        let sample_override = if capture { Some(1.0) } else { action.and_then(|action| action.sample) };
+        if let Some(rate) = sample_override {
+            if self.control.as_mut().and_then(|control| control.sample(&event, rate)).is_none() {
+                return;
+            }
+            if rate < 1.0 {
+                event.sampled = true;
+                event.sample_rate = Some(rate);
+            }
+        } else if let Some(sampler) = &mut self.sampler {
+            let Some(rate) = sampler.sample(&event) else {
+                return;
+            };
+            event.sampled = true;
+            event.sample_rate = (rate < 1.0).then_some(rate);
+        } else {
+            match event.context.as_ref().and_then(|context| context.sampled()) {
+                // Dropped upstream, e.g. by a `traceparent` flagged as not sampled
+                Some(false) => return,
+                Some(true) => event.sampled = true,
+                None => {}
+            }
+        }
+
+        if self.coalescer.as_mut().is_some_and(|coalescer| !coalescer.admit(&event)) {
+            return;
+        }
+        if let Some((pid, parent_pid)) = self.forked {
+            event.pid = Some(pid);
+            event.parent_pid = parent_pid;
+        }
+        if let Some(max_depth) = event.max_depth.or(self.config.max_arg_depth).filter(|_| !capture) {
+            if let Some(args) = &event.args {
+                event.args = Some(depth::summarize_args(args, max_depth).into_owned());
+            }
+            if let Some(result) = &event.result {
+                event.result = Some(depth::summarize(result, max_depth).into_owned());
+            }
+        }
+        // Charged after sampling and depth cuts, for the bytes actually written
+        if let Some(budget) = self.capture_budget.as_mut().filter(|_| !capture) {
+            budget.apply(&mut event, std::time::Instant::now());
+        }
+
+        // Levels are checked after sampling, which has to see every event of a call
+        let level = event.effective_level();
+        if self.config.sink_allows(None, level) {
+            for hook in &self.config.hooks {
+                hook.call(&event);
+            }
+        }
+        let rules = &self.rules;
+        let to_file = self.file.is_some() && rules.file.allows(&event, level);
+        let to_stdout = self.config.stdout && rules.stdout.allows(&event, level);
+        #[cfg(feature = "websocket")]
+        let websocket = self.websocket.as_ref().filter(|_| rules.websocket.allows(&event, level));
+        #[cfg(not(feature = "websocket"))]
+        let websocket: Option<()> = None;
+        #[cfg(feature = "grpc")]
+        let grpc = self.grpc.as_ref().filter(|_| rules.grpc.allows(&event, level));
+        #[cfg(not(feature = "grpc"))]
+        let grpc: Option<()> = None;
+        if !(to_file || to_stdout || websocket.is_some() || grpc.is_some()) {
+            return;
+        }
+
+        let mut stopwatch = Stopwatch::start(self.overhead.is_some());
+        let Ok(json) = event.to_json(self.config.timestamp_format) else {
+            return;
+        };
+        let serialize = stopwatch.lap();
+        let formats = self.config.formats;
+        let file_line = to_file.then(|| format!("{}\n", formats.file.render(&json)));
+        let stdout_line = to_stdout.then(|| formats.stdout.render(&json));
+        let format = stopwatch.lap();
+
+        #[cfg(feature = "websocket")]
+        if let Some(websocket) = websocket {
+            websocket.publish(&event, &json);
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = grpc {
+            grpc.publish(&event, &json);
+        }
+
+        if let Some(line) = file_line {
+            self.write_to_file(&line);
+        }
+        if let Some(line) = stdout_line {
+            println!("{}", line);
+        }
+        if let Some(overhead) = &mut self.overhead {
+            overhead.record(serialize, format, stopwatch.lap());
+        }
+    }
 
-            // Write to file
-            if let Some(file) = &mut self.file {
-                let _ = file.write_all(line.as_bytes());
-                let _ = file.flush();
+    /// End the error-rate interval, writing and exporting its records
+    fn write_error_rates(&mut self) {
+        let Some(records) = self.error_rates.as_mut().map(ErrorRates::take) else {
+            return;
+        };
+        for record in records {
+            if let Ok(json) = record.to_json(self.config.timestamp_format) {
+                self.write_record(&json);
+            }
+        }
+    }
+
+    /// Write the periodic records whose interval has ended
+    fn write_due(&mut self) {
+        if self.error_rates.as_ref().is_some_and(ErrorRates::is_due) {
+            self.write_error_rates();
+        }
+        if self.coalescer.as_ref().is_some_and(Coalescer::is_due) {
+            self.write_repeated();
+        }
+        if self.overhead.as_ref().is_some_and(OverheadMeter::is_due) {
+            self.write_overhead();
+        }
+        if self.aggregator.as_ref().is_some_and(Aggregator::is_due) {
+            self.write_aggregates();
+        }
+    }
+
+    /// End the coalescing window, writing and exporting its `REPEATED` records
+    fn write_repeated(&mut self) {
+        let Some(records) = self.coalescer.as_mut().map(Coalescer::take) else {
+            return;
+        };
+        for record in records {
+            if let Ok(json) = record.to_json(self.config.timestamp_format) {
+                self.write_record(&json);
+            }
+        }
+    }
+
+    /// End the aggregation interval, writing and exporting its `AGGREGATE` records
+    fn write_aggregates(&mut self) {
+        let Some(records) = self.aggregator.as_mut().map(Aggregator::take) else {
+            return;
+        };
+        for record in records {
+            if let Ok(json) = record.to_json(self.config.timestamp_format) {
+                self.write_record(&json);
+            }
+        }
+    }
+
+    /// End the measurement interval, writing and exporting its `OVERHEAD` record
+    fn write_overhead(&mut self) {
+        let Some(record) = self.overhead.as_mut().and_then(OverheadMeter::take) else {
+            return;
+        };
+        if let Ok(json) = record.to_json(self.config.timestamp_format) {
+            self.write_record(&json);
+        }
+    }
+
+    /// Write a summary record and send it to the gRPC collector
+    fn write_record(&mut self, json: &str) {
+        self.write_line(json);
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = &self.grpc {
+            grpc.publish_record(json);
+        }
+    }
+
+    /// Leave raw events out, keeping only `AGGREGATE` records, or write them again
+    pub fn set_aggregate_only(&mut self, aggregate_only: bool) {
+        if aggregate_only && self.aggregator.is_none() {
+            let interval = self
+                .config
+                .aggregate_interval_secs
+                .map_or(aggregate::DEFAULT_INTERVAL, std::time::Duration::from_secs);
+            self.aggregator = Some(Aggregator::new(interval));
+        }
+        self.aggregate_only = aggregate_only;
+    }
+
+    /// Record events for `window` from now, in `Mode::OnDemand`; other modes are unaffected
+    pub fn record_for(&mut self, window: std::time::Duration) {
+        if let Some(recording) = &mut self.recording {
+            recording.record_for(window, std::time::Instant::now());
+        }
+    }
+
+    fn write_line(&mut self, json: &str) {
+        self.write_file_line(json);
+        if self.config.stdout {
+            self.write_stdout_line(json);
+        }
+    }
+
+    /// Start stdout, and the log file if it is empty, with the header line of their formats
+    fn write_headers(&mut self) {
+        let file_is_empty = self.file.as_ref().and_then(|file| file.metadata().ok()).is_some_and(|meta| meta.len() == 0);
+        if let Some(header) = self.config.formats.file.header().filter(|_| file_is_empty) {
+            self.write_to_file(&format!("{}\n", header));
+        }
+        if let Some(header) = self.config.formats.stdout.header().filter(|_| self.config.stdout) {
+            println!("{}", header);
+        }
+    }
+
+    /// Append a record to the log file in its configured format
+    fn write_file_line(&mut self, json: &str) {
+        let line = format!("{}\n", self.config.formats.file.render(json));
+        self.write_to_file(&line);
+    }
+
+    fn write_stdout_line(&self, json: &str) {
+        println!("{}", self.config.formats.stdout.render(json));
+    }
+
+    fn write_to_file(&mut self, line: &str) {
+        match self.config.compression {
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let (batch, started) = &mut self.batch;
+                batch.extend_from_slice(line.as_bytes());
+                let started = *started.get_or_insert_with(std::time::Instant::now);
+                if batch.len() >= ZSTD_BATCH_BYTES || started.elapsed() >= ZSTD_BATCH_AGE {
+                    self.write_batch();
+                }
             }
+            _ => self.write_file(line.as_bytes()),
+        }
+    }
+
+    /// Write pending lines as one complete zstd frame
+    #[cfg(feature = "zstd")]
+    fn write_batch(&mut self) {
+        let (batch, _) = std::mem::take(&mut self.batch);
+        let mut frame = Vec::new();
+        if !batch.is_empty() && zstd::stream::copy_encode(batch.as_slice(), &mut frame, ZSTD_LEVEL).is_ok() {
+            self.write_file(&frame);
+        }
+    }
 
-            // Write to stdout
-            if self.config.stdout {
-                print!("{}", line);
+    /// Append to the log file, within the disk quota (except for the quota's own records)
+    fn write_file(&mut self, bytes: &[u8]) {
+        if !self.enforcing_quota && self.quota.as_ref().is_some_and(|quota| !quota.allows(bytes.len() as u64)) {
+            return;
+        }
+        if let Some(file) = &mut self.file {
+            // Other processes may append to the same file; the lock keeps each write whole
+            let locked = file.lock().is_ok();
+            let _ = file.write_all(bytes);
+            let _ = file.flush();
+            if locked {
+                let _ = file.unlock();
             }
         }
+        if let Some(quota) = &mut self.quota {
+            quota.record_written(bytes.len() as u64);
+            self.enforce_quota();
+        }
+    }
+
+    /// Rotate the log file or switch to errors-only as the quota requires
+    fn enforce_quota(&mut self) {
+        if self.enforcing_quota {
+            return;
+        }
+        let Some(Ok((rotated, event))) = self.quota.as_mut().map(DiskQuota::enforce) else {
+            return;
+        };
+
+        self.enforcing_quota = true;
+        if rotated {
+            self.file = OpenOptions::new().create(true).append(true).open(&self.config.log_file).ok();
+            if let Some(header) = self.config.formats.file.header() {
+                self.write_to_file(&format!("{}\n", header));
+            }
+            if let Some(json) = self.agent_info.clone().filter(|_| self.config.agent_info) {
+                self.write_file_line(&json);
+            }
+        }
+        if let Some(Ok(json)) = event.map(|event| event.to_json(self.config.timestamp_format)) {
+            self.write_file_line(&json);
+        }
+        self.enforcing_quota = false;
+    }
+
+    /// Start over in a forked child, leaving what the parent had pending to the parent
+    ///
+    /// The periodic records' counts and the zstd batch are cleared, as the
+    /// parent writes them; the log file is reopened, so its lock keeps the
+    /// child's writes apart from the parent's; the gRPC exporter, whose
+    /// threads were not forked, reconnects; and the WebSocket sink, whose port
+    /// the parent keeps, is left out. A new AGENT_INFO names the parent.
+    pub(crate) fn after_fork(&mut self) {
+        self.fork_generation = crate::fork::generation();
+        let (pid, parent_pid) = crate::fork::process_ids();
+        self.forked = Some((pid, parent_pid));
+
+        if let Some(error_rates) = &mut self.error_rates {
+            error_rates.take();
+        }
+        if let Some(coalescer) = &mut self.coalescer {
+            coalescer.take();
+        }
+        if let Some(aggregator) = &mut self.aggregator {
+            aggregator.take();
+        }
+        if let Some(overhead) = &mut self.overhead {
+            overhead.take();
+        }
+        self.dry_run_events = 0;
+        #[cfg(feature = "zstd")]
+        {
+            self.batch = (Vec::new(), None);
+        }
+
+        if self.file.is_some() {
+            self.file = OpenOptions::new().create(true).append(true).open(&self.config.log_file).ok();
+        }
+        if let Some(max_bytes) = self.config.max_total_disk_bytes.filter(|_| self.file.is_some()) {
+            self.quota = DiskQuota::new(&self.config.log_file, max_bytes, self.config.quota_action).ok();
+        }
+
+        let mut header = AgentInfo::current()
+            .with_environment(&self.config.env_allowlist)
+            .with_resource(&self.config.resource);
+        header.parent_pid = parent_pid;
+        self.agent_info = header.to_json(self.config.timestamp_format).ok();
+
+        // Dropping them would act on the parent's connections
+        #[cfg(feature = "websocket")]
+        std::mem::forget(self.websocket.take());
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = self.grpc.take() {
+            std::mem::forget(grpc);
+            let endpoint = self.config.grpc_endpoint.clone().unwrap_or_default();
+            let agent_info = self.agent_info.clone().unwrap_or_default();
+            self.grpc = crate::grpc::GrpcExporter::connect(&endpoint, agent_info, &self.config).ok();
+        }
+
+        if self.config.mode.writes() && self.config.agent_info {
+            if let Some(json) = self.agent_info.clone() {
+                self.write_line(&json);
+            }
+        }
+    }
+
+    /// Events counted and dropped so far in `Mode::Dry`
+    pub fn dry_run_events(&self) -> u64 {
+        self.dry_run_events
+    }
+
+    /// Flush buffered output to the log file and stdout
+    pub fn flush(&mut self) {
+        #[cfg(feature = "zstd")]
+        self.write_batch();
+        if let Some(file) = &mut self.file {
+            let _ = file.flush();
+        }
+        if self.config.stdout {
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    /// Write due periodic records and flush, as on each `Config::flush_interval_ms` tick
+    pub fn tick(&mut self) {
+        if !self.config.mode.writes() || self.fork_generation != crate::fork::generation() {
+            return;
+        }
+        self.write_due();
+        self.flush();
+    }
+
+    /// Circuit breaker state and fallback accounting of the gRPC export, if any
+    #[cfg(feature = "grpc")]
+    pub fn grpc_stats(&self) -> Option<crate::grpc::ExportStats> {
+        self.grpc.as_ref().map(crate::grpc::GrpcExporter::stats)
     }
 }
 
 impl Drop for Logger {
     fn drop(&mut self) {
+        if self.fork_generation != crate::fork::generation() {
+            self.after_fork();
+        }
+        if self.config.mode == Mode::Dry {
+            eprintln!("flowtrace: dry run: {} events counted, none written", self.dry_run_events);
+        }
+        self.write_error_rates();
+        self.write_repeated();
+        self.write_aggregates();
+        self.write_overhead();
+        #[cfg(feature = "zstd")]
+        self.write_batch();
         if let Some(file) = &mut self.file {
             let _ = file.flush();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The log file at `path` and its rotations, removing any left from earlier runs
+    fn clean(path: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let files: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|file| file.file_name().unwrap().to_string_lossy().starts_with(&name))
+            .collect();
+        files.iter().map(std::fs::remove_file).for_each(drop);
+        files
+    }
+
+    #[test]
+    fn test_concurrent_writers_keep_lines_whole() {
+        let path = std::env::temp_dir().join("flowtrace_logger_shared.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let args = "x".repeat(256 * 1024);
+
+        // Separate loggers behave like separate processes appending to one file
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let mut logger = Logger::new(Config {
+                        log_file: path.display().to_string(),
+                        ..Default::default()
+                    })
+                    .unwrap();
+                    for _ in 0..20 {
+                        logger.log(TraceEvent::enter("app", "work", Some(args.clone())));
+                    }
+                });
+            }
+        });
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.lines().count(), 4 * 21);
+        assert!(log.lines().all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()));
+    }
+
+    #[test]
+    fn test_error_rates_survive_sampling() {
+        let path = std::env::temp_dir().join("flowtrace_logger_error_rate.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            error_rate_interval_secs: Some(60),
+            // Everything past the first call is sampled out
+            max_events_per_second: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..10 {
+            logger.log(TraceEvent::enter("app", "charge", None));
+            logger.log(TraceEvent::exception("app", "charge", "declined", Some(1)));
+        }
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rate = crate::ErrorRate::from_json_line(log.lines().last().unwrap()).unwrap();
+        assert_eq!((rate.function.as_str(), rate.calls, rate.errors), ("charge", 10, 10));
+        assert_eq!(log.lines().count(), 1 + 2 + 1);
+    }
+
+    #[test]
+    fn test_sink_min_level() {
+        let path = std::env::temp_dir().join("flowtrace_logger_levels.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            min_level: Some(crate::Level::Error),
+            sink_levels: crate::SinkLevels {
+                file: Some(crate::Level::Warn),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        logger.log(TraceEvent::enter("app", "work", None));
+        logger.log(TraceEvent::exit("app", "work", None, Some(3_000)).with_budget(1_000, true));
+        logger.log(TraceEvent::exception("app", "charge", "declined", None));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let methods: Vec<String> = log.lines().skip(1).map(|line| TraceEvent::from_json_line(line).unwrap().function).collect();
+        assert_eq!(methods, vec!["work", "charge"]);
+    }
+
+    #[test]
+    fn test_hooks_see_filtered_events() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = std::sync::Arc::clone(&seen);
+        let mut logger = Logger::new(
+            Config::builder()
+                .log_file("")
+                .exclude_module("app::metrics")
+                .on_event(move |event| record.lock().unwrap().push(event.function.clone()))
+                .on_event(|_| panic!("broken hook"))
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+
+        logger.log(TraceEvent::enter("app", "work", None));
+        logger.log(TraceEvent::enter("app::metrics", "count", None));
+        logger.log(TraceEvent::exit("app", "work", None, Some(1)));
+        assert_eq!(*seen.lock().unwrap(), vec!["work", "work"]);
+    }
+
+    #[test]
+    fn test_file_format() {
+        let path = std::env::temp_dir().join("flowtrace_logger_logfmt.log");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            formats: crate::SinkFormats {
+                file: crate::OutputFormat::Logfmt,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        logger.log(TraceEvent::enter("app", "work", None));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" event=AGENT_INFO "), "{}", lines[0]);
+        assert!(lines[1].contains(" event=ENTER "), "{}", lines[1]);
+    }
+
+    #[test]
+    fn test_csv_header_once() {
+        let path = std::env::temp_dir().join("flowtrace_logger.csv");
+        let _ = std::fs::remove_file(&path);
+        let config = || Config {
+            log_file: path.display().to_string(),
+            formats: crate::SinkFormats {
+                file: crate::OutputFormat::Csv,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let mut logger = Logger::new(config()).unwrap();
+            logger.log(TraceEvent::exit("app", "work", None, Some(5)));
+        }
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * 2);
+        assert_eq!(lines[0], crate::format::CSV_COLUMNS.join(","));
+        assert!(lines[2].contains(",EXIT,app,work,5,"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_coalesces_exception_storms() {
+        let path = std::env::temp_dir().join("flowtrace_logger_repeated.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            coalesce_window_secs: Some(60),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..1000 {
+            logger.log(TraceEvent::exception("app", "charge", "declined", Some(1)));
+        }
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.lines().count(), 1 + 1 + 1);
+        let repeated = crate::Repeated::from_json_line(log.lines().last().unwrap()).unwrap();
+        assert_eq!((repeated.function.as_str(), repeated.count), ("charge", 999));
+    }
+
+    #[test]
+    fn test_aggregate_only() {
+        let path = std::env::temp_dir().join("flowtrace_logger_aggregate.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            aggregate_only: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        for duration in [10, 30] {
+            logger.log(TraceEvent::enter("app", "work", None));
+            logger.log(TraceEvent::exit("app", "work", None, Some(duration)));
+        }
+        logger.set_aggregate_only(false);
+        logger.log(TraceEvent::enter("app", "traced", None));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1 + 1 + 1);
+        assert_eq!(TraceEvent::from_json_line(lines[1]).unwrap().function, "traced");
+        let aggregate = crate::Aggregate::from_json_line(lines[2]).unwrap();
+        assert_eq!((aggregate.calls, aggregate.total_micros, aggregate.max_micros), (2, 40, 30));
+    }
+
+    #[test]
+    fn test_on_demand_records_windows() {
+        let path = std::env::temp_dir().join("flowtrace_logger_on_demand.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            mode: Mode::OnDemand,
+            log_file: path.display().to_string(),
+            agent_info: false,
+            ..Default::default()
+        })
+        .unwrap();
+
+        logger.log(TraceEvent::enter("app", "before", None));
+        logger.log(TraceEvent::exit("app", "before", None, Some(1)));
+        logger.record_for(std::time::Duration::from_secs(60));
+        logger.log(TraceEvent::enter("app", "during", None));
+        logger.log(TraceEvent::exit("app", "during", None, Some(1)));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let functions: Vec<String> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap().function).collect();
+        assert_eq!(functions, ["during", "during"]);
+        assert_eq!("on_demand".parse(), Ok(Mode::OnDemand));
+    }
+
+    #[test]
+    fn test_overhead_record() {
+        let path = std::env::temp_dir().join("flowtrace_logger_overhead.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            overhead_interval_secs: Some(60),
+            ..Default::default()
+        })
+        .unwrap();
+        logger.log(TraceEvent::enter("app", "work", None));
+        logger.log(TraceEvent::exit("app", "work", None, Some(1)));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let overhead = crate::Overhead::from_json_line(log.lines().last().unwrap()).unwrap();
+        assert_eq!(overhead.events, 2);
+        assert!(overhead.write_micros_per_1k > 0.0);
+    }
+
+    #[test]
+    fn test_after_fork() {
+        let path = std::env::temp_dir().join("flowtrace_logger_fork.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            aggregate_interval_secs: Some(60),
+            ..Default::default()
+        })
+        .unwrap();
+        logger.log(TraceEvent::exit("app", "parent", None, Some(1)));
+        logger.after_fork();
+        logger.log(TraceEvent::exit("app", "child", None, Some(1)));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", log);
+        assert_eq!(TraceEvent::from_json_line(lines[1]).unwrap().pid, None);
+
+        // The child announces itself, stamps its events and only counts its own calls
+        let header = AgentInfo::from_json_line(lines[2]).unwrap();
+        assert!(header.parent_pid.is_some());
+        let child = TraceEvent::from_json_line(lines[3]).unwrap();
+        assert_eq!((child.pid, child.parent_pid), (Some(std::process::id()), header.parent_pid));
+        let aggregate = crate::Aggregate::from_json_line(lines[4]).unwrap();
+        assert_eq!((aggregate.function.as_str(), aggregate.calls), ("child", 1));
+    }
+
+    #[test]
+    fn test_max_arg_depth() {
+        let path = std::env::temp_dir().join("flowtrace_logger_depth.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            agent_info: false,
+            max_arg_depth: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let args = r#"{"order": Order { id: 7, customer: Customer { id: 3 } }}"#.to_string();
+        logger.log(TraceEvent::enter("app", "ship", Some(args.clone())));
+        logger.log(TraceEvent::exit("app", "ship", Some("Ok([Parcel { id: 1 }])".to_string()), None));
+        logger.log(TraceEvent::enter("app", "ship", Some(args)).with_max_depth(0));
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<TraceEvent> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+        assert_eq!(events[0].args.as_deref(), Some(r#"{"order": Order { id: 7, customer: Customer {...} }}"#));
+        assert_eq!(events[1].result.as_deref(), Some("Ok([...])"));
+        assert_eq!(events[2].args.as_deref(), Some(r#"{"order": Order {...}}"#));
+    }
+
+    #[test]
+    fn test_sampled_events_carry_rate() {
+        let path = std::env::temp_dir().join("flowtrace_logger_sample_rate.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            sample_rate: Some(0.5),
+            ..Default::default()
+        })
+        .unwrap();
+        for _ in 0..100 {
+            logger.log(TraceEvent::enter("app", "work", None));
+            logger.log(TraceEvent::exit("app", "work", None, Some(1)));
+        }
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<TraceEvent> = log.lines().skip(1).map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+        assert!(!events.is_empty() && events.len() < 200);
+        assert!(events.iter().all(|event| event.sampled && event.sample_rate == Some(0.5)));
+    }
+
+    #[test]
+    fn test_quota_rotates_and_deletes_oldest() {
+        let path = std::env::temp_dir().join("flowtrace_quota_rotate.jsonl");
+        clean(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            max_total_disk_bytes: Some(4000),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..200 {
+            logger.log(TraceEvent::enter("app", "work", None));
+            let used: u64 = std::fs::read_dir(path.parent().unwrap())
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("flowtrace_quota_rotate.jsonl"))
+                .map(|entry| entry.metadata().unwrap().len())
+                .sum();
+            assert!(used <= 4000, "{} bytes used", used);
+        }
+        drop(logger);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let mut lines = log.lines();
+        assert!(AgentInfo::from_json_line(lines.next().unwrap()).is_ok());
+        let quota = crate::QuotaEvent::from_json_line(lines.next().unwrap()).unwrap();
+        assert_eq!(quota.max_total_disk_bytes, 4000);
+        assert!(!quota.deleted_files.is_empty());
+        assert!(clean(&path).len() <= 4);
+    }
+
+    #[test]
+    fn test_quota_errors_only() {
+        let path = std::env::temp_dir().join("flowtrace_quota_errors.jsonl");
+        clean(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            max_total_disk_bytes: Some(20_000),
+            quota_action: crate::QuotaAction::ErrorsOnly,
+            ..Default::default()
+        })
+        .unwrap();
+
+        while std::fs::metadata(&path).unwrap().len() < 20_000 {
+            logger.log(TraceEvent::enter("app", "work", None));
+        }
+        logger.log(TraceEvent::enter("app", "dropped", None));
+        logger.log(TraceEvent::exception("app", "failed", "boom", None));
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.lines().any(|line| crate::QuotaEvent::from_json_line(line).is_ok()));
+        assert!(log.contains(r#""method":"failed""#));
+        assert!(!log.contains(r#""method":"dropped""#));
+
+        // Past the headroom, not even exceptions are written
+        for _ in 0..100 {
+            logger.log(TraceEvent::exception("app", "failed", "boom", None));
+        }
+        assert!(std::fs::metadata(&path).unwrap().len() <= 22_000);
+        assert_eq!(clean(&path).len(), 1);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_frames_per_batch() {
+        let path = std::env::temp_dir().join("flowtrace_logger.jsonl.zst");
+        let _ = std::fs::remove_file(&path);
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            compression: Compression::Zstd,
+            ..Default::default()
+        })
+        .unwrap();
+
+        logger.log(TraceEvent::enter("app", "first", None));
+        logger.flush();
+        // A flushed batch is a complete frame, readable while the logger is still open
+        let partial = zstd::decode_all(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(String::from_utf8(partial).unwrap().lines().count(), 2);
+
+        logger.log(TraceEvent::exit("app", "first", None, Some(1)));
+        drop(logger);
+        let all = String::from_utf8(zstd::decode_all(std::fs::File::open(&path).unwrap()).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(all.lines().count(), 3);
+        assert!(TraceEvent::from_json_line(all.lines().last().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_control_file_overrides() {
+        let path = std::env::temp_dir().join("flowtrace_logger_control.jsonl");
+        let control = std::env::temp_dir().join("flowtrace_logger_control.txt");
+        clean(&path);
+        std::fs::write(&control, "billing::charge capture\nbilling::health off\norders::list sample=0\n").unwrap();
+        let mut logger = Logger::new(Config {
+            log_file: path.display().to_string(),
+            agent_info: false,
+            exclude_modules: vec!["billing".to_string()],
+            sample_rate: Some(0.000_001),
+            max_arg_depth: Some(0),
+            control_file: Some(control.display().to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let args = r#"{"card": Card { last4: "4242" }}"#;
+        for (module, function) in [("billing", "charge"), ("billing", "health"), ("orders", "list"), ("orders", "get")] {
+            logger.log(TraceEvent::enter(module, function, Some(args.to_string())));
+            logger.log(TraceEvent::exit(module, function, None, Some(10)));
+        }
+        logger.flush();
+        std::fs::remove_file(&control).unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        clean(&path);
+        let events: Vec<TraceEvent> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+        // Only the captured function: `health` is off, `list` sampled at 0 and `get` by the 1e-6 rate
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].function, "charge");
+        assert_eq!(events[0].args.as_deref(), Some(args));
+        assert!(!events[0].sampled);
+    }
+}
@@ -1,17 +1,105 @@
 //! Actix-Web middleware for FlowTrace
 
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, FromRequest, HttpMessage, HttpRequest,
 };
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
 use futures_util::future::LocalBoxFuture;
 use std::future::{ready, Ready};
-use std::time::Instant;
+use std::rc::Rc;
 
-use crate::{TraceEvent, log_event};
+use super::TRACE_ID_HEADER;
+use crate::{EventCapture, TraceContext, TraceEvent, TRACEPARENT, log_event, monotonic_micros};
 
 /// Actix-Web middleware for automatic request tracing
-pub struct FlowTraceMiddleware;
+///
+/// Requests continue the trace of their `traceparent` header. Behind a
+/// gateway that sends its own request IDs instead, name their headers with
+/// [`id_headers`](Self::id_headers) to adopt them as trace IDs:
+///
+/// ```rust,no_run
+/// use actix_web::App;
+/// use flowtrace_agent::middleware::actix::FlowTraceMiddleware;
+///
+/// let app = App::new().wrap(FlowTraceMiddleware.id_headers(["X-Request-Id", "X-Correlation-Id"]));
+/// ```
+///
+/// With [`trace_id_header`](Self::trace_id_header), responses tell the trace
+/// ID in an `X-FlowTrace-Id` header. With
+/// [`capture_events`](Self::capture_events), handlers can take the events
+/// logged for their request as an [`EventCapture`] argument.
+#[derive(Debug, Clone, Default)]
+pub struct FlowTraceMiddleware {
+    /// Headers tried in order when a request has no `traceparent`
+    id_headers: Vec<String>,
+    /// Whether responses carry [`TRACE_ID_HEADER`]
+    trace_id_header: bool,
+    /// Whether each request's events are kept for its handler
+    capture_events: bool,
+}
+
+/// The middleware without ID headers, so `.wrap(FlowTraceMiddleware)` reads
+/// as it did when it was a unit struct
+#[allow(non_upper_case_globals)]
+pub const FlowTraceMiddleware: FlowTraceMiddleware = FlowTraceMiddleware {
+    id_headers: Vec::new(),
+    trace_id_header: false,
+    capture_events: false,
+};
+
+impl FlowTraceMiddleware {
+    /// Adopt the first of these headers a request has as its trace ID, when it has no `traceparent`
+    ///
+    /// See [`TraceContext::from_correlation_id`] for how IDs become trace IDs.
+    pub fn id_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.id_headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add the request's trace ID to its response as [`TRACE_ID_HEADER`]
+    pub fn trace_id_header(mut self) -> Self {
+        self.trace_id_header = true;
+        self
+    }
+
+    /// Keep each request's events in memory for its handler to extract as an [`EventCapture`]
+    ///
+    /// ```rust,no_run
+    /// use actix_web::{web, HttpResponse};
+    /// use flowtrace_agent::EventCapture;
+    ///
+    /// async fn checkout(query: web::Query<std::collections::HashMap<String, String>>, trace: EventCapture) -> HttpResponse {
+    ///     // ... handle the request ...
+    ///     if query.get("debug").is_some_and(|debug| debug == "1") {
+    ///         return HttpResponse::Ok().json(trace.events());
+    ///     }
+    ///     HttpResponse::Ok().finish()
+    /// }
+    /// ```
+    ///
+    /// Gate the debug output on the caller being authorized: events carry
+    /// arguments and results.
+    pub fn capture_events(mut self) -> Self {
+        self.capture_events = true;
+        self
+    }
+}
+
+/// The events of the current request, when the middleware was set up with
+/// [`capture_events`](FlowTraceMiddleware::capture_events); empty otherwise
+impl FromRequest for EventCapture {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req.extensions().get::<EventCapture>().cloned().unwrap_or_default()))
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for FlowTraceMiddleware
 where
@@ -26,12 +114,29 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(FlowTraceMiddlewareService { service }))
+        ready(Ok(FlowTraceMiddlewareService {
+            service,
+            id_headers: self.id_headers.clone().into(),
+            trace_id_header: self.trace_id_header,
+            capture_events: self.capture_events,
+        }))
     }
 }
 
 pub struct FlowTraceMiddlewareService<S> {
     service: S,
+    id_headers: Rc<[String]>,
+    trace_id_header: bool,
+    capture_events: bool,
+}
+
+/// The trace a request continues: its `traceparent`, else the first of `id_headers` it has, else a new one
+fn request_context(headers: &HeaderMap, id_headers: &[String]) -> TraceContext {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    header(TRACEPARENT)
+        .and_then(TraceContext::from_traceparent)
+        .or_else(|| id_headers.iter().find_map(|name| header(name).and_then(TraceContext::from_correlation_id)))
+        .unwrap_or_default()
 }
 
 impl<S, B> Service<ServiceRequest> for FlowTraceMiddlewareService<S>
@@ -47,11 +152,20 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let start_time = Instant::now();
+        let start_time = monotonic_micros();
         let method = req.method().to_string();
         let path = req.path().to_string();
         let module = "actix_web";
 
+        // Continue the caller's trace, so handlers and their spawned work share its sampling decision
+        let mut context = request_context(req.headers(), &self.id_headers);
+        if self.capture_events {
+            let capture = EventCapture::new();
+            req.extensions_mut().insert(capture.clone());
+            context = context.with_capture(capture);
+        }
+        let _guard = context.attach();
+
         // Log ENTER event
         log_event(TraceEvent::enter(
             module,
@@ -65,10 +179,16 @@ where
         ));
 
         let fut = self.service.call(req);
+        let trace_id_header = self.trace_id_header.then(|| {
+            (HeaderName::from_bytes(TRACE_ID_HEADER.as_bytes()), HeaderValue::from_str(context.trace_id()))
+        });
 
-        Box::pin(async move {
-            let res = fut.await?;
-            let duration = start_time.elapsed().as_secs_f64() * 1000.0;
+        Box::pin(context.instrument(async move {
+            let mut res = fut.await?;
+            if let Some((Ok(name), Ok(trace_id))) = trace_id_header {
+                res.headers_mut().insert(name, trace_id);
+            }
+            let duration = monotonic_micros() - start_time;
 
             // Log EXIT event
             log_event(TraceEvent::exit(
@@ -77,13 +197,13 @@ where
                 Some(format!(
                     r#"{{"status":{},"duration_ms":{:.2}}}"#,
                     res.status().as_u16(),
-                    duration
+                    duration as f64 / 1000.0
                 )),
                 Some(duration),
             ));
 
             Ok(res)
-        })
+        }))
     }
 }
 
@@ -106,4 +226,89 @@ mod tests {
 
         assert!(resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn test_trace_id_header() {
+        let app = test::init_service(
+            App::new()
+                .wrap(FlowTraceMiddleware.trace_id_header())
+                .route("/test", web::get().to(|| async { HttpResponse::Ok().body("test") })),
+        )
+        .await;
+
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let req = test::TestRequest::get().uri("/test").insert_header((TRACEPARENT, traceparent)).to_request();
+        let resp = test::call_service(&app, req).await;
+        let trace_id = resp.headers().get(TRACE_ID_HEADER).and_then(|value| value.to_str().ok());
+        assert_eq!(trace_id, Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+
+        let app = test::init_service(
+            App::new()
+                .wrap(FlowTraceMiddleware)
+                .route("/test", web::get().to(|| async { HttpResponse::Ok().body("test") })),
+        )
+        .await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/test").to_request()).await;
+        assert!(resp.headers().get(TRACE_ID_HEADER).is_none());
+    }
+
+    // `test` here is actix's module, so the std attribute is named in full
+    #[::core::prelude::v1::test]
+    fn test_capture_events() {
+        async fn handle(trace: EventCapture) -> HttpResponse {
+            crate::log_event(TraceEvent::enter("app", "lookup", None));
+            crate::log_event(TraceEvent::exit("app", "lookup", None, Some(5)));
+            let functions: Vec<String> = trace.events().into_iter().map(|event| event.function).collect();
+            HttpResponse::Ok().body(functions.join(","))
+        }
+
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: String::new(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+        let bodies = crate::with_tracer(&tracer, || {
+            actix_web::rt::System::new().block_on(async {
+                let mut bodies = Vec::new();
+                for middleware in [FlowTraceMiddleware.capture_events(), FlowTraceMiddleware] {
+                    let app = test::init_service(App::new().wrap(middleware).route("/", web::get().to(handle))).await;
+                    let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+                    bodies.push(test::read_body(resp).await);
+                }
+                bodies
+            })
+        });
+        tracer.stop();
+        assert_eq!(bodies, ["GET /,lookup,lookup", ""]);
+    }
+
+    #[actix_web::test]
+    async fn test_request_context_from_id_headers() {
+        let id_headers = ["X-Request-Id".to_string(), "X-Correlation-Id".to_string()];
+        let request = |headers: &[(&str, &str)]| {
+            let mut req = test::TestRequest::get();
+            for &(name, value) in headers {
+                req = req.insert_header((name, value));
+            }
+            req.to_http_request()
+        };
+
+        let req = request(&[("x-correlation-id", "0af7651916cd43dd8448eb211c80319c")]);
+        assert_eq!(request_context(req.headers(), &id_headers).trace_id(), "0af7651916cd43dd8448eb211c80319c");
+
+        // `traceparent` wins, then the headers in the order given
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let req = request(&[("X-Request-Id", "req-1"), (TRACEPARENT, traceparent)]);
+        assert_eq!(request_context(req.headers(), &id_headers).trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        let req = request(&[("X-Correlation-Id", "corr-1"), ("X-Request-Id", "req-1")]);
+        let expected = TraceContext::from_correlation_id("req-1").unwrap();
+        assert_eq!(request_context(req.headers(), &id_headers).trace_id(), expected.trace_id());
+
+        // Not configured, so not adopted
+        let req = request(&[("X-Request-Id", "req-1")]);
+        assert_ne!(request_context(req.headers(), &[]).trace_id(), expected.trace_id());
+    }
 }
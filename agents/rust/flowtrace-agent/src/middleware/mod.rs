@@ -1,5 +1,9 @@
 //! Framework middleware for FlowTrace
 
+/// Response header carrying the request's trace ID, for users and support to
+/// quote when reporting a problem
+pub const TRACE_ID_HEADER: &str = "X-FlowTrace-Id";
+
 #[cfg(feature = "actix")]
 pub mod actix;
 
@@ -0,0 +1,277 @@
+//! Network phases of outbound requests
+//!
+//! [`RequestPhases`] traces one outbound request as a call whose stages —
+//! DNS lookup, TCP connect, TLS handshake, waiting for the first byte and
+//! reading the body — are child calls of their own, so "the API is slow" can
+//! be pinned on the stage that was. Its EXIT sums them up:
+//!
+//! ```json
+//! {"event":"EXIT","method":"GET api.example.com","result":"{\"status\": 200, \"dnsMicros\": 1200, \"connectMicros\": 8400, \"tlsMicros\": 21000, \"firstByteMicros\": 180000, \"bodyMicros\": 3100}",...}
+//! ```
+//!
+//! A client marks where each stage begins, e.g. from its connection hooks;
+//! a stage ends where the next one begins. For blocking clients over
+//! `std::net`, [`RequestPhases::connect`] resolves and connects itself.
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::{TraceContext, TraceEvent};
+
+/// A stage of an outbound request, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Dns,
+    Connect,
+    Tls,
+    /// From sending the request until the first byte of the response
+    FirstByte,
+    Body,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [Phase::Dns, Phase::Connect, Phase::Tls, Phase::FirstByte, Phase::Body];
+
+    /// Suffix of the phase's child call, e.g. `GET api.example.com::dns`
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Dns => "dns",
+            Phase::Connect => "connect",
+            Phase::Tls => "tls",
+            Phase::FirstByte => "first_byte",
+            Phase::Body => "body",
+        }
+    }
+
+    /// Key of the phase's total in the EXIT result
+    fn key(self) -> &'static str {
+        match self {
+            Phase::Dns => "dnsMicros",
+            Phase::Connect => "connectMicros",
+            Phase::Tls => "tlsMicros",
+            Phase::FirstByte => "firstByteMicros",
+            Phase::Body => "bodyMicros",
+        }
+    }
+}
+
+/// One outbound request traced as a call, with a child call per phase
+///
+/// ```rust,no_run
+/// use flowtrace_agent::net::{Phase, RequestPhases};
+///
+/// let mut request = RequestPhases::start(module_path!(), "GET example.com");
+/// let stream = request.connect("example.com:80")?;
+/// request.begin(Phase::FirstByte);
+/// // ... write the request, wait for the response head ...
+/// request.begin(Phase::Body);
+/// // ... read the body ...
+/// request.finish(Some(200));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct RequestPhases {
+    module: String,
+    function: String,
+    context: TraceContext,
+    /// Start on the `monotonic_micros` clock
+    start: i64,
+    /// The phase under way and when it began
+    current: Option<(Phase, i64)>,
+    /// Total microseconds per phase, in the order of [`Phase::ALL`]
+    totals: [Option<i64>; 5],
+    /// Set once the closing event has been logged
+    finished: bool,
+}
+
+impl RequestPhases {
+    /// Log the request's ENTER, as a child of the caller's call if any
+    pub fn start(module: &str, function: &str) -> Self {
+        // The ENTER starts a trace if the caller isn't in one
+        let context = TraceContext::current().unwrap_or_else(TraceContext::new_or_inherited);
+        context.scope(|| crate::log_event(TraceEvent::enter(module, function, None)));
+        Self {
+            module: module.to_string(),
+            function: function.to_string(),
+            context,
+            start: crate::monotonic_micros(),
+            current: None,
+            totals: [None; 5],
+            finished: false,
+        }
+    }
+
+    /// End the phase under way, if any, and begin `phase`
+    pub fn begin(&mut self, phase: Phase) {
+        self.end_phase(None);
+        let function = self.phase_function(phase);
+        self.log(TraceEvent::enter(&self.module, &function, None));
+        self.current = Some((phase, crate::monotonic_micros()));
+    }
+
+    /// Resolve `addr` and connect to it, timing the [`Phase::Dns`] and [`Phase::Connect`] phases
+    pub fn connect(&mut self, addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        self.begin(Phase::Dns);
+        let addrs: Vec<_> = match addr.to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(error) => {
+                self.end_phase(Some(&error));
+                return Err(error);
+            }
+        };
+        self.begin(Phase::Connect);
+        match TcpStream::connect(&addrs[..]) {
+            Ok(stream) => {
+                self.end_phase(None);
+                Ok(stream)
+            }
+            Err(error) => {
+                self.end_phase(Some(&error));
+                Err(error)
+            }
+        }
+    }
+
+    /// Total time of `phase` so far, in microseconds; `None` if it never began
+    pub fn phase_micros(&self, phase: Phase) -> Option<i64> {
+        let index = Phase::ALL.iter().position(|p| *p == phase)?;
+        self.totals[index]
+    }
+
+    /// End the request, logging EXIT with its HTTP `status` and phase totals
+    pub fn finish(mut self, status: Option<u16>) {
+        self.close(status, None);
+    }
+
+    /// End the request as failed, logging EXCEPTION with `error`, the phase totals as its result
+    pub fn fail(mut self, error: impl ToString) {
+        self.close(None, Some(error.to_string()));
+    }
+
+    fn close(&mut self, status: Option<u16>, error: Option<String>) {
+        if self.finished {
+            return;
+        }
+        self.end_phase(None);
+        self.finished = true;
+
+        let mut fields: Vec<String> = status.map(|status| format!("\"status\": {}", status)).into_iter().collect();
+        for (phase, total) in Phase::ALL.iter().zip(self.totals) {
+            if let Some(total) = total {
+                fields.push(format!("\"{}\": {}", phase.key(), total));
+            }
+        }
+        let result = format!("{{{}}}", fields.join(", "));
+        let duration = Some(crate::monotonic_micros() - self.start);
+
+        let event = match error {
+            Some(error) => {
+                let mut event = TraceEvent::exception(&self.module, &self.function, &error, duration);
+                event.result = Some(result);
+                event
+            }
+            None => TraceEvent::exit(&self.module, &self.function, Some(result), duration),
+        };
+        self.log(event);
+    }
+
+    /// Close the phase under way, as EXCEPTION if it failed with `error`
+    fn end_phase(&mut self, error: Option<&io::Error>) {
+        let Some((phase, began)) = self.current.take() else {
+            return;
+        };
+        let duration = crate::monotonic_micros() - began;
+        let index = Phase::ALL.iter().position(|p| *p == phase).unwrap_or_default();
+        self.totals[index] = Some(self.totals[index].unwrap_or(0) + duration);
+
+        let function = self.phase_function(phase);
+        let event = match error {
+            Some(error) => TraceEvent::exception(&self.module, &function, &error.to_string(), Some(duration)),
+            None => TraceEvent::exit(&self.module, &function, None, Some(duration)),
+        };
+        self.log(event);
+    }
+
+    fn phase_function(&self, phase: Phase) -> String {
+        format!("{}::{}", self.function, phase.name())
+    }
+
+    fn log(&self, event: TraceEvent) {
+        self.context.scope(|| crate::log_event(event));
+    }
+}
+
+impl Drop for RequestPhases {
+    fn drop(&mut self) {
+        // A request abandoned without `finish` still logs its EXIT
+        if !std::thread::panicking() {
+            self.close(None, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_phases() {
+        let path = std::env::temp_dir().join("flowtrace_net.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        crate::with_tracer(&tracer, || {
+            let mut request = RequestPhases::start("app", "GET localhost");
+            request.connect(addr).unwrap();
+            request.begin(Phase::FirstByte);
+            request.begin(Phase::Body);
+            assert!(request.phase_micros(Phase::Connect).is_some());
+            assert_eq!(request.phase_micros(Phase::Tls), None);
+            request.finish(Some(200));
+
+            let mut request = RequestPhases::start("app", "GET nowhere");
+            assert!(request.connect("nowhere.invalid:80").is_err());
+            request.fail("dns lookup failed");
+        });
+        tracer.stop();
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        let functions: Vec<&str> = events.iter().map(|event| event.function.as_str()).collect();
+        assert_eq!(
+            functions[..10],
+            [
+                "GET localhost",
+                "GET localhost::dns",
+                "GET localhost::dns",
+                "GET localhost::connect",
+                "GET localhost::connect",
+                "GET localhost::first_byte",
+                "GET localhost::first_byte",
+                "GET localhost::body",
+                "GET localhost::body",
+                "GET localhost",
+            ]
+        );
+        assert!(events[..10].iter().all(|event| event.trace_id == events[0].trace_id));
+        let result = events[9].result.as_deref().unwrap();
+        assert!(result.starts_with(r#"{"status": 200, "dnsMicros": "#), "{}", result);
+        assert!(result.contains("bodyMicros") && !result.contains("tlsMicros"));
+
+        assert_eq!(events[12].status, Some(crate::Status::Error));
+        assert_eq!(events[13].exception.as_deref(), Some("dns lookup failed"));
+        assert!(events[13].result.as_deref().unwrap().contains("dnsMicros"));
+    }
+}
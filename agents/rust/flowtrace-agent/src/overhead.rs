@@ -0,0 +1,187 @@
+//! Self-measurement of the agent's own cost
+//!
+//! With `Config::overhead_interval_secs` set, the logger times the work it
+//! does for each event it writes: serializing it to JSON, rendering it in the
+//! sinks' output formats, and writing it to the file, stdout and network
+//! sinks. At the end of each interval it writes an `OVERHEAD` record with
+//! those times per 1000 events, so the published overhead figures can be
+//! checked on the application's own workload and hardware.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ParseError, TimestampFormat};
+
+/// Value of the `event` field of an [`Overhead`] record
+pub const OVERHEAD: &str = "OVERHEAD";
+
+/// Time the agent spent on the events it wrote over one interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Overhead {
+    #[serde(default)]
+    pub event_id: String,
+    /// Always [`OVERHEAD`]
+    pub event: String,
+    /// End of the interval, in microseconds since the Unix epoch, written like event timestamps
+    #[serde(deserialize_with = "crate::parse::timestamp_micros")]
+    pub timestamp: i64,
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    /// Length of the interval
+    pub window_seconds: f64,
+    /// Events written in the interval
+    pub events: u64,
+    /// Microseconds per 1000 events spent serializing them to JSON
+    #[serde(rename = "serializeMicrosPer1k")]
+    pub serialize_micros_per_1k: f64,
+    /// Microseconds per 1000 events spent rendering them in the file and stdout formats
+    #[serde(rename = "formatMicrosPer1k")]
+    pub format_micros_per_1k: f64,
+    /// Microseconds per 1000 events spent writing and publishing them
+    #[serde(rename = "writeMicrosPer1k")]
+    pub write_micros_per_1k: f64,
+}
+
+impl Overhead {
+    /// Total microseconds per 1000 events
+    pub fn total_micros_per_1k(&self) -> f64 {
+        self.serialize_micros_per_1k + self.format_micros_per_1k + self.write_micros_per_1k
+    }
+
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(self.timestamp_nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Parse an `OVERHEAD` line
+    pub fn from_json_line(line: &str) -> Result<Overhead, ParseError> {
+        let overhead: Overhead = crate::parse::from_line(line)?;
+        if overhead.event != OVERHEAD {
+            return Err(ParseError::Schema {
+                column: 0,
+                message: format!("expected an {} record, found `{}`", OVERHEAD, overhead.event),
+            });
+        }
+        Ok(overhead)
+    }
+}
+
+/// Times the stages of writing one event; a no-op when not measuring
+pub(crate) struct Stopwatch(Option<Instant>);
+
+impl Stopwatch {
+    pub(crate) fn start(measuring: bool) -> Self {
+        Self(measuring.then(Instant::now))
+    }
+
+    /// Time since the start or the previous lap
+    pub(crate) fn lap(&mut self) -> Duration {
+        let Some(start) = &mut self.0 else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let elapsed = now - *start;
+        *start = now;
+        elapsed
+    }
+}
+
+/// Sums up the time spent per stage in the current interval
+pub(crate) struct OverheadMeter {
+    interval: Duration,
+    window_start: Instant,
+    events: u64,
+    serialize: Duration,
+    format: Duration,
+    write: Duration,
+}
+
+impl OverheadMeter {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            window_start: Instant::now(),
+            events: 0,
+            serialize: Duration::ZERO,
+            format: Duration::ZERO,
+            write: Duration::ZERO,
+        }
+    }
+
+    /// Add the stage times of one written event
+    pub(crate) fn record(&mut self, serialize: Duration, format: Duration, write: Duration) {
+        self.events += 1;
+        self.serialize += serialize;
+        self.format += format;
+        self.write += write;
+    }
+
+    /// Whether the current interval has ended
+    pub(crate) fn is_due(&self) -> bool {
+        self.window_start.elapsed() >= self.interval
+    }
+
+    /// End the current interval, returning its record if any event was written in it
+    pub(crate) fn take(&mut self) -> Option<Overhead> {
+        let window_seconds = self.window_start.elapsed().as_secs_f64();
+        let meter = std::mem::replace(self, Self::new(self.interval));
+        if meter.events == 0 {
+            return None;
+        }
+
+        let per_1k = |total: Duration| total.as_secs_f64() * 1e6 * 1000.0 / meter.events as f64;
+        let nanos = crate::wall_clock_nanos();
+        Some(Overhead {
+            event_id: crate::ulid::generate(),
+            event: OVERHEAD.to_string(),
+            timestamp: nanos / 1000,
+            timestamp_nanos: nanos,
+            window_seconds,
+            events: meter.events,
+            serialize_micros_per_1k: per_1k(meter.serialize),
+            format_micros_per_1k: per_1k(meter.format),
+            write_micros_per_1k: per_1k(meter.write),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_per_1k_events() {
+        let mut meter = OverheadMeter::new(Duration::from_secs(60));
+        assert!(meter.take().is_none());
+
+        for _ in 0..4 {
+            meter.record(Duration::from_micros(2), Duration::from_micros(1), Duration::from_micros(5));
+        }
+        assert!(!meter.is_due());
+        let overhead = meter.take().unwrap();
+        assert_eq!(overhead.events, 4);
+        assert!((overhead.serialize_micros_per_1k - 2_000.0).abs() < 1e-6);
+        assert!((overhead.total_micros_per_1k() - 8_000.0).abs() < 1e-6);
+        assert!(meter.take().is_none());
+
+        let line = overhead.to_json(TimestampFormat::Rfc3339).unwrap();
+        assert!(line.contains(r#""event":"OVERHEAD""#) && line.contains("writeMicrosPer1k"), "{}", line);
+        assert_eq!(Overhead::from_json_line(&line).unwrap().events, 4);
+        assert!(Overhead::from_json_line(r#"{"event":"ENTER","timestamp":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let mut idle = Stopwatch::start(false);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(idle.lap(), Duration::ZERO);
+
+        let mut stopwatch = Stopwatch::start(true);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(stopwatch.lap() >= Duration::from_millis(1));
+    }
+}
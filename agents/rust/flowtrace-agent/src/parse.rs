@@ -0,0 +1,124 @@
+//! Strict parsing of FlowTrace JSONL lines
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+use crate::TraceEvent;
+
+/// Integer timestamps above this are epoch nanoseconds rather than microseconds
+const NANOS_THRESHOLD: i64 = 100_000_000_000_000_000;
+
+/// Why a line could not be parsed as a [`TraceEvent`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line is empty or only whitespace
+    Empty,
+    /// The line is not valid JSON
+    Syntax { column: usize, message: String },
+    /// The line is JSON but does not match the event schema
+    Schema { column: usize, message: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty line"),
+            Self::Syntax { column, message } => write!(f, "invalid JSON at column {}: {}", column, message),
+            Self::Schema { column, message } => write!(f, "invalid event at column {}: {}", column, message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl TraceEvent {
+    /// Parse one line of a FlowTrace log
+    ///
+    /// Unlike `serde_json::from_str`, errors say whether the line is broken
+    /// JSON or a well-formed object that is not an event. Unknown fields are
+    /// accepted so logs from newer agents still parse.
+    pub fn from_json_line(line: &str) -> Result<TraceEvent, ParseError> {
+        from_line(line)
+    }
+}
+
+/// Deserialize one log line, classifying failures as [`ParseError`]s
+pub(crate) fn from_line<T: DeserializeOwned>(line: &str) -> Result<T, ParseError> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Err(ParseError::Empty);
+    }
+
+    serde_json::from_str(line).map_err(|e| {
+        let column = e.column();
+        let message = strip_position(&e.to_string());
+        match e.classify() {
+            serde_json::error::Category::Data => ParseError::Schema { column, message },
+            _ => ParseError::Syntax { column, message },
+        }
+    })
+}
+
+/// Drop serde_json's trailing " at line L column C", which `ParseError` reports itself
+fn strip_position(message: &str) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message.to_string(),
+    }
+}
+
+/// Read `timestamp` in any [`TimestampFormat`](crate::TimestampFormat), as epoch microseconds
+pub(crate) fn timestamp_micros<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Epoch(i64),
+        Text(String),
+    }
+
+    match Timestamp::deserialize(deserializer)? {
+        Timestamp::Epoch(value) if value > NANOS_THRESHOLD => Ok(value / 1000),
+        Timestamp::Epoch(value) => Ok(value),
+        Timestamp::Text(text) => chrono::DateTime::parse_from_rfc3339(&text)
+            .map(|time| time.timestamp_micros())
+            .map_err(|e| serde::de::Error::custom(format!("invalid RFC 3339 timestamp '{}': {}", text, e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventType, TimestampFormat};
+
+    #[test]
+    fn test_from_json_line_roundtrip() {
+        let event = TraceEvent::exit("app", "work", Some("42".to_string()), Some(1_500));
+
+        for format in [TimestampFormat::EpochMicros, TimestampFormat::EpochNanos, TimestampFormat::Rfc3339] {
+            let parsed = TraceEvent::from_json_line(&event.to_json(format).unwrap()).unwrap();
+            assert!(matches!(parsed.event_type, EventType::Exit));
+            assert_eq!(parsed.timestamp, event.timestamp);
+            assert_eq!(parsed.event_id, event.event_id);
+            assert_eq!(parsed.duration_micros, Some(1_500));
+        }
+    }
+
+    #[test]
+    fn test_from_json_line_errors() {
+        assert_eq!(TraceEvent::from_json_line("  ").unwrap_err(), ParseError::Empty);
+
+        let syntax = TraceEvent::from_json_line(r#"{"event": "ENTER","#).unwrap_err();
+        assert!(matches!(syntax, ParseError::Syntax { .. }), "{:?}", syntax);
+
+        let missing = TraceEvent::from_json_line(r#"{"event":"ENTER","timestamp":1,"class":"app","thread":"t"}"#)
+            .unwrap_err();
+        assert_eq!(missing.to_string(), "invalid event at column 58: missing field `method`");
+
+        let kind = TraceEvent::from_json_line(
+            r#"{"event":"START","timestamp":1,"class":"app","method":"a","thread":"t"}"#,
+        )
+        .unwrap_err();
+        assert!(kind.to_string().contains("unknown variant `START`"), "{}", kind);
+    }
+}
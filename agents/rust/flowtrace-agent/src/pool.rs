@@ -0,0 +1,137 @@
+//! Tracing blocking work offloaded to thread pools
+//!
+//! A closure handed to `tokio::task::spawn_blocking`, rayon or any other pool
+//! runs on a worker thread the caller's trace does not reach. [`traced_task`]
+//! wraps it so it runs in the caller's trace context, as a span of its own,
+//! `pool::<name>`: the ENTER is logged when a worker picks the task up, with
+//! the time it spent queued in the `queueWaitMicros` tag, and the duration
+//! covers its execution only:
+//!
+//! ```json
+//! {"event":"ENTER","class":"pool","method":"resize_image","tags":{"queueWaitMicros":"1840"},...}
+//! {"event":"EXIT","class":"pool","method":"resize_image","durationMicros":52310,"tags":{"queueWaitMicros":"1840"},...}
+//! ```
+
+use crate::Span;
+
+/// Module of the spans of offloaded tasks
+const MODULE: &str = "pool";
+
+/// Wrap `task` to be traced as `pool::<name>` when a pool runs it
+///
+/// The queue wait is measured from this call. A `::` in `name` logs the span
+/// under a module of its own instead of `pool`. The task also keeps the
+/// caller's [`with_tracer`](crate::with_tracer) override, and a panic in it
+/// closes the span as an EXCEPTION.
+///
+/// ```rust
+/// use flowtrace_agent::pool::traced_task;
+///
+/// let worker = std::thread::spawn(traced_task("checksum", || 42));
+/// assert_eq!(worker.join().unwrap(), 42);
+/// ```
+pub fn traced_task<R>(name: &str, task: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let name = name.to_string();
+    let queued = crate::monotonic_micros();
+    crate::tracer::bind_override(crate::bind(move || {
+        let mut span = Span::builder(&name);
+        if !name.contains("::") {
+            span = span.module(MODULE);
+        }
+        let _span = span.tag("queueWaitMicros", crate::monotonic_micros() - queued).start();
+        task()
+    }))
+}
+
+/// Run `f` on tokio's blocking pool, traced as `pool::<name>`, see [`traced_task`]
+#[cfg(feature = "tokio")]
+pub fn spawn_blocking<F, R>(name: &str, f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(traced_task(name, f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::TraceEvent;
+
+    fn logged(name: &str, f: impl FnOnce()) -> Vec<TraceEvent> {
+        let path = std::env::temp_dir().join(format!("flowtrace_pool_{}.jsonl", name));
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+        crate::with_tracer(&tracer, f);
+        tracer.stop();
+
+        let events = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        events
+    }
+
+    fn queue_wait(event: &TraceEvent) -> i64 {
+        event.tags.as_ref().unwrap()["queueWaitMicros"].parse().unwrap()
+    }
+
+    #[test]
+    fn test_traced_task() {
+        let events = logged("task", || {
+            let _request = Span::new("app", "handle");
+            let task = traced_task("resize", || std::thread::sleep(Duration::from_millis(5)));
+            std::thread::sleep(Duration::from_millis(20));
+            std::thread::spawn(task).join().unwrap();
+
+            let failing = traced_task("images::decode", || panic!("corrupt"));
+            assert!(std::thread::spawn(failing).join().is_err());
+        });
+
+        let calls: Vec<(&str, &str)> = events.iter().map(|event| (event.module.as_str(), event.function.as_str())).collect();
+        assert_eq!(
+            calls,
+            [
+                ("app", "handle"),
+                ("pool", "resize"),
+                ("pool", "resize"),
+                ("images", "decode"),
+                ("images", "decode"),
+                ("app", "handle"),
+            ]
+        );
+        assert!(events.iter().all(|event| event.trace_id == events[0].trace_id));
+        assert!(queue_wait(&events[1]) >= 20_000);
+        // ENTER is logged at pickup, so the duration covers the execution, not the time queued
+        assert!(events[1].monotonic_micros - events[0].monotonic_micros >= 20_000);
+        assert!(events[2].duration_micros.unwrap() >= 5_000);
+        assert_eq!(events[4].exception.as_deref(), Some("panicked"));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_spawn_blocking() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let events = logged("spawn_blocking", || {
+            let _request = Span::new("app", "handle");
+            let sum = runtime.block_on(async { spawn_blocking("sum", || (1..=10).sum::<u32>()).await.unwrap() });
+            assert_eq!(sum, 55);
+        });
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[1].function, "sum");
+        assert_ne!(events[1].thread, events[0].thread);
+        assert_eq!(events[1].trace_id, events[0].trace_id);
+        assert!(queue_wait(&events[1]) >= 0);
+    }
+}
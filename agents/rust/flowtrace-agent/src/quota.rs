@@ -0,0 +1,213 @@
+//! Disk quota of the log file and its rotations
+//!
+//! With `Config::max_total_disk_bytes` set, the agent never lets its log files
+//! grow past the quota. In [`QuotaAction::Rotate`] mode the log file is
+//! rotated to `<log_file>.1`, `.2`, ... each time it reaches a quarter of the
+//! quota, and the oldest rotations are deleted to make room. In
+//! [`QuotaAction::ErrorsOnly`] mode nothing is deleted: once the quota is
+//! reached only EXCEPTION events are written, within a small headroom, and
+//! then nothing at all. Either way a `QUOTA` record notes what happened.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ParseError, QuotaAction, TimestampFormat};
+
+/// Value of the `event` field of a [`QuotaEvent`] record
+pub const QUOTA: &str = "QUOTA";
+
+/// Number of files (the log file and its rotations) the quota is spread over
+const ROTATED_FILES: u64 = 4;
+/// Extra room for exceptions past the quota in errors-only mode, in percent of it
+const ERRORS_ONLY_HEADROOM_PERCENT: u64 = 10;
+
+/// Written to the log when the disk quota is reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaEvent {
+    #[serde(default)]
+    pub event_id: String,
+    /// Always [`QUOTA`]
+    pub event: String,
+    /// Microseconds since the Unix epoch, written like event timestamps
+    #[serde(deserialize_with = "crate::parse::timestamp_micros")]
+    pub timestamp: i64,
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    /// What the agent does about it
+    pub action: QuotaAction,
+    pub max_total_disk_bytes: u64,
+    /// Bytes used by the log files when the quota was reached
+    pub used_bytes: u64,
+    /// Rotated files deleted to make room
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deleted_files: Vec<String>,
+}
+
+impl QuotaEvent {
+    fn new(action: QuotaAction, max_total_disk_bytes: u64, used_bytes: u64, deleted_files: Vec<String>) -> Self {
+        let nanos = crate::wall_clock_nanos();
+        Self {
+            event_id: crate::ulid::generate(),
+            event: QUOTA.to_string(),
+            timestamp: nanos / 1000,
+            timestamp_nanos: nanos,
+            action,
+            max_total_disk_bytes,
+            used_bytes,
+            deleted_files,
+        }
+    }
+
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(self.timestamp_nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Parse a `QUOTA` line
+    pub fn from_json_line(line: &str) -> Result<QuotaEvent, ParseError> {
+        let event: QuotaEvent = crate::parse::from_line(line)?;
+        if event.event != QUOTA {
+            return Err(ParseError::Schema {
+                column: 0,
+                message: format!("expected a {} record, found `{}`", QUOTA, event.event),
+            });
+        }
+        Ok(event)
+    }
+}
+
+/// Disk usage of one log file and its rotations
+pub(crate) struct DiskQuota {
+    path: PathBuf,
+    max_bytes: u64,
+    action: QuotaAction,
+    /// Bytes in the current log file
+    current_bytes: u64,
+    /// Rotated files and their sizes, oldest first
+    rotated: Vec<(PathBuf, u64)>,
+    reached: bool,
+}
+
+impl DiskQuota {
+    /// Track `path`, picking up its size and any rotations left by earlier runs
+    pub(crate) fn new(path: &str, max_bytes: u64, action: QuotaAction) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let current_bytes = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+
+        let mut rotated = Vec::new();
+        let name = path.file_name().map(|name| format!("{}.", name.to_string_lossy()));
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if let (Some(name), Ok(entries)) = (name, fs::read_dir(&dir)) {
+            for entry in entries {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                if let Some(index) = file_name.strip_prefix(&name).and_then(|index| index.parse::<u64>().ok()) {
+                    rotated.push((index, path.with_file_name(&file_name), entry.metadata()?.len()));
+                }
+            }
+        }
+        rotated.sort();
+
+        Ok(Self {
+            path,
+            max_bytes,
+            action,
+            current_bytes,
+            rotated: rotated.into_iter().map(|(_, path, bytes)| (path, bytes)).collect(),
+            reached: false,
+        })
+    }
+
+    /// Bytes used by the log file and its rotations
+    pub(crate) fn used_bytes(&self) -> u64 {
+        self.current_bytes + self.rotated.iter().map(|(_, bytes)| bytes).sum::<u64>()
+    }
+
+    /// Whether `bytes` more may be written
+    ///
+    /// Only refuses in errors-only mode, past the quota and its headroom.
+    pub(crate) fn allows(&self, bytes: u64) -> bool {
+        self.action != QuotaAction::ErrorsOnly
+            || self.used_bytes() + bytes <= self.max_bytes + self.max_bytes * ERRORS_ONLY_HEADROOM_PERCENT / 100
+    }
+
+    /// Whether only exceptions are logged now
+    pub(crate) fn errors_only(&self) -> bool {
+        self.reached && self.action == QuotaAction::ErrorsOnly
+    }
+
+    pub(crate) fn record_written(&mut self, bytes: u64) {
+        self.current_bytes += bytes;
+    }
+
+    /// Apply the quota after a write
+    ///
+    /// Rotates the log file if it is due, returning the `QUOTA` record to
+    /// write when rotations had to be deleted or errors-only mode just began.
+    /// The caller reopens the log file after a rotation.
+    pub(crate) fn enforce(&mut self) -> io::Result<(bool, Option<QuotaEvent>)> {
+        match self.action {
+            QuotaAction::Rotate if self.current_bytes >= self.max_bytes / ROTATED_FILES => {
+                let used_bytes = self.used_bytes();
+                let index = self
+                    .rotated
+                    .last()
+                    .and_then(|(path, _)| path.extension()?.to_str()?.parse::<u64>().ok())
+                    .unwrap_or(0)
+                    + 1;
+                let rotated = rotated_path(&self.path, index);
+                fs::rename(&self.path, &rotated)?;
+                self.rotated.push((rotated, self.current_bytes));
+                self.current_bytes = 0;
+
+                // Leave room for the new file to grow to its share
+                let mut deleted_files = Vec::new();
+                while self.used_bytes() + self.max_bytes / ROTATED_FILES > self.max_bytes && !self.rotated.is_empty() {
+                    let (oldest, _) = self.rotated.remove(0);
+                    let _ = fs::remove_file(&oldest);
+                    deleted_files.push(oldest.display().to_string());
+                }
+
+                let event = (!deleted_files.is_empty())
+                    .then(|| QuotaEvent::new(self.action, self.max_bytes, used_bytes, deleted_files));
+                Ok((true, event))
+            }
+            QuotaAction::ErrorsOnly if !self.reached && self.used_bytes() >= self.max_bytes => {
+                self.reached = true;
+                Ok((false, Some(QuotaEvent::new(self.action, self.max_bytes, self.used_bytes(), Vec::new()))))
+            }
+            _ => Ok((false, None)),
+        }
+    }
+}
+
+fn rotated_path(path: &Path, index: u64) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", index));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_event_roundtrip() {
+        let event = QuotaEvent::new(QuotaAction::Rotate, 100, 120, vec!["app.jsonl.1".to_string()]);
+        let line = event.to_json(TimestampFormat::EpochMicros).unwrap();
+        assert!(line.contains(r#""event":"QUOTA""#) && line.contains(r#""action":"rotate""#), "{}", line);
+
+        let parsed = QuotaEvent::from_json_line(&line).unwrap();
+        assert_eq!(parsed.deleted_files, ["app.jsonl.1"]);
+        assert!(QuotaEvent::from_json_line(&crate::AgentInfo::current().to_json(TimestampFormat::EpochMicros).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Recording windows of `Mode::OnDemand`
+//!
+//! In `Mode::OnDemand` the tracer opens its sinks but drops every event until
+//! [`record_for`](crate::record_for) opens a window. Calls entered while it is
+//! open are recorded through their EXIT or EXCEPTION, even one logged after the
+//! window closed, while calls already running when it opened are left out: the
+//! log holds complete calls only.
+//!
+//! A closing event is matched to its ENTER by call ID, so an `async fn` resuming
+//! on another worker thread, or interleaved with other tasks on one, still has
+//! its EXIT recorded. Events logged without a call ID fall back to the order of
+//! calls on their thread.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::{EventType, TraceEvent};
+
+#[derive(Default)]
+pub(crate) struct Recording {
+    /// End of the current or last window
+    until: Option<Instant>,
+    /// Recorded calls still open, by call ID
+    calls: HashSet<u64>,
+    /// Whether each open call without a call ID is recorded, innermost last,
+    /// for the threads with such a recorded call open
+    open: HashMap<String, Vec<bool>>,
+}
+
+impl Recording {
+    /// Record for `window` from `now`, extending a window that is already open
+    pub(crate) fn record_for(&mut self, window: Duration, now: Instant) {
+        let until = now + window;
+        self.until = Some(self.until.map_or(until, |current| current.max(until)));
+    }
+
+    /// Whether `event` is recorded
+    pub(crate) fn admit(&mut self, event: &TraceEvent, now: Instant) -> bool {
+        match event.event_type {
+            EventType::Enter => {
+                let recording = self.until.is_some_and(|until| now < until);
+                if let Some(call_id) = event.call_id {
+                    if recording {
+                        self.calls.insert(call_id);
+                    }
+                    return recording;
+                }
+                match self.open.get_mut(&event.thread) {
+                    Some(open) => open.push(recording),
+                    None if recording => {
+                        self.open.insert(event.thread.clone(), vec![true]);
+                    }
+                    // Not recorded and nothing recorded around it: no need to track it
+                    None => {}
+                }
+                recording
+            }
+            EventType::Exit | EventType::Exception => {
+                if let Some(call_id) = event.call_id {
+                    return self.calls.remove(&call_id);
+                }
+                let Some(open) = self.open.get_mut(&event.thread) else {
+                    return false;
+                };
+                let recorded = open.pop().unwrap_or_default();
+                if open.is_empty() {
+                    self.open.remove(&event.thread);
+                }
+                recorded
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_whole_calls_entered_in_window() {
+        let start = Instant::now();
+        let mut recording = Recording::default();
+        let enter = |function| TraceEvent::enter("app", function, None);
+        let exit = |function| TraceEvent::exit("app", function, None, Some(1));
+
+        // Running before the window opens, so never recorded
+        assert!(!recording.admit(&enter("serve"), start));
+        recording.record_for(Duration::from_secs(10), start);
+        assert!(recording.admit(&enter("handle"), start));
+        // Closed after the window, but entered in it
+        let after = start + Duration::from_secs(11);
+        assert!(!recording.admit(&enter("late_child"), after));
+        assert!(!recording.admit(&exit("late_child"), after));
+        assert!(recording.admit(&exit("handle"), after));
+        assert!(!recording.admit(&exit("serve"), after));
+        assert!(recording.open.is_empty());
+
+        // A second call extends the window rather than cutting it short
+        recording.record_for(Duration::from_secs(60), start);
+        recording.record_for(Duration::from_secs(1), start);
+        assert!(recording.admit(&enter("handle"), start + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_matches_calls_by_id_across_threads() {
+        let start = Instant::now();
+        let mut recording = Recording::default();
+        let call = |function, thread: &str| {
+            let mut event = TraceEvent::enter("app", function, None).with_call_id(TraceEvent::next_call_id());
+            event.thread = thread.to_string();
+            event
+        };
+        let exit = |enter: &TraceEvent, thread: &str| {
+            let mut event = TraceEvent::exit("app", &enter.function, None, Some(1)).with_call_id(enter.call_id.unwrap());
+            event.thread = thread.to_string();
+            event
+        };
+
+        let before = call("before", "worker-1");
+        assert!(!recording.admit(&before, start));
+        recording.record_for(Duration::from_secs(10), start);
+        let (first, second) = (call("first", "worker-1"), call("second", "worker-1"));
+        assert!(recording.admit(&first, start));
+        assert!(recording.admit(&second, start));
+
+        // Interleaved on one worker and resumed on another, after the window
+        let after = start + Duration::from_secs(11);
+        assert!(!recording.admit(&exit(&before, "worker-1"), after));
+        assert!(recording.admit(&exit(&first, "worker-2"), after));
+        assert!(recording.admit(&exit(&second, "worker-3"), after));
+        assert!(recording.calls.is_empty() && recording.open.is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+//! Coalescing of repeated exceptions
+//!
+//! With `Config::coalesce_window_secs` set, only the first EXCEPTION of a
+//! function with a given error is written in each window. Identical ones
+//! after it are counted instead, and a `REPEATED` record per function and
+//! error notes how many were left out, so an error storm costs a few lines
+//! per window. Records are written with the next event after the window
+//! ends, and for the last partial window when the logger is dropped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EventType, ParseError, TimestampFormat, TraceEvent};
+
+/// Value of the `event` field of a [`Repeated`] record
+pub const REPEATED: &str = "REPEATED";
+
+/// Exceptions left out over one window because they repeated an earlier one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Repeated {
+    #[serde(default)]
+    pub event_id: String,
+    /// Always [`REPEATED`]
+    pub event: String,
+    /// End of the window, in microseconds since the Unix epoch, written like event timestamps
+    #[serde(deserialize_with = "crate::parse::timestamp_micros")]
+    pub timestamp: i64,
+    #[serde(skip)]
+    pub timestamp_nanos: i64,
+    #[serde(rename = "class")]
+    pub module: String,
+    #[serde(rename = "method")]
+    pub function: String,
+    /// The error repeated
+    pub exception: String,
+    /// Length of the window
+    pub window_seconds: f64,
+    /// Exceptions not written, after the first one of the window
+    pub count: u64,
+}
+
+impl Repeated {
+    /// Serialize as one JSON line with `timestamp` written in `format`
+    pub fn to_json(&self, format: TimestampFormat) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        value["timestamp"] = format.render(self.timestamp_nanos);
+        serde_json::to_string(&value)
+    }
+
+    /// Parse a `REPEATED` line
+    pub fn from_json_line(line: &str) -> Result<Repeated, ParseError> {
+        let repeated: Repeated = crate::parse::from_line(line)?;
+        if repeated.event != REPEATED {
+            return Err(ParseError::Schema {
+                column: 0,
+                message: format!("expected a {} record, found `{}`", REPEATED, repeated.event),
+            });
+        }
+        Ok(repeated)
+    }
+}
+
+/// Exceptions seen in the current window
+pub(crate) struct Coalescer {
+    window: Duration,
+    window_start: Instant,
+    /// Repeats left out by module, function and error
+    seen: BTreeMap<(String, String, String), u64>,
+}
+
+impl Coalescer {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            window_start: Instant::now(),
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Whether `event` should be written, counting it when it repeats an earlier exception
+    pub(crate) fn admit(&mut self, event: &TraceEvent) -> bool {
+        let (EventType::Exception, Some(error)) = (&event.event_type, &event.exception) else {
+            return true;
+        };
+        let key = (event.module.clone(), event.function.clone(), error.clone());
+        match self.seen.get_mut(&key) {
+            Some(repeats) => {
+                *repeats += 1;
+                false
+            }
+            None => {
+                self.seen.insert(key, 0);
+                true
+            }
+        }
+    }
+
+    /// Whether the current window has ended
+    pub(crate) fn is_due(&self) -> bool {
+        self.window_start.elapsed() >= self.window
+    }
+
+    /// End the current window, returning a record per exception that repeated in it
+    pub(crate) fn take(&mut self) -> Vec<Repeated> {
+        let window_seconds = self.window_start.elapsed().as_secs_f64();
+        self.window_start = Instant::now();
+        let nanos = crate::wall_clock_nanos();
+
+        std::mem::take(&mut self.seen)
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|((module, function, exception), count)| Repeated {
+                event_id: crate::ulid::generate(),
+                event: REPEATED.to_string(),
+                timestamp: nanos / 1000,
+                timestamp_nanos: nanos,
+                module,
+                function,
+                exception,
+                window_seconds,
+                count,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesces_identical_exceptions() {
+        let mut coalescer = Coalescer::new(Duration::from_secs(60));
+        let declined = || TraceEvent::exception("app", "charge", "declined", Some(1));
+
+        assert!(coalescer.admit(&declined()));
+        assert!(!coalescer.admit(&declined()));
+        assert!(!coalescer.admit(&declined()));
+        assert!(coalescer.admit(&TraceEvent::exception("app", "charge", "timeout", Some(1))));
+        assert!(coalescer.admit(&TraceEvent::exit("app", "charge", None, Some(1))));
+        assert!(coalescer.admit(&TraceEvent::exit("app", "charge", None, Some(1))));
+        assert!(!coalescer.is_due());
+
+        let records = coalescer.take();
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].exception.as_str(), records[0].count), ("declined", 2));
+        assert!(coalescer.admit(&declined()));
+
+        let line = records[0].to_json(TimestampFormat::Rfc3339).unwrap();
+        let parsed = Repeated::from_json_line(&line).unwrap();
+        assert_eq!((parsed.function.as_str(), parsed.count), ("charge", 2));
+        assert!(Repeated::from_json_line(&serde_json::to_string(&declined()).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! The OpenTelemetry resource identifying the traced service
+//!
+//! Written with OTel attribute names (`service.name`, `service.version`,
+//! `deployment.environment`) in the `AGENT_INFO` record that opens every
+//! stream, so each exporter reports the same identity.
+
+use std::collections::BTreeMap;
+use std::env;
+
+use serde::Deserialize;
+
+pub const SERVICE_NAME: &str = "service.name";
+pub const SERVICE_VERSION: &str = "service.version";
+pub const DEPLOYMENT_ENVIRONMENT: &str = "deployment.environment";
+
+/// Identity of the service producing the traces
+///
+/// In `flowtrace.toml`:
+///
+/// ```toml
+/// [agent.resource]
+/// service_name = "checkout"
+/// service_version = "1.4.2"
+/// deployment_environment = "prod"
+/// attributes = { "k8s.pod.name" = "checkout-7d9f" }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Resource {
+    pub service_name: Option<String>,
+    pub service_version: Option<String>,
+    pub deployment_environment: Option<String>,
+    /// Any other attributes, keyed by their OTel names
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Resource {
+    /// A resource for the service `service_name`
+    pub fn new(service_name: impl Into<String>) -> Self {
+        Self {
+            service_name: Some(service_name.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_version(mut self, service_version: impl Into<String>) -> Self {
+        self.service_version = Some(service_version.into());
+        self
+    }
+
+    pub fn with_deployment_environment(mut self, environment: impl Into<String>) -> Self {
+        self.deployment_environment = Some(environment.into());
+        self
+    }
+
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Read the standard `OTEL_RESOURCE_ATTRIBUTES` and `OTEL_SERVICE_NAME` variables
+    ///
+    /// `OTEL_RESOURCE_ATTRIBUTES` holds `key=value` pairs separated by commas;
+    /// `OTEL_SERVICE_NAME` takes precedence over a `service.name` in it.
+    pub fn from_env() -> Self {
+        let mut resource = Self::default();
+        for (key, value) in env::var("OTEL_RESOURCE_ATTRIBUTES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|pair| pair.split_once('='))
+        {
+            resource.set(key.trim(), value.trim().to_string());
+        }
+        if let Ok(name) = env::var("OTEL_SERVICE_NAME") {
+            resource.set(SERVICE_NAME, name);
+        }
+        resource
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        if key.is_empty() || value.is_empty() {
+            return;
+        }
+        match key {
+            SERVICE_NAME => self.service_name = Some(value),
+            SERVICE_VERSION => self.service_version = Some(value),
+            DEPLOYMENT_ENVIRONMENT => self.deployment_environment = Some(value),
+            _ => {
+                self.attributes.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    /// Whether no attribute is set
+    pub fn is_empty(&self) -> bool {
+        self.to_attributes().is_empty()
+    }
+
+    /// All attributes, keyed by their OTel names
+    pub fn to_attributes(&self) -> BTreeMap<String, String> {
+        let mut attributes = self.attributes.clone();
+        for (key, value) in [
+            (SERVICE_NAME, &self.service_name),
+            (SERVICE_VERSION, &self.service_version),
+            (DEPLOYMENT_ENVIRONMENT, &self.deployment_environment),
+        ] {
+            if let Some(value) = value {
+                attributes.insert(key.to_string(), value.clone());
+            }
+        }
+        attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_attributes() {
+        let resource = Resource::new("checkout")
+            .with_version("1.4.2")
+            .with_attribute("k8s.pod.name", "checkout-7d9f");
+        let attributes = resource.to_attributes();
+        assert_eq!(attributes[SERVICE_NAME], "checkout");
+        assert_eq!(attributes[SERVICE_VERSION], "1.4.2");
+        assert_eq!(attributes["k8s.pod.name"], "checkout-7d9f");
+        assert!(!attributes.contains_key(DEPLOYMENT_ENVIRONMENT));
+        assert!(Resource::default().is_empty());
+    }
+
+    #[test]
+    fn test_set_routes_known_keys() {
+        let mut resource = Resource::default();
+        resource.set(DEPLOYMENT_ENVIRONMENT, "prod".to_string());
+        resource.set("region", "eu-west-1".to_string());
+        resource.set("empty", String::new());
+        assert_eq!(resource.deployment_environment.as_deref(), Some("prod"));
+        assert_eq!(resource.attributes.len(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+//! Background timers without a hard runtime dependency
+//!
+//! With `Config::flush_interval_ms` set, the tracer flushes the log file and
+//! writes due `ERROR_RATE`, `REPEATED`, `AGGREGATE` and `OVERHEAD` records on a
+//! timer, instead of only when the next event is logged. The timer runs on a
+//! [`Runtime`]: [`ThreadRuntime`] (the default) sleeps on one std thread, with
+//! the `tokio` feature [`TokioRuntime`] runs it as a task of a tokio runtime
+//! the application already has, and with the `async-std` feature
+//! [`AsyncStdRuntime`] runs it as an async-std task. Other runtimes implement
+//! the trait.
+
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+/// Where the agent runs its periodic work
+pub trait Runtime: fmt::Debug + Send + Sync {
+    /// Call `tick` every `period`, starting one period from now, until it returns `false`
+    ///
+    /// `tick` locks the logger briefly and never waits on IO other than the log file.
+    fn spawn_interval(&self, period: Duration, tick: Box<dyn FnMut() -> bool + Send>);
+}
+
+/// Timers on a dedicated std thread each; needs no async runtime
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadRuntime;
+
+impl Runtime for ThreadRuntime {
+    fn spawn_interval(&self, period: Duration, mut tick: Box<dyn FnMut() -> bool + Send>) {
+        let _ = thread::Builder::new().name("flowtrace-timer".to_string()).spawn(move || loop {
+            thread::sleep(period);
+            if !tick() {
+                break;
+            }
+        });
+    }
+}
+
+/// Timers as tasks of an existing tokio runtime
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct TokioRuntime(tokio::runtime::Handle);
+
+#[cfg(feature = "tokio")]
+impl TokioRuntime {
+    /// The runtime of the calling task; `None` outside of one
+    pub fn current() -> Option<Self> {
+        tokio::runtime::Handle::try_current().ok().map(Self)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl From<tokio::runtime::Handle> for TokioRuntime {
+    fn from(handle: tokio::runtime::Handle) -> Self {
+        Self(handle)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn_interval(&self, period: Duration, mut tick: Box<dyn FnMut() -> bool + Send>) {
+        self.0.spawn(async move {
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                if !tick() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Timers as tasks of the async-std global executor
+#[cfg(feature = "async-std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async-std")]
+impl Runtime for AsyncStdRuntime {
+    fn spawn_interval(&self, period: Duration, mut tick: Box<dyn FnMut() -> bool + Send>) {
+        async_std::task::spawn(async move {
+            loop {
+                async_std::task::sleep(period).await;
+                if !tick() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn count_ticks(runtime: &dyn Runtime) -> mpsc::Receiver<u32> {
+        let (sender, receiver) = mpsc::channel();
+        let mut ticks = 0;
+        runtime.spawn_interval(
+            Duration::from_millis(5),
+            Box::new(move || {
+                ticks += 1;
+                let _ = sender.send(ticks);
+                ticks < 3
+            }),
+        );
+        receiver
+    }
+
+    #[test]
+    fn test_thread_runtime() {
+        let ticks: Vec<u32> = count_ticks(&ThreadRuntime).iter().collect();
+        assert_eq!(ticks, [1, 2, 3]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_runtime() {
+        assert!(TokioRuntime::current().is_none());
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_time()
+            .build()
+            .unwrap();
+        let ticks: Vec<u32> = count_ticks(&TokioRuntime::from(runtime.handle().clone())).iter().collect();
+        assert_eq!(ticks, [1, 2, 3]);
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn test_async_std_runtime() {
+        let ticks: Vec<u32> = count_ticks(&AsyncStdRuntime).iter().collect();
+        assert_eq!(ticks, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,264 @@
+//! Sampling of whole root calls
+//!
+//! Sampling decisions are made when a thread enters a root call and apply to
+//! every event nested in it, so sampled logs never contain partial call trees.
+//! Events logged in a [`TraceContext`](crate::TraceContext) share one decision
+//! per trace instead, wherever they were logged.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{EventType, TraceEvent};
+
+/// Window over which the event rate is measured
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// The open root call of one thread
+struct Call {
+    depth: usize,
+    /// Sampling rate it was kept at, or `None` when dropped
+    rate: Option<f64>,
+}
+
+/// Applies one decision per root call to all of its events
+#[derive(Default)]
+pub(crate) struct CallSampler {
+    calls: HashMap<String, Call>,
+}
+
+impl CallSampler {
+    /// Whether `event` is kept, and at what rate
+    ///
+    /// `decide` is called at each root call (and for stray EXIT events),
+    /// returning the rate the call is kept at, or `None` to drop it.
+    pub(crate) fn sample(&mut self, event: &TraceEvent, decide: impl FnOnce() -> Option<f64>) -> Option<f64> {
+        match event.event_type {
+            EventType::Enter => {
+                let call = self.calls.entry(event.thread.clone()).or_insert(Call { depth: 0, rate: None });
+                if call.depth == 0 {
+                    call.rate = decide();
+                }
+                call.depth += 1;
+                call.rate
+            }
+            EventType::Exit | EventType::Exception => match self.calls.get_mut(&event.thread) {
+                Some(call) => {
+                    let rate = call.rate;
+                    call.depth -= 1;
+                    if call.depth == 0 {
+                        self.calls.remove(&event.thread);
+                    }
+                    rate
+                }
+                None => decide(),
+            },
+        }
+    }
+}
+
+/// Keep a root call with probability `rate`
+pub(crate) fn with_probability(rate: f64) -> Option<f64> {
+    (rate >= 1.0 || crate::ulid::random_fraction() < rate).then_some(rate.min(1.0))
+}
+
+/// Keep a trace with probability `rate`, deciding the same for every event of it
+///
+/// The decision depends only on the trace ID, so exporters sampling the same
+/// trace independently (in this process or another one) agree.
+#[cfg(feature = "grpc")]
+pub(crate) fn by_trace_id(trace_id: &str, rate: f64) -> Option<f64> {
+    let position = trace_id.get(..16).and_then(|prefix| u64::from_str_radix(prefix, 16).ok())?;
+    (rate >= 1.0 || (position as f64 / u64::MAX as f64) < rate).then_some(rate.min(1.0))
+}
+
+/// How the logger samples root calls
+pub(crate) enum Sampler {
+    Fixed(FixedSampler),
+    Adaptive(AdaptiveSampler),
+}
+
+impl Sampler {
+    /// The sampler `config` asks for, if any
+    pub(crate) fn from_config(config: &crate::Config) -> Option<Self> {
+        match (config.sample_rate, config.max_events_per_second) {
+            (_, Some(max_events_per_second)) => Some(Self::Adaptive(AdaptiveSampler::new(max_events_per_second))),
+            (Some(rate), None) => Some(Self::Fixed(FixedSampler::new(rate))),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether `event` is kept, and at what rate
+    pub(crate) fn sample(&mut self, event: &TraceEvent) -> Option<f64> {
+        match self {
+            Self::Fixed(sampler) => sampler.sample(event),
+            Self::Adaptive(sampler) => sampler.sample(event),
+        }
+    }
+}
+
+/// Keeps a fixed fraction of root calls
+pub(crate) struct FixedSampler {
+    rate: f64,
+    calls: CallSampler,
+}
+
+impl FixedSampler {
+    pub(crate) fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            calls: CallSampler::default(),
+        }
+    }
+
+    /// Whether `event` is kept, and at what rate
+    pub(crate) fn sample(&mut self, event: &TraceEvent) -> Option<f64> {
+        let rate = self.rate;
+        let decide = || with_probability(rate);
+        match &event.context {
+            Some(context) => context.decide(decide),
+            None => self.calls.sample(event, decide),
+        }
+    }
+}
+
+/// Samples root calls to stay within a budget of events per second
+///
+/// The sampling rate for each second is the budget divided by the rate
+/// events were offered at in the previous one, so quiet periods are kept in
+/// full. Within a second, new root calls are dropped once the budget is
+/// spent, which bounds bursts before the rate catches up.
+pub(crate) struct AdaptiveSampler {
+    max_per_second: f64,
+    calls: CallSampler,
+    window_start: Instant,
+    offered: u64,
+    kept: u64,
+    rate: f64,
+}
+
+impl AdaptiveSampler {
+    pub(crate) fn new(max_events_per_second: u32) -> Self {
+        Self {
+            max_per_second: max_events_per_second as f64,
+            calls: CallSampler::default(),
+            window_start: Instant::now(),
+            offered: 0,
+            kept: 0,
+            rate: 1.0,
+        }
+    }
+
+    /// Whether `event` is kept, and at what rate
+    pub(crate) fn sample(&mut self, event: &TraceEvent) -> Option<f64> {
+        self.sample_at(event, Instant::now())
+    }
+
+    fn sample_at(&mut self, event: &TraceEvent, now: Instant) -> Option<f64> {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= WINDOW {
+            let offered_per_second = self.offered as f64 / elapsed.as_secs_f64();
+            self.rate = if offered_per_second > 0.0 {
+                (self.max_per_second / offered_per_second).min(1.0)
+            } else {
+                1.0
+            };
+            self.window_start = now;
+            self.offered = 0;
+            self.kept = 0;
+        }
+        self.offered += 1;
+
+        let (rate, over_budget) = (self.rate, self.kept as f64 >= self.max_per_second);
+        let decide = || if over_budget { None } else { with_probability(rate) };
+        let kept = match &event.context {
+            Some(context) => context.decide(decide),
+            None => self.calls.sample(event, decide),
+        };
+        if kept.is_some() {
+            self.kept += 1;
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_sampler_keeps_whole_calls() {
+        let mut sampler = CallSampler::default();
+        let mut decisions = vec![Some(0.5), None].into_iter();
+        let mut sample = |event: TraceEvent| sampler.sample(&event, || decisions.next().unwrap());
+
+        assert_eq!(sample(TraceEvent::enter("app", "kept", None)), Some(0.5));
+        assert_eq!(sample(TraceEvent::enter("app", "child", None)), Some(0.5));
+        assert_eq!(sample(TraceEvent::exit("app", "child", None, Some(1))), Some(0.5));
+        assert_eq!(sample(TraceEvent::exit("app", "kept", None, Some(2))), Some(0.5));
+        assert_eq!(sample(TraceEvent::enter("app", "dropped", None)), None);
+        assert_eq!(sample(TraceEvent::exit("app", "dropped", None, Some(1))), None);
+    }
+
+    #[test]
+    fn test_fixed_sampler_keeps_whole_calls() {
+        let mut sampler = FixedSampler::new(0.5);
+        let kept = (0..2000)
+            .filter(|_| {
+                let enter = sampler.sample(&TraceEvent::enter("app", "work", None));
+                assert_eq!(enter, sampler.sample(&TraceEvent::exit("app", "work", None, Some(1))));
+                enter == Some(0.5)
+            })
+            .count();
+        assert!((800..1200).contains(&kept), "{} calls kept", kept);
+        assert_eq!(FixedSampler::new(1.0).sample(&TraceEvent::enter("app", "work", None)), Some(1.0));
+    }
+
+    #[test]
+    fn test_adaptive_sampler_follows_rate() {
+        let mut sampler = AdaptiveSampler::new(100);
+        let start = sampler.window_start;
+        let call = |sampler: &mut AdaptiveSampler, at: Instant| {
+            let enter = sampler.sample_at(&TraceEvent::enter("app", "work", None), at);
+            let exit = sampler.sample_at(&TraceEvent::exit("app", "work", None, Some(1)), at);
+            assert_eq!(enter, exit);
+            enter
+        };
+
+        // A burst of 5000 events in the first second: cut off at the budget
+        let kept = (0..2500).filter(|_| call(&mut sampler, start).is_some()).count();
+        assert_eq!(kept, 50);
+
+        // The next second samples at 100 / 5000 per second
+        let next = start + WINDOW;
+        let rates: Vec<Option<f64>> = (0..2500).map(|_| call(&mut sampler, next)).collect();
+        assert!(rates.iter().flatten().all(|rate| (rate - 0.02).abs() < 1e-9));
+        assert!(rates.iter().flatten().count() <= 50);
+
+        // Quiet again (5000 events over 100s): full fidelity
+        assert_eq!(call(&mut sampler, next + WINDOW * 100), Some(1.0));
+    }
+
+    #[test]
+    fn test_adaptive_sampler_decides_per_trace() {
+        let mut sampler = AdaptiveSampler::new(1);
+        let start = sampler.window_start;
+        let in_trace = |context: &crate::TraceContext, event: TraceEvent| TraceEvent {
+            context: Some(context.clone()),
+            ..event
+        };
+
+        // The budget is spent by the first trace, whose events stay kept
+        let kept = crate::TraceContext::new_root();
+        let dropped = crate::TraceContext::new_root();
+        assert_eq!(sampler.sample_at(&in_trace(&kept, TraceEvent::enter("app", "a", None)), start), Some(1.0));
+        assert_eq!(sampler.sample_at(&in_trace(&dropped, TraceEvent::enter("app", "b", None)), start), None);
+        // Including events of the trace logged by another thread
+        let worker = TraceEvent {
+            thread: "worker".to_string(),
+            ..in_trace(&kept, TraceEvent::enter("app", "c", None))
+        };
+        assert_eq!(sampler.sample_at(&worker, start), Some(1.0));
+        assert_eq!(kept.sampled(), Some(true));
+        assert_eq!(dropped.sampled(), Some(false));
+    }
+}
@@ -0,0 +1,75 @@
+//! JSON Schema for trace events, generated from the serde model
+//!
+//! The generated schema is committed as `schema/trace-event.schema.json` so
+//! consumers of the Java and Node agents' logs can validate against the same
+//! contract, alongside `schema/agent-info.schema.json` for the record that
+//! opens each stream. Regenerate them with
+//! `FLOWTRACE_UPDATE_SCHEMA=1 cargo test --features schema schema`.
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject, SubschemaValidation};
+
+use crate::{AgentInfo, TraceEvent};
+
+/// Path of the committed schema, relative to the crate root
+pub const SCHEMA_FILE: &str = "schema/trace-event.schema.json";
+
+/// Path of the committed `AGENT_INFO` schema, relative to the crate root
+pub const AGENT_INFO_SCHEMA_FILE: &str = "schema/agent-info.schema.json";
+
+/// JSON Schema describing one line of a FlowTrace log
+pub fn event_schema() -> RootSchema {
+    schemars::schema_for!(TraceEvent)
+}
+
+/// JSON Schema describing the `AGENT_INFO` record
+pub fn agent_info_schema() -> RootSchema {
+    schemars::schema_for!(AgentInfo)
+}
+
+/// `timestamp` is epoch micros, epoch nanos or an RFC 3339 string
+pub(crate) fn timestamp_schema(_: &mut SchemaGenerator) -> Schema {
+    let epoch = SchemaObject {
+        instance_type: Some(InstanceType::Integer.into()),
+        format: Some("int64".to_string()),
+        ..Default::default()
+    };
+    let text = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        format: Some("date-time".to_string()),
+        ..Default::default()
+    };
+
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![epoch.into(), text.into()]),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_committed_schema_is_current() {
+        for (file, schema) in [(SCHEMA_FILE, event_schema()), (AGENT_INFO_SCHEMA_FILE, agent_info_schema())] {
+            let generated = serde_json::to_string_pretty(&schema).unwrap() + "\n";
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(file);
+
+            if std::env::var_os("FLOWTRACE_UPDATE_SCHEMA").is_some() {
+                std::fs::write(&path, &generated).unwrap();
+            }
+
+            let committed = std::fs::read_to_string(&path).unwrap_or_default();
+            assert!(
+                committed == generated,
+                "{} is out of date; rerun with FLOWTRACE_UPDATE_SCHEMA=1",
+                file
+            );
+        }
+    }
+}
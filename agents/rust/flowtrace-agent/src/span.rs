@@ -1,98 +1,322 @@
 //! Span API for manual tracing control
+//!
+//! Open spans are also tracked per thread, so code deep in the call stack can
+//! tag the innermost one, or start a child of it, through [`current_span`]
+//! instead of being passed the span.
 
-use std::time::Instant;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use serde::{Deserialize, Serialize};
 use crate::TraceEvent;
 
+thread_local! {
+    /// Spans started on this thread and not closed yet, innermost last
+    static ACTIVE: RefCell<Vec<Active>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Active {
+    module: String,
+    attributes: Weak<Mutex<Attributes>>,
+}
+
+/// The part of a span a [`SpanRef`] can change
+#[derive(Debug, Default)]
+struct Attributes {
+    tags: BTreeMap<String, String>,
+    error: Option<String>,
+    /// Set once the closing EXIT/EXCEPTION event has been logged
+    ended: bool,
+}
+
+fn lock(attributes: &Mutex<Attributes>) -> MutexGuard<'_, Attributes> {
+    attributes.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The innermost span open on this thread, if any
+///
+/// Only manual spans are tracked: `#[trace]` functions log their own events
+/// and are not returned. A span moved to another thread stays current on the
+/// thread that started it until it closes.
+pub fn current_span() -> Option<SpanRef> {
+    ACTIVE.with(|active| {
+        active.borrow().iter().rev().find_map(|span| {
+            let attributes = span.attributes.upgrade()?;
+            let open = !lock(&attributes).ended;
+            open.then(|| SpanRef {
+                module: span.module.clone(),
+                attributes,
+            })
+        })
+    })
+}
+
+/// A handle to an open span, returned by [`current_span`]
+///
+/// Changes made after the span closed are ignored.
+#[derive(Debug, Clone)]
+pub struct SpanRef {
+    module: String,
+    attributes: Arc<Mutex<Attributes>>,
+}
+
+impl SpanRef {
+    /// Add a tag to the span
+    pub fn set_tag(&self, key: impl Into<String>, value: impl ToString) -> &Self {
+        lock(&self.attributes).tags.insert(key.into(), value.to_string());
+        self
+    }
+
+    /// Mark the span as errored
+    pub fn set_error(&self, error: impl ToString) -> &Self {
+        lock(&self.attributes).error = Some(error.to_string());
+        self
+    }
+
+    /// Start a span nested in this one, see [`Span::child`]
+    pub fn child(&self, name: &str) -> Span {
+        child_of(&self.module, name)
+    }
+}
+
+/// Start `name` under the module of its parent, unless `name` has its own
+fn child_of(module: &str, name: &str) -> Span {
+    let builder = Span::builder(name);
+    if name.contains("::") {
+        builder.start()
+    } else {
+        builder.module(module).start()
+    }
+}
+
+/// Role of a span in its trace, following OpenTelemetry's span kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SpanKind {
+    /// Work inside the application, the default
+    Internal,
+    /// Handling a request from a remote caller
+    Server,
+    /// A request to a remote service, e.g. an HTTP call or a database query
+    Client,
+    /// Sending a message to be handled later, e.g. publishing to a queue
+    Producer,
+    /// Handling a message a producer sent
+    Consumer,
+}
+
+/// Attributes of a span known before it starts, see [`Span::builder`]
+#[derive(Debug, Clone)]
+#[must_use = "the span starts when `start` is called"]
+pub struct SpanBuilder {
+    module: String,
+    function: String,
+    kind: Option<SpanKind>,
+    tags: BTreeMap<String, String>,
+    attempt: Option<u32>,
+}
+
+impl SpanBuilder {
+    /// A builder for `function` in `module`, both taken as given
+    fn new(module: &str, function: &str) -> Self {
+        Self {
+            module: module.to_string(),
+            function: function.to_string(),
+            kind: None,
+            tags: BTreeMap::new(),
+            attempt: None,
+        }
+    }
+
+    /// Set the module the span is logged under, instead of the one in its name
+    pub fn module(mut self, module: impl Into<String>) -> Self {
+        self.module = module.into();
+        self
+    }
+
+    /// Set the role of the span in its trace
+    pub fn kind(mut self, kind: SpanKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Add a tag, recorded on the ENTER event as well as at exit
+    pub fn tag(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.tags.insert(key.into(), value.to_string());
+        self
+    }
+
+    /// Record which attempt of a retried operation the span covers (1-based)
+    pub fn attempt(mut self, attempt: u32) -> Self {
+        self.attempt = Some(attempt);
+        self
+    }
+
+    /// Log the ENTER event, with the kind and tags set so far, and start timing
+    pub fn start(self) -> Span {
+        let call_id = TraceEvent::next_call_id();
+        let mut enter = TraceEvent::enter(&self.module, &self.function, None).with_call_id(call_id);
+        enter.attempt = self.attempt;
+        enter.kind = self.kind;
+        if !self.tags.is_empty() {
+            enter.tags = Some(self.tags.clone());
+        }
+        crate::log_event(enter);
+
+        let attributes = Arc::new(Mutex::new(Attributes {
+            tags: self.tags,
+            ..Default::default()
+        }));
+        ACTIVE.with(|active| {
+            active.borrow_mut().push(Active {
+                module: self.module.clone(),
+                attributes: Arc::downgrade(&attributes),
+            })
+        });
+        Span {
+            module: self.module,
+            function: self.function,
+            start_time: crate::monotonic_micros(),
+            attributes,
+            attempt: self.attempt,
+            call_id,
+        }
+    }
+}
+
 /// A tracing span for timing and tagging operations
+///
+/// A span logs ENTER when it starts and exactly one closing event: at
+/// [`end`](Span::end), [`end_with_error`](Span::end_with_error) or
+/// [`abandon`](Span::abandon), or when it is dropped. Dropped without one of
+/// those, it closes with the error set so far (EXCEPTION) or without one
+/// (EXIT), and as an EXCEPTION when dropped by a panic unwinding.
 pub struct Span {
     module: String,
     function: String,
-    start_time: Instant,
-    tags: HashMap<String, String>,
-    error: Option<String>,
+    /// Start on the `monotonic_micros` clock
+    start_time: i64,
+    /// Tags, error and state, shared with the span's [`SpanRef`]s
+    attributes: Arc<Mutex<Attributes>>,
+    attempt: Option<u32>,
+    /// Ties the closing event to the ENTER, even when the span ends on another thread
+    call_id: u64,
 }
 
 impl Span {
     /// Create a new span
     pub fn new(module: &str, function: &str) -> Self {
-        // Log ENTER event
-        crate::log_event(TraceEvent::enter(module, function, None));
+        SpanBuilder::new(module, function).start()
+    }
 
-        Self {
-            module: module.to_string(),
-            function: function.to_string(),
-            start_time: Instant::now(),
-            tags: HashMap::new(),
-            error: None,
-        }
+    /// Describe a span to start later, e.g.
+    /// `Span::builder("http::get").kind(SpanKind::Client).tag("url", url).start()`
+    ///
+    /// A `::` in `name` separates the module from the function; without one,
+    /// the module is empty unless set with [`SpanBuilder::module`].
+    pub fn builder(name: &str) -> SpanBuilder {
+        let (module, function) = name.rsplit_once("::").unwrap_or(("", name));
+        SpanBuilder::new(module, function)
+    }
+
+    /// Start a span nested in this one
+    ///
+    /// Without a `::` in `name`, the child is logged under this span's module.
+    pub fn child(&self, name: &str) -> Span {
+        child_of(&self.module, name)
     }
 
     /// Add a tag to the span
     pub fn set_tag(&mut self, key: impl Into<String>, value: impl ToString) -> &mut Self {
-        self.tags.insert(key.into(), value.to_string());
+        lock(&self.attributes).tags.insert(key.into(), value.to_string());
         self
     }
 
     /// Mark the span as errored
     pub fn set_error(&mut self, error: impl ToString) -> &mut Self {
-        self.error = Some(error.to_string());
+        lock(&self.attributes).error = Some(error.to_string());
+        self
+    }
+
+    /// Record which attempt of a retried operation this span covers (1-based)
+    pub fn set_attempt(&mut self, attempt: u32) -> &mut Self {
+        self.attempt = Some(attempt);
         self
     }
 
     /// Get the duration of the span in microseconds
     pub fn duration_micros(&self) -> i64 {
-        self.start_time.elapsed().as_micros() as i64
+        crate::monotonic_micros() - self.start_time
     }
 
     /// End the span and log EXIT or EXCEPTION event
-    pub fn end(self) {
-        let duration_micros = self.duration_micros();
-
-        if let Some(error) = &self.error {
-            // Log EXCEPTION event
-            crate::log_event(TraceEvent::exception(
-                &self.module,
-                &self.function,
-                error,
-                Some(duration_micros),
-            ));
-        } else {
-            // Log EXIT event with tags as result
-            let result = if self.tags.is_empty() {
-                None
-            } else {
-                Some(format!("{:?}", self.tags))
-            };
+    pub fn end(mut self) {
+        self.finish();
+    }
+
+    /// End the span with `error`, logging EXCEPTION
+    pub fn end_with_error(mut self, error: impl ToString) {
+        self.set_error(error);
+        self.finish();
+    }
 
-            crate::log_event(TraceEvent::exit(
-                &self.module,
-                &self.function,
-                result,
-                Some(duration_micros),
-            ));
+    /// Close the span without an outcome, e.g. when its work was handed off or given up
+    ///
+    /// Logs EXIT with `"abandoned"` as the result, dropping the tags and any
+    /// error set, so the call still pairs with its ENTER.
+    pub fn abandon(mut self) {
+        {
+            let mut attributes = lock(&self.attributes);
+            attributes.error = None;
+            attributes.tags.clear();
         }
+        self.close(Some("abandoned".to_string()));
+    }
+
+    fn finish(&mut self) {
+        self.close(None);
+    }
+
+    /// Log the closing event with the span's tags, EXCEPTION if an error is
+    /// set, unless one was already logged
+    fn close(&mut self, result: Option<String>) {
+        let mut event = {
+            let mut attributes = lock(&self.attributes);
+            if attributes.ended {
+                return;
+            }
+            attributes.ended = true;
+            let duration_micros = self.duration_micros();
+
+            let mut event = match &attributes.error {
+                Some(error) => TraceEvent::exception(&self.module, &self.function, error, Some(duration_micros)),
+                None => TraceEvent::exit(&self.module, &self.function, result, Some(duration_micros)),
+            };
+            if !attributes.tags.is_empty() {
+                event.tags = Some(std::mem::take(&mut attributes.tags));
+            }
+            event
+        };
+        event.attempt = self.attempt;
+        event.call_id = Some(self.call_id);
+        // Spans closed on another thread than they started on are skipped by `current_span`
+        let _ = ACTIVE.try_with(|active| {
+            active
+                .borrow_mut()
+                .retain(|span| !std::ptr::eq(span.attributes.as_ptr(), Arc::as_ptr(&self.attributes)))
+        });
+        crate::log_event(event);
     }
 }
 
 impl Drop for Span {
     fn drop(&mut self) {
-        // If end() wasn't called explicitly, log EXIT automatically
-        if !std::thread::panicking() {
-            let duration = self.duration_micros();
-            let result = if self.tags.is_empty() {
-                None
-            } else {
-                Some(format!("{:?}", self.tags))
-            };
-
-            crate::log_event(TraceEvent::exit(
-                &self.module,
-                &self.function,
-                result,
-                Some(duration),
-            ));
+        // Not ended explicitly: an early return, or a panic unwinding through the span
+        if std::thread::panicking() {
+            lock(&self.attributes).error.get_or_insert_with(|| "panicked".to_string());
         }
+        self.finish();
     }
 }
 
@@ -101,10 +325,80 @@ pub fn start_span(module: &str, function: &str) -> Span {
     Span::new(module, function)
 }
 
+/// Run `op` until it succeeds or `max_attempts` is reached, tracing each attempt
+///
+/// Each attempt is a child span carrying its 1-based `attempt` number and
+/// ending in EXIT or EXCEPTION. The enclosing span records the final outcome
+/// and, as its `attempt`, how many attempts were made.
+pub fn retry<T, E: std::fmt::Debug>(
+    module: &str,
+    function: &str,
+    max_attempts: u32,
+    mut op: impl FnMut(u32) -> Result<T, E>,
+) -> Result<T, E> {
+    let mut outer = Span::new(module, function);
+    let mut attempt = 1;
+
+    loop {
+        let mut span = SpanBuilder::new(module, function).attempt(attempt).start();
+        let result = op(attempt);
+        if let Err(error) = &result {
+            span.set_error(format!("{:?}", error));
+        }
+        span.end();
+
+        if result.is_ok() || attempt >= max_attempts {
+            outer.set_attempt(attempt);
+            if let Err(error) = &result {
+                outer.set_error(format!("{:?}", error));
+            }
+            outer.end();
+            return result;
+        }
+        attempt += 1;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Events logged while running `f`, from a log file named after `name`
+    fn logged(name: &str, f: impl FnOnce()) -> Vec<TraceEvent> {
+        let path = std::env::temp_dir().join(format!("flowtrace_span_{}.jsonl", name));
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                ..Default::default()
+            })
+            .unwrap();
+        crate::with_tracer(&tracer, f);
+        tracer.stop();
+
+        let events = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        events
+    }
+
+    /// Type and result or exception of each closing event
+    fn closes(events: &[TraceEvent]) -> Vec<(&'static str, Option<String>)> {
+        events
+            .iter()
+            .filter_map(|event| match event.event_type {
+                crate::EventType::Enter => None,
+                crate::EventType::Exit => Some(("EXIT", event.result.clone())),
+                crate::EventType::Exception => Some(("EXCEPTION", event.exception.clone())),
+            })
+            .collect()
+    }
+
     #[test]
     fn test_span_creation() {
         let span = Span::new("test_module", "test_function");
@@ -118,14 +412,252 @@ mod tests {
         span.set_tag("user_id", 123)
             .set_tag("action", "login");
 
-        assert_eq!(span.tags.get("user_id").unwrap(), "123");
-        assert_eq!(span.tags.get("action").unwrap(), "login");
+        let attributes = lock(&span.attributes);
+        assert_eq!(attributes.tags.get("user_id").unwrap(), "123");
+        assert_eq!(attributes.tags.get("action").unwrap(), "login");
+    }
+
+    #[test]
+    fn test_span_builder() {
+        let span = Span::builder("http::client::get").start();
+        assert_eq!((span.module.as_str(), span.function.as_str()), ("http::client", "get"));
+        let span = Span::builder("get").module("http").attempt(2).start();
+        assert_eq!((span.module.as_str(), span.function.as_str()), ("http", "get"));
+        assert_eq!(span.attempt, Some(2));
+        // Only `builder` splits the name; `new` takes the function as given
+        let span = Span::new("app", "Order::total");
+        assert_eq!((span.module.as_str(), span.function.as_str()), ("app", "Order::total"));
+    }
+
+    #[test]
+    fn test_builder_records_tags_on_enter() {
+        let events = logged("builder", || {
+            let span = Span::builder("http::get")
+                .kind(SpanKind::Client)
+                .tag("url", "https://example.com")
+                .tag("retries", 0)
+                .start();
+            span.end();
+        });
+
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].module.as_str(), events[0].function.as_str()), ("http", "get"));
+        assert_eq!(events[0].kind, Some(SpanKind::Client));
+        let tags = events[0].tags.as_ref().unwrap();
+        assert_eq!(tags.get("url").map(String::as_str), Some("https://example.com"));
+        assert_eq!(tags.get("retries").map(String::as_str), Some("0"));
+        assert!(events[1].kind.is_none());
+    }
+
+    #[test]
+    fn test_span_attempt() {
+        let mut span = Span::new("test", "func");
+        span.set_attempt(3);
+        assert_eq!(span.attempt, Some(3));
+    }
+
+    #[test]
+    fn test_retry_records_attempts() {
+        let path = std::env::temp_dir().join("flowtrace_span_retry.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = crate::Tracer::new();
+        tracer
+            .start(crate::Config {
+                log_file: path.display().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let result: Result<u32, &str> = crate::with_tracer(&tracer, || {
+            retry("test", "Client::fetch", 5, |attempt| if attempt < 3 { Err("timeout") } else { Ok(attempt) })
+        });
+        tracer.stop();
+        assert_eq!(result, Ok(3));
+
+        let events: Vec<TraceEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .skip(1) // AGENT_INFO
+            .map(|line| TraceEvent::from_json_line(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        // Outer ENTER, three ENTER/close pairs, outer EXIT
+        assert_eq!(events.len(), 8);
+        let closes: Vec<(Option<u32>, Option<crate::Status>)> = events
+            .iter()
+            .filter(|event| !matches!(event.event_type, crate::EventType::Enter))
+            .map(|event| (event.attempt, event.status))
+            .collect();
+        let (ok, error) = (Some(crate::Status::Ok), Some(crate::Status::Error));
+        assert_eq!(closes, vec![(Some(1), error), (Some(2), error), (Some(3), ok), (Some(3), ok)]);
+        assert!(events.iter().all(|event| event.function == "Client::fetch"));
     }
 
     #[test]
     fn test_span_error() {
         let mut span = Span::new("test", "func");
         span.set_error("Something went wrong");
-        assert!(span.error.is_some());
+        assert!(lock(&span.attributes).error.is_some());
+    }
+
+    #[test]
+    fn test_span_closes_once() {
+        let events = logged("closes_once", || {
+            // Stored in an Option, ended through it and then dropped with it
+            let mut slot = Some(Span::new("test", "stored"));
+            if let Some(span) = slot.take() {
+                span.end_with_error("failed");
+            }
+            drop(slot);
+
+            Span::new("test", "dropped").set_tag("k", "v").set_error("ignored");
+            let mut span = Span::new("test", "handed_off");
+            span.set_tag("k", "v").set_error("ignored");
+            span.abandon();
+        });
+
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            closes(&events),
+            vec![
+                ("EXCEPTION", Some("failed".to_string())),
+                ("EXCEPTION", Some("ignored".to_string())),
+                ("EXIT", Some("abandoned".to_string())),
+            ]
+        );
+        assert!(events[5].tags.is_none());
+    }
+
+    #[test]
+    fn test_span_early_return() {
+        fn charge(amount: u32) -> Result<u32, String> {
+            let mut span = Span::new("test", "charge");
+            if amount == 0 {
+                span.set_error("empty charge");
+                return Err("empty charge".to_string());
+            }
+            span.set_tag("amount", amount);
+            Ok(amount)
+        }
+
+        let events = logged("early_return", || {
+            let _ = charge(0);
+            let _ = charge(5);
+        });
+        assert_eq!(
+            closes(&events),
+            vec![
+                ("EXCEPTION", Some("empty charge".to_string())),
+                ("EXIT", None),
+            ]
+        );
+        assert_eq!(events[3].tags.as_ref().and_then(|tags| tags.get("amount")).map(String::as_str), Some("5"));
+    }
+
+    #[test]
+    fn test_tags_are_a_json_object() {
+        let events = logged("tags_json", || {
+            let mut span = Span::builder("db::query").tag("table", "orders").start();
+            span.set_tag("rows", 3);
+            span.end();
+            Span::new("db", "insert").set_tag("table", "orders").set_error("duplicate key");
+        });
+
+        let json: Vec<serde_json::Value> = events
+            .iter()
+            .map(|event| serde_json::from_str(&event.to_json(crate::TimestampFormat::EpochMicros).unwrap()).unwrap())
+            .collect();
+        assert_eq!(json[0]["tags"], serde_json::json!({ "table": "orders" }));
+        assert_eq!(json[1]["tags"], serde_json::json!({ "rows": "3", "table": "orders" }));
+        assert!(json[1].get("result").is_none());
+        assert_eq!(json[2].get("tags"), None);
+        assert_eq!(json[3]["tags"], serde_json::json!({ "table": "orders" }));
+        assert_eq!(json[3]["exception"], "duplicate key");
+    }
+
+    #[test]
+    fn test_span_panic() {
+        let events = logged("panic", || {
+            let result = std::panic::catch_unwind(|| {
+                let _outer = Span::new("test", "outer");
+                let mut inner = Span::new("test", "inner");
+                inner.set_error("bad input");
+                panic!("boom");
+            });
+            assert!(result.is_err());
+        });
+        assert_eq!(
+            closes(&events),
+            vec![
+                ("EXCEPTION", Some("bad input".to_string())),
+                ("EXCEPTION", Some("panicked".to_string())),
+            ]
+        );
+        assert!(events.iter().all(|event| event.trace_id == events[0].trace_id));
+    }
+
+    #[test]
+    fn test_current_span() {
+        fn lookup(id: u32) -> Result<(), String> {
+            let span = current_span().expect("called inside a span");
+            span.set_tag("user_id", id);
+            let mut query = span.child("query");
+            query.set_tag("table", "users");
+            if id == 0 {
+                current_span().unwrap().set_error("no such user");
+            }
+            query.end();
+            Ok(())
+        }
+
+        fn handle(id: u32) -> Result<(), String> {
+            let _span = Span::new("users", "handle");
+            lookup(id)
+        }
+
+        assert!(current_span().is_none());
+        let events = logged("current", || {
+            let _ = handle(7);
+            let _ = handle(0);
+            assert!(current_span().is_none());
+        });
+
+        let calls: Vec<(&str, &str, &str)> = events
+            .iter()
+            .map(|event| {
+                let kind = match event.event_type {
+                    crate::EventType::Enter => "ENTER",
+                    crate::EventType::Exit => "EXIT",
+                    crate::EventType::Exception => "EXCEPTION",
+                };
+                (kind, event.module.as_str(), event.function.as_str())
+            })
+            .collect();
+        assert_eq!(
+            calls[..4],
+            [("ENTER", "users", "handle"), ("ENTER", "users", "query"), ("EXIT", "users", "query"), ("EXIT", "users", "handle")]
+        );
+        assert_eq!(events[3].tags.as_ref().unwrap().get("user_id").map(String::as_str), Some("7"));
+        // The error was set on the query, the innermost span at the time
+        assert_eq!(calls[6], ("EXCEPTION", "users", "query"));
+        assert_eq!(calls[7], ("EXIT", "users", "handle"));
+    }
+
+    #[test]
+    fn test_span_ref_outlives_span() {
+        let events = logged("span_ref", || {
+            let parent = Span::new("jobs", "run");
+            let child = parent.child("billing::charge");
+            let current = current_span().unwrap();
+            child.end();
+            assert_eq!(current_span().unwrap().module, "jobs");
+            parent.end();
+            // Closed spans ignore their handles
+            current.set_tag("late", true);
+            assert!(current_span().is_none());
+        });
+        assert_eq!((events[1].module.as_str(), events[1].function.as_str()), ("billing", "charge"));
+        assert!(events[2].tags.is_none());
     }
 }
@@ -0,0 +1,203 @@
+//! On-disk spool of records a network exporter could not deliver
+//!
+//! Records are appended to segment files named by ULID in the spool
+//! directory, so they sort oldest first. Once the collector answers again,
+//! closed segments are replayed ahead of new records and deleted as they are
+//! sent; segments left by an earlier run of the process are replayed too.
+//! The directory never grows past its size cap: the oldest segments are
+//! deleted (and their records counted as dropped) to make room.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Size at which the segment being written is closed
+const SEGMENT_BYTES: u64 = 1024 * 1024;
+const SEGMENT_EXTENSION: &str = "jsonl";
+
+struct Segment {
+    path: PathBuf,
+    bytes: u64,
+    records: u64,
+}
+
+struct State {
+    /// Closed segments, oldest first
+    closed: Vec<Segment>,
+    /// The segment being appended to
+    current: Option<(Segment, File)>,
+    total_bytes: u64,
+}
+
+/// A size-capped directory of undelivered records
+pub(crate) struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+impl Spool {
+    /// Open (creating if needed) the spool in `dir`, picking up segments already there
+    pub(crate) fn open(dir: impl AsRef<Path>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut closed = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == SEGMENT_EXTENSION) {
+                let bytes = fs::metadata(&path)?.len();
+                let records = BufReader::new(File::open(&path)?).lines().count() as u64;
+                closed.push(Segment { path, bytes, records });
+            }
+        }
+        closed.sort_by(|a, b| a.path.cmp(&b.path));
+        let total_bytes = closed.iter().map(|segment| segment.bytes).sum();
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(State {
+                closed,
+                current: None,
+                total_bytes,
+            }),
+        })
+    }
+
+    /// Append one record
+    ///
+    /// Returns whether it was written, and how many older records were
+    /// deleted to make room for it.
+    pub(crate) fn append(&self, json: &str) -> (bool, u64) {
+        let Ok(mut state) = self.state.lock() else {
+            return (false, 0);
+        };
+        let line = format!("{}\n", json);
+        let bytes = line.len() as u64;
+
+        let mut evicted = 0;
+        while state.total_bytes + bytes > self.max_bytes {
+            if state.closed.is_empty() {
+                if state.current.is_none() {
+                    return (false, evicted);
+                }
+                state.close_current();
+            }
+            let oldest = state.closed.remove(0);
+            let _ = fs::remove_file(&oldest.path);
+            state.total_bytes -= oldest.bytes;
+            evicted += oldest.records;
+        }
+
+        if state.current.as_ref().is_some_and(|(segment, _)| segment.bytes + bytes > SEGMENT_BYTES) {
+            state.close_current();
+        }
+        if state.current.is_none() {
+            let path = self.dir.join(format!("{}.{}", crate::ulid::generate(), SEGMENT_EXTENSION));
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => state.current = Some((Segment { path, bytes: 0, records: 0 }, file)),
+                Err(_) => return (false, evicted),
+            }
+        }
+
+        let (segment, file) = state.current.as_mut().expect("segment opened above");
+        if file.write_all(line.as_bytes()).is_err() {
+            return (false, evicted);
+        }
+        segment.bytes += bytes;
+        segment.records += 1;
+        state.total_bytes += bytes;
+        (true, evicted)
+    }
+
+    /// Close the current segment and take the oldest one for replay
+    pub(crate) fn take_oldest(&self) -> Option<PathBuf> {
+        let mut state = self.state.lock().ok()?;
+        state.close_current();
+        (!state.closed.is_empty()).then(|| state.closed[0].path.clone())
+    }
+
+    /// Delete a segment returned by [`take_oldest`](Self::take_oldest) once it has been sent
+    pub(crate) fn remove(&self, path: &Path) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(index) = state.closed.iter().position(|segment| segment.path == path) {
+                let segment = state.closed.remove(index);
+                state.total_bytes -= segment.bytes;
+            }
+        }
+        let _ = fs::remove_file(path);
+    }
+
+    /// Bytes currently spooled
+    pub(crate) fn bytes(&self) -> u64 {
+        self.state.lock().map(|state| state.total_bytes).unwrap_or(0)
+    }
+}
+
+impl State {
+    fn close_current(&mut self) {
+        if let Some((segment, mut file)) = self.current.take() {
+            let _ = file.flush();
+            self.closed.push(segment);
+        }
+    }
+}
+
+/// Read the records of a spooled segment
+pub(crate) fn read_segment(path: &Path) -> Vec<String> {
+    File::open(path)
+        .map(|file| {
+            BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_spool_replays_across_reopen() {
+        let dir = spool_dir("flowtrace_spool_reopen");
+        let spool = Spool::open(&dir, 1024).unwrap();
+        assert_eq!(spool.append("one"), (true, 0));
+        assert_eq!(spool.append("two"), (true, 0));
+        drop(spool);
+
+        let spool = Spool::open(&dir, 1024).unwrap();
+        assert_eq!(spool.bytes(), 8);
+        let segment = spool.take_oldest().unwrap();
+        assert_eq!(read_segment(&segment), ["one", "two"]);
+        spool.remove(&segment);
+        assert!(spool.take_oldest().is_none());
+        assert_eq!(spool.bytes(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_drops_oldest_over_cap() {
+        let dir = spool_dir("flowtrace_spool_cap");
+        let spool = Spool::open(&dir, 10).unwrap();
+        assert_eq!(spool.append("old1"), (true, 0));
+        assert_eq!(spool.append("old2"), (true, 0));
+        assert_eq!(spool.append("new"), (true, 2));
+        let segment = spool.take_oldest().unwrap();
+        assert_eq!(read_segment(&segment), ["new"]);
+
+        assert_eq!(spool.append("far too long"), (false, 1));
+        assert_eq!(spool.bytes(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+//! Tracer handles owning a logger and its configuration
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::runtime::{Runtime, ThreadRuntime};
+use crate::{Config, FlowTraceError, Logger, Mode, TraceEvent};
+
+type SharedLogger = Arc<Mutex<Logger>>;
+
+thread_local! {
+    /// Loggers installed by `with_tracer` on this thread, innermost last
+    static OVERRIDES: RefCell<Vec<Option<SharedLogger>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An independent tracer with its own configuration and log sink
+///
+/// `start_tracing` and `log_event` use a built-in global tracer. Libraries
+/// embedded in a host application can declare their own instead and point
+/// `#[trace(tracer = MY_TRACER)]` at it:
+///
+/// ```rust
+/// use flowtrace_agent::{trace, Config, Tracer};
+///
+/// static MY_TRACER: Tracer = Tracer::new();
+///
+/// #[trace(tracer = MY_TRACER)]
+/// fn parse(input: &str) -> usize {
+///     input.len()
+/// }
+///
+/// MY_TRACER.start(Config { log_file: String::new(), ..Default::default() }).unwrap();
+/// parse("abc");
+/// MY_TRACER.stop();
+/// ```
+pub struct Tracer {
+    logger: RwLock<Option<SharedLogger>>,
+}
+
+impl Tracer {
+    /// Create a stopped tracer; usable in a `static`
+    pub const fn new() -> Self {
+        Self {
+            logger: RwLock::new(None),
+        }
+    }
+
+    /// Start logging events with `config`
+    ///
+    /// With `Mode::Off` nothing is started and the tracer stays stopped.
+    /// Otherwise the configuration is checked first (see [`Config::validate`]).
+    pub fn start(&self, config: Config) -> Result<(), FlowTraceError> {
+        let mut tracer = self.logger.write().unwrap_or_else(PoisonError::into_inner);
+        if tracer.is_some() {
+            return Err(FlowTraceError::AlreadyInitialized);
+        }
+        if config.mode == Mode::Off {
+            return Ok(());
+        }
+        config.validate()?;
+        let flush_interval = config.flush_interval_ms.filter(|&millis| millis > 0).map(Duration::from_millis);
+        let runtime = config.runtime.clone();
+        let logger = Arc::new(Mutex::new(Logger::new(config)?));
+        // Prime the monotonic clock's epoch before the flusher starts
+        crate::monotonic_micros();
+        if let Some(period) = flush_interval {
+            start_flusher(&logger, runtime, period);
+        }
+        *tracer = Some(logger);
+        Ok(())
+    }
+
+    /// Stop logging and close the log file
+    pub fn stop(&self) {
+        if let Ok(mut tracer) = self.logger.write() {
+            *tracer = None;
+        }
+    }
+
+    /// Whether the tracer has been started
+    pub fn is_active(&self) -> bool {
+        self.logger.read().is_ok_and(|tracer| tracer.is_some())
+    }
+
+    /// Log a trace event; dropped while the tracer is stopped
+    pub fn log(&self, event: TraceEvent) {
+        if let Ok(tracer) = self.logger.read() {
+            if let Some(logger) = tracer.as_ref() {
+                write(logger, event);
+            }
+        }
+    }
+
+    /// Leave raw events out, keeping only `AGGREGATE` records, or write them again
+    pub fn set_aggregate_only(&self, aggregate_only: bool) {
+        if let Ok(tracer) = self.logger.read() {
+            if let Some(Ok(mut logger)) = tracer.as_ref().map(|logger| logger.lock()) {
+                logger.set_aggregate_only(aggregate_only);
+            }
+        }
+    }
+
+    /// Record events for `window` from now, in `Mode::OnDemand`
+    ///
+    /// A window already open is extended, never cut short. Other modes are
+    /// unaffected.
+    pub fn record_for(&self, window: Duration) {
+        if let Ok(tracer) = self.logger.read() {
+            if let Some(Ok(mut logger)) = tracer.as_ref().map(|logger| logger.lock()) {
+                logger.record_for(window);
+            }
+        }
+    }
+
+    /// Flush events written so far
+    pub fn flush(&self) {
+        if let Ok(tracer) = self.logger.read() {
+            if let Some(Ok(mut logger)) = tracer.as_ref().map(|logger| logger.lock()) {
+                logger.flush();
+            }
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tick `logger` every `period` until it is stopped
+fn start_flusher(logger: &SharedLogger, runtime: Option<Arc<dyn Runtime>>, period: Duration) {
+    let logger = Arc::downgrade(logger);
+    let runtime = runtime.unwrap_or_else(|| Arc::new(ThreadRuntime));
+    runtime.spawn_interval(
+        period,
+        Box::new(move || {
+            let Some(logger) = logger.upgrade() else {
+                return false;
+            };
+            if let Ok(mut logger) = logger.lock() {
+                logger.tick();
+            }
+            true
+        }),
+    );
+}
+
+fn write(logger: &SharedLogger, mut event: TraceEvent) {
+    crate::context::stamp(&mut event);
+    crate::io::stamp(&mut event);
+    crate::capture::record(&event);
+    if let Ok(mut logger) = logger.lock() {
+        logger.log(event);
+    }
+}
+
+/// Run `f` with this thread's default events sent to `tracer`
+///
+/// Everything that would go to the global tracer (`log_event`, plain
+/// `#[trace]`, spans) on the current thread is logged to `tracer` instead
+/// until `f` returns, so parallel tests each capture only their own events.
+/// Functions using `#[trace(tracer = ...)]` keep their explicit tracer, and
+/// work moved to other threads is not covered, except tasks wrapped with
+/// [`pool::traced_task`](crate::pool::traced_task).
+///
+/// ```rust
+/// use flowtrace_agent::{trace, with_tracer, Config, Tracer};
+///
+/// #[trace]
+/// fn work() {}
+///
+/// let tracer = Tracer::new();
+/// tracer.start(Config { log_file: String::new(), ..Default::default() }).unwrap();
+/// with_tracer(&tracer, || work());
+/// ```
+pub fn with_tracer<R>(tracer: &Tracer, f: impl FnOnce() -> R) -> R {
+    let logger = tracer.logger.read().ok().and_then(|logger| logger.clone());
+    with_logger(logger, f)
+}
+
+/// Wrap `f` to run under the calling thread's `with_tracer` override, if any, wherever it runs
+pub(crate) fn bind_override<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let logger = OVERRIDES.with(|overrides| overrides.borrow().last().cloned());
+    move || match logger {
+        Some(logger) => with_logger(logger, f),
+        None => f(),
+    }
+}
+
+fn with_logger<R>(logger: Option<SharedLogger>, f: impl FnOnce() -> R) -> R {
+    struct Restore;
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            OVERRIDES.with(|overrides| overrides.borrow_mut().pop());
+        }
+    }
+
+    OVERRIDES.with(|overrides| overrides.borrow_mut().push(logger));
+    let _restore = Restore;
+    f()
+}
+
+/// Log `event` to this thread's `with_tracer` override, handing it back when there is none
+pub(crate) fn log_override(event: TraceEvent) -> Option<TraceEvent> {
+    OVERRIDES.with(|overrides| match overrides.borrow().last() {
+        Some(Some(logger)) => {
+            write(logger, event);
+            None
+        }
+        // Overridden by a stopped tracer: drop the event
+        Some(None) => None,
+        None => Some(event),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracers_are_independent() {
+        let first_file = std::env::temp_dir().join("flowtrace_tracer_first.jsonl");
+        let second_file = std::env::temp_dir().join("flowtrace_tracer_second.jsonl");
+        let _ = std::fs::remove_file(&first_file);
+        let _ = std::fs::remove_file(&second_file);
+
+        let first = Tracer::new();
+        let second = Tracer::new();
+        let config = |path: &std::path::Path| Config {
+            log_file: path.display().to_string(),
+            ..Default::default()
+        };
+        first.start(config(&first_file)).unwrap();
+        second.start(config(&second_file)).unwrap();
+        assert!(matches!(first.start(Config::default()), Err(FlowTraceError::AlreadyInitialized)));
+
+        first.log(TraceEvent::enter("app", "a", None));
+        second.log(TraceEvent::enter("app", "b", None));
+        second.stop();
+        second.log(TraceEvent::enter("app", "dropped", None));
+        first.stop();
+        assert!(!first.is_active());
+
+        let first_log = std::fs::read_to_string(&first_file).unwrap();
+        let second_log = std::fs::read_to_string(&second_file).unwrap();
+        assert_eq!(first_log.lines().count(), 2);
+        assert!(crate::AgentInfo::from_json_line(first_log.lines().next().unwrap()).is_ok());
+        assert!(first_log.contains(r#""method":"a""#));
+        assert_eq!(second_log.lines().count(), 2);
+        assert!(second_log.contains(r#""method":"b""#));
+
+        std::fs::remove_file(first_file).unwrap();
+        std::fs::remove_file(second_file).unwrap();
+    }
+
+    #[test]
+    fn test_start_errors() {
+        let tracer = Tracer::new();
+        let error = tracer
+            .start(Config {
+                sample_rate: Some(2.0),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(error, FlowTraceError::Config(crate::ConfigError::InvalidSampleRate(_))));
+        assert!(std::error::Error::source(&error).is_some());
+
+        #[cfg(feature = "websocket")]
+        {
+            let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let error = tracer
+                .start(Config {
+                    log_file: String::new(),
+                    websocket_addr: Some(taken.local_addr().unwrap().to_string()),
+                    ..Default::default()
+                })
+                .unwrap_err();
+            assert!(matches!(error, FlowTraceError::Exporter { exporter: "websocket", .. }), "{}", error);
+        }
+        assert!(!tracer.is_active());
+    }
+
+    #[test]
+    fn test_modes() {
+        let path = std::env::temp_dir().join("flowtrace_tracer_modes.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let config = |mode| Config {
+            mode,
+            log_file: path.display().to_string(),
+            ..Default::default()
+        };
+
+        let tracer = Tracer::new();
+        tracer.start(config(Mode::Off)).unwrap();
+        assert!(!tracer.is_active());
+        assert!(!path.exists());
+
+        // The sinks are opened, but nothing is written to them
+        tracer.start(config(Mode::Dry)).unwrap();
+        tracer.log(TraceEvent::enter("app", "work", None));
+        tracer.log(TraceEvent::exit("app", "work", None, Some(1)));
+        let counted = tracer.logger.read().unwrap().as_ref().unwrap().lock().unwrap().dry_run_events();
+        tracer.stop();
+        assert_eq!(counted, 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("dry".parse(), Ok(Mode::Dry));
+    }
+
+    #[test]
+    fn test_flush_interval_writes_due_records() {
+        type Tick = Box<dyn FnMut() -> bool + Send>;
+
+        /// Keeps the timer's tick for the test to call
+        struct Manual(Arc<Mutex<Option<Tick>>>);
+
+        impl std::fmt::Debug for Manual {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("Manual")
+            }
+        }
+
+        impl Runtime for Manual {
+            fn spawn_interval(&self, _period: Duration, tick: Tick) {
+                *self.0.lock().unwrap() = Some(tick);
+            }
+        }
+
+        let path = std::env::temp_dir().join("flowtrace_tracer_flush.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tick = Arc::new(Mutex::new(None));
+        let tracer = Tracer::new();
+        tracer
+            .start(Config {
+                log_file: path.display().to_string(),
+                agent_info: false,
+                aggregate_interval_secs: Some(1),
+                flush_interval_ms: Some(100),
+                runtime: Some(Arc::new(Manual(tick.clone()))),
+                ..Default::default()
+            })
+            .unwrap();
+        tracer.log(TraceEvent::enter("app", "work", None));
+        tracer.log(TraceEvent::exit("app", "work", None, Some(1)));
+
+        let mut tick = tick.lock().unwrap().take().unwrap();
+        assert!(tick());
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(crate::AGGREGATE));
+        std::thread::sleep(Duration::from_millis(1050));
+        assert!(tick());
+        // Written by the tick, with no event logged after the interval ended
+        assert!(std::fs::read_to_string(&path).unwrap().contains(crate::AGGREGATE));
+        tracer.stop();
+        assert!(!tick());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_with_tracer_isolates_threads() {
+        let handles: Vec<_> = ["one", "two"]
+            .into_iter()
+            .map(|name| {
+                std::thread::spawn(move || {
+                    let path = std::env::temp_dir().join(format!("flowtrace_override_{}.jsonl", name));
+                    let _ = std::fs::remove_file(&path);
+                    let tracer = Tracer::new();
+                    tracer
+                        .start(Config {
+                            log_file: path.display().to_string(),
+                            ..Default::default()
+                        })
+                        .unwrap();
+
+                    with_tracer(&tracer, || {
+                        crate::log_event(TraceEvent::enter("app", name, None));
+                        with_tracer(&Tracer::new(), || crate::log_event(TraceEvent::enter("app", "muted", None)));
+                        crate::log_event(TraceEvent::exit("app", name, None, Some(1)));
+                    });
+                    tracer.stop();
+
+                    let log = std::fs::read_to_string(&path).unwrap();
+                    std::fs::remove_file(&path).unwrap();
+                    (name, log)
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let (name, log) = handle.join().unwrap();
+            assert_eq!(log.lines().count(), 3);
+            assert!(log.lines().skip(1).all(|line| line.contains(&format!(r#""method":"{}""#, name))));
+        }
+        assert!(log_override(TraceEvent::enter("app", "after", None)).is_some());
+    }
+
+    #[test]
+    fn test_trace_context_follows_work_and_sampling() {
+        let path = std::env::temp_dir().join("flowtrace_tracer_context.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let tracer = Tracer::new();
+        tracer
+            .start(Config {
+                log_file: path.display().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        tracer.log(TraceEvent::enter("app", "root", None));
+        std::thread::scope(|scope| {
+            scope.spawn(crate::bind(|| tracer.log(TraceEvent::enter("app", "worker", None))));
+        });
+        tracer.log(TraceEvent::exit("app", "root", None, Some(1)));
+
+        let unsampled = crate::TraceContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").unwrap();
+        unsampled.scope(|| tracer.log(TraceEvent::enter("app", "dropped", None)));
+        tracer.stop();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<TraceEvent> = log.lines().skip(1).map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].trace_id.is_some());
+        assert!(events.iter().all(|event| event.trace_id == events[0].trace_id));
+    }
+}
@@ -0,0 +1,100 @@
+//! Cheap ULID generation for event identifiers
+//!
+//! A ULID is a 48-bit millisecond timestamp followed by 80 random bits,
+//! written as 26 Crockford base32 characters. IDs sort by creation time,
+//! which keeps deduplication indexes compact downstream.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+thread_local! {
+    /// Generator state, and the fork generation it was seeded in
+    static RNG: Cell<(u64, u64)> = Cell::new((seed(), crate::fork::generation()));
+}
+
+/// Generate a new ULID string
+pub fn generate() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let random = RNG.with(|rng| {
+        let high = next(rng) & 0xFFFF;
+        let low = next(rng);
+        ((high as u128) << 64) | low as u128
+    });
+
+    encode(millis, random)
+}
+
+/// Random 64 bits from the same per-thread generator
+pub(crate) fn random_u64() -> u64 {
+    RNG.with(next)
+}
+
+/// Uniform random number in `[0, 1)`
+pub(crate) fn random_fraction() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Encode a timestamp and 80 random bits as a 26 character ULID
+fn encode(millis: u64, random: u128) -> String {
+    let value = ((millis as u128 & 0xFFFF_FFFF_FFFF) << 80) | (random & ((1 << 80) - 1));
+
+    (0..26)
+        .rev()
+        .map(|index| ALPHABET[((value >> (index * 5)) & 0x1F) as usize] as char)
+        .collect()
+}
+
+/// Per-thread seed from the std hasher's random keys
+fn seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.write_u64(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0),
+    );
+    hasher.finish() | 1
+}
+
+/// xorshift64* step
+fn next(state: &Cell<(u64, u64)>) -> u64 {
+    let (mut x, mut generation) = state.get();
+    // A forked child would otherwise repeat its parent's IDs
+    if generation != crate::fork::generation() {
+        x = seed();
+        generation = crate::fork::generation();
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.set((x, generation));
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(0, 0), "00000000000000000000000000");
+        assert_eq!(encode(1_469_922_850_259, 0), "01ARZ3NDEK0000000000000000");
+        assert_eq!(encode(0, u128::MAX), "0000000000ZZZZZZZZZZZZZZZZ");
+    }
+
+    #[test]
+    fn test_generate_unique() {
+        let ids: std::collections::HashSet<String> = (0..1000).map(|_| generate()).collect();
+        assert_eq!(ids.len(), 1000);
+        assert!(ids.iter().all(|id| id.len() == 26));
+    }
+}
@@ -0,0 +1,227 @@
+//! Live event streaming to WebSocket clients
+//!
+//! With `Config::websocket_addr` set, the logger also serves every event to
+//! connected clients as JSON text messages, opening each connection with an
+//! `AGENT_INFO` record. Clients choose what they receive in the query string
+//! of the URL they connect to:
+//!
+//! ```text
+//! ws://127.0.0.1:7878/?module=billing&module=auth&level=warn
+//! ```
+//!
+//! `module` may repeat and matches like `Config::modules`; `level` is the
+//! minimum severity, where events without one count as INFO and exceptions
+//! as ERROR. A client that falls too far behind misses events rather than
+//! slowing down the traced program.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::Message;
+
+use crate::{AgentInfo, Level, TimestampFormat, TraceEvent};
+
+/// Events buffered per client before new ones are dropped for it
+const CLIENT_BUFFER: usize = 1024;
+
+/// Which events a client asked for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamFilter {
+    /// Only events from these modules (and their submodules); all if empty
+    pub modules: Vec<String>,
+    /// Only events at this severity or above
+    pub min_level: Option<Level>,
+}
+
+impl StreamFilter {
+    /// Read `module` and `level` parameters from a URL query string
+    ///
+    /// Unknown parameters and unparsable levels are ignored.
+    pub fn from_query(query: &str) -> Self {
+        let mut filter = Self::default();
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "module" if !value.is_empty() => filter.modules.push(value.to_string()),
+                "level" => filter.min_level = value.parse().ok(),
+                _ => {}
+            }
+        }
+        filter
+    }
+
+    /// Whether `event` passes the filter
+    pub fn matches(&self, event: &TraceEvent) -> bool {
+        let level = event.effective_level();
+
+        let matches = |module: &String| crate::config::module_matches(&event.module, module);
+
+        (self.modules.is_empty() || self.modules.iter().any(matches))
+            && self.min_level.is_none_or(|min_level| level >= min_level)
+    }
+}
+
+struct Client {
+    filter: StreamFilter,
+    sender: SyncSender<String>,
+}
+
+/// A WebSocket server streaming events to its connected clients
+pub struct WebSocketSink {
+    local_addr: SocketAddr,
+    clients: Arc<Mutex<Vec<Client>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl WebSocketSink {
+    /// Listen on `addr` (e.g. `127.0.0.1:7878`, or port 0 for any free port)
+    ///
+    /// Each connection opens with a copy of `agent_info`, timestamped when
+    /// the client connected.
+    pub fn bind(addr: &str, agent_info: AgentInfo, format: TimestampFormat) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let accept_clients = Arc::clone(&clients);
+        let accept_closed = Arc::clone(&closed);
+        let agent_info = Arc::new(agent_info);
+        thread::Builder::new()
+            .name("flowtrace-websocket".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if accept_closed.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let clients = Arc::clone(&accept_clients);
+                        let agent_info = Arc::clone(&agent_info);
+                        thread::spawn(move || serve(stream, clients, &agent_info, format));
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            local_addr,
+            clients,
+            closed,
+        })
+    }
+
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Number of connected clients
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().map(|clients| clients.len()).unwrap_or(0)
+    }
+
+    /// Send `event`, already serialized as `json`, to every client whose filter it passes
+    pub fn publish(&self, event: &TraceEvent, json: &str) {
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.retain(|client| {
+                !client.filter.matches(event)
+                    || !matches!(client.sender.try_send(json.to_string()), Err(TrySendError::Disconnected(_)))
+            });
+        }
+    }
+}
+
+impl Drop for WebSocketSink {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        // Wake the accept loop so it sees `closed`; dropping the senders ends the client threads
+        let _ = TcpStream::connect(self.local_addr);
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.clear();
+        }
+    }
+}
+
+/// Handshake with one client, then forward its events until either side hangs up
+fn serve(stream: TcpStream, clients: Arc<Mutex<Vec<Client>>>, agent_info: &AgentInfo, format: TimestampFormat) {
+    let mut filter = StreamFilter::default();
+    // The error type is fixed by tungstenite's `Callback`
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        filter = StreamFilter::from_query(request.uri().query().unwrap_or_default());
+        Ok(response)
+    };
+    let Ok(mut socket) = tungstenite::accept_hdr(stream, callback) else {
+        return;
+    };
+
+    if let Ok(info) = agent_info.restamped().to_json(format) {
+        if socket.send(Message::Text(info.into())).is_err() {
+            return;
+        }
+    }
+
+    let (sender, receiver): (SyncSender<String>, Receiver<String>) = mpsc::sync_channel(CLIENT_BUFFER);
+    match clients.lock() {
+        Ok(mut clients) => clients.push(Client { filter, sender }),
+        Err(_) => return,
+    }
+
+    for json in receiver {
+        if socket.send(Message::Text(json.into())).is_err() {
+            break;
+        }
+    }
+    let _ = socket.close(None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_filter() {
+        let filter = StreamFilter::from_query("module=billing&level=warn&x=1");
+        assert_eq!(filter.modules, vec!["billing"]);
+        assert_eq!(filter.min_level, Some(Level::Warn));
+
+        let failed = TraceEvent::exception("shop::billing", "charge", "declined", Some(5));
+        let slow = TraceEvent::exit("shop::billing", "charge", None, Some(5)).with_budget(1, true);
+        assert!(filter.matches(&failed));
+        assert!(filter.matches(&slow));
+        assert!(!filter.matches(&TraceEvent::enter("shop::billing", "charge", None)));
+        assert!(!filter.matches(&TraceEvent::exception("shop::auth", "login", "denied", None)));
+        assert!(StreamFilter::from_query("").matches(&TraceEvent::enter("app", "a", None)));
+    }
+
+    #[test]
+    fn test_streams_filtered_events() {
+        let agent_info = AgentInfo::current().with_resource(&crate::Resource::new("checkout"));
+        let sink = WebSocketSink::bind("127.0.0.1:0", agent_info, TimestampFormat::EpochMicros).unwrap();
+        let url = format!("ws://{}/?module=billing", sink.local_addr());
+        let (mut socket, _) = tungstenite::connect(url.as_str()).unwrap();
+
+        let info = socket.read().unwrap().into_text().unwrap();
+        assert_eq!(AgentInfo::from_json_line(&info).unwrap().resource["service.name"], "checkout");
+        while sink.client_count() == 0 {
+            thread::yield_now();
+        }
+
+        for event in [
+            TraceEvent::enter("shop::auth", "login", None),
+            TraceEvent::enter("shop::billing", "charge", None),
+        ] {
+            sink.publish(&event, &serde_json::to_string(&event).unwrap());
+        }
+
+        let line = socket.read().unwrap().into_text().unwrap();
+        let event = TraceEvent::from_json_line(&line).unwrap();
+        assert_eq!(event.function, "charge");
+
+        drop(sink);
+        assert!(socket.read().is_ok_and(|message| message.is_close()));
+    }
+}
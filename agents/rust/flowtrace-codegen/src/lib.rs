@@ -0,0 +1,858 @@
+//! Code generation behind `#[trace]`
+//!
+//! Kept free of `proc_macro` types so `flowctl-rs expand` can call it and show
+//! exactly what the attribute generates.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{
+    Attribute, FnArg, GenericArgument, GenericParam, Generics, Ident, ImplItem, ItemFn, ItemImpl, Lit, LitStr, Meta,
+    Pat, Path, PathArguments, ReturnType, Signature, Token, Type, TypeParamBound, WherePredicate,
+};
+
+/// Arguments of `#[trace(...)]`
+#[derive(Default)]
+pub struct TraceArgs {
+    /// `tracer = PATH`: log to this `Tracer` instead of the global one
+    pub tracer: Option<Path>,
+    /// `warn_over_ms = N`: flag EXIT events slower than this, in microseconds
+    pub warn_over_micros: Option<i64>,
+    /// `escalate`: also raise flagged EXIT events to WARN
+    pub escalate: bool,
+    /// `target = "..."`: logical subsystem recorded on every event
+    pub target: Option<LitStr>,
+    /// `no_move`: run an async body in a plain `async` block, capturing
+    /// arguments the way the body uses them instead of moving all of them
+    pub no_move: bool,
+    /// `main`: start the global tracer from the environment before the body
+    /// and stop it, flushing the log, when the function returns
+    pub main: bool,
+    /// `capture_self(fields(a, b))`: fields of `self` added to the ENTER args
+    pub self_fields: Vec<Ident>,
+    /// `fingerprint_body`: hash the body into the fingerprint as well as the signature
+    pub fingerprint_body: bool,
+    /// `echo_args(a, b)`: arguments repeated in the `args` of EXIT and EXCEPTION events
+    pub echo_args: Vec<Ident>,
+    /// `skip(a, b)`: arguments left out of the ENTER args, e.g. secrets
+    pub skip: Vec<Ident>,
+    /// `max_depth = N`: nesting depth captured values are cut to, overriding `Config::max_arg_depth`
+    pub max_depth: Option<usize>,
+}
+
+impl TraceArgs {
+    /// Parse the tokens inside `#[trace(...)]`
+    pub fn parse(attr: TokenStream) -> syn::Result<Self> {
+        let mut args = Self::default();
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("tracer") {
+                args.tracer = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("target") {
+                args.target = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("warn_over_ms") {
+                let millis = match meta.value()?.parse()? {
+                    Lit::Int(lit) => lit.base10_parse::<f64>()?,
+                    Lit::Float(lit) => lit.base10_parse::<f64>()?,
+                    other => return Err(syn::Error::new_spanned(other, "expected a number of milliseconds")),
+                };
+                args.warn_over_micros = Some((millis * 1000.0) as i64);
+                Ok(())
+            } else if meta.path.is_ident("max_depth") {
+                let depth: syn::LitInt = meta.value()?.parse()?;
+                args.max_depth = Some(depth.base10_parse()?);
+                Ok(())
+            } else if meta.path.is_ident("escalate") {
+                args.escalate = true;
+                Ok(())
+            } else if meta.path.is_ident("no_move") {
+                args.no_move = true;
+                Ok(())
+            } else if meta.path.is_ident("main") {
+                args.main = true;
+                Ok(())
+            } else if meta.path.is_ident("fingerprint_body") {
+                args.fingerprint_body = true;
+                Ok(())
+            } else if meta.path.is_ident("echo_args") {
+                meta.parse_nested_meta(|arg| {
+                    let name = arg.path.get_ident().ok_or_else(|| arg.error("expected an argument name"))?;
+                    args.echo_args.push(name.clone());
+                    Ok(())
+                })
+            } else if meta.path.is_ident("skip") {
+                meta.parse_nested_meta(|arg| {
+                    let name = arg.path.get_ident().ok_or_else(|| arg.error("expected an argument name"))?;
+                    args.skip.push(name.clone());
+                    Ok(())
+                })
+            } else if meta.path.is_ident("capture_self") {
+                meta.parse_nested_meta(|fields| {
+                    if !fields.path.is_ident("fields") {
+                        return Err(fields.error("expected `fields(...)`"));
+                    }
+                    fields.parse_nested_meta(|field| {
+                        let name = field.path.get_ident().ok_or_else(|| field.error("expected a field name"))?;
+                        args.self_fields.push(name.clone());
+                        Ok(())
+                    })
+                })
+            } else {
+                Err(meta.error(
+                    "unsupported #[trace] argument, expected `tracer = PATH`, `target = \"NAME\"`, \
+                     `warn_over_ms = N`, `escalate`, `max_depth = N`, `no_move`, `main`, \
+                     `fingerprint_body`, `echo_args(...)`, `skip(...)` or `capture_self(fields(...))`",
+                ))
+            }
+        });
+        parser.parse2(attr.clone())?;
+
+        if args.escalate && args.warn_over_micros.is_none() {
+            return Err(syn::Error::new_spanned(attr, "`escalate` requires `warn_over_ms = N`"));
+        }
+        Ok(args)
+    }
+}
+
+/// Instrument a function the way `#[trace]` does
+pub fn trace(args: &TraceArgs, input: &ItemFn) -> TokenStream {
+    instrument(args, input, None)
+}
+
+/// Instrument every method of an impl block the way `#[trace]` on the block does
+///
+/// A method with its own `#[trace(...)]` is instrumented with those arguments
+/// instead. `const fn`s and methods returning `!`, which can't be
+/// instrumented, are left as they are.
+pub fn trace_impl(args: &TraceArgs, input: &ItemImpl) -> TokenStream {
+    if let Err(error) = check_impl_supported(args) {
+        return error.to_compile_error();
+    }
+
+    let mut output = input.clone();
+    for item in &mut output.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        // `#[trace]` sees a method as a plain function item
+        let mut function = ItemFn {
+            attrs: method.attrs.clone(),
+            vis: method.vis.clone(),
+            sig: method.sig.clone(),
+            block: Box::new(method.block.clone()),
+        };
+        let own_args = match take_trace_args(&mut function.attrs) {
+            Ok(own_args) => own_args,
+            Err(error) => {
+                *item = ImplItem::Verbatim(error.to_compile_error());
+                continue;
+            }
+        };
+        if own_args.is_none() && !can_instrument(&function.sig) {
+            continue;
+        }
+        *item = ImplItem::Verbatim(trace_method(own_args.as_ref().unwrap_or(args), &function, input));
+    }
+    quote! { #output }
+}
+
+/// Instrument a method of `owner` the way `#[trace]` on the impl block does,
+/// logging it under `module_path::Type` instead of the module alone
+pub fn trace_method(args: &TraceArgs, input: &ItemFn, owner: &ItemImpl) -> TokenStream {
+    instrument(args, input, Some(owner))
+}
+
+fn instrument(args: &TraceArgs, input: &ItemFn, owner: Option<&ItemImpl>) -> TokenStream {
+    let fn_name = &input.sig.ident;
+    let fn_name_str = fn_name.to_string();
+    let fn_block = &input.block;
+    let fn_vis = &input.vis;
+    let fn_sig = &input.sig;
+    let fn_attrs = &input.attrs;
+
+    // How the call's events are logged: where they go and what every one of them carries
+    let events = Events::new(args, input);
+
+    // Arguments repeated on EXIT and EXCEPTION events, captured before the body can move them
+    let echo_capture = if args.echo_args.is_empty() {
+        quote! {}
+    } else {
+        let echo_strings = args.echo_args.iter().map(|name| {
+            let name_str = name.to_string();
+            quote! {
+                format!("\"{}\": {:?}", #name_str, #name)
+            }
+        });
+        quote! { let __flowtrace_echo = format!("{{{}}}", vec![#(#echo_strings),*].join(", ")); }
+    };
+
+    // Block the body of an async fn is awaited in
+    let async_body = if args.no_move {
+        quote! { async #fn_block }
+    } else {
+        quote! { async move #fn_block }
+    };
+
+    // Determine module path at compile time, followed by the type for methods of a traced impl block
+    let module_path = match owner {
+        Some(owner) => {
+            let type_name = format!("::{}", type_name(&owner.self_ty));
+            quote! { concat!(module_path!(), #type_name) }
+        }
+        None => quote! { module_path!() },
+    };
+
+    // Check if function is async
+    let is_async = fn_sig.asyncness.is_some();
+
+    if let Err(error) = check_supported(args, fn_sig) {
+        return error.to_compile_error();
+    }
+
+    // Types of generic parameters (and `Self`) that `{:?}` can't be proven to work on
+    let opaque = opaque_type_params(fn_sig, owner);
+
+    // Extract function arguments for automatic capture, leaving out skipped ones and
+    // those not provably Debug
+    let arg_names: Vec<_> = fn_sig
+        .inputs
+        .iter()
+        .filter_map(|arg| {
+            if let FnArg::Typed(pat_type) = arg {
+                if let Pat::Ident(ident) = &*pat_type.pat {
+                    if !args.skip.contains(&ident.ident) && is_debug(&pat_type.ty, &opaque) {
+                        return Some(&ident.ident);
+                    }
+                }
+            }
+            None
+        })
+        .collect();
+
+    // Build args string: "{\"arg1\": value1, \"arg2\": value2}"
+    let args_capture = if arg_names.is_empty() && args.self_fields.is_empty() {
+        quote! { None }
+    } else {
+        let self_strings = args.self_fields.iter().map(|field| {
+            let name_str = format!("self.{}", field);
+            quote! {
+                format!("\"{}\": {:?}", #name_str, self.#field)
+            }
+        });
+        let arg_strings: Vec<_> = self_strings
+            .chain(arg_names.iter().map(|name| {
+                let name_str = name.to_string();
+                quote! {
+                    format!("\"{}\": {:?}", #name_str, #name)
+                }
+            }))
+            .collect();
+
+        quote! {
+            Some(format!("{{{}}}", vec![#(#arg_strings),*].join(", ")))
+        }
+    };
+
+    // `-> impl Future<Output = T>`: the body only builds the future, so trace the future
+    // itself; holds `T` and whether the future is boxed, as `#[async_trait]` methods return it
+    let future_output = match &fn_sig.output {
+        ReturnType::Type(_, ty) if !is_async => returned_future_output(ty),
+        _ => None,
+    };
+
+    // Values that need not implement Debug (e.g. `impl Responder`, or a `T` without a
+    // `Debug` bound) are logged without a value
+    let value_type = match (future_output, &fn_sig.output) {
+        (Some((output, _)), _) => Some(output),
+        (None, ReturnType::Type(_, ty)) => Some(&**ty),
+        (None, ReturnType::Default) => None,
+    };
+    let result_capture = match value_type {
+        Some(ty) if !is_debug(ty, &opaque) => quote! { None },
+        _ => quote! { Some(format!("{:?}", __flowtrace_result)) },
+    };
+
+    // `Ok` values and errors of a `Result<T, E>` the same way; an error that can't be
+    // formatted is logged by its type name
+    let (ok_type, err_type) = match value_type {
+        Some(Type::Path(type_path)) => {
+            let arguments = type_path
+                .path
+                .segments
+                .last()
+                .map_or_else(Vec::new, |segment| generic_types(&segment.arguments));
+            (arguments.first().copied(), arguments.get(1).copied())
+        }
+        _ => (None, None),
+    };
+    let (ok_pattern, ok_capture) = match ok_type {
+        Some(ty) if !is_debug(ty, &opaque) => (quote! { _ }, quote! { None }),
+        _ => (quote! { value }, quote! { Some(format!("{:?}", value)) }),
+    };
+    let error_message = match err_type {
+        Some(ty) if !is_debug(ty, &opaque) => quote! { std::any::type_name_of_val(error) },
+        _ => quote! { &format!("{:?}", error) },
+    };
+
+    // Log how `__flowtrace_result` ended the call: EXIT, or EXCEPTION for an `Err`
+    let log_result = if value_type.is_some_and(is_result_type) {
+        let exit = events.exit(&ok_capture);
+        let exception = events.exception(&error_message);
+        quote! {
+            match &__flowtrace_result {
+                Ok(#ok_pattern) => {
+                    // Log EXIT event with result
+                    #exit
+                }
+                Err(error) => {
+                    // Log EXCEPTION event with error
+                    #exception
+                }
+            }
+        }
+    } else {
+        let exit = events.exit(&result_capture);
+        quote! {
+            // Log EXIT event with result
+            #exit
+        }
+    };
+
+    let instrumented_body = if let Some((_, boxed)) = future_output {
+        let enter = events.enter(&module_path, &fn_name_str, &quote! { __flowtrace_args });
+        let traced_future = quote! {
+            async move {
+                #enter
+
+                // Drive the returned future
+                let __flowtrace_result = __flowtrace_future.await;
+
+                // Calculate duration in microseconds
+                let __flowtrace_duration = flowtrace_agent::monotonic_micros() - __flowtrace_start;
+
+                #log_result
+
+                __flowtrace_result
+            }
+        };
+        let traced_future = if boxed {
+            quote! { Box::pin(#traced_future) }
+        } else {
+            traced_future
+        };
+
+        quote! {
+            // Capture args before the body can move them into the future
+            let __flowtrace_args = #args_capture;
+            let __flowtrace_future = #fn_block;
+
+            #traced_future
+        }
+    } else if is_async {
+        // Async function instrumentation
+        let enter = events.enter(&module_path, &fn_name_str, &args_capture);
+        quote! {
+            #enter
+
+            // Execute original function body
+            let __flowtrace_result = #async_body.await;
+
+            // Calculate duration in microseconds
+            let __flowtrace_duration = flowtrace_agent::monotonic_micros() - __flowtrace_start;
+
+            #log_result
+
+            __flowtrace_result
+        }
+    } else {
+        // Sync function instrumentation
+        let enter = events.enter(&module_path, &fn_name_str, &args_capture);
+        let log_panic = events.exception(&quote! { &__flowtrace_panic_message });
+        let panic_message = panic_message(&quote! { panic_info });
+        quote! {
+            #enter
+
+            // Execute original function body with panic handling
+            let __flowtrace_panic_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                #fn_block
+            }));
+
+            // Calculate duration in microseconds
+            let __flowtrace_duration = flowtrace_agent::monotonic_micros() - __flowtrace_start;
+
+            match __flowtrace_panic_result {
+                Ok(__flowtrace_result) => {
+                    #log_result
+                    __flowtrace_result
+                }
+                Err(panic_info) => {
+                    // Log panic as EXCEPTION event
+                    let __flowtrace_panic_message = #panic_message;
+                    #log_panic
+
+                    std::panic::resume_unwind(panic_info);
+                }
+            }
+        }
+    };
+
+    // Declared first, so tracing stops after the EXIT event is logged
+    let init = if args.main {
+        quote! { let __flowtrace_guard = flowtrace_agent::init_from_env(); }
+    } else {
+        quote! {}
+    };
+
+    // Rebuild the function with instrumentation
+    quote! {
+        #(#fn_attrs)*
+        #fn_vis #fn_sig {
+            #init
+            #echo_capture
+            #instrumented_body
+        }
+    }
+}
+
+/// Logs the events of one traced call, in terms of the `__flowtrace_*` variables
+/// the generated code declares
+struct Events {
+    /// Where events go: the global tracer unless `tracer = PATH` was given
+    log_event: TokenStream,
+    /// Builder calls applied to every event, starting with the call ID
+    common: TokenStream,
+    /// Builder calls applied to EXIT and EXCEPTION events
+    closing: TokenStream,
+    /// Latency budget check applied to EXIT events
+    budget: TokenStream,
+}
+
+impl Events {
+    fn new(args: &TraceArgs, input: &ItemFn) -> Self {
+        let log_event = match &args.tracer {
+            Some(tracer) => quote! { #tracer.log },
+            None => quote! { flowtrace_agent::log_event },
+        };
+
+        // Subsystem recorded on every event
+        let target = args.target.as_ref().map(|target| quote! { .with_target(#target) });
+        // Nesting depth captured values are cut to
+        let max_depth = args.max_depth.map(|depth| quote! { .with_max_depth(#depth) });
+        // Hash of the code as compiled, so a latency change can be tied to the function changing
+        let fingerprint = fingerprint(input, args.fingerprint_body);
+
+        // Arguments captured by `echo_args` before the body ran
+        let echo = (!args.echo_args.is_empty()).then(|| quote! { .with_args(__flowtrace_echo) });
+        let budget = args.warn_over_micros.map(|micros| {
+            let escalate = args.escalate;
+            quote! { .with_budget(#micros, #escalate) }
+        });
+
+        Self {
+            log_event,
+            common: quote! { .with_call_id(__flowtrace_call) #target #max_depth .with_fingerprint(#fingerprint) },
+            closing: quote! { #echo },
+            budget: quote! { #budget },
+        }
+    }
+
+    /// Start timing the call and log its ENTER event with `args`
+    fn enter(&self, module_path: &TokenStream, function: &str, args: &TokenStream) -> TokenStream {
+        let Self { log_event, common, .. } = self;
+        quote! {
+            let __flowtrace_start = flowtrace_agent::monotonic_micros();
+            let __flowtrace_module = #module_path;
+            let __flowtrace_function = #function;
+            // Ties the closing event to the ENTER, whichever thread the call ends on
+            let __flowtrace_call = flowtrace_agent::TraceEvent::next_call_id();
+
+            // Log ENTER event with args
+            #log_event(
+                flowtrace_agent::TraceEvent::enter(
+                    __flowtrace_module,
+                    __flowtrace_function,
+                    #args,
+                ) #common
+            );
+        }
+    }
+
+    /// Log the EXIT event with `result`, once `__flowtrace_duration` is known
+    fn exit(&self, result: &TokenStream) -> TokenStream {
+        let Self { log_event, common, closing, budget } = self;
+        quote! {
+            #log_event(
+                flowtrace_agent::TraceEvent::exit(
+                    __flowtrace_module,
+                    __flowtrace_function,
+                    #result,
+                    Some(__flowtrace_duration),
+                ) #common #closing #budget
+            );
+        }
+    }
+
+    /// Log an EXCEPTION event with `message`, once `__flowtrace_duration` is known
+    fn exception(&self, message: &TokenStream) -> TokenStream {
+        let Self { log_event, common, closing, .. } = self;
+        quote! {
+            #log_event(
+                flowtrace_agent::TraceEvent::exception(
+                    __flowtrace_module,
+                    __flowtrace_function,
+                    #message,
+                    Some(__flowtrace_duration),
+                ) #common #closing
+            );
+        }
+    }
+}
+
+/// The message of the panic whose payload is `payload`, as a `String`
+fn panic_message(payload: &TokenStream) -> TokenStream {
+    quote! {
+        if let Some(s) = #payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = #payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "Unknown panic".to_string()
+        }
+    }
+}
+
+/// Reject functions the generated code can't instrument, pointing at the cause
+fn check_supported(args: &TraceArgs, sig: &Signature) -> syn::Result<()> {
+    if let Some(constness) = &sig.constness {
+        return Err(syn::Error::new_spanned(
+            constness,
+            "#[trace] cannot instrument a `const fn`: events are logged at run time; \
+             remove `const` or trace the functions calling it",
+        ));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(syn::Error::new_spanned(
+            variadic,
+            "#[trace] cannot instrument a variadic function; trace a Rust wrapper around it instead",
+        ));
+    }
+    if let ReturnType::Type(_, ty) = &sig.output {
+        if matches!(**ty, Type::Never(_)) {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "#[trace] cannot instrument a function returning `!`: it never logs an EXIT; \
+                 trace the functions it calls instead",
+            ));
+        }
+    }
+    if args.main {
+        if let Some(tracer) = &args.tracer {
+            return Err(syn::Error::new_spanned(
+                tracer,
+                "`main` starts the global tracer, so it can't be combined with `tracer = PATH`",
+            ));
+        }
+        if let ReturnType::Type(_, ty) = &sig.output {
+            if returned_future_output(ty).is_some() {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "`main` would stop tracing before the returned future runs; use it on an `async fn` instead",
+                ));
+            }
+        }
+    }
+    // `echo_args` and `skip` name arguments the function takes by name
+    let lists = [("echo_args", &args.echo_args), ("skip", &args.skip)];
+    for (option, name) in lists.iter().flat_map(|(option, names)| names.iter().map(move |name| (option, name))) {
+        let is_arg = sig.inputs.iter().any(|arg| match arg {
+            FnArg::Typed(pat_type) => matches!(&*pat_type.pat, Pat::Ident(ident) if ident.ident == *name),
+            FnArg::Receiver(_) => false,
+        });
+        if !is_arg {
+            return Err(syn::Error::new_spanned(
+                name,
+                format!("`{}`: `{}` is not an argument of this function", option, name),
+            ));
+        }
+    }
+    // A skipped argument would still be logged on EXIT
+    if let Some(name) = args.echo_args.iter().find(|name| args.skip.contains(name)) {
+        return Err(syn::Error::new_spanned(
+            name,
+            format!("`{}` is skipped, so it can't be repeated with `echo_args`", name),
+        ));
+    }
+    // `capture_self` needs a `self` to read the fields from
+    let has_receiver = sig.inputs.iter().any(|arg| matches!(arg, FnArg::Receiver(_)));
+    if !args.self_fields.is_empty() && !has_receiver {
+        return Err(syn::Error::new_spanned(&sig.ident, "`capture_self` requires a method taking `self`"));
+    }
+    Ok(())
+}
+
+/// Reject `#[trace(...)]` arguments on an impl block that only make sense on one function
+fn check_impl_supported(args: &TraceArgs) -> syn::Result<()> {
+    if args.main {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`main` starts tracing around one function, so it can't be used on an `impl` block",
+        ));
+    }
+    let lists = [("echo_args", &args.echo_args), ("skip", &args.skip), ("capture_self", &args.self_fields)];
+    if let Some((option, names)) = lists.iter().find(|(_, names)| !names.is_empty()) {
+        return Err(syn::Error::new_spanned(
+            &names[0],
+            format!("`{}` names arguments of one method; put it in a `#[trace(...)]` on that method", option),
+        ));
+    }
+    Ok(())
+}
+
+/// Remove the `#[trace]` attribute of a method in a traced impl block, returning its arguments
+fn take_trace_args(attrs: &mut Vec<Attribute>) -> syn::Result<Option<TraceArgs>> {
+    let Some(index) = attrs.iter().position(is_trace_attribute) else {
+        return Ok(None);
+    };
+    let args = match &attrs.remove(index).meta {
+        Meta::List(list) => TraceArgs::parse(list.tokens.clone())?,
+        _ => TraceArgs::default(),
+    };
+    Ok(Some(args))
+}
+
+/// `#[trace]`, `#[flowtrace_agent::trace]` or `#[flowtrace_derive::trace]`
+fn is_trace_attribute(attr: &Attribute) -> bool {
+    let segments: Vec<String> = attr.path().segments.iter().map(|segment| segment.ident.to_string()).collect();
+    match segments.as_slice() {
+        [name] => name == "trace",
+        [root, name] => name == "trace" && (root == "flowtrace_agent" || root == "flowtrace_derive"),
+        _ => false,
+    }
+}
+
+/// Whether `check_supported` accepts a method of any arguments
+fn can_instrument(sig: &Signature) -> bool {
+    let never_returns = matches!(&sig.output, ReturnType::Type(_, ty) if matches!(**ty, Type::Never(_)));
+    sig.constness.is_none() && !never_returns
+}
+
+/// Name of the type an impl block belongs to: `Order` for `impl<T> Order<T>`
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => {
+            type_path.path.segments.last().map(|segment| segment.ident.to_string()).unwrap_or_default()
+        }
+        other => quote! { #other }.to_string().replace(' ', ""),
+    }
+}
+
+/// `T` of a returned future, and whether it is a boxed `Pin<Box<dyn Future<Output = T>>>`
+fn returned_future_output(ty: &Type) -> Option<(&Type, bool)> {
+    match ty {
+        Type::ImplTrait(impl_trait) => future_bound_output(&impl_trait.bounds).map(|output| (output, false)),
+        Type::Path(type_path) => {
+            let segment = type_path.path.segments.last()?;
+            let arguments = generic_types(&segment.arguments);
+            match segment.ident.to_string().as_str() {
+                // `Pin<Box<dyn Future<Output = T> + Send + 'a>>`
+                "Pin" => {
+                    let Some(Type::Path(boxed)) = arguments.first() else {
+                        return None;
+                    };
+                    let segment = boxed.path.segments.last().filter(|segment| segment.ident == "Box")?;
+                    let Some(Type::TraitObject(object)) = generic_types(&segment.arguments).first() else {
+                        return None;
+                    };
+                    future_bound_output(&object.bounds).map(|output| (output, true))
+                }
+                // `futures::future::BoxFuture<'a, T>`
+                "BoxFuture" | "LocalBoxFuture" => arguments.first().map(|output| (*output, true)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `T` of a `Future<Output = T>` bound among `bounds`
+fn future_bound_output(bounds: &Punctuated<TypeParamBound, Token![+]>) -> Option<&Type> {
+    bounds.iter().find_map(|bound| {
+        let TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last().filter(|segment| segment.ident == "Future")?;
+        let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+            return None;
+        };
+        arguments.args.iter().find_map(|argument| match argument {
+            GenericArgument::AssocType(assoc) if assoc.ident == "Output" => Some(&assoc.ty),
+            _ => None,
+        })
+    })
+}
+
+/// Type arguments of a path segment, e.g. `Box<T>`
+fn generic_types(arguments: &PathArguments) -> Vec<&Type> {
+    match arguments {
+        PathArguments::AngleBracketed(arguments) => arguments
+            .args
+            .iter()
+            .filter_map(|argument| match argument {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Stable hash of the function's signature, and its body with `body`, as 16 hex digits
+///
+/// FNV-1a over the tokens one at a time, so it changes with the code but not
+/// with its formatting.
+pub fn fingerprint(input: &ItemFn, body: bool) -> String {
+    let sig = &input.sig;
+    let block = &input.block;
+    let tokens = if body {
+        quote! { #sig #block }
+    } else {
+        quote! { #sig }
+    };
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    hash_tokens(tokens, &mut hash);
+    format!("{:016x}", hash)
+}
+
+fn hash_tokens(tokens: TokenStream, hash: &mut u64) {
+    for token in tokens {
+        match token {
+            proc_macro2::TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    proc_macro2::Delimiter::Parenthesis => ("(", ")"),
+                    proc_macro2::Delimiter::Brace => ("{", "}"),
+                    proc_macro2::Delimiter::Bracket => ("[", "]"),
+                    proc_macro2::Delimiter::None => ("", ""),
+                };
+                hash_text(open, hash);
+                hash_tokens(group.stream(), hash);
+                hash_text(close, hash);
+            }
+            other => hash_text(&other.to_string(), hash),
+        }
+    }
+}
+
+/// Add `text`, and a separator so adjacent tokens can't run together
+fn hash_text(text: &str, hash: &mut u64) {
+    for byte in text.bytes().chain([0]) {
+        *hash ^= u64::from(byte);
+        *hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+}
+
+/// Type parameters of `sig` and of its `owner` impl block without a `Debug`
+/// bound, and `Self` unless `where Self: Debug`
+///
+/// The macro only sees the function, or the impl block, so a type counts as
+/// Debug when its own bounds or a `where` clause say so. In a traced impl
+/// block, `Self` is the block's type, and counts as Debug like any other type
+/// when it names no opaque parameter.
+fn opaque_type_params(sig: &Signature, owner: Option<&ItemImpl>) -> Vec<Ident> {
+    let generics: Vec<&Generics> = owner.map(|owner| &owner.generics).into_iter().chain([&sig.generics]).collect();
+    let mut params: Vec<(Ident, bool)> = generics
+        .iter()
+        .flat_map(|generics| &generics.params)
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some((param.ident.clone(), has_debug_bound(&param.bounds))),
+            _ => None,
+        })
+        .collect();
+    params.push((Ident::new("Self", proc_macro2::Span::call_site()), false));
+
+    for predicate in generics.iter().flat_map(|generics| &generics.where_clause).flat_map(|clause| &clause.predicates) {
+        let WherePredicate::Type(predicate) = predicate else {
+            continue;
+        };
+        let Type::Path(bounded) = &predicate.bounded_ty else {
+            continue;
+        };
+        let Some(ident) = bounded.path.get_ident() else {
+            continue;
+        };
+        if has_debug_bound(&predicate.bounds) {
+            for (param, debug) in &mut params {
+                *debug |= param == ident;
+            }
+        }
+    }
+    let mut opaque: Vec<Ident> = params.into_iter().filter(|(_, debug)| !debug).map(|(ident, _)| ident).collect();
+    if let Some(owner) = owner {
+        let others: Vec<Ident> = opaque.iter().filter(|ident| *ident != "Self").cloned().collect();
+        if is_debug(&owner.self_ty, &others) {
+            opaque = others;
+        }
+    }
+    opaque
+}
+
+/// Whether `bounds` include `Debug`, or `Error` which requires it
+fn has_debug_bound(bounds: &Punctuated<TypeParamBound, Token![+]>) -> bool {
+    bounds.iter().any(|bound| match bound {
+        TypeParamBound::Trait(bound) => bound
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Debug" || segment.ident == "Error"),
+        _ => false,
+    })
+}
+
+/// Whether values of `ty` are provably Debug: it names none of the `opaque`
+/// types, and its `impl Trait` and `dyn Trait` parts are bounded by `Debug`
+fn is_debug(ty: &Type, opaque: &[Ident]) -> bool {
+    match ty {
+        Type::Path(type_path) => {
+            // `T`, or an associated type like `T::Item`
+            if type_path.path.segments.first().is_some_and(|segment| opaque.contains(&segment.ident)) {
+                return false;
+            }
+            if type_path.qself.as_ref().is_some_and(|qself| !is_debug(&qself.ty, opaque)) {
+                return false;
+            }
+            type_path.path.segments.iter().all(|segment| {
+                let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+                    return true;
+                };
+                arguments.args.iter().all(|argument| match argument {
+                    GenericArgument::Type(ty) => is_debug(ty, opaque),
+                    GenericArgument::AssocType(assoc) => is_debug(&assoc.ty, opaque),
+                    _ => true,
+                })
+            })
+        }
+        Type::Reference(reference) => is_debug(&reference.elem, opaque),
+        Type::Ptr(pointer) => is_debug(&pointer.elem, opaque),
+        Type::Slice(slice) => is_debug(&slice.elem, opaque),
+        Type::Array(array) => is_debug(&array.elem, opaque),
+        Type::Paren(paren) => is_debug(&paren.elem, opaque),
+        Type::Group(group) => is_debug(&group.elem, opaque),
+        Type::Tuple(tuple) => tuple.elems.iter().all(|elem| is_debug(elem, opaque)),
+        Type::ImplTrait(impl_trait) => has_debug_bound(&impl_trait.bounds),
+        Type::TraitObject(object) => has_debug_bound(&object.bounds),
+        Type::BareFn(_) | Type::Never(_) => true,
+        _ => false,
+    }
+}
+
+/// Helper function to detect Result<T, E> type
+fn is_result_type(ty: &Type) -> bool {
+    if let Type::Path(type_path) = ty {
+        if let Some(segment) = type_path.path.segments.last() {
+            return segment.ident == "Result";
+        }
+    }
+    false
+}
@@ -5,7 +5,8 @@
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, FnArg, ItemFn, Pat, ReturnType, Type};
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Expr, ForeignItemFn, Item, ItemFn, ItemImpl, LitStr, Token, TraitItemFn};
 
 /// Automatic function tracing attribute macro with intelligent arg/result/error capture
 ///
@@ -23,6 +24,44 @@ use syn::{parse_macro_input, FnArg, ItemFn, Pat, ReturnType, Type};
 /// }
 /// ```
 ///
+/// Events go to the global tracer started by `start_tracing`; use
+/// `#[trace(tracer = MY_TRACER)]` to log to a `static MY_TRACER: Tracer` instead.
+///
+/// `warn_over_ms` declares a latency budget. Slower calls get
+/// `"budgetExceeded": true` on their EXIT event, and `escalate` also marks
+/// them `"level": "WARN"`:
+///
+/// ```rust
+/// use flowtrace_agent::trace;
+///
+/// #[trace(warn_over_ms = 200, escalate)]
+/// fn checkout(cart: u32) -> u32 {
+///     cart
+/// }
+/// # checkout(1);
+/// ```
+///
+/// The body of an `async fn` is awaited in an `async move` block; `no_move`
+/// uses a plain `async` block, which only moves the arguments the body consumes.
+///
+/// `target = "..."` records a logical subsystem on every event, independent
+/// of the module path: `#[trace(target = "db")]`.
+///
+/// Methods can log a few fields of `self` with the ENTER event's args:
+/// `#[trace(capture_self(fields(id, status)))]`.
+///
+/// `max_depth = N` cuts captured arguments and results past `N` levels of
+/// nesting to `{...}`, overriding `Config::max_arg_depth`:
+/// `#[trace(max_depth = 2)]`.
+///
+/// `#[trace(main)]` starts the global tracer with `Config::from_env()` before
+/// the body and stops it when the function returns.
+///
+/// On an `impl` block, `#[trace]` instruments every method, private or not,
+/// and logs them under `module_path::Type`. A method's own `#[trace(...)]`
+/// replaces the block's arguments for it; `echo_args`, `skip` and
+/// `capture_self` can only be given there.
+///
 /// Expands to instrumented code with:
 /// - Automatic argument capture (formats all args as JSON-like string)
 /// - Automatic return value capture (formats result/error)
@@ -30,367 +69,40 @@ use syn::{parse_macro_input, FnArg, ItemFn, Pat, ReturnType, Type};
 /// - Result<T, E> error handling
 /// - Panic handling
 #[proc_macro_attribute]
-pub fn trace(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemFn);
-
-    let fn_name = &input.sig.ident;
-    let fn_name_str = fn_name.to_string();
-    let fn_block = &input.block;
-    let fn_vis = &input.vis;
-    let fn_sig = &input.sig;
-    let fn_attrs = &input.attrs;
-
-    // Determine module path at compile time
-    let module_path = quote! { module_path!() };
-
-    // Check if function is async
-    let is_async = fn_sig.asyncness.is_some();
-
-    // Extract function arguments for automatic capture
-    let arg_names: Vec<_> = fn_sig
-        .inputs
-        .iter()
-        .filter_map(|arg| {
-            if let FnArg::Typed(pat_type) = arg {
-                if let Pat::Ident(ident) = &*pat_type.pat {
-                    return Some(&ident.ident);
-                }
-            }
-            None
-        })
-        .collect();
-
-    // Build args string: "{\"arg1\": value1, \"arg2\": value2}"
-    let args_capture = if arg_names.is_empty() {
-        quote! { None }
-    } else {
-        let arg_strings: Vec<_> = arg_names
-            .iter()
-            .map(|name| {
-                let name_str = name.to_string();
-                quote! {
-                    format!("\"{}\": {:?}", #name_str, #name)
-                }
-            })
-            .collect();
-
-        quote! {
-            Some(format!("{{{}}}", vec![#(#arg_strings),*].join(", ")))
-        }
-    };
-
-    // Check return type for Result<T, E> or regular return
-    let (has_return, is_result_type) = match &fn_sig.output {
-        ReturnType::Default => (false, false),
-        ReturnType::Type(_, ty) => {
-            let is_result = is_result_type(ty);
-            (true, is_result)
-        }
-    };
-
-    let instrumented_body = if is_async {
-        // Async function instrumentation
-        if is_result_type {
-            // Async function returning Result<T, E>
-            quote! {
-                let __flowtrace_start = std::time::Instant::now();
-                let __flowtrace_module = #module_path;
-                let __flowtrace_function = #fn_name_str;
-
-                // Log ENTER event with args
-                flowtrace_agent::log_event(
-                    flowtrace_agent::TraceEvent::enter(
-                        __flowtrace_module,
-                        __flowtrace_function,
-                        #args_capture,
-                    )
-                );
-
-                // Execute original function body
-                let __flowtrace_result = async move #fn_block.await;
-
-                // Calculate duration in microseconds
-                let __flowtrace_duration = __flowtrace_start.elapsed().as_micros() as i64;
-
-                // Handle Result<T, E>
-                match &__flowtrace_result {
-                    Ok(value) => {
-                        // Log EXIT event with result
-                        flowtrace_agent::log_event(
-                            flowtrace_agent::TraceEvent::exit(
-                                __flowtrace_module,
-                                __flowtrace_function,
-                                Some(format!("{:?}", value)),
-                                Some(__flowtrace_duration),
-                            )
-                        );
-                    }
-                    Err(error) => {
-                        // Log EXCEPTION event with error
-                        flowtrace_agent::log_event(
-                            flowtrace_agent::TraceEvent::exception(
-                                __flowtrace_module,
-                                __flowtrace_function,
-                                &format!("{:?}", error),
-                                Some(__flowtrace_duration),
-                            )
-                        );
-                    }
-                }
-
-                __flowtrace_result
-            }
-        } else {
-            // Async function with regular return
-            quote! {
-                let __flowtrace_start = std::time::Instant::now();
-                let __flowtrace_module = #module_path;
-                let __flowtrace_function = #fn_name_str;
-
-                // Log ENTER event with args
-                flowtrace_agent::log_event(
-                    flowtrace_agent::TraceEvent::enter(
-                        __flowtrace_module,
-                        __flowtrace_function,
-                        #args_capture,
-                    )
-                );
-
-                // Execute original function body
-                let __flowtrace_result = async move #fn_block.await;
-
-                // Calculate duration in microseconds
-                let __flowtrace_duration = __flowtrace_start.elapsed().as_micros() as i64;
-
-                // Log EXIT event with result
-                flowtrace_agent::log_event(
-                    flowtrace_agent::TraceEvent::exit(
-                        __flowtrace_module,
-                        __flowtrace_function,
-                        Some(format!("{:?}", __flowtrace_result)),
-                        Some(__flowtrace_duration),
-                    )
-                );
-
-                __flowtrace_result
-            }
-        }
-    } else if is_result_type {
-        // Sync function returning Result<T, E>
-        quote! {
-            let __flowtrace_start = std::time::Instant::now();
-            let __flowtrace_module = #module_path;
-            let __flowtrace_function = #fn_name_str;
-
-            // Log ENTER event with args
-            flowtrace_agent::log_event(
-                flowtrace_agent::TraceEvent::enter(
-                    __flowtrace_module,
-                    __flowtrace_function,
-                    #args_capture,
-                )
-            );
-
-            // Execute original function body with panic handling
-            let __flowtrace_panic_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                #fn_block
-            }));
-
-            // Calculate duration in microseconds
-            let __flowtrace_duration = __flowtrace_start.elapsed().as_micros() as i64;
-
-            match __flowtrace_panic_result {
-                Ok(__flowtrace_result) => {
-                    // Handle Result<T, E>
-                    match &__flowtrace_result {
-                        Ok(value) => {
-                            // Log EXIT event with result
-                            flowtrace_agent::log_event(
-                                flowtrace_agent::TraceEvent::exit(
-                                    __flowtrace_module,
-                                    __flowtrace_function,
-                                    Some(format!("{:?}", value)),
-                                    Some(__flowtrace_duration),
-                                )
-                            );
-                        }
-                        Err(error) => {
-                            // Log EXCEPTION event with error
-                            flowtrace_agent::log_event(
-                                flowtrace_agent::TraceEvent::exception(
-                                    __flowtrace_module,
-                                    __flowtrace_function,
-                                    &format!("{:?}", error),
-                                    Some(__flowtrace_duration),
-                                )
-                            );
-                        }
-                    }
-                    __flowtrace_result
-                }
-                Err(panic_info) => {
-                    // Log panic as EXCEPTION event
-                    let error_msg = if let Some(s) = panic_info.downcast_ref::<&str>() {
-                        s.to_string()
-                    } else if let Some(s) = panic_info.downcast_ref::<String>() {
-                        s.clone()
-                    } else {
-                        "Unknown panic".to_string()
-                    };
-
-                    flowtrace_agent::log_event(
-                        flowtrace_agent::TraceEvent::exception(
-                            __flowtrace_module,
-                            __flowtrace_function,
-                            &error_msg,
-                            Some(__flowtrace_duration),
-                        )
-                    );
-
-                    std::panic::resume_unwind(panic_info);
-                }
-            }
-        }
-    } else if has_return {
-        // Sync function with return value (non-Result)
-        quote! {
-            let __flowtrace_start = std::time::Instant::now();
-            let __flowtrace_module = #module_path;
-            let __flowtrace_function = #fn_name_str;
-
-            // Log ENTER event with args
-            flowtrace_agent::log_event(
-                flowtrace_agent::TraceEvent::enter(
-                    __flowtrace_module,
-                    __flowtrace_function,
-                    #args_capture,
-                )
-            );
-
-            // Execute original function body with panic handling
-            let __flowtrace_panic_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                #fn_block
-            }));
-
-            // Calculate duration in microseconds
-            let __flowtrace_duration = __flowtrace_start.elapsed().as_micros() as i64;
-
-            match __flowtrace_panic_result {
-                Ok(__flowtrace_result) => {
-                    // Log EXIT event with result
-                    flowtrace_agent::log_event(
-                        flowtrace_agent::TraceEvent::exit(
-                            __flowtrace_module,
-                            __flowtrace_function,
-                            Some(format!("{:?}", __flowtrace_result)),
-                            Some(__flowtrace_duration),
-                        )
-                    );
-                    __flowtrace_result
-                }
-                Err(panic_info) => {
-                    // Log panic as EXCEPTION event
-                    let error_msg = if let Some(s) = panic_info.downcast_ref::<&str>() {
-                        s.to_string()
-                    } else if let Some(s) = panic_info.downcast_ref::<String>() {
-                        s.clone()
-                    } else {
-                        "Unknown panic".to_string()
-                    };
-
-                    flowtrace_agent::log_event(
-                        flowtrace_agent::TraceEvent::exception(
-                            __flowtrace_module,
-                            __flowtrace_function,
-                            &error_msg,
-                            Some(__flowtrace_duration),
-                        )
-                    );
-
-                    std::panic::resume_unwind(panic_info);
-                }
-            }
-        }
-    } else {
-        // Sync function without return value (void)
-        quote! {
-            let __flowtrace_start = std::time::Instant::now();
-            let __flowtrace_module = #module_path;
-            let __flowtrace_function = #fn_name_str;
-
-            // Log ENTER event with args
-            flowtrace_agent::log_event(
-                flowtrace_agent::TraceEvent::enter(
-                    __flowtrace_module,
-                    __flowtrace_function,
-                    #args_capture,
-                )
-            );
-
-            // Execute original function body with panic handling
-            let __flowtrace_panic_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                #fn_block
-            }));
-
-            // Calculate duration in microseconds
-            let __flowtrace_duration = __flowtrace_start.elapsed().as_micros() as i64;
-
-            match __flowtrace_panic_result {
-                Ok(_) => {
-                    // Log EXIT event (void function)
-                    flowtrace_agent::log_event(
-                        flowtrace_agent::TraceEvent::exit(
-                            __flowtrace_module,
-                            __flowtrace_function,
-                            Some("()".to_string()),
-                            Some(__flowtrace_duration),
-                        )
-                    );
-                }
-                Err(panic_info) => {
-                    // Log panic as EXCEPTION event
-                    let error_msg = if let Some(s) = panic_info.downcast_ref::<&str>() {
-                        s.to_string()
-                    } else if let Some(s) = panic_info.downcast_ref::<String>() {
-                        s.clone()
-                    } else {
-                        "Unknown panic".to_string()
-                    };
-
-                    flowtrace_agent::log_event(
-                        flowtrace_agent::TraceEvent::exception(
-                            __flowtrace_module,
-                            __flowtrace_function,
-                            &error_msg,
-                            Some(__flowtrace_duration),
-                        )
-                    );
-
-                    std::panic::resume_unwind(panic_info);
-                }
-            }
-        }
+pub fn trace(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = match flowtrace_codegen::TraceArgs::parse(attr.into()) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
     };
-
-    // Rebuild the function with instrumentation
-    let output = quote! {
-        #(#fn_attrs)*
-        #fn_vis #fn_sig {
-            #instrumented_body
-        }
+    if let Ok(input) = syn::parse::<ItemImpl>(item.clone()) {
+        return TokenStream::from(flowtrace_codegen::trace_impl(&args, &input));
+    }
+    let input = match syn::parse::<ItemFn>(item.clone()) {
+        Ok(input) => input,
+        Err(e) => return unsupported_item(item.into(), e).to_compile_error().into(),
     };
 
-    TokenStream::from(output)
+    TokenStream::from(flowtrace_codegen::trace(&args, &input))
 }
 
-/// Helper function to detect Result<T, E> type
-fn is_result_type(ty: &Type) -> bool {
-    if let Type::Path(type_path) = ty {
-        if let Some(segment) = type_path.path.segments.last() {
-            return segment.ident == "Result";
-        }
+/// Explain why `item`, which `#[trace]` was put on, is not a function it can instrument
+///
+/// `parse_error` is returned for input that is not an item at all.
+fn unsupported_item(item: proc_macro2::TokenStream, parse_error: syn::Error) -> syn::Error {
+    let declaration = syn::parse2::<ForeignItemFn>(item.clone())
+        .map(|declaration| declaration.sig)
+        .or_else(|_| syn::parse2::<TraitItemFn>(item.clone()).map(|declaration| declaration.sig));
+    if let Ok(sig) = declaration {
+        return syn::Error::new_spanned(
+            sig,
+            "#[trace] needs a function body: declarations in a trait or `extern` block \
+             can't be instrumented; trace each `impl` of the method, or a Rust function calling it",
+        );
+    }
+    match syn::parse2::<Item>(item) {
+        Ok(item) => syn::Error::new_spanned(item, "#[trace] can only be applied to functions, methods and `impl` blocks"),
+        Err(_) => parse_error,
     }
-    false
 }
 
 /// Trace a block of code
@@ -408,26 +120,31 @@ fn is_result_type(ty: &Type) -> bool {
 /// ```
 #[proc_macro]
 pub fn trace_block(input: TokenStream) -> TokenStream {
-    let input = proc_macro2::TokenStream::from(input);
+    let TraceBlockInput { name, body } = parse_macro_input!(input as TraceBlockInput);
+
+    let name = match name {
+        Some(name) => quote! { #name },
+        None => quote! { stringify!(#body) },
+    };
 
     let output = quote! {
         {
-            let __flowtrace_start = std::time::Instant::now();
+            let __flowtrace_start = flowtrace_agent::monotonic_micros();
             flowtrace_agent::log_event(
                 flowtrace_agent::TraceEvent::enter(
                     module_path!(),
-                    stringify!(#input),
+                    #name,
                     None,
                 )
             );
 
-            let __flowtrace_result = #input;
+            let __flowtrace_result = #body;
 
-            let __flowtrace_duration = __flowtrace_start.elapsed().as_micros() as i64;
+            let __flowtrace_duration = flowtrace_agent::monotonic_micros() - __flowtrace_start;
             flowtrace_agent::log_event(
                 flowtrace_agent::TraceEvent::exit(
                     module_path!(),
-                    stringify!(#input),
+                    #name,
                     Some(format!("{:?}", __flowtrace_result)),
                     Some(__flowtrace_duration),
                 )
@@ -439,3 +156,26 @@ pub fn trace_block(input: TokenStream) -> TokenStream {
 
     TokenStream::from(output)
 }
+
+/// Input for `trace_block!`: an optional block name followed by the traced expression
+struct TraceBlockInput {
+    name: Option<LitStr>,
+    body: Expr,
+}
+
+impl Parse for TraceBlockInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = if input.peek(LitStr) && input.peek2(Token![,]) {
+            let name = input.parse()?;
+            input.parse::<Token![,]>()?;
+            Some(name)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name,
+            body: input.parse()?,
+        })
+    }
+}
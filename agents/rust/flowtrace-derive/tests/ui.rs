@@ -0,0 +1,9 @@
+//! `#[trace]` expansions that must compile and run, alone and combined with other
+//! attribute macros, and the errors it reports on functions it can't instrument
+
+#[test]
+fn test_expansions_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/*.rs");
+    cases.compile_fail("tests/ui/fail/*.rs");
+}
@@ -0,0 +1,22 @@
+use actix_web::{get, web, App, HttpResponse, Responder};
+use flowtrace_agent::trace;
+
+#[get("/users/{id}")]
+#[trace]
+async fn get_user(id: web::Path<u32>) -> impl Responder {
+    HttpResponse::Ok().body(format!("user {}", id))
+}
+
+#[trace]
+#[get("/health")]
+async fn health() -> impl Responder {
+    HttpResponse::Ok()
+}
+
+#[actix_web::main]
+async fn main() {
+    let app = actix_web::test::init_service(App::new().service(get_user).service(health)).await;
+    let request = actix_web::test::TestRequest::get().uri("/users/7").to_request();
+    let body = actix_web::test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "user 7");
+}
@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use flowtrace_agent::{trace, Config, TraceEvent, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+#[async_trait]
+trait Repository {
+    async fn find(&self, id: u32) -> Result<String, String>;
+    async fn count(&self) -> usize;
+    async fn load(&self);
+}
+
+struct Memory(Vec<String>);
+
+#[async_trait]
+impl Repository for Memory {
+    #[trace]
+    async fn find(&self, id: u32) -> Result<String, String> {
+        self.0.get(id as usize).cloned().ok_or_else(|| format!("no user {}", id))
+    }
+
+    #[trace]
+    async fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    #[trace(tracer = TRACER)]
+    async fn load(&self) {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let repository = Memory(vec!["ada".to_string()]);
+    assert_eq!(repository.find(0).await.unwrap(), "ada");
+    assert!(repository.find(1).await.is_err());
+    assert_eq!(repository.count().await, 1);
+
+    // Timed across the await, not just while the boxed future is built
+    let path = std::env::temp_dir().join("flowtrace_ui_async_trait.jsonl");
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config { log_file: path.display().to_string(), agent_info: false, ..Default::default() })
+        .unwrap();
+    repository.load().await;
+    TRACER.stop();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let exit = TraceEvent::from_json_line(log.lines().last().unwrap()).unwrap();
+    assert!(exit.duration_micros.unwrap() >= 20_000, "{:?}", exit);
+}
@@ -0,0 +1,23 @@
+use cached::proc_macro::cached;
+use flowtrace_agent::trace;
+
+#[cached]
+#[trace]
+fn fibonacci(n: u64) -> u64 {
+    if n < 2 {
+        n
+    } else {
+        fibonacci(n - 1) + fibonacci(n - 2)
+    }
+}
+
+#[trace]
+#[cached]
+fn square(n: u64) -> u64 {
+    n * n
+}
+
+fn main() {
+    assert_eq!(fibonacci(20), 6765);
+    assert_eq!(square(12), 144);
+}
@@ -0,0 +1,32 @@
+use flowtrace_agent::trace;
+
+#[derive(Debug)]
+enum Status {
+    Open,
+}
+
+struct Order {
+    id: u64,
+    status: Status,
+    #[allow(dead_code)]
+    lines: Vec<String>,
+}
+
+impl Order {
+    #[trace(capture_self(fields(id, status)))]
+    fn total(&self, discount: u32) -> u32 {
+        100 - discount
+    }
+
+    #[trace(capture_self(fields(id)))]
+    async fn ship(&mut self) {
+        self.status = Status::Open;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut order = Order { id: 7, status: Status::Open, lines: Vec::new() };
+    assert_eq!(order.total(10), 90);
+    order.ship().await;
+}
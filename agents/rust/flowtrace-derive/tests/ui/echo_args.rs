@@ -0,0 +1,20 @@
+use flowtrace_agent::trace;
+
+#[trace(echo_args(user_id))]
+fn charge(user_id: u64, card: String) -> Result<u32, String> {
+    // `card` is moved; `user_id` was captured for the EXIT before the body ran
+    drop(card);
+    if user_id == 0 { Err("no user".to_string()) } else { Ok(100) }
+}
+
+#[trace(echo_args(name))]
+async fn greet(name: String) -> String {
+    format!("hello {}", name)
+}
+
+#[tokio::main]
+async fn main() {
+    assert_eq!(charge(7, "visa".to_string()), Ok(100));
+    assert!(charge(0, "visa".to_string()).is_err());
+    assert_eq!(greet("ada".to_string()).await, "hello ada");
+}
@@ -0,0 +1,13 @@
+use flowtrace_agent::trace;
+
+#[derive(Debug)]
+struct Order {
+    id: u64,
+}
+
+#[trace(capture_self(fields(id)))]
+fn ship(order: Order) -> u64 {
+    order.id
+}
+
+fn main() {}
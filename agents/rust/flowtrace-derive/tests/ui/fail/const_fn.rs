@@ -0,0 +1,10 @@
+use flowtrace_agent::trace;
+
+#[trace]
+const fn answer() -> u32 {
+    42
+}
+
+fn main() {
+    answer();
+}
@@ -0,0 +1,8 @@
+use flowtrace_agent::trace;
+
+#[trace(echo_args(account))]
+fn refund(user_id: u64) -> u64 {
+    user_id
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use flowtrace_agent::trace;
+
+extern "C" {
+    #[trace]
+    pub fn abs(input: i32) -> i32;
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use flowtrace_agent::trace;
+
+struct Account {
+    balance: u64,
+}
+
+#[trace(echo_args(amount))]
+impl Account {
+    fn deposit(&mut self, amount: u64) {
+        self.balance += amount;
+    }
+}
+
+fn main() {}
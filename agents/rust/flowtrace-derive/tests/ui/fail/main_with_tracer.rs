@@ -0,0 +1,10 @@
+use flowtrace_agent::{trace, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+#[trace(main, tracer = TRACER)]
+fn run() {}
+
+fn main() {
+    run();
+}
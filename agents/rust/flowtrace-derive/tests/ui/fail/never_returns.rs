@@ -0,0 +1,8 @@
+use flowtrace_agent::trace;
+
+#[trace]
+fn serve() -> ! {
+    loop {}
+}
+
+fn main() {}
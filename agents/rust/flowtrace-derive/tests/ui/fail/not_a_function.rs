@@ -0,0 +1,8 @@
+use flowtrace_agent::trace;
+
+#[trace]
+struct Order {
+    id: u64,
+}
+
+fn main() {}
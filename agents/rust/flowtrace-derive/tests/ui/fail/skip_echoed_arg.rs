@@ -0,0 +1,8 @@
+use flowtrace_agent::trace;
+
+#[trace(skip(token), echo_args(token))]
+fn refresh(token: String) -> usize {
+    token.len()
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use flowtrace_agent::trace;
+
+trait Repository {
+    #[trace]
+    fn find(&self, id: u32) -> Option<String>;
+}
+
+fn main() {}
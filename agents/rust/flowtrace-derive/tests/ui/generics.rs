@@ -0,0 +1,85 @@
+use std::fmt::Debug;
+
+use flowtrace_agent::{trace, Config, TraceEvent, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+#[derive(Clone)]
+struct Opaque;
+
+// `item` has no `Debug` bound, so only `count` is captured
+#[trace(tracer = TRACER)]
+fn repeat<T: Clone>(item: T, count: usize) -> Vec<T> {
+    vec![item; count]
+}
+
+#[trace]
+fn largest<'a, T>(items: &'a [T], fallback: &'a T) -> &'a T
+where
+    T: PartialOrd + Debug,
+{
+    items.iter().fold(fallback, |max, item| if item > max { item } else { max })
+}
+
+#[trace]
+fn parse<T, E>(text: &str, parser: impl Fn(&str) -> Result<T, E>) -> Result<T, E> {
+    parser(text)
+}
+
+#[trace]
+async fn first<I>(items: I) -> Option<I::Item>
+where
+    I: IntoIterator,
+{
+    items.into_iter().next()
+}
+
+trait Shape {
+    fn area(&self) -> f64;
+
+    #[trace]
+    fn larger(&self, other: &Self) -> bool {
+        self.area() > other.area()
+    }
+
+    #[trace]
+    fn scaled(&self, factor: f64) -> f64
+    where
+        Self: Debug,
+    {
+        self.area() * factor
+    }
+}
+
+#[derive(Debug)]
+struct Square(f64);
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.0 * self.0
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let path = std::env::temp_dir().join("flowtrace_ui_generics.jsonl");
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config { log_file: path.display().to_string(), agent_info: false, ..Default::default() })
+        .unwrap();
+    assert_eq!(repeat(Opaque, 2).len(), 2);
+    TRACER.stop();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let events: Vec<TraceEvent> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+    assert_eq!(events[0].args.as_deref(), Some(r#"{"count": 2}"#));
+    assert_eq!(events[1].result, None);
+
+    assert_eq!(*largest(&[1, 5, 3], &0), 5);
+    assert!(parse("x", |_| Err::<u32, Opaque>(Opaque)).is_err());
+    assert_eq!(parse("7", |text| text.parse::<u32>()).unwrap(), 7);
+    assert_eq!(first(vec![Opaque]).await.map(|_| ()), Some(()));
+    assert!(Square(2.0).larger(&Square(1.0)));
+    assert_eq!(Square(2.0).scaled(2.0), 8.0);
+}
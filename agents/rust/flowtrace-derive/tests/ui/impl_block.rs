@@ -0,0 +1,120 @@
+use std::fmt::Debug;
+
+use flowtrace_agent::{trace, Config, TraceEvent, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+#[derive(Clone)]
+struct Opaque;
+
+struct Stack<T> {
+    items: Vec<T>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    count: u32,
+}
+
+// `Self` is `Counter`, so `new` and `bump` log their results
+#[trace(tracer = TRACER)]
+impl Counter {
+    fn new() -> Self {
+        Self { count: 0 }
+    }
+
+    fn bump(self) -> Self {
+        Self { count: self.count + 1 }
+    }
+}
+
+// Every method is traced, logged under `<module>::Stack`
+#[trace(tracer = TRACER)]
+impl<T: Clone> Stack<T> {
+    const LIMIT: usize = 2;
+
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    // `item` has no `Debug` bound, so it is not captured
+    fn push(&mut self, item: T) -> Result<usize, String> {
+        if self.is_full() {
+            return Err("full".to_string());
+        }
+        self.items.push(item);
+        Ok(self.items.len())
+    }
+
+    fn is_full(&self) -> bool {
+        self.items.len() >= Self::LIMIT
+    }
+
+    #[trace(tracer = TRACER, skip(label))]
+    fn peek(&self, label: &str) -> Option<T> {
+        let _ = label;
+        self.items.last().cloned()
+    }
+
+    async fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    // Left as it is: it can't be instrumented
+    const fn limit() -> usize {
+        Self::LIMIT
+    }
+}
+
+trait Describe {
+    fn describe(&self, verbose: bool) -> String;
+}
+
+#[trace]
+impl<T: Debug> Describe for Vec<T> {
+    fn describe(&self, verbose: bool) -> String {
+        if verbose { format!("{:?}", self) } else { self.len().to_string() }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let path = std::env::temp_dir().join("flowtrace_ui_impl_block.jsonl");
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config { log_file: path.display().to_string(), agent_info: false, ..Default::default() })
+        .unwrap();
+    let mut stack = Stack::new();
+    assert_eq!(stack.push(Opaque), Ok(1));
+    assert!(stack.peek("top").is_some());
+    assert_eq!(stack.len().await, 1);
+    TRACER.stop();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let events: Vec<TraceEvent> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+    let class = format!("{}::Stack", module_path!());
+    assert!(events.iter().all(|event| event.module == class));
+    let calls: Vec<&str> = events.iter().map(|event| event.function.as_str()).collect();
+    assert_eq!(calls, ["new", "new", "push", "is_full", "is_full", "push", "peek", "peek", "len", "len"]);
+    assert_eq!(events[2].args, None);
+    assert_eq!(events[6].args, None);
+    // `Self` is `Stack<T>` with `T` not known to be `Debug`
+    assert_eq!(events[1].result, None);
+
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config { log_file: path.display().to_string(), agent_info: false, ..Default::default() })
+        .unwrap();
+    assert_eq!(Counter::new().bump().count, 1);
+    TRACER.stop();
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let events: Vec<TraceEvent> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+    assert_eq!(events[1].function, "new");
+    assert_eq!(events[1].result.as_deref(), Some("Counter { count: 0 }"));
+    assert_eq!(events[3].result.as_deref(), Some("Counter { count: 1 }"));
+
+    assert_eq!(Stack::<Opaque>::limit(), 2);
+    assert_eq!(vec![1, 2].describe(false), "2");
+}
@@ -0,0 +1,39 @@
+use flowtrace_agent::{trace, Config, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+#[derive(Debug)]
+struct Customer {
+    id: u32,
+}
+
+#[derive(Debug)]
+struct Order {
+    id: u32,
+    customer: Customer,
+}
+
+#[trace(tracer = TRACER, max_depth = 1)]
+fn ship(order: Order) -> Vec<Order> {
+    vec![order]
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("flowtrace_ui_max_depth.jsonl");
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config {
+            log_file: path.display().to_string(),
+            agent_info: false,
+            ..Default::default()
+        })
+        .unwrap();
+    ship(Order { id: 7, customer: Customer { id: 3 } });
+    TRACER.stop();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let events: Vec<_> = log.lines().map(|line| flowtrace_agent::TraceEvent::from_json_line(line).unwrap()).collect();
+    assert_eq!(events[0].args.as_deref(), Some(r#"{"order": Order { id: 7, customer: Customer {...} }}"#));
+    assert_eq!(events[1].result.as_deref(), Some("[Order {...}]"));
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use flowtrace_agent::{trace, Config, EventType, Mode, TraceEvent, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+// Resumes on whichever worker is free, interleaved with the other calls
+#[trace(tracer = TRACER)]
+async fn handle(id: u32) -> u32 {
+    for _ in 0..5 {
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    id
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let path = std::env::temp_dir().join("flowtrace_ui_on_demand.jsonl");
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config {
+            log_file: path.display().to_string(),
+            mode: Mode::OnDemand,
+            agent_info: false,
+            ..Default::default()
+        })
+        .unwrap();
+
+    // Running when the window opens, so left out
+    let before: Vec<_> = (100..104).map(|id| tokio::spawn(handle(id))).collect();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    // Entered in the window, which closes while they are still awaiting
+    TRACER.record_for(Duration::from_millis(50));
+    let during: Vec<_> = (0..8).map(|id| tokio::spawn(handle(id))).collect();
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let after: Vec<_> = (200..204).map(|id| tokio::spawn(handle(id))).collect();
+
+    for call in before.into_iter().chain(during).chain(after) {
+        call.await.unwrap();
+    }
+    TRACER.stop();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let events: Vec<_> = log.lines().map(|line| TraceEvent::from_json_line(line).unwrap()).collect();
+    // The id of each call, from its ENTER args or its EXIT result
+    let entered: BTreeSet<String> = events
+        .iter()
+        .filter(|event| matches!(event.event_type, EventType::Enter))
+        .map(|event| event.args.clone().unwrap())
+        .collect();
+    let exited: BTreeSet<String> = events
+        .iter()
+        .filter(|event| matches!(event.event_type, EventType::Exit))
+        .map(|event| format!("{{\"id\": {}}}", event.result.as_deref().unwrap()))
+        .collect();
+
+    let expected: BTreeSet<String> = (0..8).map(|id| format!("{{\"id\": {}}}", id)).collect();
+    assert_eq!(entered, expected, "{}", log);
+    assert_eq!(exited, expected, "{}", log);
+    assert_eq!(events.len(), 16, "{}", log);
+}
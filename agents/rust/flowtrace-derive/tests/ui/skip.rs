@@ -0,0 +1,36 @@
+use flowtrace_agent::{trace, Config, Tracer};
+
+static TRACER: Tracer = Tracer::new();
+
+#[trace(tracer = TRACER, skip(password, api_key))]
+fn login(user: &str, password: &str, api_key: String) -> bool {
+    !password.is_empty() && !api_key.is_empty() && user == "ada"
+}
+
+#[trace(tracer = TRACER, skip(token), echo_args(user))]
+async fn refresh(user: u64, token: String) -> Result<u64, String> {
+    if token.is_empty() { Err("no token".to_string()) } else { Ok(user) }
+}
+
+#[tokio::main]
+async fn main() {
+    let path = std::env::temp_dir().join("flowtrace_ui_skip.jsonl");
+    let _ = std::fs::remove_file(&path);
+    TRACER
+        .start(Config {
+            log_file: path.display().to_string(),
+            agent_info: false,
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(login("ada", "hunter2", "sk-123".to_string()));
+    assert_eq!(refresh(7, "secret".to_string()).await, Ok(7));
+    TRACER.stop();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!log.contains("hunter2") && !log.contains("sk-123") && !log.contains("secret"));
+    let events: Vec<_> = log.lines().map(|line| flowtrace_agent::TraceEvent::from_json_line(line).unwrap()).collect();
+    assert_eq!(events[0].args.as_deref(), Some(r#"{"user": "ada"}"#));
+    assert_eq!(events[2].args.as_deref(), Some(r#"{"user": 7}"#));
+}
@@ -0,0 +1,22 @@
+use flowtrace_agent::trace;
+
+#[trace]
+async fn double(x: u32) -> u32 {
+    x * 2
+}
+
+#[tokio::main]
+#[trace]
+async fn main() {
+    assert_eq!(inner().await, 4);
+}
+
+#[trace]
+#[tokio::main(flavor = "current_thread")]
+async fn inner_runtime() -> u32 {
+    double(2).await
+}
+
+async fn inner() -> u32 {
+    std::thread::spawn(inner_runtime).join().unwrap()
+}
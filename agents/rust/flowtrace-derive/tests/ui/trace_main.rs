@@ -0,0 +1,26 @@
+use flowtrace_agent::trace;
+
+#[trace]
+fn work(n: u32) -> u32 {
+    n + 1
+}
+
+#[trace(main)]
+fn run() {
+    work(1);
+}
+
+fn main() {
+    let log = std::env::temp_dir().join(format!("flowtrace_ui_main_{}.jsonl", std::process::id()));
+    std::env::set_var("FLOWTRACE_LOGFILE", &log);
+
+    run();
+    // The tracer was started before ENTER and stopped, flushing, after EXIT
+    let written = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    let methods: Vec<&str> = written
+        .lines()
+        .filter_map(|line| line.split("\"method\":\"").nth(1)?.split('"').next())
+        .collect();
+    assert_eq!(methods, ["run", "work", "work", "run"]);
+}
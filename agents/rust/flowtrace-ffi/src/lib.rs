@@ -0,0 +1,6 @@
+//! C API of the FlowTrace Rust agent, see `include/flowtrace.h`
+//!
+//! Builds `libflowtrace.so` / `.dylib` / `flowtrace.dll` and the static
+//! `libflowtrace.a` exporting the functions of `flowtrace_agent::ffi`.
+
+pub use flowtrace_agent::ffi::*;